@@ -0,0 +1,1544 @@
+use crate::board::movegen::Move;
+use crate::board::piece::Color;
+use crate::board::{parse, GameState, Position};
+use crate::book::polyglot_key;
+use crate::pgn::{self, Game, Node, PgnReader, START_FEN};
+use crate::tablebase::{Dtm, Tablebase};
+use crate::testsuite;
+use crate::timeman::Limits;
+use crate::uci::client::{EngineError, UciEngine};
+use crate::uci::UciScore;
+use anyhow::{anyhow, Result};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Engines may overstep their time by this many milliseconds before they lose on time
+const TIME_MARGIN: u64 = 100;
+
+// Mate scores count as this many centipawns when adjudicating
+const MATE_CP: i32 = 100_000;
+
+/// What an engine answered to go: its move in UCI notation and the
+/// last score and depth it reported.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reply {
+    pub mv: String,
+    pub score: Option<UciScore>,
+    pub depth: u32,
+}
+
+/// The game so far as a player gets it: the start position of the game,
+/// the moves since then, the position they lead to and the clock.
+pub struct Turn<'a> {
+    pub fen: &'a str,
+    pub moves: &'a [Move],
+    pub pos: &'a Position,
+    pub limits: &'a Limits,
+    /// The player's first move of a game, players are kept between games.
+    pub new_game: bool,
+}
+
+/// Plays the moves of one side in one game.
+pub type Player = Box<dyn FnMut(&Turn) -> Result<Reply>>;
+
+/// A UCI engine binary and how to set it up.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineConfig {
+    pub name: String,
+    pub cmd: String,
+    pub args: Vec<String>,
+    /// Sent with setoption after the handshake.
+    pub options: Vec<(String, String)>,
+}
+
+/// Time given to the engines, in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeControl {
+    /// base for every moves moves, or the whole game, plus inc per move.
+    Clock {
+        moves: Option<u32>,
+        base: u64,
+        inc: u64,
+    },
+    MoveTime(u64),
+}
+
+impl TimeControl {
+    /// Reads the cutechess format, moves/seconds+increment, e.g. 40/60, 10+0.1 or 60.
+    pub fn parse(s: &str) -> Result<TimeControl> {
+        let invalid = || anyhow!("Invalid time control '{s}'");
+        let seconds = |s: &str| -> Result<u64> {
+            let secs: f64 = s.parse().map_err(|_| invalid())?;
+            if secs < 0.0 {
+                return Err(invalid());
+            }
+            Ok((secs * 1000.0).round() as u64)
+        };
+
+        let (moves, rest) = match s.split_once('/') {
+            Some((moves, rest)) => (Some(moves.parse().map_err(|_| invalid())?), rest),
+            None => (None, s),
+        };
+        let (base, inc) = match rest.split_once('+') {
+            Some((base, inc)) => (seconds(base)?, seconds(inc)?),
+            None => (seconds(rest)?, 0),
+        };
+
+        if moves == Some(0) || base == 0 {
+            return Err(invalid());
+        }
+        Ok(TimeControl::Clock { moves, base, inc })
+    }
+}
+
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeControl::Clock { moves, base, inc } => {
+                if let Some(moves) = moves {
+                    write!(f, "{moves}/")?;
+                }
+                write!(f, "{}", *base as f64 / 1000.0)?;
+                if *inc > 0 {
+                    write!(f, "+{}", *inc as f64 / 1000.0)?;
+                }
+                Ok(())
+            }
+            TimeControl::MoveTime(ms) => write!(f, "{}/move", *ms as f64 / 1000.0),
+        }
+    }
+}
+
+/// Draw once both engines have scored within score centipawns of zero
+/// for movecount moves in a row, from move movenumber on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawRule {
+    pub movenumber: u32,
+    pub movecount: u32,
+    pub score: i32,
+}
+
+/// Loss for an engine that has scored score centipawns or worse
+/// for movecount moves in a row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResignRule {
+    pub movecount: u32,
+    pub score: i32,
+}
+
+/// Sequential probability ratio test of elo1 against elo0,
+/// with the error rates alpha and beta.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// H0 holds, the patch isn't elo1 stronger.
+    H0,
+    H1,
+}
+
+/// Expected score of an engine elo stronger than its opponent.
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+impl Sprt {
+    /// Log likelihood ratios at which H0 and H1 are accepted.
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    /// Log likelihood ratio of H1 against H0, with the trinomial
+    /// approximation of the generalized SPRT.
+    pub fn llr(&self, score: &MatchScore) -> f64 {
+        let Some((mean, variance)) = score.mean_and_variance() else {
+            return 0.0;
+        };
+        if variance == 0.0 {
+            return 0.0;
+        }
+
+        let s0 = expected_score(self.elo0);
+        let s1 = expected_score(self.elo1);
+        score.games() as f64 * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+
+    pub fn verdict(&self, score: &MatchScore) -> Option<Verdict> {
+        let llr = self.llr(score);
+        let (lower, upper) = self.bounds();
+
+        if llr <= lower {
+            Some(Verdict::H0)
+        } else if llr >= upper {
+            Some(Verdict::H1)
+        } else {
+            None
+        }
+    }
+}
+
+/// Results from the point of view of the first engine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MatchScore {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Average points per game and their variance.
+    fn mean_and_variance(&self) -> Option<(f64, f64)> {
+        let n = self.games() as f64;
+        if n == 0.0 {
+            return None;
+        }
+
+        let mean = (self.wins as f64 + self.draws as f64 / 2.0) / n;
+        let variance = (self.wins as f64 * (1.0 - mean).powi(2)
+            + self.draws as f64 * (0.5 - mean).powi(2)
+            + self.losses as f64 * mean.powi(2))
+            / n;
+        Some((mean, variance))
+    }
+
+    /// Elo difference and the margin of its 95% confidence interval.
+    /// None until the result is neither all wins nor all losses.
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let (mean, variance) = self.mean_and_variance()?;
+        if mean <= 0.0 || mean >= 1.0 {
+            return None;
+        }
+
+        let elo = |score: f64| {
+            let score = score.clamp(1e-9, 1.0 - 1e-9);
+            400.0 * (score / (1.0 - score)).log10()
+        };
+        let deviation = (variance / self.games() as f64).sqrt();
+        let low = elo(mean - 1.96 * deviation);
+        let high = elo(mean + 1.96 * deviation);
+
+        Some((elo(mean), (high - low) / 2.0))
+    }
+
+    /// Likelihood of superiority, the chance that the first engine is stronger.
+    pub fn los(&self) -> f64 {
+        let decisive = (self.wins + self.losses) as f64;
+        if decisive == 0.0 {
+            return 0.5;
+        }
+
+        0.5 * (1.0 + erf((self.wins as f64 - self.losses as f64) / (2.0 * decisive).sqrt()))
+    }
+
+    fn add(&mut self, winner: Option<Color>, first_is_white: bool) {
+        match winner {
+            None => self.draws += 1,
+            Some(Color::White) if first_is_white => self.wins += 1,
+            Some(Color::Black) if !first_is_white => self.wins += 1,
+            Some(_) => self.losses += 1,
+        }
+    }
+}
+
+/// Abramowitz and Stegun 7.1.26, good to 1.5e-7.
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1.0 - poly * (-x * x).exp();
+
+    if x < 0.0 {
+        -y
+    } else {
+        y
+    }
+}
+
+/// A position to start games from, played with both colours.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opening {
+    pub fen: String,
+    pub moves: Vec<Move>,
+}
+
+impl Default for Opening {
+    fn default() -> Self {
+        Opening {
+            fen: START_FEN.to_string(),
+            moves: vec![],
+        }
+    }
+}
+
+/// Reads openings from a PGN file, or from an EPD file with one position per line.
+pub fn read_openings(path: &str) -> Result<Vec<Opening>> {
+    let reader = BufReader::new(File::open(path).map_err(|e| anyhow!("{path}: {e}"))?);
+
+    let is_pgn = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pgn"));
+    let openings = if is_pgn {
+        let mut out = vec![];
+        for game in PgnReader::new(reader) {
+            let game = game?;
+            out.push(Opening {
+                fen: game.tag("FEN").unwrap_or(START_FEN).to_string(),
+                moves: game.moves.iter().map(|n| n.mv).collect(),
+            });
+        }
+        out
+    } else {
+        let lines: Vec<String> = reader.lines().collect::<Result<_, _>>()?;
+        // Validates the positions, the FEN is kept as it came
+        testsuite::read_suite(lines.join("\n").as_bytes())?;
+        lines
+            .iter()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| Opening {
+                fen: format!(
+                    "{} 0 1",
+                    l.split_whitespace().take(4).collect::<Vec<_>>().join(" ")
+                ),
+                moves: vec![],
+            })
+            .collect()
+    };
+
+    if openings.is_empty() {
+        return Err(anyhow!("No openings in {path}"));
+    }
+    Ok(openings)
+}
+
+/// Everything a match needs besides the openings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchConfig {
+    pub engines: [EngineConfig; 2],
+    pub games: u32,
+    pub concurrency: usize,
+    pub openings: Option<String>,
+    pub time: TimeControl,
+    pub draw: Option<DrawRule>,
+    pub resign: Option<ResignRule>,
+    /// Draw after this many moves of each side.
+    pub max_moves: Option<u32>,
+    /// Directory with tables written by tbgen.
+    pub tablebases: Option<String>,
+    pub sprt: Option<Sprt>,
+    pub pgn_out: Option<String>,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            engines: Default::default(),
+            games: 100,
+            concurrency: 1,
+            openings: None,
+            time: TimeControl::Clock {
+                moves: None,
+                base: 10_000,
+                inc: 100,
+            },
+            draw: None,
+            resign: None,
+            max_moves: None,
+            tablebases: None,
+            sprt: None,
+            pgn_out: None,
+        }
+    }
+}
+
+pub const MATCH_USAGE: &str = "Usage: match -engine cmd=<path> [name=<name>] [arg=<arg>] \
+[option.<name>=<value>] -engine ... [-games n] [-concurrency n] [-openings <file.epd|file.pgn>] \
+[-tc <moves/seconds+inc> | -st <seconds>] [-draw movenumber=n movecount=n score=cp] \
+[-resign movecount=n score=cp] [-maxmoves n] [-tb <directory>] \
+[-sprt elo0=e elo1=e alpha=a beta=b] [-pgnout <file>]";
+
+impl MatchConfig {
+    /// Parses command line arguments in the style of cutechess-cli.
+    pub fn from_args(args: &[String]) -> Result<MatchConfig> {
+        let mut config = MatchConfig::default();
+        let mut engines = vec![];
+        let mut i = 0;
+
+        while i < args.len() {
+            let flag = args[i].as_str();
+            let values: Vec<&str> = args[i + 1..]
+                .iter()
+                .take_while(|a| !a.starts_with('-'))
+                .map(|a| a.as_str())
+                .collect();
+            i += 1 + values.len();
+
+            let single = || -> Result<&str> {
+                match values[..] {
+                    [v] => Ok(v),
+                    _ => Err(anyhow!("{flag} takes one value")),
+                }
+            };
+            let pairs = || -> Result<Vec<(&str, &str)>> {
+                values
+                    .iter()
+                    .map(|v| {
+                        v.split_once('=')
+                            .ok_or_else(|| anyhow!("Expected key=value after {flag}, got '{v}'"))
+                    })
+                    .collect()
+            };
+
+            match flag {
+                "-engine" => engines.push(engine_config(&pairs()?)?),
+                "-games" => config.games = number(flag, single()?)?,
+                "-concurrency" => config.concurrency = number::<usize>(flag, single()?)?.max(1),
+                "-openings" => config.openings = Some(single()?.to_string()),
+                "-tc" => config.time = TimeControl::parse(single()?)?,
+                "-st" => {
+                    let secs: f64 = number(flag, single()?)?;
+                    config.time = TimeControl::MoveTime((secs * 1000.0).round() as u64);
+                }
+                "-draw" => {
+                    let mut rule = DrawRule {
+                        movenumber: 40,
+                        movecount: 8,
+                        score: 10,
+                    };
+                    for (key, value) in pairs()? {
+                        match key {
+                            "movenumber" => rule.movenumber = number(key, value)?,
+                            "movecount" => rule.movecount = number(key, value)?,
+                            "score" => rule.score = number(key, value)?,
+                            _ => return Err(anyhow!("Unknown -draw setting '{key}'")),
+                        }
+                    }
+                    config.draw = Some(rule);
+                }
+                "-resign" => {
+                    let mut rule = ResignRule {
+                        movecount: 3,
+                        score: 600,
+                    };
+                    for (key, value) in pairs()? {
+                        match key {
+                            "movecount" => rule.movecount = number(key, value)?,
+                            "score" => rule.score = number(key, value)?,
+                            _ => return Err(anyhow!("Unknown -resign setting '{key}'")),
+                        }
+                    }
+                    config.resign = Some(rule);
+                }
+                "-maxmoves" => config.max_moves = Some(number(flag, single()?)?),
+                "-tb" => config.tablebases = Some(single()?.to_string()),
+                "-sprt" => {
+                    let mut sprt = Sprt {
+                        elo0: 0.0,
+                        elo1: 5.0,
+                        alpha: 0.05,
+                        beta: 0.05,
+                    };
+                    for (key, value) in pairs()? {
+                        match key {
+                            "elo0" => sprt.elo0 = number(key, value)?,
+                            "elo1" => sprt.elo1 = number(key, value)?,
+                            "alpha" => sprt.alpha = number(key, value)?,
+                            "beta" => sprt.beta = number(key, value)?,
+                            _ => return Err(anyhow!("Unknown -sprt setting '{key}'")),
+                        }
+                    }
+                    if sprt.elo1 <= sprt.elo0
+                        || !(0.0..0.5).contains(&sprt.alpha)
+                        || !(0.0..0.5).contains(&sprt.beta)
+                    {
+                        return Err(anyhow!("-sprt needs elo0 < elo1 and alpha, beta below 0.5"));
+                    }
+                    config.sprt = Some(sprt);
+                }
+                "-pgnout" => config.pgn_out = Some(single()?.to_string()),
+                _ => return Err(anyhow!("Unknown argument '{flag}'")),
+            }
+        }
+
+        config.engines = match <[EngineConfig; 2]>::try_from(engines) {
+            Ok(engines) => engines,
+            Err(_) => return Err(anyhow!("A match needs two engines")),
+        };
+        Ok(config)
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value '{value}' for {name}"))
+}
+
+fn engine_config(pairs: &[(&str, &str)]) -> Result<EngineConfig> {
+    let mut engine = EngineConfig::default();
+
+    for (key, value) in pairs {
+        match *key {
+            "cmd" => engine.cmd = value.to_string(),
+            "name" => engine.name = value.to_string(),
+            "arg" => engine.args.push(value.to_string()),
+            _ => match key.strip_prefix("option.") {
+                Some(name) => engine.options.push((name.to_string(), value.to_string())),
+                None => return Err(anyhow!("Unknown -engine setting '{key}'")),
+            },
+        }
+    }
+
+    if engine.cmd.is_empty() {
+        return Err(anyhow!("-engine needs cmd=<path>"));
+    }
+    if engine.name.is_empty() {
+        engine.name = Path::new(&engine.cmd)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| engine.cmd.clone());
+    }
+    Ok(engine)
+}
+
+/// Starts the engine and has it play through UCI, losing on time
+/// when it doesn't answer within the time it has left.
+pub fn uci_player(engine: &EngineConfig) -> Result<Player> {
+    let mut uci = UciEngine::spawn(&engine.cmd, &engine.args)?;
+    for (name, value) in &engine.options {
+        uci.set_option(name, value)?;
+    }
+
+    Ok(Box::new(move |turn: &Turn| {
+        if turn.new_game {
+            uci.new_game()?;
+        }
+        let moves: Vec<String> = turn.moves.iter().map(|m| turn.pos.move_to_uci(m)).collect();
+        uci.set_position(turn.fen, &moves)?;
+
+        let limits = turn.limits;
+        let allowed = match turn.pos.side() {
+            _ if limits.movetime.is_some() => limits.movetime,
+            Color::White => limits.wtime,
+            Color::Black => limits.btime,
+        };
+        let timeout = allowed.map(|ms| Duration::from_millis(ms + TIME_MARGIN));
+        let result = uci.go(limits, timeout)?;
+
+        Ok(Reply {
+            score: result.score(),
+            depth: result.depth().unwrap_or(0),
+            mv: result.best,
+        })
+    }))
+}
+
+/// How a game ended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub winner: Option<Color>,
+    pub reason: String,
+    /// Value of the PGN Termination tag.
+    pub termination: &'static str,
+}
+
+impl Outcome {
+    fn new(winner: Option<Color>, reason: impl Into<String>, termination: &'static str) -> Self {
+        Outcome {
+            winner,
+            reason: reason.into(),
+            termination,
+        }
+    }
+
+    pub fn result(&self) -> &'static str {
+        match self.winner {
+            Some(Color::White) => "1-0",
+            Some(Color::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+}
+
+fn side_name(c: Color) -> &'static str {
+    match c {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+fn centipawns(score: UciScore) -> i32 {
+    match score {
+        UciScore::Cp(cp) => cp,
+        UciScore::Mate(n) if n > 0 => MATE_CP,
+        UciScore::Mate(_) => -MATE_CP,
+    }
+}
+
+/// Adjudication settings shared by every game of a match.
+pub struct Rules<'a> {
+    pub time: TimeControl,
+    pub draw: Option<DrawRule>,
+    pub resign: Option<ResignRule>,
+    pub max_moves: Option<u32>,
+    pub tablebases: Option<&'a Tablebase>,
+}
+
+impl Rules<'_> {
+    /// The end of the game by the rules of chess or adjudication, if it is over.
+    fn judge(&self, pos: &Position, keys: &[u64], scores: &[i32], played: u32) -> Option<Outcome> {
+        let side = pos.side();
+        match pos.gamestate() {
+            GameState::Won(c) => {
+                let reason = if pos.legal_moves().is_empty() {
+                    format!("{} mates", side_name(*c))
+                } else {
+                    format!("{} wins", side_name(*c))
+                };
+                return Some(Outcome::new(Some(*c), reason, "normal"));
+            }
+            GameState::Draw => {
+                let reason = if pos.legal_moves().is_empty() {
+                    "Draw by stalemate"
+                } else if pos.halfturn() >= 100 {
+                    "Draw by fifty moves rule"
+                } else {
+                    "Draw by insufficient mating material"
+                };
+                return Some(Outcome::new(None, reason, "normal"));
+            }
+            _ => (),
+        }
+
+        let key = keys.last().copied();
+        if keys.iter().filter(|k| Some(**k) == key).count() >= 3 {
+            return Some(Outcome::new(None, "Draw by 3-fold repetition", "normal"));
+        }
+
+        if let Some(dtm) = self.tablebases.and_then(|tb| tb.probe(pos)) {
+            let (winner, reason) = match dtm {
+                Dtm::Win(_) => (Some(side), format!("{} wins", side_name(side))),
+                Dtm::Loss(_) => (
+                    Some(side.opposite()),
+                    format!("{} wins", side_name(side.opposite())),
+                ),
+                Dtm::Draw => (None, "Draw".to_string()),
+            };
+            return Some(Outcome::new(
+                winner,
+                format!("{reason} by adjudication: tablebase"),
+                "adjudication",
+            ));
+        }
+
+        if let Some(resign) = self.resign {
+            // The scores of the side that just moved, newest first
+            let own: Vec<i32> = scores.iter().rev().step_by(2).copied().collect();
+            let n = resign.movecount as usize;
+            if n > 0 && own.len() >= n && own[..n].iter().all(|s| *s <= -resign.score) {
+                let loser = side.opposite();
+                return Some(Outcome::new(
+                    Some(side),
+                    format!("{} resigns by adjudication", side_name(loser)),
+                    "adjudication",
+                ));
+            }
+        }
+
+        if let Some(draw) = self.draw {
+            let n = 2 * draw.movecount as usize;
+            if played / 2 >= draw.movenumber
+                && n > 0
+                && scores.len() >= n
+                && scores[scores.len() - n..]
+                    .iter()
+                    .all(|s| s.abs() <= draw.score)
+            {
+                return Some(Outcome::new(None, "Draw by adjudication", "adjudication"));
+            }
+        }
+
+        if self.max_moves.is_some_and(|max| played >= 2 * max) {
+            return Some(Outcome::new(
+                None,
+                "Draw by adjudication: move limit",
+                "adjudication",
+            ));
+        }
+
+        None
+    }
+}
+
+/// A finished game, ready to be written as PGN.
+pub struct Played {
+    pub game: Game,
+    pub outcome: Outcome,
+    /// Side whose player crashed or hung, it has to be restarted.
+    pub failed: Option<Color>,
+}
+
+/// Plays a game from the opening with players[0] as white.
+pub fn play_game(
+    opening: &Opening,
+    players: [&mut Player; 2],
+    names: [&str; 2],
+    rules: &Rules,
+) -> Result<Played> {
+    let start = parse::from_fen(opening.fen.clone())
+        .map_err(|e| anyhow!("Invalid opening {}: {e}", opening.fen))?;
+    let mut number: usize = opening
+        .fen
+        .split_whitespace()
+        .nth(5)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1);
+
+    let mut pos = start.clone();
+    let mut nodes: Vec<Node> = vec![];
+    let mut keys = vec![polyglot_key(&pos)];
+    let mut record = |pos: &mut Position, mv: Move, comment: Option<String>| -> Result<()> {
+        let side = pos.side();
+        let san = pos.move_to_san(&mv);
+        pos.play(mv)?;
+        nodes.push(Node {
+            mv,
+            san,
+            number,
+            side,
+            nags: vec![],
+            comment_before: None,
+            comment,
+            variations: vec![],
+        });
+        if side == Color::Black {
+            number += 1;
+        }
+        Ok(())
+    };
+
+    for mv in &opening.moves {
+        record(&mut pos, *mv, None)?;
+        keys.push(polyglot_key(&pos));
+    }
+
+    let (mut clocks, moves_per_session) = match rules.time {
+        TimeControl::Clock { moves, base, .. } => ([base; 2], moves),
+        TimeControl::MoveTime(_) => ([0; 2], None),
+    };
+    let mut scores: Vec<i32> = vec![];
+    let mut played = 0;
+    let mut has_moved = [false; 2];
+    let mut failed = None;
+
+    let outcome = loop {
+        if let Some(outcome) = rules.judge(&pos, &keys, &scores, played) {
+            break outcome;
+        }
+
+        let side = pos.side();
+        let i = match side {
+            Color::White => 0,
+            Color::Black => 1,
+        };
+        let limits = match rules.time {
+            TimeControl::Clock { inc, .. } => Limits {
+                wtime: Some(clocks[0]),
+                btime: Some(clocks[1]),
+                winc: Some(inc),
+                binc: Some(inc),
+                movestogo: moves_per_session.map(|moves| (moves - (played / 2) % moves) as u64),
+                ..Default::default()
+            },
+            TimeControl::MoveTime(ms) => Limits {
+                movetime: Some(ms),
+                ..Default::default()
+            },
+        };
+
+        let started = Instant::now();
+        let reply = players[i](&Turn {
+            fen: &opening.fen,
+            moves: pos.history(),
+            pos: &pos,
+            limits: &limits,
+            new_game: !has_moved[i],
+        });
+        let elapsed = started.elapsed().as_millis() as u64;
+        has_moved[i] = true;
+
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) if matches!(e.downcast_ref(), Some(EngineError::Timeout(_))) => {
+                failed = Some(side);
+                break Outcome::new(
+                    Some(side.opposite()),
+                    format!("{} loses on time", side_name(side)),
+                    "time forfeit",
+                );
+            }
+            Err(e) => {
+                failed = Some(side);
+                break Outcome::new(
+                    Some(side.opposite()),
+                    format!("{} disconnects: {e}", names[i]),
+                    "abandoned",
+                );
+            }
+        };
+
+        let allowed = match rules.time {
+            TimeControl::Clock { .. } => clocks[i],
+            TimeControl::MoveTime(ms) => ms,
+        };
+        if elapsed > allowed + TIME_MARGIN {
+            break Outcome::new(
+                Some(side.opposite()),
+                format!("{} loses on time", side_name(side)),
+                "time forfeit",
+            );
+        }
+        if let TimeControl::Clock { inc, .. } = rules.time {
+            clocks[i] = clocks[i].saturating_sub(elapsed) + inc;
+            if let (Some(moves), TimeControl::Clock { base, .. }) = (moves_per_session, rules.time)
+            {
+                if (played / 2 + 1).is_multiple_of(moves) {
+                    clocks[i] += base;
+                }
+            }
+        }
+
+        let mv = match pos.clone().parse_uci(&reply.mv) {
+            Ok(mv) => mv,
+            Err(_) => {
+                break Outcome::new(
+                    Some(side.opposite()),
+                    format!("{} makes an illegal move: {}", side_name(side), reply.mv),
+                    "rules infraction",
+                );
+            }
+        };
+
+        let comment = match reply.score {
+            Some(UciScore::Mate(n)) => format!(
+                "{}M{}/{}",
+                if n > 0 { "+" } else { "-" },
+                n.abs(),
+                reply.depth
+            ),
+            Some(UciScore::Cp(cp)) => format!("{:+.2}/{}", cp as f64 / 100.0, reply.depth),
+            None => "book".to_string(),
+        };
+        let comment = format!("{comment} {:.3}s", elapsed as f64 / 1000.0);
+        // Unknown scores never adjudicate
+        scores.push(reply.score.map(centipawns).unwrap_or(MATE_CP));
+
+        record(&mut pos, mv, Some(comment))?;
+        keys.push(polyglot_key(&pos));
+        played += 1;
+    };
+
+    if let Some(last) = nodes.last_mut() {
+        last.comment = Some(match &last.comment {
+            Some(c) => format!("{c}, {}", outcome.reason),
+            None => outcome.reason.clone(),
+        });
+    }
+
+    let mut tags = vec![
+        ("Event".to_string(), "StockCrab match".to_string()),
+        ("Site".to_string(), "?".to_string()),
+        ("White".to_string(), names[0].to_string()),
+        ("Black".to_string(), names[1].to_string()),
+        ("Result".to_string(), outcome.result().to_string()),
+        ("Termination".to_string(), outcome.termination.to_string()),
+        ("TimeControl".to_string(), rules.time.to_string()),
+    ];
+    if opening.fen != START_FEN {
+        tags.push(("FEN".to_string(), opening.fen.clone()));
+        tags.push(("SetUp".to_string(), "1".to_string()));
+    }
+
+    Ok(Played {
+        game: Game {
+            tags,
+            start,
+            moves: nodes,
+            result: outcome.result().to_string(),
+        },
+        outcome,
+        failed,
+    })
+}
+
+/// Plays the games of a match on config.concurrency threads. Every thread
+/// starts its players with spawn(0) and spawn(1) and keeps them for all its
+/// games, restarting one only after it crashed or hung. An engine that
+/// doesn't start loses that game. Every opening is played twice with the
+/// colours reversed. Progress goes to out, games to the PGN file of the
+/// config. Stops early once the SPRT reaches a verdict.
+pub fn run_match<S, W>(
+    config: &MatchConfig,
+    openings: &[Opening],
+    spawn: S,
+    out: &mut W,
+) -> Result<MatchScore>
+where
+    S: Fn(usize) -> Result<Player> + Sync,
+    W: Write + Send,
+{
+    let tablebases = match &config.tablebases {
+        Some(dir) => Some(Tablebase::load(Path::new(dir))?),
+        None => None,
+    };
+    let rules = Rules {
+        time: config.time,
+        draw: config.draw,
+        resign: config.resign,
+        max_moves: config.max_moves,
+        tablebases: tablebases.as_ref(),
+    };
+    let default_opening = [Opening::default()];
+    let openings = if openings.is_empty() {
+        &default_opening[..]
+    } else {
+        openings
+    };
+
+    let pgn_out = match &config.pgn_out {
+        Some(path) => Some(
+            File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow!("{path}: {e}"))?,
+        ),
+        None => None,
+    };
+
+    let names = [
+        config.engines[0].name.as_str(),
+        config.engines[1].name.as_str(),
+    ];
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let shared = Mutex::new((MatchScore::default(), pgn_out, out));
+
+    let worker = || -> Result<()> {
+        // Indexed by engine, not by colour
+        let mut players: [Option<Player>; 2] = [None, None];
+
+        loop {
+            let n = next.fetch_add(1, Ordering::SeqCst);
+            if n >= config.games as usize || stop.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            // The first engine is white in even games
+            let first_is_white = n.is_multiple_of(2);
+            let order = if first_is_white { [0, 1] } else { [1, 0] };
+
+            let mut not_started = vec![];
+            for (side, engine) in [Color::White, Color::Black].into_iter().zip(order) {
+                if players[engine].is_none() {
+                    match spawn(engine) {
+                        Ok(player) => players[engine] = Some(player),
+                        Err(e) => not_started
+                            .push((side, format!("{} fails to start: {e}", names[engine]))),
+                    }
+                }
+            }
+
+            let opening = &openings[(n / 2) % openings.len()];
+            let result = match &mut not_started[..] {
+                [] => {
+                    let [Some(first), Some(second)] = &mut players else {
+                        unreachable!("both players have started");
+                    };
+                    let (white, black) = if first_is_white {
+                        (first, second)
+                    } else {
+                        (second, first)
+                    };
+                    let names = [names[order[0]], names[order[1]]];
+                    play_game(opening, [white, black], names, &rules).map(|mut played| {
+                        // A player that crashed or hung starts over for the next game
+                        if let Some(side) = played.failed {
+                            let i = if side == Color::White { 0 } else { 1 };
+                            players[order[i]] = None;
+                        }
+                        played
+                            .game
+                            .tags
+                            .insert(2, ("Round".to_string(), (n + 1).to_string()));
+                        (played.outcome, Some(played.game))
+                    })
+                }
+                [(side, reason)] => Ok((
+                    Outcome::new(Some(side.opposite()), std::mem::take(reason), "abandoned"),
+                    None,
+                )),
+                _ => Err(anyhow!("neither engine starts")),
+            };
+
+            let mut shared = shared.lock().unwrap();
+            let (score, pgn_out, out) = &mut *shared;
+            match result {
+                Ok((outcome, game)) => {
+                    score.add(outcome.winner, first_is_white);
+                    if let (Some(file), Some(game)) = (pgn_out, game) {
+                        pgn::write_game(file, &game)?;
+                    }
+                    writeln!(
+                        out,
+                        "Finished game {} ({} vs {}): {} {{{}}}",
+                        n + 1,
+                        names[order[0]],
+                        names[order[1]],
+                        outcome.result(),
+                        outcome.reason
+                    )?;
+                }
+                // Neither a broken opening nor two engines that fail to start is scored
+                Err(e) => writeln!(
+                    out,
+                    "Game {} ({} vs {}) not played: {e}",
+                    n + 1,
+                    names[order[0]],
+                    names[order[1]]
+                )?,
+            }
+            write!(out, "{}", Report::new(*score, names, config.sprt))?;
+            out.flush()?;
+
+            if config
+                .sprt
+                .is_some_and(|sprt| sprt.verdict(score).is_some())
+            {
+                stop.store(true, Ordering::SeqCst);
+            }
+        }
+    };
+
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..config.concurrency.max(1))
+            .map(|_| s.spawn(worker))
+            .collect();
+        handles.into_iter().try_for_each(|h| {
+            h.join()
+                .unwrap_or_else(|_| Err(anyhow!("A match thread panicked")))
+                .inspect_err(|_| stop.store(true, Ordering::SeqCst))
+        })
+    })?;
+
+    let (score, _, _) = shared.into_inner().unwrap();
+    Ok(score)
+}
+
+/// Standing of a match: the score, the Elo difference and the SPRT.
+pub struct Report<'a> {
+    pub score: MatchScore,
+    pub names: [&'a str; 2],
+    pub sprt: Option<Sprt>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(score: MatchScore, names: [&'a str; 2], sprt: Option<Sprt>) -> Self {
+        Report { score, names, sprt }
+    }
+}
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = &self.score;
+        let points = s.wins as f64 + s.draws as f64 / 2.0;
+        writeln!(
+            f,
+            "Score of {} vs {}: {} - {} - {}  [{:.3}] {}",
+            self.names[0],
+            self.names[1],
+            s.wins,
+            s.losses,
+            s.draws,
+            points / s.games().max(1) as f64,
+            s.games()
+        )?;
+
+        match s.elo() {
+            Some((elo, margin)) => writeln!(
+                f,
+                "Elo difference: {elo:.1} +/- {margin:.1}, LOS: {:.1} %",
+                s.los() * 100.0
+            )?,
+            None => writeln!(f, "Elo difference: unknown, LOS: {:.1} %", s.los() * 100.0)?,
+        }
+
+        if let Some(sprt) = self.sprt {
+            let (lower, upper) = sprt.bounds();
+            let verdict = match sprt.verdict(s) {
+                Some(Verdict::H0) => "H0 was accepted",
+                Some(Verdict::H1) => "H1 was accepted",
+                None => "no verdict yet",
+            };
+            writeln!(
+                f,
+                "SPRT: llr {:.2} ({lower:.2}, {upper:.2}), elo0 {} elo1 {}: {verdict}",
+                sprt.llr(s),
+                sprt.elo0,
+                sprt.elo1
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tablebase::Material;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    /// Plays the move given for the ply, or the first legal move.
+    fn scripted(script: &'static [&'static str], score: i32) -> Player {
+        Box::new(move |turn: &Turn| {
+            let ply = turn.moves.len();
+            let mv = match script.get(ply) {
+                Some(mv) => mv.to_string(),
+                None => turn.pos.clone().move_to_uci(&turn.pos.legal_moves()[0]),
+            };
+            Ok(Reply {
+                mv,
+                score: Some(UciScore::Cp(score)),
+                depth: 1,
+            })
+        })
+    }
+
+    const FOOLS_MATE: [&str; 4] = ["f2f3", "e7e5", "g2g4", "d8h4"];
+
+    fn rules() -> Rules<'static> {
+        Rules {
+            time: TimeControl::MoveTime(1000),
+            draw: None,
+            resign: None,
+            max_moves: None,
+            tablebases: None,
+        }
+    }
+
+    #[test]
+    fn config() {
+        let config = MatchConfig::from_args(&args(
+            "-engine cmd=./new name=patch option.Hash=64 -engine cmd=/usr/bin/stock-crab arg=-q \
+             -games 200 -concurrency 4 -tc 40/60+0.5 -sprt elo0=-1 elo1=4 \
+             -draw movenumber=30 -resign score=800 -maxmoves 150 -pgnout games.pgn",
+        ))
+        .unwrap();
+
+        assert_eq!(config.engines[0].name, "patch");
+        assert_eq!(
+            config.engines[0].options,
+            vec![("Hash".to_string(), "64".to_string())]
+        );
+        assert_eq!(config.engines[1].name, "stock-crab");
+        assert_eq!(config.engines[1].args, vec!["-q"]);
+        assert_eq!(config.games, 200);
+        assert_eq!(config.concurrency, 4);
+        assert_eq!(
+            config.time,
+            TimeControl::Clock {
+                moves: Some(40),
+                base: 60_000,
+                inc: 500
+            }
+        );
+        assert_eq!(config.time.to_string(), "40/60+0.5");
+        assert_eq!(config.sprt.unwrap().elo0, -1.0);
+        assert_eq!(config.draw.unwrap().movenumber, 30);
+        assert_eq!(config.draw.unwrap().movecount, 8);
+        assert_eq!(config.resign.unwrap().score, 800);
+        assert_eq!(config.max_moves, Some(150));
+        assert_eq!(config.pgn_out.as_deref(), Some("games.pgn"));
+
+        let st = MatchConfig::from_args(&args("-engine cmd=a -engine cmd=b -st 0.2")).unwrap();
+        assert_eq!(st.time, TimeControl::MoveTime(200));
+
+        assert!(MatchConfig::from_args(&args("-engine cmd=a")).is_err());
+        assert!(MatchConfig::from_args(&args("-engine name=a -engine cmd=b")).is_err());
+        assert!(MatchConfig::from_args(&args("-engine cmd=a -engine cmd=b -tc 0")).is_err());
+        assert!(
+            MatchConfig::from_args(&args("-engine cmd=a -engine cmd=b -sprt elo0=5 elo1=0"))
+                .is_err()
+        );
+        assert!(MatchConfig::from_args(&args("-engine cmd=a -engine cmd=b -foo")).is_err());
+    }
+
+    #[test]
+    fn statistics() {
+        let score = MatchScore {
+            wins: 60,
+            draws: 100,
+            losses: 40,
+        };
+        let (elo, margin) = score.elo().unwrap();
+        assert!((elo - 34.86).abs() < 0.01, "{elo}");
+        assert!((margin - 34.16).abs() < 0.01, "{margin}");
+        assert!((score.los() - 0.9772).abs() < 0.001, "{}", score.los());
+
+        let sprt = Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        };
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 0.001 && (lower + 2.944).abs() < 0.001);
+        assert!(
+            (sprt.llr(&score) - 0.545).abs() < 0.001,
+            "{}",
+            sprt.llr(&score)
+        );
+        assert_eq!(sprt.verdict(&score), None);
+
+        let bad = MatchScore {
+            wins: 300,
+            draws: 1000,
+            losses: 500,
+        };
+        assert_eq!(sprt.verdict(&bad), Some(Verdict::H0));
+        let good = MatchScore {
+            wins: 500,
+            draws: 1000,
+            losses: 300,
+        };
+        assert_eq!(sprt.verdict(&good), Some(Verdict::H1));
+
+        assert_eq!(MatchScore::default().elo(), None);
+        assert_eq!(
+            MatchScore {
+                wins: 3,
+                ..Default::default()
+            }
+            .elo(),
+            None
+        );
+    }
+
+    #[test]
+    fn games() {
+        let mut white = scripted(&["f2f3", "e7e5", "f1f1"], 0);
+        let mut black = scripted(&["f2f3", "e7e5", "f1f1"], 0);
+        let played = play_game(
+            &Opening::default(),
+            [&mut white, &mut black],
+            ["a", "b"],
+            &rules(),
+        )
+        .unwrap();
+        assert_eq!(played.outcome.result(), "0-1");
+        assert_eq!(played.outcome.reason, "White makes an illegal move: f1f1");
+        assert_eq!(played.outcome.termination, "rules infraction");
+
+        // Fool's mate
+        let mut white = scripted(&FOOLS_MATE, 0);
+        let mut black = scripted(&FOOLS_MATE, 0);
+        let played = play_game(
+            &Opening::default(),
+            [&mut white, &mut black],
+            ["a", "b"],
+            &rules(),
+        )
+        .unwrap();
+        assert_eq!(played.outcome.result(), "0-1");
+        assert_eq!(played.outcome.reason, "Black mates");
+
+        let mut text = vec![];
+        pgn::write_game(&mut text, &played.game).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("[White \"a\"]"), "{text}");
+        let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(flat.contains("2... Qh4# {+0.00/1"), "{text}");
+        assert!(flat.contains("Black mates} 0-1"), "{text}");
+        assert_eq!(pgn::read_games(&text).unwrap()[0].moves.len(), 4);
+
+        // A player that fails loses
+        let mut broken: Player = Box::new(|_: &Turn| Err(anyhow!("The engine terminated")));
+        let played = play_game(
+            &Opening::default(),
+            [&mut white, &mut broken],
+            ["a", "b"],
+            &rules(),
+        )
+        .unwrap();
+        assert_eq!(played.outcome.winner, Some(Color::White));
+        assert_eq!(
+            played.outcome.reason,
+            "b disconnects: The engine terminated"
+        );
+
+        let mut slow: Player =
+            Box::new(|_: &Turn| Err(EngineError::Timeout("go".to_string()).into()));
+        let played = play_game(
+            &Opening::default(),
+            [&mut slow, &mut black],
+            ["a", "b"],
+            &rules(),
+        )
+        .unwrap();
+        assert_eq!(played.outcome.reason, "White loses on time");
+        assert_eq!(played.outcome.termination, "time forfeit");
+    }
+
+    #[test]
+    fn adjudication() {
+        let opening = Opening {
+            fen: "4k3/8/8/8/8/8/8/R3K3 w - - 0 1".to_string(),
+            moves: vec![],
+        };
+        let shuffle = |a: &'static str, b: &'static str| -> Player {
+            Box::new(move |turn: &Turn| {
+                let mv = if turn.moves.len() % 4 < 2 { a } else { b };
+                Ok(Reply {
+                    mv: mv.to_string(),
+                    score: Some(UciScore::Cp(0)),
+                    depth: 1,
+                })
+            })
+        };
+
+        let mut white = shuffle("a1a2", "a2a1");
+        let mut black = shuffle("e8d8", "d8e8");
+        let played = play_game(&opening, [&mut white, &mut black], ["a", "b"], &rules()).unwrap();
+        assert_eq!(played.outcome.reason, "Draw by 3-fold repetition");
+        assert_eq!(played.game.moves.len(), 8);
+
+        let draw = Rules {
+            draw: Some(DrawRule {
+                movenumber: 0,
+                movecount: 1,
+                score: 10,
+            }),
+            ..rules()
+        };
+        let played = play_game(&opening, [&mut white, &mut black], ["a", "b"], &draw).unwrap();
+        assert_eq!(played.outcome.reason, "Draw by adjudication");
+        assert_eq!(played.game.moves.len(), 2);
+
+        let max = Rules {
+            max_moves: Some(1),
+            ..rules()
+        };
+        let played = play_game(&opening, [&mut white, &mut black], ["a", "b"], &max).unwrap();
+        assert_eq!(played.outcome.reason, "Draw by adjudication: move limit");
+        assert!(played
+            .game
+            .tags
+            .contains(&("FEN".to_string(), opening.fen.clone())));
+
+        // Black resigns after three bad scores of its own
+        let mut white = scripted(&[], 700);
+        let mut black = scripted(&[], -700);
+        let resign = Rules {
+            resign: Some(ResignRule {
+                movecount: 3,
+                score: 600,
+            }),
+            ..rules()
+        };
+        let played = play_game(
+            &Opening::default(),
+            [&mut white, &mut black],
+            ["a", "b"],
+            &resign,
+        )
+        .unwrap();
+        assert_eq!(played.outcome.reason, "Black resigns by adjudication");
+        assert_eq!(played.outcome.winner, Some(Color::White));
+        assert_eq!(played.game.moves.len(), 6);
+
+        let tb = Tablebase::generate(&[Material::Kqk]).unwrap();
+        let tablebase = Rules {
+            tablebases: Some(&tb),
+            ..rules()
+        };
+        let opening = Opening {
+            fen: "4k3/8/8/8/8/8/8/3QK3 b - - 0 1".to_string(),
+            moves: vec![],
+        };
+        let played = play_game(&opening, [&mut white, &mut black], ["a", "b"], &tablebase).unwrap();
+        assert_eq!(
+            played.outcome.reason,
+            "White wins by adjudication: tablebase"
+        );
+        assert!(played.game.moves.is_empty());
+    }
+
+    #[test]
+    fn matches() {
+        let config = MatchConfig {
+            engines: [
+                EngineConfig {
+                    name: "first".to_string(),
+                    ..Default::default()
+                },
+                EngineConfig {
+                    name: "second".to_string(),
+                    ..Default::default()
+                },
+            ],
+            games: 6,
+            concurrency: 2,
+            time: TimeControl::MoveTime(1000),
+            ..Default::default()
+        };
+
+        // Both play the fool's mate, which black wins
+        let openings = [Opening::default()];
+        let spawn = |_: usize| -> Result<Player> { Ok(scripted(&FOOLS_MATE, 0)) };
+        let mut out = vec![];
+        let score = run_match(&config, &openings, spawn, &mut out).unwrap();
+        assert_eq!(
+            score,
+            MatchScore {
+                wins: 3,
+                draws: 0,
+                losses: 3
+            }
+        );
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains("(first vs second): 0-1 {Black mates}"),
+            "{out}"
+        );
+        assert!(
+            out.contains("(second vs first): 0-1 {Black mates}"),
+            "{out}"
+        );
+        assert!(
+            out.contains("Score of first vs second: 3 - 3 - 0  [0.500] 6"),
+            "{out}"
+        );
+
+        // An SPRT verdict ends the match early. The first engine loses
+        // every game with white and draws the rest on the move limit.
+        let config = MatchConfig {
+            games: 1000,
+            concurrency: 1,
+            max_moves: Some(2),
+            sprt: Some(Sprt {
+                elo0: 0.0,
+                elo1: 5.0,
+                alpha: 0.05,
+                beta: 0.05,
+            }),
+            ..config
+        };
+        let spawn = |i: usize| -> Result<Player> {
+            Ok(match i {
+                0 => scripted(&["a1a1"], 0),
+                _ => scripted(&[], 0),
+            })
+        };
+        let score = run_match(&config, &[], spawn, &mut vec![]).unwrap();
+        assert!(score.games() < 1000);
+        assert_eq!(config.sprt.unwrap().verdict(&score), Some(Verdict::H0));
+    }
+
+    #[test]
+    fn failures() {
+        let config = MatchConfig {
+            engines: [
+                EngineConfig {
+                    name: "first".to_string(),
+                    ..Default::default()
+                },
+                EngineConfig {
+                    name: "second".to_string(),
+                    ..Default::default()
+                },
+            ],
+            games: 4,
+            concurrency: 1,
+            time: TimeControl::MoveTime(1000),
+            ..Default::default()
+        };
+
+        // Players are kept between games and told when a new one starts,
+        // only the one that crashes is started again
+        let spawned = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        let spawn = |i: usize| -> Result<Player> {
+            spawned[i].fetch_add(1, Ordering::SeqCst);
+            let mut player = scripted(&[], 0);
+            Ok(Box::new(move |turn: &Turn| {
+                assert_eq!(turn.new_game, turn.moves.len() < 2);
+                match i {
+                    0 => player(turn),
+                    _ => Err(anyhow!("crashed")),
+                }
+            }))
+        };
+        let mut out = vec![];
+        let score = run_match(&config, &[], spawn, &mut out).unwrap();
+        assert_eq!(score.wins, 4);
+        assert_eq!(spawned[0].load(Ordering::SeqCst), 1);
+        assert_eq!(spawned[1].load(Ordering::SeqCst), 4);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("{second disconnects: crashed}"), "{out}");
+
+        // An engine that doesn't start loses the game, the match goes on
+        let spawn = |i: usize| -> Result<Player> {
+            match i {
+                0 => Ok(scripted(&[], 0)),
+                _ => Err(anyhow!("no such file")),
+            }
+        };
+        let mut out = vec![];
+        let score = run_match(&config, &[], spawn, &mut out).unwrap();
+        assert_eq!(score.wins, 4);
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains("{second fails to start: no such file}"),
+            "{out}"
+        );
+
+        // Broken openings aren't scored
+        let openings = [Opening {
+            fen: "4k3/8/8/8/8/8/8/4K3 x - - 0 1".to_string(),
+            moves: vec![],
+        }];
+        let spawn = |_: usize| -> Result<Player> { Ok(scripted(&[], 0)) };
+        let mut out = vec![];
+        let score = run_match(&config, &openings, spawn, &mut out).unwrap();
+        assert_eq!(score.games(), 0);
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains("Game 4 (second vs first) not played: Invalid opening"),
+            "{out}"
+        );
+    }
+}
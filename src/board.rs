@@ -1,168 +1,380 @@
-#![allow(dead_code)]
-
-mod bitboard;
-mod eval;
-mod movegen;
+pub mod atomic;
+pub mod bitboard;
+pub mod chess960;
+pub mod crazyhouse;
+pub mod epd;
+pub mod eval;
+pub mod movegen;
+pub mod notation;
 pub mod parse;
 pub mod piece;
-mod square;
+pub mod square;
+pub mod variant;
+pub mod zobrist;
 
 use bitboard::BitBoard;
+use crazyhouse::Pocket;
 use movegen::Move;
+use notation::IllegalMove;
 use piece::{Color, PieceType};
 use square::Square;
-use std::collections::HashMap;
+use variant::Variant;
 
-#[derive(Clone, Debug, PartialEq)]
+/// Castling rights of one side, kept as the files of the rooks that may
+/// still castle so Chess960 start positions work as well.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Castling {
-    king_side: bool,
-    queen_side: bool,
+    king_side: Option<u32>,
+    queen_side: Option<u32>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct SideMap(HashMap<PieceType, BitBoard>);
+impl Castling {
+    pub fn king_side(&self) -> bool {
+        self.king_side.is_some()
+    }
+
+    pub fn queen_side(&self) -> bool {
+        self.queen_side.is_some()
+    }
+
+    pub fn king_side_rook(&self) -> Option<u32> {
+        self.king_side
+    }
+
+    pub fn queen_side_rook(&self) -> Option<u32> {
+        self.queen_side
+    }
+
+    /// Takes away the right to castle with the rook on file.
+    fn remove_rook(&mut self, file: u32) {
+        if self.king_side == Some(file) {
+            self.king_side = None;
+        }
+        if self.queen_side == Some(file) {
+            self.queen_side = None;
+        }
+    }
+}
+
+/// One bitboard per piece type, indexed by PieceType::index.
+/// Kept as a plain array so positions are cheap to copy between threads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SideMap([BitBoard; 6]);
 
 impl SideMap {
     pub fn new() -> SideMap {
-        SideMap(HashMap::from([
-            (PieceType::King, BitBoard::new()),
-            (PieceType::Queen, BitBoard::new()),
-            (PieceType::Rook, BitBoard::new()),
-            (PieceType::Bishop, BitBoard::new()),
-            (PieceType::Knight, BitBoard::new()),
-            (PieceType::Pawn, BitBoard::new()),
-        ]))
+        SideMap([BitBoard::new(); 6])
     }
 
     pub fn toggle(&mut self, ptype: PieceType, file: u32, rank: u32) {
-        self.0.entry(ptype).and_modify(|b| b.toggle(file, rank));
+        self.0[ptype.index()].toggle(file, rank);
     }
 
     pub fn get(&self, ptype: PieceType, file: u32, rank: u32) -> Option<bool> {
-        self.0.get(&ptype).unwrap().get(file, rank)
+        self.0[ptype.index()].get(file, rank)
     }
 
     pub fn set(&mut self, ptype: PieceType, file: u32, rank: u32) {
-        self.0.get_mut(&ptype).unwrap().set(file, rank)
+        self.0[ptype.index()].set(file, rank)
     }
 
     pub fn unset(&mut self, ptype: PieceType, file: u32, rank: u32) {
-        self.0.get_mut(&ptype).unwrap().unset(file, rank)
+        self.0[ptype.index()].unset(file, rank)
     }
 
     pub fn combine(&self) -> BitBoard {
         let mut out = BitBoard::new();
-        for (_, pieceboard) in &self.0 {
+        for pieceboard in &self.0 {
             out.or_assign(*pieceboard);
         }
 
         out
     }
 
-    pub fn get_map(&self) -> &HashMap<PieceType, BitBoard> {
-        &self.0
+    pub fn iter(&self) -> impl Iterator<Item = (PieceType, &BitBoard)> {
+        PieceType::ALL.into_iter().zip(self.0.iter())
     }
 
-    pub fn get_mut_map(&mut self) -> &mut HashMap<PieceType, BitBoard> {
-        &mut self.0
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (PieceType, &mut BitBoard)> {
+        PieceType::ALL.into_iter().zip(self.0.iter_mut())
     }
 
     pub fn get_board(&self, piece: PieceType) -> BitBoard {
-        self.0.get(&piece).unwrap().clone()
+        self.0[piece.index()]
     }
 
     pub fn get_mut_board(&mut self, piece: PieceType) -> &mut BitBoard {
-        self.0.get_mut(&piece).unwrap()
+        &mut self.0[piece.index()]
+    }
+}
+
+impl Default for SideMap {
+    fn default() -> Self {
+        SideMap::new()
     }
 }
 
+/// A position with everything needed to go on playing from it.
+/// Build one with parse::from_fen, then change it with play and undo.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Position {
-    pub gamestate: GameState,
+    pub(crate) gamestate: GameState,
+
+    pub(crate) side: Color,
+    pub(crate) halfturn: usize,
+
+    pub(crate) w_castling: Castling,
+
+    pub(crate) b_castling: Castling,
 
-    pub side: Color,
-    pub halfturn: usize,
+    pub(crate) en_passant: Option<Square>,
 
-    pub w_castling: Castling,
+    pub(crate) w_pieces_all: BitBoard,
+    pub(crate) w_pieces: SideMap,
 
-    pub b_castling: Castling,
+    pub(crate) w_attacks_all: BitBoard,
+    pub(crate) w_attacks: SideMap,
 
-    pub en_passant: Option<Square>,
+    pub(crate) b_pieces_all: BitBoard,
+    pub(crate) b_pieces: SideMap,
 
-    pub w_pieces_all: BitBoard,
-    pub w_pieces: SideMap,
+    pub(crate) b_attacks_all: BitBoard,
+    pub(crate) b_attacks: SideMap,
 
-    pub w_attacks_all: BitBoard,
-    pub w_attacks: SideMap,
+    pub(crate) history: Vec<Move>,
+    pub(crate) legal_moves: Vec<Move>,
 
-    pub b_pieces_all: BitBoard,
-    pub b_pieces: SideMap,
+    /// Castling moves are written as the king taking its own rook.
+    pub(crate) chess960: bool,
 
-    pub b_attacks_all: BitBoard,
-    pub b_attacks: SideMap,
+    pub(crate) variant: Variant,
+    /// Checks each side has given, only counted in Three-check.
+    pub(crate) w_checks: u32,
+    pub(crate) b_checks: u32,
 
-    pub history: Vec<Move>,
-    pub legal_moves: Vec<Move>,
+    /// Pieces in hand and pieces that were pawns, only used in Crazyhouse.
+    pub(crate) w_pocket: Pocket,
+    pub(crate) b_pocket: Pocket,
+    pub(crate) promoted: BitBoard,
+
+    /// Zobrist key, see Position::key.
+    pub(crate) key: u64,
 }
 
 impl Position {
+    fn put_piece(&mut self, c: Color, p: PieceType, file: u32, rank: u32) {
+        let (sidemap, all_board) = match c {
+            Color::White => (&mut self.w_pieces, &mut self.w_pieces_all),
+            Color::Black => (&mut self.b_pieces, &mut self.b_pieces_all),
+        };
+
+        sidemap.set(p, file, rank);
+        all_board.set(file, rank);
+        self.key ^= zobrist::piece(c, p, file, rank);
+    }
+
+    fn remove_piece(&mut self, c: Color, p: PieceType, file: u32, rank: u32) {
+        let (sidemap, all_board) = match c {
+            Color::White => (&mut self.w_pieces, &mut self.w_pieces_all),
+            Color::Black => (&mut self.b_pieces, &mut self.b_pieces_all),
+        };
+
+        sidemap.unset(p, file, rank);
+        all_board.unset(file, rank);
+        self.key ^= zobrist::piece(c, p, file, rank);
+    }
+
+    /// Files the rook moves from and to when m castles.
+    fn castling_rook_files(m: &Move) -> Option<(u32, u32)> {
+        let rook_from = m.change.castling_rook?;
+        let rook_to = if m.to.file == 6 { 5 } else { 3 };
+
+        Some((rook_from, rook_to))
+    }
+
+    pub fn side(&self) -> Color {
+        self.side
+    }
+
+    /// Half moves since the last capture or pawn move.
+    pub fn halfturn(&self) -> usize {
+        self.halfturn
+    }
+
+    pub fn castling(&self, c: Color) -> Castling {
+        match c {
+            Color::White => self.w_castling,
+            Color::Black => self.b_castling,
+        }
+    }
+
+    pub fn en_passant(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    pub fn gamestate(&self) -> &GameState {
+        &self.gamestate
+    }
+
+    /// Legal moves of the side to move.
+    pub fn legal_moves(&self) -> &[Move] {
+        &self.legal_moves
+    }
+
+    /// Moves played since the position was set up, the last one last.
+    pub fn history(&self) -> &[Move] {
+        &self.history
+    }
+
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
+
+    /// Switches castling between the king's move and the king taking its rook in UCI notation.
+    pub fn set_chess960(&mut self, chess960: bool) {
+        self.chess960 = chess960;
+    }
+
+    /// Plays m if it is legal and updates the game state.
+    pub fn play(&mut self, m: Move) -> Result<(), IllegalMove> {
+        let legal = self.generate_legal_moves();
+        let found = legal.iter().find(|l| l.same_as(&m)).or_else(|| {
+            // Castling built by hand as a plain king move
+            legal
+                .iter()
+                .find(|l| !l.drop && l.from == m.from && l.to == m.to && l.promotion == m.promotion)
+        });
+
+        match found {
+            Some(legal) => {
+                self.change_board(*legal);
+                self.update_gamestate();
+                Ok(())
+            }
+            None if m.drop => Err(self.explain_drop(m.ptype, m.to)),
+            None => Err(self.explain_illegal(m.from, m.to, m.promotion)),
+        }
+    }
+
+    /// Plays a move in UCI notation, e.g. e2e4.
+    pub fn play_uci(&mut self, s: &str) -> Result<(), IllegalMove> {
+        let m = self.find_uci(s)?;
+        self.play(m)
+    }
+
+    /// Plays a move in Standard Algebraic Notation, e.g. Nf3.
+    pub fn play_san(&mut self, s: &str) -> Result<(), IllegalMove> {
+        let m = self.find_san(s)?;
+        self.play(m)
+    }
+
+    /// A copy of the position with m played, leaving this one as it is.
+    pub fn after(&self, m: Move) -> Result<Position, IllegalMove> {
+        let mut next = self.clone();
+        next.play(m)?;
+        Ok(next)
+    }
+
+    /// Takes back the last move, None if there is nothing to take back.
+    pub fn undo(&mut self) -> Option<Move> {
+        let m = *self.history.last()?;
+
+        self.reverse_last_change();
+        self.update_gamestate();
+        Some(m)
+    }
+
     /// Assumes that m is a legal move.
     /// Will not do any checking.
-    fn change_board(&mut self, m: Move) {
+    pub(crate) fn change_board(&mut self, mut m: Move) {
         let x_from = m.from.file;
         let y_from = m.from.rank;
 
         let x_to = m.to.file;
         let y_to = m.to.rank;
 
-        let sidemap = match self.side {
-            Color::White => &mut self.w_pieces,
-            Color::Black => &mut self.b_pieces,
+        let side = self.side;
+        self.key ^= self.state_key();
+
+        // Remember what can't be worked out from the move itself
+        m.change.w_castling = self.w_castling;
+        m.change.b_castling = self.b_castling;
+        m.change.en_passant = self.en_passant;
+        m.change.halfturn = self.halfturn;
+        m.change.w_checks = self.w_checks;
+        m.change.b_checks = self.b_checks;
+        m.change.promoted = self.promoted;
+        self.change_pockets(&m);
+
+        if let Some(piece) = m.change.captured {
+            let y_captured = if m.is_en_passant() { y_from } else { y_to };
+            self.remove_piece(side.opposite(), piece, x_to, y_captured);
         };
 
-        let all_board = match self.side {
-            Color::White => &mut self.w_pieces_all,
-            Color::Black => &mut self.b_pieces_all,
-        };
+        // In Chess960 the king and rook can land on each other's squares,
+        // so both are lifted before either is put down
+        let rook = Position::castling_rook_files(&m);
+        if !m.drop {
+            self.remove_piece(side, m.ptype, x_from, y_from);
+        }
+        if let Some((rook_from, _)) = rook {
+            self.remove_piece(side, PieceType::Rook, rook_from, y_from);
+        }
 
-        all_board.unset(x_from, y_from);
-        all_board.set(x_to, y_to);
-        sidemap
-            .get_mut_map()
-            .get_mut(&m.ptype)
-            .unwrap()
-            .unset(x_from, y_from);
-
-        sidemap
-            .get_mut_map()
-            .get_mut(&m.ptype)
-            .unwrap()
-            .set(x_to, y_to);
-
-        if let Some(piece) = &m.change.captured {
-            let opp_sidemap = match self.side {
-                Color::White => &mut self.b_pieces,
-                Color::Black => &mut self.w_pieces,
-            };
-
-            let opp_all_board = match self.side {
-                Color::White => &mut self.b_pieces_all,
-                Color::Black => &mut self.w_pieces_all,
-            };
-
-            opp_all_board.unset(x_to, y_to);
-
-            opp_sidemap
-                .get_mut_map()
-                .get_mut(&piece)
-                .unwrap()
-                .unset(x_to, y_to);
-        };
+        self.put_piece(side, m.promotion.unwrap_or(m.ptype), x_to, y_to);
+        if let Some((_, rook_to)) = rook {
+            self.put_piece(side, PieceType::Rook, rook_to, y_from);
+        }
+
+        if m.ptype == PieceType::King {
+            match side {
+                Color::White => self.w_castling = Castling::default(),
+                Color::Black => self.b_castling = Castling::default(),
+            }
+        }
+
+        // A rook leaving or being captured on its starting square
+        for sqr in [m.from, m.to] {
+            match sqr.rank {
+                0 => self.w_castling.remove_rook(sqr.file),
+                7 => self.b_castling.remove_rook(sqr.file),
+                _ => (),
+            }
+        }
+
+        if self.variant == Variant::Atomic && m.change.captured.is_some() {
+            self.explode(&mut m);
+        }
+
+        self.en_passant = None;
+        if m.ptype == PieceType::Pawn && y_from.abs_diff(y_to) == 2 {
+            self.en_passant = Some(Square {
+                file: x_from,
+                rank: (y_from + y_to) / 2,
+            });
+        }
+
+        if m.ptype == PieceType::Pawn || m.change.captured.is_some() {
+            self.halfturn = 0;
+        } else {
+            self.halfturn += 1;
+        }
+
+        self.side = side.opposite();
+        self.update_attack_maps();
+
+        if self.variant == Variant::ThreeCheck && self.is_in_check(self.side) {
+            match side {
+                Color::White => self.w_checks += 1,
+                Color::Black => self.b_checks += 1,
+            }
+        }
 
+        self.key ^= self.state_key();
         self.history.push(m);
     }
 
-    fn reverse_last_change(&mut self) {
+    pub(crate) fn reverse_last_change(&mut self) {
         let m = self.history.pop().unwrap();
 
         let x_from = m.from.file;
@@ -171,59 +383,53 @@ impl Position {
         let x_to = m.to.file;
         let y_to = m.to.rank;
 
-        let sidemap = match self.side {
-            Color::White => &mut self.w_pieces,
-            Color::Black => &mut self.b_pieces,
-        };
+        let side = self.side.opposite();
+        self.key ^= self.state_key();
 
-        let all_board = match self.side {
-            Color::White => &mut self.w_pieces_all,
-            Color::Black => &mut self.b_pieces_all,
-        };
+        if self.variant == Variant::Atomic && m.change.captured.is_some() {
+            self.unexplode(&m, side);
+        }
 
-        all_board.set(x_from, y_from);
-        all_board.unset(x_to, y_to);
-        sidemap
-            .get_mut_map()
-            .get_mut(&m.ptype)
-            .unwrap()
-            .set(x_from, y_from);
-
-        sidemap
-            .get_mut_map()
-            .get_mut(&m.ptype)
-            .unwrap()
-            .unset(x_to, y_to);
-
-        if let Some(piece) = &m.change.captured {
-            let opp_sidemap = match self.side {
-                Color::White => &mut self.b_pieces,
-                Color::Black => &mut self.w_pieces,
-            };
-
-            let opp_all_board = match self.side {
-                Color::White => &mut self.b_pieces_all,
-                Color::Black => &mut self.w_pieces_all,
-            };
-
-            opp_all_board.set(x_to, y_to);
-            opp_sidemap
-                .get_mut_map()
-                .get_mut(&piece)
-                .unwrap()
-                .set(x_to, y_to);
+        let rook = Position::castling_rook_files(&m);
+        self.remove_piece(side, m.promotion.unwrap_or(m.ptype), x_to, y_to);
+        if let Some((_, rook_to)) = rook {
+            self.remove_piece(side, PieceType::Rook, rook_to, y_from);
+        }
+
+        if !m.drop {
+            self.put_piece(side, m.ptype, x_from, y_from);
+        }
+        if let Some((rook_from, _)) = rook {
+            self.put_piece(side, PieceType::Rook, rook_from, y_from);
+        }
+
+        if let Some(piece) = m.change.captured {
+            let y_captured = if m.is_en_passant() { y_from } else { y_to };
+            self.put_piece(side.opposite(), piece, x_to, y_captured);
         };
+
+        self.w_castling = m.change.w_castling;
+        self.b_castling = m.change.b_castling;
+        self.en_passant = m.change.en_passant;
+        self.halfturn = m.change.halfturn;
+        self.w_checks = m.change.w_checks;
+        self.b_checks = m.change.b_checks;
+        self.reverse_pockets(&m, side);
+
+        self.side = side;
+        self.key ^= self.state_key();
+        self.update_attack_maps();
     }
 
     fn update_attack_maps(&mut self) {
         let w_pieces = &self.w_pieces;
         let mut new_w_attacks = SideMap::new();
 
-        for (piece, board) in new_w_attacks.get_mut_map() {
+        for (piece, board) in new_w_attacks.iter_mut() {
             *board = piece::get_piece_attack_map(
-                *piece,
+                piece,
                 Color::White,
-                w_pieces.get_map().get(piece).unwrap(),
+                &w_pieces.get_board(piece),
                 &self.w_pieces_all,
                 &self.b_pieces_all,
             );
@@ -233,11 +439,11 @@ impl Position {
         let b_pieces = &self.b_pieces;
         let mut new_b_attacks = SideMap::new();
 
-        for (piece, board) in new_b_attacks.get_mut_map() {
+        for (piece, board) in new_b_attacks.iter_mut() {
             *board = piece::get_piece_attack_map(
-                *piece,
+                piece,
                 Color::Black,
-                b_pieces.get_map().get(piece).unwrap(),
+                &b_pieces.get_board(piece),
                 &self.b_pieces_all,
                 &self.w_pieces_all,
             );
@@ -250,12 +456,100 @@ impl Position {
         self.b_attacks_all = new_b_attacks_all;
     }
 
+    pub fn pieces(&self, c: Color) -> &SideMap {
+        match c {
+            Color::White => &self.w_pieces,
+            Color::Black => &self.b_pieces,
+        }
+    }
+
+    pub fn pieces_all(&self, c: Color) -> &BitBoard {
+        match c {
+            Color::White => &self.w_pieces_all,
+            Color::Black => &self.b_pieces_all,
+        }
+    }
+
+    pub fn attacks(&self, c: Color) -> &SideMap {
+        match c {
+            Color::White => &self.w_attacks,
+            Color::Black => &self.b_attacks,
+        }
+    }
+
+    pub fn attacks_all(&self, c: Color) -> &BitBoard {
+        match c {
+            Color::White => &self.w_attacks_all,
+            Color::Black => &self.b_attacks_all,
+        }
+    }
+
+    /// Fills in legal_moves and works out the gamestate for the side to move.
+    pub fn update_gamestate(&mut self) {
+        self.legal_moves = self.generate_legal_moves();
+        self.gamestate = self.gamestate_with(&self.legal_moves);
+    }
+
+    /// The gamestate for the side to move when legal_moves are its legal moves.
+    pub(crate) fn gamestate_with(&self, legal_moves: &[Move]) -> GameState {
+        let side = self.side;
+        let in_check = self.is_in_check(side);
+
+        if let Some(winner) = self.variant_winner() {
+            GameState::Won(winner)
+        } else if legal_moves.is_empty() && self.variant == Variant::Antichess {
+            // Having no pieces left, or none that can move, wins
+            GameState::Won(side)
+        } else if legal_moves.is_empty() && in_check {
+            GameState::Won(side.opposite())
+        } else if legal_moves.is_empty() || self.halfturn >= 100 || self.is_dead_draw() {
+            GameState::Draw
+        } else if in_check {
+            GameState::InCheck(side)
+        } else {
+            GameState::Normal
+        }
+    }
+
+    /// Whether neither side can ever mate: bare kings, a single minor piece,
+    /// or bishops that all stand on squares of the same colour.
+    /// The variants can all be decided without mating material.
+    pub fn is_dead_draw(&self) -> bool {
+        if self.variant != Variant::Standard {
+            return false;
+        }
+
+        let mut knights = 0;
+        let mut bishops = vec![];
+
+        for c in [Color::White, Color::Black] {
+            let pieces = self.pieces(c);
+            let heavy = [PieceType::Queen, PieceType::Rook, PieceType::Pawn]
+                .iter()
+                .any(|p| pieces.get_board(*p).count() > 0);
+            if heavy {
+                return false;
+            }
+
+            knights += pieces.get_board(PieceType::Knight).count();
+            bishops.extend(pieces.get_board(PieceType::Bishop).squares());
+        }
+
+        let colour = |sqr: &Square| (sqr.file + sqr.rank) % 2;
+        match (knights, bishops.first()) {
+            (0, None) | (1, None) => true,
+            (0, Some(first)) => bishops.iter().all(|b| colour(b) == colour(first)),
+            _ => false,
+        }
+    }
+
     /// Useful for displaying the position in a terminal.
     /// Lowecase letters refer to black pieces, uppercase refers to white.
+    #[allow(clippy::wrong_self_convention)]
     pub fn into_char_vec(&self) -> Vec<char> {
         let mut out = vec![' '; 64];
 
-        for (ptype, board) in self.w_pieces.get_map() {
+        for (ptype, board) in self.w_pieces.iter() {
             let c = ptype.to_char(Color::White);
 
             for x in 0..8 {
@@ -267,7 +561,7 @@ impl Position {
             }
         }
 
-        for (ptype, board) in self.b_pieces.get_map() {
+        for (ptype, board) in self.b_pieces.iter() {
             let c = ptype.to_char(Color::Black);
 
             for x in 0..8 {
@@ -316,6 +610,145 @@ mod tests {
             "rnbqkbnr/pppppppp/7/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string()
         )
         .is_err());
+
+        // Ranks that don't add up to 8 files, or the wrong number of ranks
+        assert!(parse::from_fen("8/8/8/8/8/8/8/KKKKKKKKK w - - 0 1".to_string()).is_err());
+        assert!(parse::from_fen("8/8/8/8/8/8/8/K6k1 w - - 0 1".to_string()).is_err());
+        assert!(parse::from_fen("Kk w - - 0 1".to_string()).is_err());
+        assert!(parse::from_fen("8/8/8/8/8/8/8/K6k/8 w - - 0 1".to_string()).is_err());
+        assert!(parse::from_fen("8/8/8/8/8/8/8/K5k0 w - - 0 1".to_string()).is_err());
+
+        // One king for each side, unless the variant can do without
+        assert!(parse::from_fen("8/8/8/8/8/8/8/8 w - - 0 1".to_string()).is_err());
+        assert!(parse::from_fen("8/8/8/8/8/8/8/K5kk w - - 0 1".to_string()).is_err());
+        assert!(parse::from_fen("8/8/8/8/8/8/8/KK5k w - - 0 1".to_string()).is_err());
+        let antichess = "8/8/8/8/8/8/8/RR6 w - - 0 1".to_string();
+        assert!(parse::from_fen_variant(antichess, Variant::Antichess).is_ok());
+
+        // Missing fields, and runs of spaces between them
+        assert!(parse::from_fen("8/8/8/8/8/8/8/K6k w".to_string()).is_err());
+        assert!(parse::from_fen("".to_string()).is_err());
+        assert!(parse::from_fen(" 8/8/8/8/8/8/8/K6k  w -\t- 0  1 ".to_string()).is_ok());
+    }
+
+    #[test]
+    fn gamestate() {
+        let state = |fen: &str| parse::from_fen(fen.to_string()).unwrap().gamestate;
+
+        let pos =
+            parse::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string())
+                .unwrap();
+        assert_eq!(pos.gamestate, GameState::Normal);
+        assert_eq!(pos.legal_moves.len(), 20);
+
+        // Fool's mate
+        let mate = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        assert_eq!(state(mate), GameState::Won(Color::Black));
+        assert_eq!(
+            state("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP1BP/RNBQK1NR w KQkq - 1 3"),
+            GameState::InCheck(Color::White)
+        );
+        assert_eq!(state("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), GameState::Draw);
+        assert_eq!(state("7k/8/6K1/8/8/8/8/R7 w - - 100 80"), GameState::Draw);
+
+        // Material that can't mate
+        assert_eq!(state("7k/8/6K1/8/8/8/8/8 w - - 0 1"), GameState::Draw);
+        assert_eq!(state("7k/8/6K1/8/8/8/8/6N1 w - - 0 1"), GameState::Draw);
+        assert_eq!(state("7k/8/6K1/2b5/8/8/8/6B1 w - - 0 1"), GameState::Draw);
+        assert_eq!(state("7k/8/6K1/1b6/8/8/8/6B1 w - - 0 1"), GameState::Normal);
+        assert_eq!(state("7k/8/6K1/8/8/8/8/5NN1 w - - 0 1"), GameState::Normal);
+        assert_eq!(state("7k/8/6K1/8/8/8/8/6BN w - - 0 1"), GameState::Normal);
+    }
+
+    #[test]
+    fn play_and_undo() {
+        let mut pos =
+            parse::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string())
+                .unwrap();
+        let start = pos.clone();
+
+        let m = pos.parse_uci("e2e4").unwrap();
+        pos.play(m).unwrap();
+        assert_eq!(pos.side(), Color::Black);
+        assert_eq!(pos.legal_moves().len(), 20);
+        assert_eq!(pos.en_passant(), Some(Square::from_str("e3").unwrap()));
+
+        // Not legal any more
+        assert_eq!(pos.play(m), Err(IllegalMove::NoPiece(m.from)));
+
+        assert_eq!(pos.undo(), Some(m));
+        assert_eq!(pos, start);
+        assert_eq!(pos.undo(), None);
+    }
+
+    #[test]
+    fn illegal_moves() {
+        let mut pos = parse::from_fen("r3k3/8/8/8/8/8/4N3/R3K2R w KQq - 0 1".to_string()).unwrap();
+        let before = pos.clone();
+        let sqr = |s: &str| Square::from_str(s).unwrap();
+
+        assert_eq!(pos.play_uci("d4d5"), Err(IllegalMove::NoPiece(sqr("d4"))));
+        assert_eq!(
+            pos.play_uci("a8a7"),
+            Err(IllegalMove::OpponentsPiece {
+                piece: PieceType::Rook,
+                square: sqr("a8")
+            })
+        );
+        assert_eq!(
+            pos.play_uci("a1b3"),
+            Err(IllegalMove::Unreachable {
+                piece: PieceType::Rook,
+                from: sqr("a1"),
+                to: sqr("b3")
+            })
+        );
+        assert_eq!(
+            pos.play_uci("h1b1"),
+            Err(IllegalMove::Blocked {
+                piece: PieceType::Rook,
+                from: sqr("h1"),
+                by: sqr("e1")
+            })
+        );
+        assert_eq!(pos.play_uci("e1e2"), Err(IllegalMove::OwnPiece(sqr("e2"))));
+        assert_eq!(
+            pos.play_san("Rb8"),
+            Err(IllegalMove::NoPieceCanMove {
+                piece: PieceType::Rook,
+                to: sqr("b8")
+            })
+        );
+        assert!(matches!(pos.play_san("Qx"), Err(IllegalMove::Invalid(_))));
+        assert_eq!(pos, before);
+
+        // The rook on d8 covers d1, so only the king side is open
+        pos.play_san("Ng3").unwrap();
+        pos.play_san("Rd8").unwrap();
+        assert_eq!(pos.play_san("O-O-O"), Err(IllegalMove::CastlingNotAllowed));
+        assert_eq!(pos.play_uci("e1c1"), Err(IllegalMove::CastlingNotAllowed));
+        let mut castle = pos.parse_san("O-O").unwrap();
+        castle.change.castling_rook = None;
+        let after = pos.after(castle).unwrap();
+        assert_eq!(after.piece_at(5, 0), Some((PieceType::Rook, Color::White)));
+        assert_eq!(after.history().len(), pos.history().len() + 1);
+
+        let mut pinned = parse::from_fen("4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1".to_string()).unwrap();
+        assert_eq!(pinned.play_san("Nc3"), Err(IllegalMove::LeavesKingInCheck));
+
+        let mut pawn = parse::from_fen("k7/4P3/8/8/8/8/4P3/4K3 w - - 0 1".to_string()).unwrap();
+        assert_eq!(
+            pawn.play_uci("e7e8"),
+            Err(IllegalMove::MustPromote(sqr("e8")))
+        );
+        assert_eq!(pawn.play_uci("e2e4q"), Err(IllegalMove::CantPromote));
+        assert_eq!(pawn.play_uci("e2e4").map(|_| pawn.side()), Ok(Color::Black));
+
+        let mut mated = parse::from_fen(
+            "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3".to_string(),
+        )
+        .unwrap();
+        assert_eq!(mated.play_uci("a2a3"), Err(IllegalMove::GameOver));
     }
 
     #[test]
@@ -329,8 +762,11 @@ mod tests {
             from: Square::from_str("e5").unwrap(),
             to: Square::from_str("f4").unwrap(),
             ptype: PieceType::Pawn,
+            promotion: None,
+            drop: false,
             change: movegen::StateChange {
                 captured: Some(PieceType::Pawn),
+                ..Default::default()
             },
         };
         tui::print_position(&pos);
@@ -0,0 +1,211 @@
+use super::movegen::Move;
+use super::piece::{Color, PieceType};
+use super::variant::Variant;
+use super::{Position, Square};
+
+/// Squares around a capture, in the order StateChange::exploded keeps them.
+const AROUND: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+fn around(sqr: Square, (df, dr): (i32, i32)) -> Option<Square> {
+    let file = sqr.file as i32 + df;
+    let rank = sqr.rank as i32 + dr;
+
+    ((0..8).contains(&file) && (0..8).contains(&rank)).then_some(Square {
+        file: file as u32,
+        rank: rank as u32,
+    })
+}
+
+impl Position {
+    /// Blows up the piece that captured with m along with every piece but a
+    /// pawn next to the capture square, and remembers them in m so the move
+    /// can be taken back. Called by change_board once the pieces have moved.
+    pub(crate) fn explode(&mut self, m: &mut Move) {
+        let side = self.side;
+        let to = m.to;
+        self.remove_piece(side, m.promotion.unwrap_or(m.ptype), to.file, to.rank);
+
+        for (i, dir) in AROUND.iter().enumerate() {
+            let Some(sqr) = around(to, *dir) else {
+                continue;
+            };
+
+            match self.piece_at(sqr.file, sqr.rank) {
+                Some((p, c)) if p != PieceType::Pawn => {
+                    self.remove_piece(c, p, sqr.file, sqr.rank);
+                    m.change.exploded[i] = Some((p, c));
+
+                    match sqr.rank {
+                        0 => self.w_castling.remove_rook(sqr.file),
+                        7 => self.b_castling.remove_rook(sqr.file),
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Puts back what m blew up, leaving the capturing piece on the capture
+    /// square for reverse_last_change to move back.
+    pub(crate) fn unexplode(&mut self, m: &Move, side: Color) {
+        let to = m.to;
+        self.put_piece(side, m.promotion.unwrap_or(m.ptype), to.file, to.rank);
+
+        for (dir, exploded) in AROUND.iter().zip(m.change.exploded) {
+            if let (Some(sqr), Some((p, c))) = (around(to, *dir), exploded) {
+                self.put_piece(c, p, sqr.file, sqr.rank);
+            }
+        }
+    }
+
+    /// Whether the kings stand next to each other. Neither can then be
+    /// captured, since that would blow up the capturer's own king too.
+    pub(crate) fn kings_touch(&self) -> bool {
+        match (
+            self.king_square(Color::White),
+            self.king_square(Color::Black),
+        ) {
+            (Some(w), Some(b)) => w.file.abs_diff(b.file) <= 1 && w.rank.abs_diff(b.rank) <= 1,
+            _ => false,
+        }
+    }
+
+    /// Atomic moves: kings can't capture, the own king may not blow up and
+    /// blowing up the other king is legal even when it leaves the own in check.
+    pub(crate) fn atomic_moves(&mut self) -> Vec<Move> {
+        let side = self.side;
+        let had_king = self.king_square(side).is_some();
+        let mut out = vec![];
+
+        for m in self.pseudo_legal_moves() {
+            if m.ptype == PieceType::King && m.change.captured.is_some() {
+                continue;
+            }
+
+            self.change_board(m);
+            let legal = match (self.king_square(side), self.king_square(side.opposite())) {
+                (None, _) => !had_king,
+                (Some(_), None) => true,
+                _ => !self.is_in_check(side),
+            };
+            self.reverse_last_change();
+
+            if legal {
+                out.push(m);
+            }
+        }
+
+        out
+    }
+
+    /// In Atomic chess the side whose king blew up has lost.
+    pub(crate) fn atomic_winner(&self) -> Option<Color> {
+        debug_assert_eq!(self.variant, Variant::Atomic);
+
+        match (
+            self.king_square(Color::White),
+            self.king_square(Color::Black),
+        ) {
+            (Some(_), None) => Some(Color::White),
+            (None, Some(_)) => Some(Color::Black),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::notation::IllegalMove;
+    use super::super::parse;
+    use super::super::GameState;
+    use super::*;
+
+    fn position(fen: &str) -> Position {
+        parse::from_fen_variant(fen.to_string(), Variant::Atomic).unwrap()
+    }
+
+    fn perft(fen: &str, depth: u32) -> u64 {
+        let mut pos = position(fen);
+        let before = pos.clone();
+        let nodes = pos.perft(depth);
+
+        assert_eq!(pos, before);
+        nodes
+    }
+
+    #[test]
+    fn perft_atomic() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(perft(start, 3), 8902);
+
+        let fen = "rn2kb1r/1pp1p2p/p2q1pp1/3P4/2P3b1/4PN2/PP3PPP/R2QKB1R b KQkq - 0 1";
+        assert_eq!(perft(fen, 1), 40);
+        assert_eq!(perft(fen, 2), 1238);
+        assert_eq!(perft(fen, 3), 45237);
+    }
+
+    #[test]
+    fn perft_atomic960() {
+        // Castling next to the other king, whose squares count as safe
+        let fen = "8/8/8/8/8/8/2k5/rR4KR w KQ - 0 1";
+        assert_eq!(perft(fen, 1), 18);
+        assert_eq!(perft(fen, 2), 180);
+        assert_eq!(perft(fen, 3), 4364);
+
+        let fen = "Rr2k1rR/3K4/3p4/8/8/8/7P/8 w kq - 0 1";
+        assert_eq!(perft(fen, 1), 21);
+        assert_eq!(perft(fen, 2), 465);
+        assert_eq!(perft(fen, 3), 10631);
+    }
+
+    #[test]
+    fn explosions() {
+        // The knight next to d5 goes, the pawn on c6 and the bishop on f5 stay
+        let mut pos = position("4k3/8/2p5/3pnb2/4P3/8/8/4K3 w - - 0 1");
+        let before = pos.clone();
+        pos.play_san("exd5").unwrap();
+        assert_eq!(pos.piece_at(3, 4), None);
+        assert_eq!(pos.piece_at(4, 4), None);
+        assert_eq!(pos.piece_at(2, 5), Some((PieceType::Pawn, Color::Black)));
+        assert_eq!(pos.piece_at(5, 4), Some((PieceType::Bishop, Color::Black)));
+        pos.undo();
+        assert_eq!(pos, before);
+
+        // Blowing up the king wins, even out of check
+        let mut pos = position("4k3/R3r3/8/8/8/8/3q4/4K3 w - - 0 1");
+        assert_eq!(pos.gamestate(), &GameState::InCheck(Color::White));
+        pos.play_san("Rxe7").unwrap();
+        assert_eq!(pos.gamestate(), &GameState::Won(Color::White));
+        assert_eq!(pos.king_square(Color::Black), None);
+
+        // Exploded rooks lose their castling rights
+        let mut pos = position("rn2k3/8/8/8/8/8/8/1R2K3 w q - 0 1");
+        pos.play_san("Rxb8").unwrap();
+        assert!(!pos.castling(Color::Black).queen_side());
+    }
+
+    #[test]
+    fn kings() {
+        // Touching kings can't be checked
+        let pos = position("8/8/8/8/8/3k4/3K3r/8 w - - 0 1");
+        assert!(!pos.is_in_check(Color::White));
+        assert_eq!(pos.gamestate(), &GameState::Normal);
+
+        let mut pos = position("7k/8/8/8/8/8/3Kn3/8 w - - 0 1");
+        assert_eq!(pos.play_san("Kxe2"), Err(IllegalMove::KingCantCapture));
+
+        // Taking next to the own king blows it up
+        let mut pos = position("7k/8/8/8/8/8/3Kn3/4R3 w - - 0 1");
+        assert_eq!(pos.play_san("Rxe2"), Err(IllegalMove::LeavesKingInCheck));
+    }
+}
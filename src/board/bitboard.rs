@@ -1,8 +1,7 @@
-#![allow(dead_code)]
-
+use super::square::Square;
 use std::fmt::Display;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct BitBoard(u64);
 
 impl Display for BitBoard {
@@ -12,12 +11,12 @@ impl Display for BitBoard {
         for y in 0..8 {
             for x in 0..8 {
                 match self.get(x, 7 - y) {
-                    Some(true) => output.push_str("1"),
-                    Some(false) => output.push_str("0"),
+                    Some(true) => output.push('1'),
+                    Some(false) => output.push('0'),
                     None => (),
                 }
             }
-            output.push_str("\n");
+            output.push('\n');
         }
 
         write!(f, "{}", output)
@@ -77,7 +76,6 @@ impl BitBoard {
         if y <= 7 && x <= 7 {
             let mask: u64 = 1 << (y * 8 + x);
             self.0 ^= mask;
-        } else {
         }
     }
 
@@ -93,6 +91,37 @@ impl BitBoard {
     pub fn or_assign(&mut self, b: BitBoard) {
         self.0 |= b.as_u64();
     }
+
+    pub fn and_assign(&mut self, b: BitBoard) {
+        self.0 &= b.as_u64();
+    }
+
+    /// Clear every square that is set in b.
+    pub fn clear_assign(&mut self, b: BitBoard) {
+        self.0 &= !b.as_u64();
+    }
+
+    /// Every square that is set, from a1 to h8.
+    pub fn squares(&self) -> Vec<Square> {
+        let mut out = vec![];
+        let mut bits = self.0;
+
+        while bits != 0 {
+            let i = bits.trailing_zeros();
+            out.push(Square {
+                file: i % 8,
+                rank: i / 8,
+            });
+            bits &= bits - 1;
+        }
+
+        out
+    }
+
+    /// Number of squares that are set.
+    pub fn count(&self) -> u32 {
+        self.0.count_ones()
+    }
 }
 
 #[cfg(test)]
@@ -109,7 +138,7 @@ mod test {
         assert!(bb1.as_u64() == 0);
 
         bb1.toggle(8, 0);
-        assert!(bb1.get(8, 0) == None);
+        assert!(bb1.get(8, 0).is_none());
 
         bb1.toggle(7, 7);
         assert!(bb1.get(7, 7) == Some(true));
@@ -0,0 +1,218 @@
+use super::piece::{Color, PieceType};
+use super::{parse, Position};
+use anyhow::{anyhow, Result};
+
+/// Number of Chess960 start positions.
+pub const COUNT: u32 = 960;
+
+/// Number of the standard start position.
+pub const STANDARD: u32 = 518;
+
+// Files of the two knights among the five squares left after the bishops and queen
+const KNIGHTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+/// Back rank of start position n in the Scharnagl numbering, from the a to the h file.
+pub fn back_rank(n: u32) -> Result<[PieceType; 8]> {
+    if n >= COUNT {
+        return Err(anyhow!(
+            "There is no Chess960 position {n}, they go from 0 to 959"
+        ));
+    }
+
+    let mut rank: [Option<PieceType>; 8] = [None; 8];
+    let n = n as usize;
+
+    // Bishops on a light and a dark square
+    rank[(n % 4) * 2 + 1] = Some(PieceType::Bishop);
+    rank[(n / 4 % 4) * 2] = Some(PieceType::Bishop);
+
+    let empty = |rank: &[Option<PieceType>; 8]| -> Vec<usize> {
+        (0..8).filter(|f| rank[*f].is_none()).collect()
+    };
+
+    let n = n / 16;
+    rank[empty(&rank)[n % 6]] = Some(PieceType::Queen);
+
+    let (a, b) = KNIGHTS[n / 6];
+    let free = empty(&rank);
+    rank[free[a]] = Some(PieceType::Knight);
+    rank[free[b]] = Some(PieceType::Knight);
+
+    // The king goes between the rooks on what is left
+    let free = empty(&rank);
+    for (f, p) in free
+        .into_iter()
+        .zip([PieceType::Rook, PieceType::King, PieceType::Rook])
+    {
+        rank[f] = Some(p);
+    }
+
+    Ok(rank.map(|p| p.unwrap()))
+}
+
+/// X-FEN of start position n. KQkq is unambiguous with only two rooks.
+pub fn start_fen(n: u32) -> Result<String> {
+    let white: String = back_rank(n)?
+        .iter()
+        .map(|p| p.to_char(Color::White))
+        .collect();
+    let black = white.to_ascii_lowercase();
+
+    Ok(format!(
+        "{black}/pppppppp/8/8/8/8/PPPPPPPP/{white} w KQkq - 0 1"
+    ))
+}
+
+/// Start position n, with castling written the Chess960 way.
+pub fn start_position(n: u32) -> Result<Position> {
+    let mut pos = parse::from_fen(start_fen(n)?)?;
+    pos.chess960 = true;
+
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(fen: &str, depth: u32) -> u64 {
+        let mut pos = parse::from_fen(fen.to_string()).unwrap();
+        let before = pos.clone();
+        let nodes = pos.perft(depth);
+
+        assert_eq!(pos, before);
+        nodes
+    }
+
+    #[test]
+    fn start_positions() {
+        let rank = |n| start_fen(n).unwrap()[..8].to_string();
+        assert_eq!(rank(0), "bbqnnrkr");
+        assert_eq!(rank(STANDARD), "rnbqkbnr");
+        assert_eq!(rank(959), "rkrnnqbb");
+        assert!(start_fen(COUNT).is_err());
+
+        let mut seen = std::collections::HashSet::new();
+        for n in 0..COUNT {
+            let rank = back_rank(n).unwrap();
+            assert!(seen.insert(rank));
+
+            let files = |p: PieceType| (0..8).filter(move |f| rank[*f] == p);
+            let bishops: Vec<usize> = files(PieceType::Bishop).collect();
+            let rooks: Vec<usize> = files(PieceType::Rook).collect();
+            let king = files(PieceType::King).next().unwrap();
+            assert_ne!(bishops[0] % 2, bishops[1] % 2);
+            assert!(rooks[0] < king && king < rooks[1]);
+        }
+
+        let pos = start_position(STANDARD).unwrap();
+        assert!(pos.chess960);
+        assert_eq!(pos.w_castling.king_side_rook(), Some(7));
+    }
+
+    #[test]
+    fn castling_rights() {
+        // Shredder-FEN and X-FEN for the same position
+        let shredder =
+            parse::from_fen("1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1".to_string())
+                .unwrap();
+        let xfen =
+            parse::from_fen("1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w KQkq - 0 1".to_string())
+                .unwrap();
+        assert_eq!(shredder, xfen);
+        assert!(xfen.chess960);
+        assert_eq!(xfen.b_castling.king_side_rook(), Some(6));
+        assert_eq!(xfen.b_castling.queen_side_rook(), Some(1));
+
+        // X-FEN names the inner rook by its file when there are two on one side
+        let pos = parse::from_fen("4k3/8/8/8/8/8/8/RR2K3 w B - 0 1".to_string()).unwrap();
+        assert_eq!(pos.w_castling.queen_side_rook(), Some(1));
+        let pos = parse::from_fen("4k3/8/8/8/8/8/8/RR2K3 w Q - 0 1".to_string()).unwrap();
+        assert_eq!(pos.w_castling.queen_side_rook(), Some(0));
+
+        let standard = parse::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".to_string()).unwrap();
+        assert!(!standard.chess960);
+
+        assert!(parse::from_fen("4k3/8/8/8/8/8/8/R3K3 w X - 0 1".to_string()).is_err());
+        assert!(parse::from_fen("8/8/8/8/8/8/8/R7 w A - 0 1".to_string()).is_err());
+    }
+
+    #[test]
+    fn castling_moves() {
+        // King on g1 castles king side without moving, the rook jumps over to f1
+        let mut pos = parse::from_fen("4k3/8/8/8/8/8/8/1R4KR w HB - 0 1".to_string()).unwrap();
+        let before = pos.clone();
+        let m = pos.parse_uci("g1h1").unwrap();
+        assert!(m.is_castling());
+        assert_eq!(pos.move_to_uci(&m), "g1h1");
+        assert_eq!(pos.move_to_san(&m), "O-O");
+
+        pos.change_board(m);
+        assert_eq!(pos.piece_at(6, 0), Some((PieceType::King, Color::White)));
+        assert_eq!(pos.piece_at(5, 0), Some((PieceType::Rook, Color::White)));
+        assert_eq!(pos.piece_at(7, 0), None);
+        assert!(!pos.w_castling.queen_side());
+        pos.reverse_last_change();
+        assert_eq!(pos, before);
+
+        // The king takes the rook's square and the other way round
+        let m = pos.parse_san("O-O-O").unwrap();
+        assert_eq!(pos.move_to_uci(&m), "g1b1");
+        pos.change_board(m);
+        assert_eq!(pos.piece_at(2, 0), Some((PieceType::King, Color::White)));
+        assert_eq!(pos.piece_at(3, 0), Some((PieceType::Rook, Color::White)));
+        pos.reverse_last_change();
+        assert_eq!(pos, before);
+
+        // g1f1 is a king move, not castling
+        let m = pos.parse_uci("g1f1").unwrap();
+        assert!(!m.is_castling());
+
+        // Standard castling takes both notations, but only writes the king's move
+        let mut pos = parse::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".to_string()).unwrap();
+        let m = pos.parse_uci("e1h1").unwrap();
+        assert_eq!(m, pos.parse_uci("e1g1").unwrap());
+        assert_eq!(pos.move_to_uci(&m), "e1g1");
+        pos.chess960 = true;
+        assert_eq!(pos.move_to_uci(&m), "e1h1");
+        assert!(pos.parse_uci("e1g1").is_err());
+
+        // The rook on b1 may not be attacked, only the squares of the king matter
+        let mut pos = parse::from_fen("1r2k3/8/8/8/8/8/8/R3K3 w Q - 0 1".to_string()).unwrap();
+        assert!(pos.parse_san("O-O-O").is_ok());
+    }
+
+    #[test]
+    fn perft_chess960() {
+        let fen = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+        assert_eq!(perft(fen, 1), 21);
+        assert_eq!(perft(fen, 2), 528);
+        assert_eq!(perft(fen, 3), 12189);
+
+        let fen = "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9";
+        assert_eq!(perft(fen, 1), 21);
+        assert_eq!(perft(fen, 2), 807);
+        assert_eq!(perft(fen, 3), 18002);
+
+        let fen = "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9";
+        assert_eq!(perft(fen, 1), 20);
+        assert_eq!(perft(fen, 2), 479);
+        assert_eq!(perft(fen, 3), 10471);
+
+        let fen = "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9";
+        assert_eq!(perft(fen, 1), 22);
+        assert_eq!(perft(fen, 2), 593);
+        assert_eq!(perft(fen, 3), 13440);
+    }
+}
@@ -0,0 +1,298 @@
+use super::movegen::Move;
+use super::piece::{Color, PieceType};
+use super::variant::Variant;
+use super::{BitBoard, Position, Square};
+use anyhow::{anyhow, Result};
+
+/// Pieces in hand in Crazyhouse, counted by PieceType::index.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pocket([u32; 6]);
+
+impl Pocket {
+    pub fn count(&self, p: PieceType) -> u32 {
+        self.0[p.index()]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|n| *n == 0)
+    }
+
+    /// Piece types in hand with how many of each, queens first.
+    pub fn iter(&self) -> impl Iterator<Item = (PieceType, u32)> + '_ {
+        PieceType::ALL
+            .into_iter()
+            .map(|p| (p, self.count(p)))
+            .filter(|(_, n)| *n > 0)
+    }
+
+    pub(crate) fn add(&mut self, p: PieceType) {
+        self.0[p.index()] += 1;
+    }
+
+    pub(crate) fn remove(&mut self, p: PieceType) {
+        self.0[p.index()] -= 1;
+    }
+
+    /// The pieces as FEN letters, e.g. QNP for white or np for black.
+    pub fn to_fen(&self, c: Color) -> String {
+        self.iter()
+            .flat_map(|(p, n)| std::iter::repeat_n(p.to_char(c), n as usize))
+            .collect()
+    }
+}
+
+/// Reads the inside of a [QNnp] pocket, uppercase for white and lowercase for black.
+pub(crate) fn parse_pockets(s: &str) -> Result<(Pocket, Pocket)> {
+    let mut w_pocket = Pocket::default();
+    let mut b_pocket = Pocket::default();
+
+    for c in s.chars() {
+        match PieceType::from_char(c) {
+            Ok((PieceType::King, _)) | Err(_) => {
+                return Err(anyhow!("Invalid piece '{c}' in pocket"));
+            }
+            Ok((p, Color::White)) => w_pocket.add(p),
+            Ok((p, Color::Black)) => b_pocket.add(p),
+        }
+    }
+
+    Ok((w_pocket, b_pocket))
+}
+
+impl Position {
+    pub fn pocket(&self, c: Color) -> &Pocket {
+        match c {
+            Color::White => &self.w_pocket,
+            Color::Black => &self.b_pocket,
+        }
+    }
+
+    fn pocket_mut(&mut self, c: Color) -> &mut Pocket {
+        match c {
+            Color::White => &mut self.w_pocket,
+            Color::Black => &mut self.b_pocket,
+        }
+    }
+
+    /// Pieces of either side that started out as pawns.
+    pub fn promoted(&self) -> BitBoard {
+        self.promoted
+    }
+
+    /// Every piece in hand on every empty square, pawns not on the first or last rank.
+    pub(crate) fn drop_moves(&self, moves: &mut Vec<Move>) {
+        let pocket = *self.pocket(self.side);
+        if pocket.is_empty() {
+            return;
+        }
+
+        let mut occupied = *self.pieces_all(Color::White);
+        occupied.or_assign(*self.pieces_all(Color::Black));
+
+        for (p, _) in pocket.iter() {
+            for file in 0..8 {
+                for rank in 0..8 {
+                    if occupied.get(file, rank) == Some(true)
+                        || (p == PieceType::Pawn && (rank == 0 || rank == 7))
+                    {
+                        continue;
+                    }
+
+                    let to = Square { file, rank };
+                    let mut m = self.new_move(to, to, p);
+                    m.drop = true;
+                    moves.push(m);
+                }
+            }
+        }
+    }
+
+    /// Moves captured pieces into the pocket, as pawns if they were promoted,
+    /// takes dropped ones out and keeps track of promoted pieces.
+    /// Called by change_board before the pieces are moved.
+    pub(crate) fn change_pockets(&mut self, m: &Move) {
+        if self.variant != Variant::Crazyhouse {
+            return;
+        }
+
+        let side = self.side;
+        if let Some(piece) = m.change.captured {
+            let y_captured = if m.is_en_passant() {
+                m.from.rank
+            } else {
+                m.to.rank
+            };
+            let was_pawn = self.promoted.get(m.to.file, y_captured) == Some(true);
+
+            self.pocket_mut(side)
+                .add(if was_pawn { PieceType::Pawn } else { piece });
+            self.promoted.unset(m.to.file, y_captured);
+        }
+
+        if m.drop {
+            self.pocket_mut(side).remove(m.ptype);
+        } else if self.promoted.get(m.from.file, m.from.rank) == Some(true) || m.promotion.is_some()
+        {
+            self.promoted.unset(m.from.file, m.from.rank);
+            self.promoted.set(m.to.file, m.to.rank);
+        }
+    }
+
+    /// Undoes change_pockets for the last move, which side made.
+    pub(crate) fn reverse_pockets(&mut self, m: &Move, side: Color) {
+        if self.variant != Variant::Crazyhouse {
+            return;
+        }
+
+        self.promoted = m.change.promoted;
+
+        if let Some(piece) = m.change.captured {
+            let y_captured = if m.is_en_passant() {
+                m.from.rank
+            } else {
+                m.to.rank
+            };
+            let was_pawn = self.promoted.get(m.to.file, y_captured) == Some(true);
+
+            self.pocket_mut(side)
+                .remove(if was_pawn { PieceType::Pawn } else { piece });
+        }
+
+        if m.drop {
+            self.pocket_mut(side).add(m.ptype);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::notation::IllegalMove;
+    use super::super::parse;
+    use super::*;
+
+    fn position(fen: &str) -> Position {
+        parse::from_fen_variant(fen.to_string(), Variant::Crazyhouse).unwrap()
+    }
+
+    fn perft(fen: &str, depth: u32) -> u64 {
+        let mut pos = position(fen);
+        let before = pos.clone();
+        let nodes = pos.perft(depth);
+
+        assert_eq!(pos, before);
+        nodes
+    }
+
+    #[test]
+    fn fen_pockets() {
+        let pos = position("4k3/8/8/8/8/8/8/4K3[QNNbp] w - - 0 1");
+        assert_eq!(pos.pocket(Color::White).count(PieceType::Knight), 2);
+        assert_eq!(pos.pocket(Color::White).to_fen(Color::White), "QNN");
+        assert_eq!(pos.pocket(Color::Black).to_fen(Color::Black), "bp");
+        assert!(position("4k3/8/8/8/8/8/8/4K3[] w - - 0 1")
+            .pocket(Color::White)
+            .is_empty());
+
+        // Promoted pieces are marked with a ~
+        let pos = position("4k3/3Q~4/8/8/8/8/8/4K3[] b - - 0 1");
+        assert_eq!(pos.promoted().get(3, 6), Some(true));
+
+        let fen = |s: &str| parse::from_fen_variant(s.to_string(), Variant::Crazyhouse);
+        assert!(fen("4k3/8/8/8/8/8/8/4K3[K] w - - 0 1").is_err());
+        assert!(fen("4k3/8/8/8/8/8/8/4K3[Q w - - 0 1").is_err());
+        assert!(fen("4k3/8/8/8/8/8/8/~4K3[] w - - 0 1").is_err());
+        assert!(parse::from_fen("4k3/8/8/8/8/8/8/4K3[Q] w - - 0 1".to_string()).is_err());
+    }
+
+    #[test]
+    fn perft_crazyhouse() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1";
+        assert_eq!(perft(start, 3), 8902);
+
+        let fen = "2k5/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - - 0 1";
+        assert_eq!(perft(fen, 1), 301);
+        assert_eq!(perft(fen, 2), 75353);
+
+        // Only the king moves and the knight drops between the rook and the king
+        let fen = "4k3/8/8/8/8/8/8/r3K3[N] w - - 0 1";
+        assert_eq!(perft(fen, 1), 6);
+    }
+
+    #[test]
+    fn captures_and_drops() {
+        let mut pos = position("4k3/8/8/8/8/8/3q4/4K3[] w - - 0 1");
+        let before = pos.clone();
+        pos.play_san("Kxd2").unwrap();
+        assert_eq!(pos.pocket(Color::White).count(PieceType::Queen), 1);
+        pos.play_san("Kd7").unwrap();
+
+        let m = pos.parse_uci("Q@d5").unwrap();
+        assert!(m.drop);
+        assert_eq!(m.to_string(), "Q@d5");
+        assert_eq!(pos.move_to_san(&m), "Q@d5+");
+        pos.play(m).unwrap();
+        assert_eq!(pos.piece_at(3, 4), Some((PieceType::Queen, Color::White)));
+        assert!(pos.pocket(Color::White).is_empty());
+
+        for _ in 0..3 {
+            pos.undo();
+        }
+        assert_eq!(pos, before);
+
+        // A promoted queen goes back into the pocket as a pawn
+        let mut pos = position("4k3/3Q~4/8/8/8/8/8/4K3[] b - - 0 1");
+        let before = pos.clone();
+        pos.play_san("Kxd7").unwrap();
+        assert_eq!(pos.pocket(Color::Black).to_fen(Color::Black), "p");
+        assert!(pos.promoted().squares().is_empty());
+        pos.undo();
+        assert_eq!(pos, before);
+
+        // and promoting marks the new piece, which takes the mark along
+        let mut pos = position("4k3/P7/8/8/8/8/8/4K3[] w - - 0 1");
+        pos.play_san("a8=Q+").unwrap();
+        pos.play_san("Ke7").unwrap();
+        pos.play_san("Qb7+").unwrap();
+        assert_eq!(
+            pos.promoted().squares(),
+            vec![Square::from_str("b7").unwrap()]
+        );
+    }
+
+    #[test]
+    fn illegal_drops() {
+        let mut pos = position("4k3/8/8/8/8/8/8/r3K3[NP] w - - 0 1");
+        let sqr = |s: &str| Square::from_str(s).unwrap();
+
+        assert_eq!(pos.play_uci("N@f3"), Err(IllegalMove::LeavesKingInCheck));
+        assert_eq!(
+            pos.play_san("Q@c1"),
+            Err(IllegalMove::NotInHand(PieceType::Queen))
+        );
+        assert!(matches!(pos.play_uci("K@c1"), Err(IllegalMove::Invalid(_))));
+        assert_eq!(
+            pos.play_san("@a1"),
+            Err(IllegalMove::CantDrop {
+                piece: PieceType::Pawn,
+                to: sqr("a1")
+            })
+        );
+
+        // Pawns are written without a letter
+        pos.play_san("N@c1").unwrap();
+        pos.play_san("Kd7").unwrap();
+        let m = pos.parse_san("@e4").unwrap();
+        assert_eq!(pos.move_to_san(&m), "@e4");
+        assert_eq!(m, pos.parse_uci("P@e4").unwrap());
+        assert_eq!(
+            pos.play_san("P@e8"),
+            Err(IllegalMove::CantDrop {
+                piece: PieceType::Pawn,
+                to: sqr("e8")
+            })
+        );
+
+        let mut standard = parse::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        assert!(standard.parse_uci("N@f3").is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use super::movegen::Move;
+use super::{parse, Position};
+use anyhow::{anyhow, Result};
+
+/// A position with the EPD operations that came with it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Epd {
+    pub position: Position,
+    /// Opcodes and their operands in the order they appeared.
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+impl Epd {
+    pub fn get(&self, opcode: &str) -> Option<&[String]> {
+        self.operations
+            .iter()
+            .find(|(op, _)| op == opcode)
+            .map(|(_, operands)| operands.as_slice())
+    }
+
+    fn get_string(&self, opcode: &str) -> Option<&str> {
+        self.get(opcode)
+            .and_then(|operands| operands.first())
+            .map(|s| s.as_str())
+    }
+
+    fn get_number(&self, opcode: &str) -> Result<Option<u32>> {
+        match self.get_string(opcode) {
+            Some(n) => n
+                .parse()
+                .map(Some)
+                .map_err(|_| anyhow!("Invalid operand '{n}' for {opcode}")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.get_string("id")
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.get_string("c0")
+    }
+
+    /// Number of moves to mate.
+    pub fn direct_mate(&self) -> Result<Option<u32>> {
+        self.get_number("dm")
+    }
+
+    /// Depth the analysis was done to.
+    pub fn analysis_depth(&self) -> Result<Option<u32>> {
+        self.get_number("acd")
+    }
+
+    /// Moves of a SAN operand list like bm or am, resolved against the position.
+    pub fn moves(&self, opcode: &str) -> Result<Vec<Move>> {
+        let mut pos = self.position.clone();
+
+        self.get(opcode)
+            .unwrap_or_default()
+            .iter()
+            .map(|san| {
+                pos.parse_san(san)
+                    .map_err(|e| anyhow!("Invalid {opcode} operand: {e}"))
+            })
+            .collect()
+    }
+
+    pub fn best_moves(&self) -> Result<Vec<Move>> {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> Result<Vec<Move>> {
+        self.moves("am")
+    }
+}
+
+/// Splits the operations part of an EPD line, "bm Nf3 Nc3; id \"test 1\";"
+fn parse_operations(s: &str) -> Result<Vec<(String, Vec<String>)>> {
+    let mut out = vec![];
+    let mut words: Vec<String> = vec![];
+    let mut word = String::new();
+    let mut chars = s.chars();
+
+    let finish_word = |word: &mut String, words: &mut Vec<String>| {
+        if !word.is_empty() {
+            words.push(std::mem::take(word));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => quoted.push(c),
+                        None => return Err(anyhow!("Unterminated string in EPD operations")),
+                    }
+                }
+                finish_word(&mut word, &mut words);
+                words.push(quoted);
+            }
+            ';' => {
+                finish_word(&mut word, &mut words);
+                if words.is_empty() {
+                    return Err(anyhow!("Empty EPD operation"));
+                }
+                let opcode = words.remove(0);
+                out.push((opcode, std::mem::take(&mut words)));
+            }
+            c if c.is_whitespace() => finish_word(&mut word, &mut words),
+            c => word.push(c),
+        }
+    }
+
+    finish_word(&mut word, &mut words);
+    if !words.is_empty() {
+        return Err(anyhow!(
+            "EPD operation '{}' is missing a ';'",
+            words.join(" ")
+        ));
+    }
+
+    Ok(out)
+}
+
+/// Parses an EPD line: the first four FEN fields followed by operations.
+/// The hmvc and fmvn operations stand in for the last two FEN fields.
+pub fn from_epd(epd: &str) -> Result<Epd> {
+    let fen: Vec<&str> = epd.split_whitespace().take(4).collect();
+    if fen.len() < 4 {
+        return Err(anyhow!("EPD '{epd}' needs at least four fields"));
+    }
+
+    // The operations are what follows the fourth field, quoted operands keep their spaces
+    let mut rest = epd.trim_start();
+    for field in &fen {
+        rest = rest[field.len()..].trim_start();
+    }
+
+    let operations = parse_operations(rest)?;
+    let operand = |opcode: &str, default: &'static str| {
+        operations
+            .iter()
+            .find(|(op, _)| op == opcode)
+            .and_then(|(_, operands)| operands.first())
+            .map(|s| s.as_str())
+            .unwrap_or(default)
+            .to_string()
+    };
+
+    let fen = format!(
+        "{} {} {}",
+        fen.join(" "),
+        operand("hmvc", "0"),
+        operand("fmvn", "1")
+    );
+    let position = parse::from_fen(fen)?;
+
+    Ok(Epd {
+        position,
+        operations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn from_epd() {
+        let epd = super::from_epd(
+            r#"2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001"; c0 "queen sac; mate follows";"#,
+        )
+        .unwrap();
+
+        assert_eq!(epd.id(), Some("WAC.001"));
+        assert_eq!(epd.comment(), Some("queen sac; mate follows"));
+        assert_eq!(epd.get("bm").unwrap(), ["Qg6"]);
+
+        let bm = epd.best_moves().unwrap();
+        assert_eq!(bm.len(), 1);
+        assert_eq!(bm[0].to_string(), "g3g6");
+        assert!(epd.avoid_moves().unwrap().is_empty());
+
+        let epd = super::from_epd(
+            "r1b1k2r/ppppnppp/2n2q2/2b5/3NP3/2P1B3/PP3PPP/RN1QKB1R w KQkq - am Nxc6 Nb5; dm 3; acd 12; hmvc 4; fmvn 7;",
+        )
+        .unwrap();
+        assert_eq!(epd.avoid_moves().unwrap().len(), 2);
+        assert_eq!(epd.direct_mate().unwrap(), Some(3));
+        assert_eq!(epd.analysis_depth().unwrap(), Some(12));
+        assert_eq!(epd.position.halfturn, 4);
+
+        // Plain position without operations
+        assert!(super::from_epd("8/8/8/8/8/8/8/K6k w - -").is_ok());
+
+        // Runs of spaces between the fields
+        let epd = super::from_epd("8/8/8/8/8/8/8/K6k  w \t-  -   bm Kb1;  id \"a  b\";").unwrap();
+        assert_eq!(epd.get("bm").unwrap(), ["Kb1"]);
+        assert_eq!(epd.id(), Some("a  b"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(super::from_epd("8/8/8/8 w").is_err());
+        assert!(super::from_epd("8/8/8/8/8/8/8/K6k w - - bm Kb1").is_err());
+        assert!(super::from_epd(r#"8/8/8/8/8/8/8/K6k w - - id "x;"#).is_err());
+
+        let epd = super::from_epd("8/8/8/8/8/8/8/K6k w - - bm Kc3; dm x;").unwrap();
+        assert!(epd.best_moves().is_err());
+        assert!(epd.direct_mate().is_err());
+    }
+}
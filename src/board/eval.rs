@@ -1 +1,850 @@
+use super::piece::{self, Color, PieceType};
+use super::{BitBoard, Position};
+use std::ops::{Add, AddAssign, Mul, Sub};
 
+/// Value of an evaluation term in centipawns,
+/// split into a midgame and an endgame part.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Score {
+        Score { mg, eg }
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, rhs: Score) -> Score {
+        Score::new(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, rhs: Score) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, rhs: Score) -> Score {
+        Score::new(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+
+    fn mul(self, rhs: i32) -> Score {
+        Score::new(self.mg * rhs, self.eg * rhs)
+    }
+}
+
+const PAWN_VALUE: Score = Score::new(82, 94);
+const KNIGHT_VALUE: Score = Score::new(337, 281);
+const BISHOP_VALUE: Score = Score::new(365, 297);
+const ROOK_VALUE: Score = Score::new(477, 512);
+const QUEEN_VALUE: Score = Score::new(1025, 936);
+
+// Game phase weights, the starting position adds up to MAX_PHASE
+const KNIGHT_PHASE: i32 = 1;
+const BISHOP_PHASE: i32 = 1;
+const ROOK_PHASE: i32 = 2;
+const QUEEN_PHASE: i32 = 4;
+pub const MAX_PHASE: i32 = 24;
+
+// Bonus per safe square above the baseline, and the baseline itself
+const KNIGHT_MOBILITY: (Score, i32) = (Score::new(4, 4), 4);
+const BISHOP_MOBILITY: (Score, i32) = (Score::new(5, 5), 7);
+const ROOK_MOBILITY: (Score, i32) = (Score::new(2, 4), 7);
+const QUEEN_MOBILITY: (Score, i32) = (Score::new(1, 2), 14);
+
+const ROOK_OPEN_FILE: Score = Score::new(45, 20);
+const ROOK_SEMI_OPEN_FILE: Score = Score::new(20, 7);
+
+const BISHOP_PAIR: Score = Score::new(30, 50);
+
+const KNIGHT_OUTPOST: Score = Score::new(30, 20);
+const BISHOP_OUTPOST: Score = Score::new(15, 10);
+
+const TRAPPED_MINOR: Score = Score::new(-50, -50);
+const TRAPPED_ROOK: Score = Score::new(-50, -10);
+const TRAPPED_QUEEN: Score = Score::new(-40, -40);
+
+const DOUBLED_PAWN: Score = Score::new(-10, -20);
+const ISOLATED_PAWN: Score = Score::new(-5, -15);
+// Bonus for a passed pawn by how far it has gone, from its own back rank
+const PASSED_PAWN: [Score; 8] = [
+    Score::new(0, 0),
+    Score::new(0, 5),
+    Score::new(5, 10),
+    Score::new(10, 20),
+    Score::new(20, 35),
+    Score::new(35, 60),
+    Score::new(60, 100),
+    Score::new(0, 0),
+];
+
+// Per pawn in front of a king that stayed on its first two ranks
+const PAWN_SHIELD: Score = Score::new(15, 0);
+// Per square next to the king attacked by a piece type, indexed by PieceType::index
+const KING_ATTACK_WEIGHT: [i32; 6] = [0, 5, 3, 2, 2, 1];
+
+// Piece-square tables from a8 to h1, as white sees the board.
+// Black pieces look up the square mirrored to the other side.
+#[rustfmt::skip]
+const PAWN_SQUARES: [[i32; 64]; 2] = [
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+         98, 134,  61,  95,  68, 126,  34, -11,
+         -6,   7,  26,  31,  65,  56,  25, -20,
+        -14,  13,   6,  21,  23,  12,  17, -23,
+        -27,  -2,  -5,  12,  17,   6,  10, -25,
+        -26,  -4,  -4, -10,   3,   3,  33, -12,
+        -35,  -1, -20, -23, -15,  24,  38, -22,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+        178, 173, 158, 134, 147, 132, 165, 187,
+         94, 100,  85,  67,  56,  53,  82,  84,
+         32,  24,  13,   5,  -2,   4,  17,  17,
+         13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+          4,   7,  -6,   1,   0,  -5,  -1,  -8,
+         13,   8,   8,  10,  13,   0,   2,  -7,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+];
+
+#[rustfmt::skip]
+const KNIGHT_SQUARES: [[i32; 64]; 2] = [
+    [
+       -167, -89, -34, -49,  61, -97, -15,-107,
+        -73, -41,  72,  36,  23,  62,   7, -17,
+        -47,  60,  37,  65,  84, 129,  73,  44,
+         -9,  17,  19,  53,  37,  69,  18,  22,
+        -13,   4,  16,  13,  28,  19,  21,  -8,
+        -23,  -9,  12,  10,  19,  17,  25, -16,
+        -29, -53, -12,  -3,  -1,  18, -14, -19,
+       -105, -21, -58, -33, -17, -28, -19, -23,
+    ],
+    [
+        -58, -38, -13, -28, -31, -27, -63, -99,
+        -25,  -8, -25,  -2,  -9, -25, -24, -52,
+        -24, -20,  10,   9,  -1,  -9, -19, -41,
+        -17,   3,  22,  22,  22,  11,   8, -18,
+        -18,  -6,  16,  25,  16,  17,   4, -18,
+        -23,  -3,  -1,  15,  10,  -3, -20, -22,
+        -42, -20, -10,  -5,  -2, -20, -23, -44,
+        -29, -51, -23, -15, -22, -18, -50, -64,
+    ],
+];
+
+#[rustfmt::skip]
+const BISHOP_SQUARES: [[i32; 64]; 2] = [
+    [
+        -29,   4, -82, -37, -25, -42,   7,  -8,
+        -26,  16, -18, -13,  30,  59,  18, -47,
+        -16,  37,  43,  40,  35,  50,  37,  -2,
+         -4,   5,  19,  50,  37,  37,   7,  -2,
+         -6,  13,  13,  26,  34,  12,  10,   4,
+          0,  15,  15,  15,  14,  27,  18,  10,
+          4,  15,  16,   0,   7,  21,  33,   1,
+        -33,  -3, -14, -21, -13, -12, -39, -21,
+    ],
+    [
+        -14, -21, -11,  -8,  -7,  -9, -17, -24,
+         -8,  -4,   7, -12,  -3, -13,  -4, -14,
+          2,  -8,   0,  -1,  -2,   6,   0,   4,
+         -3,   9,  12,   9,  14,  10,   3,   2,
+         -6,   3,  13,  19,   7,  10,  -3,  -9,
+        -12,  -3,   8,  10,  13,   3,  -7, -15,
+        -14, -18,  -7,  -1,   4,  -9, -15, -27,
+        -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+    ],
+];
+
+#[rustfmt::skip]
+const ROOK_SQUARES: [[i32; 64]; 2] = [
+    [
+         32,  42,  32,  51,  63,   9,  31,  43,
+         27,  32,  58,  62,  80,  67,  26,  44,
+         -5,  19,  26,  36,  17,  45,  61,  16,
+        -24, -11,   7,  26,  24,  35,  -8, -20,
+        -36, -26, -12,  -1,   9,  -7,   6, -23,
+        -45, -25, -16, -17,   3,   0,  -5, -33,
+        -44, -16, -20,  -9,  -1,  11,  -6, -71,
+        -19, -13,   1,  17,  16,   7, -37, -26,
+    ],
+    [
+         13,  10,  18,  15,  12,  12,   8,   5,
+         11,  13,  13,  11,  -3,   3,   8,   3,
+          7,   7,   7,   5,   4,  -3,  -5,  -3,
+          4,   3,  13,   1,   2,   1,  -1,   2,
+          3,   5,   8,   4,  -5,  -6,  -8, -11,
+         -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+         -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+         -9,   2,   3,  -1,  -5, -13,   4, -20,
+    ],
+];
+
+#[rustfmt::skip]
+const QUEEN_SQUARES: [[i32; 64]; 2] = [
+    [
+        -28,   0,  29,  12,  59,  44,  43,  45,
+        -24, -39,  -5,   1, -16,  57,  28,  54,
+        -13, -17,   7,   8,  29,  56,  47,  57,
+        -27, -27, -16, -16,  -1,  17,  -2,   1,
+         -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+        -14,   2, -11,  -2,  -5,   2,  14,   5,
+        -35,  -8,  11,   2,   8,  15,  -3,   1,
+         -1, -18,  -9,  10, -15, -25, -31, -50,
+    ],
+    [
+         -9,  22,  22,  27,  27,  19,  10,  20,
+        -17,  20,  32,  41,  58,  25,  30,   0,
+        -20,   6,   9,  49,  47,  35,  19,   9,
+          3,  22,  24,  45,  57,  40,  57,  36,
+        -18,  28,  19,  47,  31,  34,  39,  23,
+        -16, -27,  15,   6,   9,  17,  10,   5,
+        -22, -23, -30, -16, -16, -23, -36, -32,
+        -33, -28, -22, -43,  -5, -32, -20, -41,
+    ],
+];
+
+#[rustfmt::skip]
+const KING_SQUARES: [[i32; 64]; 2] = [
+    [
+        -65,  23,  16, -15, -56, -34,   2,  13,
+         29,  -1, -20,  -7,  -8,  -4, -38, -29,
+         -9,  24,   2, -16, -20,   6,  22, -22,
+        -17, -20, -12, -27, -30, -25, -14, -36,
+        -49,  -1, -27, -39, -46, -44, -33, -51,
+        -14, -14, -22, -46, -44, -30, -15, -27,
+          1,   7,  -8, -64, -43, -16,   9,   8,
+        -15,  36,  12, -54,   8, -28,  24,  14,
+    ],
+    [
+        -74, -35, -18, -18, -11,  15,   4, -17,
+        -12,  17,  14,  17,  17,  38,  23,  11,
+         10,  17,  23,  15,  20,  45,  44,  13,
+         -8,  22,  24,  27,  26,  33,  26,   3,
+        -18,  -4,  21,  24,  27,  23,   9, -11,
+        -19,  -3,  11,  21,  23,  16,   7,  -9,
+        -27, -11,   4,  13,  14,   4,  -5, -17,
+        -53, -34, -21, -11, -28, -14, -24, -43,
+    ],
+];
+
+/// Piece activity terms for one side.
+/// Every term is kept separately so it can be reported on its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Activity {
+    pub mobility: Score,
+    pub rook_files: Score,
+    pub bishop_pair: Score,
+    pub outposts: Score,
+    pub trapped: Score,
+}
+
+impl Activity {
+    pub fn total(&self) -> Score {
+        self.mobility + self.rook_files + self.bishop_pair + self.outposts + self.trapped
+    }
+}
+
+/// Pawn structure terms for one side.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PawnStructure {
+    pub doubled: Score,
+    pub isolated: Score,
+    pub passed: Score,
+}
+
+impl PawnStructure {
+    pub fn total(&self) -> Score {
+        self.doubled + self.isolated + self.passed
+    }
+}
+
+/// King safety terms for one side, the attacks are counted against it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KingSafety {
+    pub shield: Score,
+    pub attacks: Score,
+}
+
+impl KingSafety {
+    pub fn total(&self) -> Score {
+        self.shield + self.attacks
+    }
+}
+
+/// Per term breakdown of the evaluation for both sides.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    pub white_material: Score,
+    pub black_material: Score,
+    pub white_squares: Score,
+    pub black_squares: Score,
+    pub white_pawns: PawnStructure,
+    pub black_pawns: PawnStructure,
+    pub white_king: KingSafety,
+    pub black_king: KingSafety,
+    pub white_activity: Activity,
+    pub black_activity: Activity,
+    pub phase: i32,
+}
+
+impl Trace {
+    /// Name, white score and black score of every term.
+    pub fn terms(&self) -> Vec<(&'static str, Score, Score)> {
+        let (w, b) = (&self.white_activity, &self.black_activity);
+        let (wp, bp) = (&self.white_pawns, &self.black_pawns);
+        let (wk, bk) = (&self.white_king, &self.black_king);
+
+        vec![
+            ("Material", self.white_material, self.black_material),
+            ("Placement", self.white_squares, self.black_squares),
+            ("Doubled", wp.doubled, bp.doubled),
+            ("Isolated", wp.isolated, bp.isolated),
+            ("Passed", wp.passed, bp.passed),
+            ("King shield", wk.shield, bk.shield),
+            ("King attacks", wk.attacks, bk.attacks),
+            ("Mobility", w.mobility, b.mobility),
+            ("Rook files", w.rook_files, b.rook_files),
+            ("Bishop pair", w.bishop_pair, b.bishop_pair),
+            ("Outposts", w.outposts, b.outposts),
+            ("Trapped", w.trapped, b.trapped),
+        ]
+    }
+
+    pub fn white_total(&self) -> Score {
+        self.white_material
+            + self.white_squares
+            + self.white_pawns.total()
+            + self.white_king.total()
+            + self.white_activity.total()
+    }
+
+    pub fn black_total(&self) -> Score {
+        self.black_material
+            + self.black_squares
+            + self.black_pawns.total()
+            + self.black_king.total()
+            + self.black_activity.total()
+    }
+
+    /// Midgame and endgame scores blended by game phase,
+    /// from the point of view of white.
+    pub fn total(&self) -> i32 {
+        taper(self.white_total() - self.black_total(), self.phase)
+    }
+}
+
+fn taper(s: Score, phase: i32) -> i32 {
+    (s.mg * phase + s.eg * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+/// Remaining non-pawn material, MAX_PHASE in the opening and 0 with only pawns left.
+pub fn game_phase(pos: &Position) -> i32 {
+    let mut phase = 0;
+
+    for c in [Color::White, Color::Black] {
+        let pieces = pos.pieces(c);
+        phase += pieces.get_board(PieceType::Knight).count() as i32 * KNIGHT_PHASE;
+        phase += pieces.get_board(PieceType::Bishop).count() as i32 * BISHOP_PHASE;
+        phase += pieces.get_board(PieceType::Rook).count() as i32 * ROOK_PHASE;
+        phase += pieces.get_board(PieceType::Queen).count() as i32 * QUEEN_PHASE;
+    }
+
+    phase.min(MAX_PHASE)
+}
+
+pub fn material(pos: &Position, c: Color) -> Score {
+    let pieces = pos.pieces(c);
+
+    PAWN_VALUE * pieces.get_board(PieceType::Pawn).count() as i32
+        + KNIGHT_VALUE * pieces.get_board(PieceType::Knight).count() as i32
+        + BISHOP_VALUE * pieces.get_board(PieceType::Bishop).count() as i32
+        + ROOK_VALUE * pieces.get_board(PieceType::Rook).count() as i32
+        + QUEEN_VALUE * pieces.get_board(PieceType::Queen).count() as i32
+}
+
+/// Sum of the piece-square table values of the pieces of color c.
+pub fn piece_squares(pos: &Position, c: Color) -> Score {
+    let mut out = Score::default();
+
+    for (p, board) in pos.pieces(c).iter() {
+        let table = match p {
+            PieceType::Pawn => &PAWN_SQUARES,
+            PieceType::Knight => &KNIGHT_SQUARES,
+            PieceType::Bishop => &BISHOP_SQUARES,
+            PieceType::Rook => &ROOK_SQUARES,
+            PieceType::Queen => &QUEEN_SQUARES,
+            PieceType::King => &KING_SQUARES,
+        };
+
+        for sqr in board.squares() {
+            let i = (7 - relative_rank(c, sqr.rank)) as usize * 8 + sqr.file as usize;
+            out += Score::new(table[0][i], table[1][i]);
+        }
+    }
+
+    out
+}
+
+/// Doubled, isolated and passed pawns of color c.
+pub fn pawn_structure(pos: &Position, c: Color) -> PawnStructure {
+    let mut out = PawnStructure::default();
+
+    let own_pawns = pos.pieces(c).get_board(PieceType::Pawn);
+    let enemy_pawns = pos.pieces(c.opposite()).get_board(PieceType::Pawn);
+
+    for file in 0..8 {
+        let on_file = (0..8)
+            .filter(|rank| own_pawns.get(file, *rank) == Some(true))
+            .count() as i32;
+        if on_file > 1 {
+            out.doubled += DOUBLED_PAWN * (on_file - 1);
+        }
+
+        let neighbours = [file.wrapping_sub(1), file + 1]
+            .iter()
+            .any(|f| *f < 8 && file_has_pawn(&own_pawns, *f));
+        if on_file > 0 && !neighbours {
+            out.isolated += ISOLATED_PAWN * on_file;
+        }
+    }
+
+    for sqr in own_pawns.squares() {
+        let ahead = |r: u32| relative_rank(c, r) > relative_rank(c, sqr.rank);
+        let blocked = [sqr.file.wrapping_sub(1), sqr.file, sqr.file + 1]
+            .iter()
+            .any(|f| (0..8).any(|r| ahead(r) && enemy_pawns.get(*f, r) == Some(true)));
+
+        if !blocked {
+            out.passed += PASSED_PAWN[relative_rank(c, sqr.rank) as usize];
+        }
+    }
+
+    out
+}
+
+/// Pawns sheltering the king of color c, and enemy pieces attacking
+/// the squares around it.
+pub fn king_safety(pos: &Position, c: Color) -> KingSafety {
+    let mut out = KingSafety::default();
+
+    let Some(king) = pos
+        .pieces(c)
+        .get_board(PieceType::King)
+        .squares()
+        .first()
+        .copied()
+    else {
+        return out;
+    };
+    let own_pawns = pos.pieces(c).get_board(PieceType::Pawn);
+
+    let mut zone = BitBoard::new();
+    zone.set(king.file, king.rank);
+    zone.or_assign(piece::get_piece_attack_map(
+        PieceType::King,
+        c,
+        &zone,
+        &BitBoard::new(),
+        &BitBoard::new(),
+    ));
+
+    if relative_rank(c, king.rank) <= 1 {
+        for sqr in own_pawns.squares() {
+            let in_front = (1..=2).contains(
+                &(relative_rank(c, sqr.rank) as i32 - relative_rank(c, king.rank) as i32),
+            );
+            if in_front && sqr.file.abs_diff(king.file) <= 1 {
+                out.shield += PAWN_SHIELD;
+            }
+        }
+    }
+
+    // Only dangerous while the opponent still has a queen
+    if pos.pieces(c.opposite()).get_board(PieceType::Queen).count() == 0 {
+        return out;
+    }
+
+    let mut weight = 0;
+    for (p, attacks) in pos.attacks(c.opposite()).iter() {
+        let mut hit = *attacks;
+        hit.and_assign(zone);
+        weight += KING_ATTACK_WEIGHT[p.index()] * hit.count() as i32;
+    }
+    out.attacks = Score::new(-weight * weight / 4, 0);
+
+    out
+}
+
+pub fn trace(pos: &Position) -> Trace {
+    Trace {
+        white_material: material(pos, Color::White),
+        black_material: material(pos, Color::Black),
+        white_squares: piece_squares(pos, Color::White),
+        black_squares: piece_squares(pos, Color::Black),
+        white_pawns: pawn_structure(pos, Color::White),
+        black_pawns: pawn_structure(pos, Color::Black),
+        white_king: king_safety(pos, Color::White),
+        black_king: king_safety(pos, Color::Black),
+        white_activity: piece_activity(pos, Color::White),
+        black_activity: piece_activity(pos, Color::Black),
+        phase: game_phase(pos),
+    }
+}
+
+/// Static evaluation in centipawns from the point of view of the side to move.
+pub fn evaluate(pos: &Position) -> i32 {
+    let score = trace(pos).total();
+
+    match pos.side {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+/// Rank as seen from the side of c, 0 being its own back rank.
+fn relative_rank(c: Color, rank: u32) -> u32 {
+    match c {
+        Color::White => rank,
+        Color::Black => 7 - rank,
+    }
+}
+
+/// Squares that a single piece can move to without landing on
+/// a friendly piece or a square attacked by an enemy pawn.
+fn safe_squares(pos: &Position, p: PieceType, c: Color, file: u32, rank: u32) -> BitBoard {
+    let mut piece = BitBoard::new();
+    piece.set(file, rank);
+
+    let friendly = pos.pieces_all(c);
+    let opposing = pos.pieces_all(c.opposite());
+
+    let mut out = piece::get_piece_attack_map(p, c, &piece, friendly, opposing);
+    out.clear_assign(*friendly);
+    out.clear_assign(pos.attacks(c.opposite()).get_board(PieceType::Pawn));
+
+    out
+}
+
+fn mobility_bonus(p: PieceType, safe: u32) -> Score {
+    let (bonus, baseline) = match p {
+        PieceType::Knight => KNIGHT_MOBILITY,
+        PieceType::Bishop => BISHOP_MOBILITY,
+        PieceType::Rook => ROOK_MOBILITY,
+        PieceType::Queen => QUEEN_MOBILITY,
+        _ => return Score::default(),
+    };
+
+    bonus * (safe as i32 - baseline)
+}
+
+fn file_has_pawn(pawns: &BitBoard, file: u32) -> bool {
+    (0..8).any(|rank| pawns.get(file, rank) == Some(true))
+}
+
+/// A square in the enemy half that is defended by a pawn
+/// and can never be attacked by an enemy pawn.
+fn is_outpost(pos: &Position, c: Color, file: u32, rank: u32) -> bool {
+    if !(3..=5).contains(&relative_rank(c, rank)) {
+        return false;
+    }
+
+    if pos.attacks(c).get_board(PieceType::Pawn).get(file, rank) != Some(true) {
+        return false;
+    }
+
+    let enemy_pawns = pos.pieces(c.opposite()).get_board(PieceType::Pawn);
+    let ahead = |r: u32| relative_rank(c, r) > relative_rank(c, rank);
+
+    for f in [file.wrapping_sub(1), file + 1] {
+        for r in 0..8 {
+            if ahead(r) && enemy_pawns.get(f, r) == Some(true) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// A rook with little room that is stuck between its own uncastled king
+/// and the edge of the board.
+fn is_rook_blocked_by_king(pos: &Position, c: Color, file: u32, rank: u32, safe: u32) -> bool {
+    if safe > 3 || relative_rank(c, rank) != 0 {
+        return false;
+    }
+
+    let castling = match c {
+        Color::White => &pos.w_castling,
+        Color::Black => &pos.b_castling,
+    };
+
+    if castling.king_side() || castling.queen_side() {
+        return false;
+    }
+
+    let king = pos.pieces(c).get_board(PieceType::King);
+
+    (0..8)
+        .any(|f| king.get(f, rank) == Some(true) && ((f >= 4 && file > f) || (f <= 3 && file < f)))
+}
+
+/// Mobility and piece activity of the pieces of color c.
+pub fn piece_activity(pos: &Position, c: Color) -> Activity {
+    let mut out = Activity::default();
+
+    let own_pawns = pos.pieces(c).get_board(PieceType::Pawn);
+    let enemy_pawns = pos.pieces(c.opposite()).get_board(PieceType::Pawn);
+
+    for p in [
+        PieceType::Knight,
+        PieceType::Bishop,
+        PieceType::Rook,
+        PieceType::Queen,
+    ] {
+        let board = pos.pieces(c).get_board(p);
+
+        for file in 0..8 {
+            for rank in 0..8 {
+                if board.get(file, rank) != Some(true) {
+                    continue;
+                }
+
+                let safe = safe_squares(pos, p, c, file, rank).count();
+                out.mobility += mobility_bonus(p, safe);
+
+                match p {
+                    PieceType::Rook if !file_has_pawn(&own_pawns, file) => {
+                        if file_has_pawn(&enemy_pawns, file) {
+                            out.rook_files += ROOK_SEMI_OPEN_FILE;
+                        } else {
+                            out.rook_files += ROOK_OPEN_FILE;
+                        }
+                    }
+                    PieceType::Knight if is_outpost(pos, c, file, rank) => {
+                        out.outposts += KNIGHT_OUTPOST;
+                    }
+                    PieceType::Bishop if is_outpost(pos, c, file, rank) => {
+                        out.outposts += BISHOP_OUTPOST;
+                    }
+                    _ => (),
+                }
+
+                // Pieces that went into enemy territory and have at most
+                // one square left to go to, like a bishop taking on a7
+                let stuck = safe <= 1 && relative_rank(c, rank) >= 4;
+
+                match p {
+                    PieceType::Knight | PieceType::Bishop if stuck => {
+                        out.trapped += TRAPPED_MINOR;
+                    }
+                    PieceType::Queen if stuck => out.trapped += TRAPPED_QUEEN,
+                    PieceType::Rook
+                        if stuck || is_rook_blocked_by_king(pos, c, file, rank, safe) =>
+                    {
+                        out.trapped += TRAPPED_ROOK;
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    if pos.pieces(c).get_board(PieceType::Bishop).count() >= 2 {
+        out.bishop_pair += BISHOP_PAIR;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::parse;
+
+    #[test]
+    fn trace() {
+        let pos =
+            parse::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string())
+                .unwrap();
+        let t = super::trace(&pos);
+        assert_eq!(t.phase, MAX_PHASE);
+        assert_eq!(t.total(), 0);
+        assert_eq!(evaluate(&pos), 0);
+
+        // Black is missing the queen
+        let pos =
+            parse::from_fen("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1".to_string())
+                .unwrap();
+        let t = super::trace(&pos);
+        assert_eq!(t.phase, MAX_PHASE - QUEEN_PHASE);
+        assert_eq!(t.white_material - t.black_material, QUEEN_VALUE);
+        assert!(t.total() > 0);
+        assert_eq!(evaluate(&pos), -t.total());
+
+        // Only pawns left, the endgame values are used
+        let pos = parse::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".to_string()).unwrap();
+        let t = super::trace(&pos);
+        assert_eq!(game_phase(&pos), 0);
+        assert_eq!(evaluate(&pos), (t.white_total() - t.black_total()).eg);
+        assert!(t.terms().iter().any(|(name, ..)| *name == "King shield"));
+    }
+
+    #[test]
+    fn piece_squares() {
+        // The same piece scores the same for either side on mirrored squares
+        let pos = parse::from_fen("4k3/8/2n5/8/8/2N5/8/4K3 w - - 0 1".to_string()).unwrap();
+        assert_eq!(
+            super::piece_squares(&pos, Color::White),
+            super::piece_squares(&pos, Color::Black)
+        );
+
+        let center = parse::from_fen("4k3/8/8/4N3/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        let corner = parse::from_fen("4k3/8/8/8/8/8/8/N3K3 w - - 0 1".to_string()).unwrap();
+        assert!(
+            super::piece_squares(&center, Color::White).mg
+                > super::piece_squares(&corner, Color::White).mg
+        );
+    }
+
+    #[test]
+    fn pawn_structure() {
+        // Doubled and isolated on the c-file, a passed pawn on h6
+        let pos = parse::from_fen("4k3/8/7P/8/2P5/2P5/8/4K3 w - - 0 1".to_string()).unwrap();
+        let w = super::pawn_structure(&pos, Color::White);
+        assert_eq!(w.doubled, DOUBLED_PAWN);
+        assert_eq!(w.isolated, ISOLATED_PAWN * 3);
+        assert_eq!(w.passed, PASSED_PAWN[5] + PASSED_PAWN[3] + PASSED_PAWN[2]);
+
+        // The d7 pawn stops c4 from being passed, and the other way round
+        let pos = parse::from_fen("4k3/3p4/8/8/2P5/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        assert_eq!(
+            super::pawn_structure(&pos, Color::White).passed,
+            Score::default()
+        );
+        assert_eq!(
+            super::pawn_structure(&pos, Color::Black).passed,
+            Score::default()
+        );
+    }
+
+    #[test]
+    fn king_safety() {
+        let castled = parse::from_fen("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1".to_string()).unwrap();
+        assert_eq!(
+            super::king_safety(&castled, Color::White).shield,
+            PAWN_SHIELD * 3
+        );
+        assert_eq!(
+            super::king_safety(&castled, Color::White).attacks,
+            Score::default()
+        );
+
+        // The queen and rook bear down on the squares around the king
+        let pos = parse::from_fen("3rk3/8/8/8/8/8/5q2/6K1 w - - 0 1".to_string()).unwrap();
+        let w = super::king_safety(&pos, Color::White);
+        assert!(w.attacks.mg < 0);
+        assert_eq!(w.attacks.eg, 0);
+        assert_eq!(
+            super::king_safety(&pos, Color::Black).attacks,
+            Score::default()
+        );
+    }
+
+    #[test]
+    fn mobility() {
+        // Symmetrical position, both sides should be equally active
+        let pos =
+            parse::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string())
+                .unwrap();
+        let w = piece_activity(&pos, Color::White);
+        let b = piece_activity(&pos, Color::Black);
+        assert_eq!(w, b);
+        assert_eq!(w.trapped, Score::default());
+
+        // a3 and c3 are covered by the pawns on b4 and d4
+        let pos = parse::from_fen("4k3/8/8/8/1p1p4/8/8/1N2K3 w - - 0 1".to_string()).unwrap();
+        let safe = safe_squares(&pos, PieceType::Knight, Color::White, 1, 0).count();
+        assert_eq!(safe, 1);
+
+        // A centralised knight is worth more than one on the rim
+        let center = parse::from_fen("4k3/8/8/4N3/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        let rim = parse::from_fen("4k3/8/8/7N/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        assert!(
+            piece_activity(&center, Color::White).mobility.mg
+                > piece_activity(&rim, Color::White).mobility.mg
+        );
+    }
+
+    #[test]
+    fn rook_files() {
+        let pos = parse::from_fen("3rk3/1p6/8/8/8/8/PP6/R3K2R w - - 0 1".to_string()).unwrap();
+        let w = piece_activity(&pos, Color::White);
+        let b = piece_activity(&pos, Color::Black);
+
+        // h-file is open, a-file is closed
+        assert_eq!(w.rook_files, ROOK_OPEN_FILE);
+        // d-file is open for black too
+        assert_eq!(b.rook_files, ROOK_OPEN_FILE);
+
+        let pos = parse::from_fen("4k3/p7/8/8/8/8/8/R3K3 w - - 0 1".to_string()).unwrap();
+        let w = piece_activity(&pos, Color::White);
+        assert_eq!(w.rook_files, ROOK_SEMI_OPEN_FILE);
+    }
+
+    #[test]
+    fn bishop_pair() {
+        let pos = parse::from_fen("2b1k3/8/8/8/8/8/8/2B1KB2 w - - 0 1".to_string()).unwrap();
+        assert_eq!(piece_activity(&pos, Color::White).bishop_pair, BISHOP_PAIR);
+        assert_eq!(
+            piece_activity(&pos, Color::Black).bishop_pair,
+            Score::default()
+        );
+    }
+
+    #[test]
+    fn outposts() {
+        // Knight on d5 supported by e4, no black pawn on c or e files
+        let pos = parse::from_fen("4k3/pp4pp/8/3N4/4P3/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        assert_eq!(piece_activity(&pos, Color::White).outposts, KNIGHT_OUTPOST);
+
+        // The c7 pawn can chase it away
+        let pos = parse::from_fen("4k3/ppp3pp/8/3N4/4P3/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        assert_eq!(
+            piece_activity(&pos, Color::White).outposts,
+            Score::default()
+        );
+
+        // Mirrored for black
+        let pos = parse::from_fen("4k3/8/8/4p3/3n4/8/PP4PP/4K3 b - - 0 1".to_string()).unwrap();
+        assert_eq!(piece_activity(&pos, Color::Black).outposts, KNIGHT_OUTPOST);
+    }
+
+    #[test]
+    fn trapped() {
+        // Bishop took on a7 and is shut in by b6
+        let pos = parse::from_fen("4k3/B1p5/1p6/8/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        assert_eq!(piece_activity(&pos, Color::White).trapped, TRAPPED_MINOR);
+
+        // Rook on h1 stuck behind a king that has lost its castling rights
+        let pos = parse::from_fen("4k3/8/8/8/8/8/5PPP/5K1R w - - 0 1".to_string()).unwrap();
+        assert_eq!(piece_activity(&pos, Color::White).trapped, TRAPPED_ROOK);
+
+        let pos = parse::from_fen("4k3/8/8/8/8/8/5PPP/4K2R w K - 0 1".to_string()).unwrap();
+        assert_eq!(piece_activity(&pos, Color::White).trapped, Score::default());
+    }
+}
@@ -1,14 +1,425 @@
-use super::{piece::PieceType, Square};
+use super::piece::{self, Color, PieceType};
+use super::variant::Variant;
+use super::{BitBoard, Castling, Position, Square};
+use std::fmt::Display;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Move {
     pub from: Square,
     pub to: Square,
     pub ptype: PieceType,
+    pub promotion: Option<PieceType>,
+    /// A Crazyhouse drop of ptype from the pocket onto to, from is the same as to.
+    pub drop: bool,
     pub change: StateChange,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Everything that is needed to take a move back.
+/// Apart from captured and castling_rook, the fields hold the state from
+/// before the move and are filled in when the move is made.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct StateChange {
     pub captured: Option<PieceType>,
+    /// File the rook comes from when the move castles.
+    pub castling_rook: Option<u32>,
+    pub w_castling: Castling,
+    pub b_castling: Castling,
+    pub en_passant: Option<Square>,
+    pub halfturn: usize,
+    pub w_checks: u32,
+    pub b_checks: u32,
+    pub promoted: BitBoard,
+    /// Pieces blown up around the capture square in Atomic chess.
+    pub exploded: [Option<(PieceType, Color)>; 8],
+}
+
+impl Move {
+    /// Castling is stored as the king moving to the g or c file,
+    /// with the file of the rook in the state change.
+    pub fn is_castling(&self) -> bool {
+        self.change.castling_rook.is_some()
+    }
+
+    /// Square of the rook a castling move is made with.
+    pub fn castling_rook(&self) -> Option<Square> {
+        self.change.castling_rook.map(|file| Square {
+            file,
+            rank: self.from.rank,
+        })
+    }
+
+    pub fn is_en_passant(&self) -> bool {
+        self.ptype == PieceType::Pawn
+            && self.change.captured.is_some()
+            && self.change.en_passant == Some(self.to)
+    }
+
+    /// UCI notation, with castling as the king taking its own rook in Chess960.
+    pub fn to_uci(self, chess960: bool) -> String {
+        match self.castling_rook() {
+            Some(rook) if chess960 => format!("{}{rook}", self.from),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Whether both moves go between the same squares and promote to the same piece.
+    pub fn same_as(&self, other: &Move) -> bool {
+        self.from == other.from
+            && self.to == other.to
+            && self.promotion == other.promotion
+            && self.is_castling() == other.is_castling()
+            && self.drop == other.drop
+            && (!self.drop || self.ptype == other.ptype)
+    }
+}
+
+/// Long algebraic notation as used by UCI, e.g. e2e4, e7e8q or N@f3.
+/// Castling is written as the king's move, see Position::move_to_uci for Chess960.
+impl Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.drop {
+            return write!(f, "{}@{}", self.ptype.to_char(Color::White), self.to);
+        }
+
+        write!(f, "{}{}", self.from, self.to)?;
+
+        if let Some(p) = self.promotion {
+            write!(f, "{}", p.to_char(Color::Black))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Position {
+    pub fn piece_at(&self, file: u32, rank: u32) -> Option<(PieceType, Color)> {
+        for c in [Color::White, Color::Black] {
+            if self.pieces_all(c).get(file, rank) != Some(true) {
+                continue;
+            }
+
+            for (p, board) in self.pieces(c).iter() {
+                if board.get(file, rank) == Some(true) {
+                    return Some((p, c));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn king_square(&self, c: Color) -> Option<Square> {
+        self.pieces(c)
+            .get_board(PieceType::King)
+            .squares()
+            .into_iter()
+            .next()
+    }
+
+    /// Always false in Antichess, where the king is an ordinary piece, and in
+    /// Atomic chess while the kings touch.
+    pub fn is_in_check(&self, c: Color) -> bool {
+        if self.variant == Variant::Antichess
+            || (self.variant == Variant::Atomic && self.kings_touch())
+        {
+            return false;
+        }
+
+        match self.king_square(c) {
+            Some(k) => self.attacks_all(c.opposite()).get(k.file, k.rank) == Some(true),
+            None => false,
+        }
+    }
+
+    pub(crate) fn new_move(&self, from: Square, to: Square, ptype: PieceType) -> Move {
+        let captured = self
+            .piece_at(to.file, to.rank)
+            .filter(|(_, c)| *c != self.side)
+            .map(|(p, _)| p);
+
+        Move {
+            from,
+            to,
+            ptype,
+            promotion: None,
+            drop: false,
+            change: StateChange {
+                captured,
+                castling_rook: None,
+                w_castling: self.w_castling,
+                b_castling: self.b_castling,
+                en_passant: self.en_passant,
+                halfturn: self.halfturn,
+                w_checks: self.w_checks,
+                b_checks: self.b_checks,
+                promoted: self.promoted,
+                exploded: [None; 8],
+            },
+        }
+    }
+
+    fn push_pawn_move(&self, moves: &mut Vec<Move>, m: Move) {
+        if m.to.rank == 0 || m.to.rank == 7 {
+            for p in self.variant.promotions().iter().copied() {
+                moves.push(Move {
+                    promotion: Some(p),
+                    ..m
+                });
+            }
+        } else {
+            moves.push(m);
+        }
+    }
+
+    fn pawn_moves(&self, moves: &mut Vec<Move>, from: Square) {
+        let c = self.side;
+        let (forward, start_rank): (i32, u32) = match c {
+            Color::White => (1, 1),
+            Color::Black => (-1, 6),
+        };
+
+        let occupied = {
+            let mut b = *self.pieces_all(Color::White);
+            b.or_assign(*self.pieces_all(Color::Black));
+            b
+        };
+
+        let one = (from.rank as i32 + forward) as u32;
+        if occupied.get(from.file, one) == Some(false) {
+            let to = Square {
+                file: from.file,
+                rank: one,
+            };
+            self.push_pawn_move(moves, self.new_move(from, to, PieceType::Pawn));
+
+            let two = (from.rank as i32 + forward * 2) as u32;
+            if from.rank == start_rank && occupied.get(from.file, two) == Some(false) {
+                let to = Square {
+                    file: from.file,
+                    rank: two,
+                };
+                moves.push(self.new_move(from, to, PieceType::Pawn));
+            }
+        }
+
+        let mut single = BitBoard::new();
+        single.set(from.file, from.rank);
+        let attacks = piece::get_piece_attack_map(
+            PieceType::Pawn,
+            c,
+            &single,
+            self.pieces_all(c),
+            self.pieces_all(c.opposite()),
+        );
+
+        for to in attacks.squares() {
+            if self.pieces_all(c.opposite()).get(to.file, to.rank) == Some(true) {
+                self.push_pawn_move(moves, self.new_move(from, to, PieceType::Pawn));
+            } else if self.en_passant == Some(to) {
+                let mut m = self.new_move(from, to, PieceType::Pawn);
+                m.change.captured = Some(PieceType::Pawn);
+                moves.push(m);
+            }
+        }
+    }
+
+    /// Castling by the Chess960 rules, which cover the standard game too:
+    /// the king ends on the g or c file and the rook next to it.
+    fn castling_moves(&self, moves: &mut Vec<Move>) {
+        let c = self.side;
+        let (castling, rank) = match c {
+            Color::White => (self.w_castling, 0),
+            Color::Black => (self.b_castling, 7),
+        };
+
+        if self.is_in_check(c) || self.variant == Variant::Antichess {
+            return;
+        }
+
+        let Some(king) = self.king_square(c).filter(|k| k.rank == rank) else {
+            return;
+        };
+
+        let mut occupied = *self.pieces_all(Color::White);
+        occupied.or_assign(*self.pieces_all(Color::Black));
+        let mut enemy = *self.attacks_all(c.opposite());
+        if self.variant == Variant::Atomic {
+            // Squares next to the other king are safe, it can't take there
+            enemy.clear_assign(self.attacks(c.opposite()).get_board(PieceType::King));
+        }
+        let between = |a: u32, b: u32| a.min(b)..=a.max(b);
+
+        let sides = [
+            (castling.king_side_rook(), 6, 5),
+            (castling.queen_side_rook(), 2, 3),
+        ];
+
+        for (rook, king_to, rook_to) in sides {
+            let Some(rook) = rook else {
+                continue;
+            };
+            if self.pieces(c).get(PieceType::Rook, rook, rank) != Some(true) {
+                continue;
+            }
+
+            // Everything the king and rook cross has to be empty, apart from themselves
+            let blocked = between(king.file, king_to)
+                .chain(between(rook, rook_to))
+                .any(|f| f != king.file && f != rook && occupied.get(f, rank) == Some(true));
+
+            // Whether the king ends up in check is left to the legality test
+            let attacked = between(king.file, king_to)
+                .any(|f| f != king_to && enemy.get(f, rank) == Some(true));
+
+            if blocked || attacked {
+                continue;
+            }
+
+            let mut m = self.new_move(
+                king,
+                Square {
+                    file: king_to,
+                    rank,
+                },
+                PieceType::King,
+            );
+            m.change.castling_rook = Some(rook);
+            moves.push(m);
+        }
+    }
+
+    /// Moves that follow the movement rules of the pieces,
+    /// but might leave the own king in check.
+    pub fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let c = self.side;
+        let mut moves = vec![];
+
+        for (p, board) in self.pieces(c).iter() {
+            for from in board.squares() {
+                if p == PieceType::Pawn {
+                    self.pawn_moves(&mut moves, from);
+                    continue;
+                }
+
+                let mut single = BitBoard::new();
+                single.set(from.file, from.rank);
+
+                let mut targets = piece::get_piece_attack_map(
+                    p,
+                    c,
+                    &single,
+                    self.pieces_all(c),
+                    self.pieces_all(c.opposite()),
+                );
+                targets.clear_assign(*self.pieces_all(c));
+
+                for to in targets.squares() {
+                    moves.push(self.new_move(from, to, p));
+                }
+            }
+        }
+
+        self.castling_moves(&mut moves);
+        if self.variant == Variant::Crazyhouse {
+            self.drop_moves(&mut moves);
+        }
+
+        moves
+    }
+
+    /// All moves the side to move can make without leaving its king in check,
+    /// or by the rules of the variant. None once a variant rule has ended the game.
+    pub fn generate_legal_moves(&mut self) -> Vec<Move> {
+        if self.variant_winner().is_some() {
+            return vec![];
+        }
+        match self.variant {
+            Variant::Antichess => return self.antichess_moves(),
+            Variant::Atomic => return self.atomic_moves(),
+            _ => (),
+        }
+
+        let side = self.side;
+        let mut out = vec![];
+
+        for m in self.pseudo_legal_moves() {
+            self.change_board(m);
+            if !self.is_in_check(side) {
+                out.push(m);
+            }
+            self.reverse_last_change();
+        }
+
+        out
+    }
+
+    /// Number of leaf nodes of the move tree at the given depth.
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = self.generate_legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        let mut nodes = 0;
+        for m in moves {
+            self.change_board(m);
+            nodes += self.perft(depth - 1);
+            self.reverse_last_change();
+        }
+
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+
+    fn perft(fen: &str, depth: u32) -> u64 {
+        let mut pos = parse::from_fen(fen.to_string()).unwrap();
+        let before = pos.clone();
+        let nodes = pos.perft(depth);
+
+        assert_eq!(pos, before);
+        nodes
+    }
+
+    #[test]
+    fn perft_start() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(perft(fen, 1), 20);
+        assert_eq!(perft(fen, 2), 400);
+        assert_eq!(perft(fen, 3), 8902);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(perft(fen, 1), 48);
+        assert_eq!(perft(fen, 2), 2039);
+    }
+
+    #[test]
+    fn perft_en_passant() {
+        let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        assert_eq!(perft(fen, 1), 14);
+        assert_eq!(perft(fen, 2), 191);
+        assert_eq!(perft(fen, 3), 2812);
+    }
+
+    #[test]
+    fn perft_promotions() {
+        let fen = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+        assert_eq!(perft(fen, 1), 6);
+        assert_eq!(perft(fen, 2), 264);
+        assert_eq!(perft(fen, 3), 9467);
+
+        let fen = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+        assert_eq!(perft(fen, 1), 44);
+        assert_eq!(perft(fen, 2), 1486);
+    }
 }
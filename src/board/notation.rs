@@ -0,0 +1,679 @@
+use super::movegen::Move;
+use super::piece::{get_piece_attack_map, Color, PieceType};
+use super::variant::Variant;
+use super::{BitBoard, Position, Square};
+use anyhow::{anyhow, Result};
+use std::fmt::Display;
+
+fn piece_name(p: PieceType) -> &'static str {
+    match p {
+        PieceType::King => "king",
+        PieceType::Queen => "queen",
+        PieceType::Rook => "rook",
+        PieceType::Bishop => "bishop",
+        PieceType::Knight => "knight",
+        PieceType::Pawn => "pawn",
+    }
+}
+
+fn file_char(file: u32) -> char {
+    char::from(b'a' + file as u8)
+}
+
+fn rank_char(rank: u32) -> char {
+    char::from(b'1' + rank as u8)
+}
+
+/// SAN of a move without the check or mate suffix.
+fn san_without_suffix(m: &Move, legal: &[Move]) -> String {
+    if m.drop {
+        return match m.ptype {
+            PieceType::Pawn => format!("@{}", m.to),
+            p => format!("{}@{}", p.to_char(Color::White), m.to),
+        };
+    }
+
+    if m.is_castling() {
+        return match m.to.file {
+            6 => "O-O".to_string(),
+            _ => "O-O-O".to_string(),
+        };
+    }
+
+    let mut out = String::new();
+
+    if m.ptype == PieceType::Pawn {
+        if m.change.captured.is_some() {
+            out.push(file_char(m.from.file));
+        }
+    } else {
+        out.push(m.ptype.to_char(Color::White));
+
+        let others: Vec<&Move> = legal
+            .iter()
+            .filter(|o| o.ptype == m.ptype && o.to == m.to && o.from != m.from && !o.drop)
+            .collect();
+
+        if !others.is_empty() {
+            if others.iter().all(|o| o.from.file != m.from.file) {
+                out.push(file_char(m.from.file));
+            } else if others.iter().all(|o| o.from.rank != m.from.rank) {
+                out.push(rank_char(m.from.rank));
+            } else {
+                out.push(file_char(m.from.file));
+                out.push(rank_char(m.from.rank));
+            }
+        }
+    }
+
+    if m.change.captured.is_some() {
+        out.push('x');
+    }
+
+    out.push_str(&m.to.to_string());
+
+    if let Some(p) = m.promotion {
+        out.push('=');
+        out.push(p.to_char(Color::White));
+    }
+
+    out
+}
+
+/// Why a move can't be played.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IllegalMove {
+    /// The text doesn't describe a move at all.
+    Invalid(String),
+    /// The side to move has no legal moves left.
+    GameOver,
+    NoPiece(Square),
+    OpponentsPiece {
+        piece: PieceType,
+        square: Square,
+    },
+    /// The piece doesn't move like that.
+    Unreachable {
+        piece: PieceType,
+        from: Square,
+        to: Square,
+    },
+    /// Another piece is in the way.
+    Blocked {
+        piece: PieceType,
+        from: Square,
+        by: Square,
+    },
+    /// A piece of the side to move already stands on the square.
+    OwnPiece(Square),
+    MustPromote(Square),
+    CantPromote,
+    LeavesKingInCheck,
+    /// In Antichess, a move that doesn't capture while another one does.
+    MustCapture,
+    /// In Atomic chess the king would blow itself up.
+    KingCantCapture,
+    CastlingNotAllowed,
+    /// SAN naming a piece type that has none able to go there.
+    NoPieceCanMove {
+        piece: PieceType,
+        to: Square,
+    },
+    /// SAN that fits more than one move, with the moves it could be.
+    Ambiguous(Vec<String>),
+    /// A Crazyhouse drop of a piece the side to move doesn't have in hand.
+    NotInHand(PieceType),
+    /// A drop onto an occupied square, or of a pawn onto the first or last rank.
+    CantDrop {
+        piece: PieceType,
+        to: Square,
+    },
+}
+
+impl Display for IllegalMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IllegalMove::Invalid(msg) => write!(f, "{msg}"),
+            IllegalMove::GameOver => write!(f, "the game is over"),
+            IllegalMove::NoPiece(sqr) => write!(f, "there is no piece on {sqr}"),
+            IllegalMove::OpponentsPiece { piece, square } => write!(
+                f,
+                "the {} on {square} belongs to the opponent",
+                piece_name(*piece)
+            ),
+            IllegalMove::Unreachable { piece, from, to } => {
+                write!(f, "the {} on {from} can't move to {to}", piece_name(*piece))
+            }
+            IllegalMove::Blocked { piece, from, by } => write!(
+                f,
+                "the {} on {from} is blocked by the piece on {by}",
+                piece_name(*piece)
+            ),
+            IllegalMove::OwnPiece(sqr) => write!(f, "there is already an own piece on {sqr}"),
+            IllegalMove::MustPromote(sqr) => write!(f, "a pawn reaching {sqr} has to promote"),
+            IllegalMove::CantPromote => {
+                write!(f, "only pawns reaching the last rank can promote")
+            }
+            IllegalMove::LeavesKingInCheck => write!(f, "it would leave the king in check"),
+            IllegalMove::MustCapture => write!(f, "a capture is possible and has to be made"),
+            IllegalMove::KingCantCapture => write!(f, "the king can't capture in Atomic chess"),
+            IllegalMove::CastlingNotAllowed => write!(f, "castling is not possible"),
+            IllegalMove::NoPieceCanMove { piece, to } => {
+                write!(f, "no {} can move to {to}", piece_name(*piece))
+            }
+            IllegalMove::Ambiguous(options) => write!(f, "could be {}", options.join(" or ")),
+            IllegalMove::NotInHand(piece) => {
+                write!(f, "there is no {} in hand", piece_name(*piece))
+            }
+            IllegalMove::CantDrop { piece, to } => {
+                write!(f, "a {} can't be dropped on {to}", piece_name(*piece))
+            }
+        }
+    }
+}
+
+impl std::error::Error for IllegalMove {}
+
+/// The error parse_uci and parse_san give, naming the move that was tried.
+fn describe(s: &str, e: IllegalMove) -> anyhow::Error {
+    match e {
+        IllegalMove::Invalid(msg) => anyhow!(msg),
+        IllegalMove::Ambiguous(_) => anyhow!("Ambiguous move '{s}', {e}"),
+        e => anyhow!("Illegal move '{s}': {e}"),
+    }
+}
+
+impl Position {
+    /// Standard Algebraic Notation of a legal move, e.g. Nbd7, exf4, O-O-O or e8=Q+.
+    pub fn move_to_san(&mut self, m: &Move) -> String {
+        let legal = self.generate_legal_moves();
+        let mut out = san_without_suffix(m, &legal);
+
+        self.change_board(*m);
+        if self.is_in_check(self.side) {
+            if self.generate_legal_moves().is_empty() {
+                out.push('#');
+            } else {
+                out.push('+');
+            }
+        }
+        self.reverse_last_change();
+
+        out
+    }
+
+    /// UCI long algebraic notation of a move. In Chess960 castling is
+    /// written as the king taking its own rook, e.g. e1h1.
+    pub fn move_to_uci(&self, m: &Move) -> String {
+        m.to_uci(self.chess960)
+    }
+
+    /// Parses a move in UCI long algebraic notation, e.g. e2e4 or e7e8q.
+    /// Castling can also be given as the king taking its own rook, which
+    /// is the only way in Chess960.
+    pub fn parse_uci(&mut self, s: &str) -> Result<Move> {
+        self.find_uci(s).map_err(|e| describe(s.trim(), e))
+    }
+
+    pub(crate) fn find_uci(&mut self, s: &str) -> Result<Move, IllegalMove> {
+        let s = s.trim();
+
+        if let Some((piece, to)) = s.split_once('@') {
+            return self.find_drop(s, piece, to);
+        }
+
+        if s.len() != 4 && s.len() != 5 {
+            return Err(IllegalMove::Invalid(format!(
+                "Invalid move '{s}', expected e.g. e2e4 or e7e8q"
+            )));
+        }
+
+        let square = |s: &str| Square::from_str(s).map_err(|e| IllegalMove::Invalid(e.to_string()));
+        let from = square(&s[0..2])?;
+        let to = square(&s[2..4])?;
+
+        let promotion = match s.chars().nth(4) {
+            Some(c) => match PieceType::from_char(c) {
+                Ok((p, _)) if self.variant.promotions().contains(&p) => Some(p),
+                _ => {
+                    return Err(IllegalMove::Invalid(format!(
+                        "Invalid promotion piece '{c}' in move '{s}'"
+                    )))
+                }
+            },
+            None => None,
+        };
+
+        let legal = self.generate_legal_moves();
+        let chess960 = self.chess960;
+        if let Some(m) = legal.iter().find(|m| {
+            let target = match m.castling_rook() {
+                Some(rook) => rook == to || (!chess960 && m.to == to),
+                None => m.to == to,
+            };
+            !m.drop && m.from == from && target && m.promotion == promotion
+        }) {
+            return Ok(*m);
+        }
+
+        Err(self.explain_illegal(from, to, promotion))
+    }
+
+    /// A Crazyhouse drop written as N@f3, or @f3 for a pawn.
+    fn find_drop(&mut self, s: &str, piece: &str, to: &str) -> Result<Move, IllegalMove> {
+        let mut chars = piece.chars();
+        let ptype = match (chars.next(), chars.next()) {
+            (None, _) => PieceType::Pawn,
+            (Some(c), None) => match PieceType::from_char(c) {
+                Ok((p, _)) if p != PieceType::King => p,
+                _ => {
+                    return Err(IllegalMove::Invalid(format!(
+                        "Invalid piece '{c}' in drop '{s}'"
+                    )))
+                }
+            },
+            _ => return Err(IllegalMove::Invalid(format!("Invalid drop '{s}'"))),
+        };
+        let to = Square::from_str(to).map_err(|e| IllegalMove::Invalid(e.to_string()))?;
+
+        let legal = self.generate_legal_moves();
+        match legal
+            .iter()
+            .find(|m| m.drop && m.ptype == ptype && m.to == to)
+        {
+            Some(m) => Ok(*m),
+            None => Err(self.explain_drop(ptype, to)),
+        }
+    }
+
+    /// Works out why piece can't be dropped on to.
+    pub(crate) fn explain_drop(&mut self, piece: PieceType, to: Square) -> IllegalMove {
+        if self.variant != Variant::Crazyhouse {
+            return IllegalMove::Invalid("Drops are only possible in Crazyhouse".to_string());
+        }
+        if self.generate_legal_moves().is_empty() {
+            return IllegalMove::GameOver;
+        }
+        if self.pocket(self.side).count(piece) == 0 {
+            return IllegalMove::NotInHand(piece);
+        }
+
+        let pseudo = self.pseudo_legal_moves();
+        if pseudo
+            .iter()
+            .any(|m| m.drop && m.ptype == piece && m.to == to)
+        {
+            IllegalMove::LeavesKingInCheck
+        } else {
+            IllegalMove::CantDrop { piece, to }
+        }
+    }
+
+    /// Works out why there is no legal move from one square to another.
+    pub(crate) fn explain_illegal(
+        &mut self,
+        from: Square,
+        to: Square,
+        promotion: Option<PieceType>,
+    ) -> IllegalMove {
+        if self.generate_legal_moves().is_empty() {
+            return IllegalMove::GameOver;
+        }
+
+        let (piece, c) = match self.piece_at(from.file, from.rank) {
+            Some(piece) => piece,
+            None => return IllegalMove::NoPiece(from),
+        };
+
+        if c != self.side {
+            return IllegalMove::OpponentsPiece {
+                piece,
+                square: from,
+            };
+        }
+
+        let pseudo = self.pseudo_legal_moves();
+        let reachable: Vec<&Move> = pseudo
+            .iter()
+            .filter(|m| m.from == from && m.to == to)
+            .collect();
+
+        if reachable.is_empty() {
+            return self.explain_unreachable(piece, from, to);
+        }
+
+        if reachable.iter().all(|m| m.promotion.is_some()) && promotion.is_none() {
+            IllegalMove::MustPromote(to)
+        } else if reachable.iter().all(|m| m.promotion != promotion) {
+            IllegalMove::CantPromote
+        } else {
+            self.not_legal(reachable[0])
+        }
+    }
+
+    /// Why the pseudo legal move m isn't legal.
+    fn not_legal(&self, m: &Move) -> IllegalMove {
+        match self.variant {
+            Variant::Antichess => IllegalMove::MustCapture,
+            Variant::Atomic if m.ptype == PieceType::King && m.change.captured.is_some() => {
+                IllegalMove::KingCantCapture
+            }
+            _ => IllegalMove::LeavesKingInCheck,
+        }
+    }
+
+    /// For a piece of the side to move that can't even pseudo legally go to to.
+    fn explain_unreachable(&self, piece: PieceType, from: Square, to: Square) -> IllegalMove {
+        let c = self.side;
+        let occupied = |sqr: Square| self.piece_at(sqr.file, sqr.rank).is_some();
+
+        // Two squares sideways, or onto its own rook
+        let castling = piece == PieceType::King
+            && from.rank == to.rank
+            && (from.file.abs_diff(to.file) == 2
+                || self.pieces(c).get(PieceType::Rook, to.file, to.rank) == Some(true));
+        if castling {
+            return IllegalMove::CastlingNotAllowed;
+        }
+
+        if self.pieces_all(c).get(to.file, to.rank) == Some(true) {
+            return IllegalMove::OwnPiece(to);
+        }
+
+        let mut single = BitBoard::new();
+        single.set(from.file, from.rank);
+        let empty = BitBoard::new();
+        let mut lines = get_piece_attack_map(piece, c, &single, &empty, &empty);
+
+        // Pawns move straight ahead, one square or two from their starting rank
+        if piece == PieceType::Pawn {
+            let (forward, start): (i32, u32) = match c {
+                Color::White => (1, 1),
+                Color::Black => (-1, 6),
+            };
+            lines = BitBoard::new();
+            for n in [1, 2].into_iter().filter(|n| *n == 1 || from.rank == start) {
+                lines.set(from.file, (from.rank as i32 + forward * n) as u32);
+            }
+        }
+
+        // Only sliders and pawn pushes have squares in between
+        let steps = !matches!(piece, PieceType::Knight | PieceType::King);
+        if !steps || lines.get(to.file, to.rank) != Some(true) {
+            return IllegalMove::Unreachable { piece, from, to };
+        }
+
+        let step = |a: u32, b: u32| (b as i32 - a as i32).signum();
+        let (df, dr) = (step(from.file, to.file), step(from.rank, to.rank));
+        let mut sqr = from;
+        while sqr != to {
+            sqr = Square {
+                file: (sqr.file as i32 + df) as u32,
+                rank: (sqr.rank as i32 + dr) as u32,
+            };
+            let capture = sqr == to && piece != PieceType::Pawn;
+            if occupied(sqr) && !capture {
+                return IllegalMove::Blocked {
+                    piece,
+                    from,
+                    by: sqr,
+                };
+            }
+        }
+
+        IllegalMove::Unreachable { piece, from, to }
+    }
+
+    /// Parses a move in Standard Algebraic Notation against the legal moves
+    /// of the position. Check and annotation suffixes are ignored.
+    pub fn parse_san(&mut self, s: &str) -> Result<Move> {
+        self.find_san(s).map_err(|e| describe(s.trim(), e))
+    }
+
+    pub(crate) fn find_san(&mut self, s: &str) -> Result<Move, IllegalMove> {
+        let original = s.trim();
+        let s = original.trim_end_matches(['+', '#', '!', '?']);
+        let invalid = |msg: String| Err(IllegalMove::Invalid(msg));
+
+        let legal = self.generate_legal_moves();
+        if legal.is_empty() {
+            return Err(IllegalMove::GameOver);
+        }
+
+        if matches!(s, "O-O" | "0-0" | "O-O-O" | "0-0-0") {
+            let file = if s.len() == 3 { 6 } else { 2 };
+            return legal
+                .iter()
+                .find(|m| m.is_castling() && m.to.file == file)
+                .copied()
+                .ok_or(IllegalMove::CastlingNotAllowed);
+        }
+
+        if let Some((piece, to)) = s.split_once('@') {
+            return self.find_drop(original, piece, to);
+        }
+
+        let mut chars: Vec<char> = s.chars().filter(|c| *c != 'x' && *c != '-').collect();
+
+        let ptype = match chars.first() {
+            Some(c) if c.is_ascii_uppercase() => {
+                let Ok((p, _)) = PieceType::from_char(*c) else {
+                    return invalid(format!("Invalid piece '{c}' in move '{original}'"));
+                };
+                chars.remove(0);
+                p
+            }
+            Some(_) => PieceType::Pawn,
+            None => return invalid("Empty move".to_string()),
+        };
+
+        // e8=Q or e8Q
+        let mut promotion = None;
+        if let Some(c) = chars.last().copied() {
+            if c.is_ascii_uppercase() {
+                let p = match PieceType::from_char(c) {
+                    Ok((p, _)) if self.variant.promotions().contains(&p) => p,
+                    _ => {
+                        return invalid(format!(
+                            "Invalid promotion piece '{c}' in move '{original}'"
+                        ))
+                    }
+                };
+                promotion = Some(p);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+
+        if chars.len() < 2 || chars.len() > 4 {
+            return invalid(format!("Invalid move '{original}'"));
+        }
+
+        let to: String = chars[chars.len() - 2..].iter().collect();
+        let Ok(to) = Square::from_str(&to) else {
+            return invalid(format!(
+                "Invalid destination square '{to}' in move '{original}'"
+            ));
+        };
+
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in &chars[..chars.len() - 2] {
+            match c {
+                'a'..='h' => from_file = Some(*c as u32 - 'a' as u32),
+                '1'..='8' => from_rank = Some(*c as u32 - '1' as u32),
+                _ => return invalid(format!("Invalid character '{c}' in move '{original}'")),
+            }
+        }
+
+        let candidates: Vec<&Move> = legal
+            .iter()
+            .filter(|m| {
+                m.ptype == ptype
+                    && m.to == to
+                    && !m.is_castling()
+                    && !m.drop
+                    && from_file.is_none_or(|f| m.from.file == f)
+                    && from_rank.is_none_or(|r| m.from.rank == r)
+            })
+            .collect();
+
+        let matching: Vec<&Move> = candidates
+            .iter()
+            .filter(|m| m.promotion == promotion)
+            .copied()
+            .collect();
+
+        match matching.len() {
+            1 => Ok(*matching[0]),
+            0 if !candidates.is_empty() && promotion.is_none() => Err(IllegalMove::MustPromote(to)),
+            0 if !candidates.is_empty() => Err(IllegalMove::CantPromote),
+            0 => {
+                let pseudo = self.pseudo_legal_moves();
+                let reachable = pseudo.iter().find(|m| {
+                    !m.drop
+                        && m.ptype == ptype
+                        && m.to == to
+                        && from_file.is_none_or(|f| m.from.file == f)
+                        && from_rank.is_none_or(|r| m.from.rank == r)
+                });
+
+                match reachable {
+                    Some(m) => Err(self.not_legal(m)),
+                    None => Err(IllegalMove::NoPieceCanMove { piece: ptype, to }),
+                }
+            }
+            _ => Err(IllegalMove::Ambiguous(
+                matching
+                    .iter()
+                    .map(|m| san_without_suffix(m, &legal))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    fn san(fen: &str, uci: &str) -> String {
+        let mut pos = parse::from_fen(fen.to_string()).unwrap();
+        let m = pos.parse_uci(uci).unwrap();
+        pos.move_to_san(&m)
+    }
+
+    #[test]
+    fn move_to_san() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san(start, "e2e4"), "e4");
+        assert_eq!(san(start, "g1f3"), "Nf3");
+
+        // Knights on b8 and f6 can both go to d7
+        let fen = "rnbqkb1r/ppp2ppp/5n2/3pp3/4PP2/2N5/PPPP2PP/R1BQKBNR b KQkq - 1 4";
+        assert_eq!(san(fen, "b8d7"), "Nbd7");
+        assert_eq!(san(fen, "e5f4"), "exf4");
+
+        // Rooks on the same file
+        let fen = "4k3/8/R7/8/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1a3"), "R1a3");
+
+        // Three queens, only file and rank together are unique
+        let fen = "8/8/1k6/8/4Q2Q/8/8/K6Q w - - 0 1";
+        assert_eq!(san(fen, "h4e1"), "Qh4e1");
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+        assert_eq!(san(fen, "e1g1"), "O-O");
+
+        let fen = "8/4P3/8/8/8/8/k7/4K3 w - - 0 1";
+        assert_eq!(san(fen, "e7e8q"), "e8=Q");
+        let fen = "3k4/4P3/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "e7e8q"), "e8=Q+");
+
+        // Scholar's mate
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        assert_eq!(san(fen, "h5f7"), "Qxf7#");
+    }
+
+    #[test]
+    fn parse_san() {
+        let fen = "rnbqkb1r/ppp2ppp/5n2/3pp3/4PP2/2N5/PPPP2PP/R1BQKBNR b KQkq - 1 4";
+        let mut pos = parse::from_fen(fen.to_string()).unwrap();
+
+        assert_eq!(pos.parse_san("Nbd7").unwrap().to_string(), "b8d7");
+        assert_eq!(pos.parse_san("exf4").unwrap().to_string(), "e5f4");
+        assert_eq!(pos.parse_san("dxe4!?").unwrap().to_string(), "d5e4");
+
+        let err = pos.parse_san("Nd7").unwrap_err().to_string();
+        assert!(err.contains("Ambiguous"), "{err}");
+        assert!(err.contains("Nbd7") && err.contains("Nfd7"), "{err}");
+
+        let err = pos.parse_san("Nc4").unwrap_err().to_string();
+        assert!(err.contains("no knight can move to c4"), "{err}");
+
+        let err = pos.parse_san("O-O").unwrap_err().to_string();
+        assert!(err.contains("castling"), "{err}");
+
+        assert!(pos.parse_san("Zf3").is_err());
+        assert!(pos.parse_san("e9").is_err());
+        assert!(pos.parse_san("").is_err());
+
+        // Pinned knight
+        let fen = "4k3/8/8/8/8/8/4N3/r3K3 w - - 0 1";
+        let mut pos = parse::from_fen(fen.to_string()).unwrap();
+        let fen = "4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1";
+        let mut pinned = parse::from_fen(fen.to_string()).unwrap();
+        assert!(pos.parse_san("Nc3").is_err());
+        let err = pinned.parse_san("Nc3").unwrap_err().to_string();
+        assert!(err.contains("leave the king in check"), "{err}");
+
+        let fen = "8/4P3/8/8/8/8/k7/4K3 w - - 0 1";
+        let mut pos = parse::from_fen(fen.to_string()).unwrap();
+        assert_eq!(pos.parse_san("e8=N").unwrap().to_string(), "e7e8n");
+        assert_eq!(pos.parse_san("e8Q").unwrap().to_string(), "e7e8q");
+        let err = pos.parse_san("e8").unwrap_err().to_string();
+        assert!(err.contains("promote"), "{err}");
+    }
+
+    #[test]
+    fn parse_uci() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mut pos = parse::from_fen(start.to_string()).unwrap();
+
+        let m = pos.parse_uci("e2e4").unwrap();
+        assert_eq!(m.ptype, PieceType::Pawn);
+        assert_eq!(m.to_string(), "e2e4");
+
+        let err = pos.parse_uci("e3e4").unwrap_err().to_string();
+        assert!(err.contains("no piece on e3"), "{err}");
+        let err = pos.parse_uci("e7e5").unwrap_err().to_string();
+        assert!(err.contains("belongs to the opponent"), "{err}");
+        let err = pos.parse_uci("e2e5").unwrap_err().to_string();
+        assert!(err.contains("can't move to e5"), "{err}");
+        assert!(pos.parse_uci("e2").is_err());
+        assert!(pos.parse_uci("e7e8k").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ];
+
+        for fen in fens {
+            let mut pos = parse::from_fen(fen.to_string()).unwrap();
+
+            for m in pos.generate_legal_moves() {
+                let san = pos.move_to_san(&m);
+                assert_eq!(pos.parse_san(&san).unwrap(), m, "{san}");
+                assert_eq!(pos.parse_uci(&m.to_string()).unwrap(), m);
+            }
+        }
+    }
+}
@@ -1,78 +1,206 @@
+use super::crazyhouse::{self, Pocket};
+use super::variant::{self, Variant};
 use super::{BitBoard, Castling, Color, GameState, PieceType, Position, SideMap, Square};
 use anyhow::{anyhow, Result};
 
-fn parse_pieces(fen: String) -> Result<(SideMap, SideMap)> {
+/// Reads the board part of a FEN, along with the pieces marked as promoted by a
+/// ~ after them as Crazyhouse does. Every one of the 8 ranks has to add up to 8 files.
+fn parse_pieces(fen: String) -> Result<(SideMap, SideMap, BitBoard)> {
     let mut w_pieces: SideMap = SideMap::new();
     let mut b_pieces: SideMap = SideMap::new();
+    let mut promoted = BitBoard::new();
 
-    let mut rank: u32 = 7;
-    let mut file: u32 = 0;
+    let ranks: Vec<&str> = fen.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(anyhow!("FEN board has {} ranks instead of 8", ranks.len()));
+    }
+
+    for (i, text) in ranks.iter().enumerate() {
+        let rank = 7 - i as u32;
+        let mut file: u32 = 0;
+        let mut last = None;
 
-    for (index, c) in fen.as_str().chars().enumerate() {
-        if c.is_ascii_digit() {
-            if file + c.to_digit(10).unwrap() <= 8 {
-                file += c.to_digit(10).unwrap();
+        for c in text.chars() {
+            if let Some(n) = c.to_digit(10).filter(|n| (1..=8).contains(n)) {
+                last = None;
+                file += n;
+            } else if let Ok((p, c)) = PieceType::from_char(c) {
+                if file < 8 {
+                    match c {
+                        Color::White => w_pieces.set(p, file, rank),
+                        Color::Black => b_pieces.set(p, file, rank),
+                    }
+                }
+                last = Some(file);
+                file += 1;
+            } else if c == '~' {
+                let Some(file) = last.take() else {
+                    return Err(anyhow!(
+                        "'~' in rank {} of the FEN doesn't follow a piece",
+                        rank + 1
+                    ));
+                };
+                promoted.set(file, rank);
             } else {
                 return Err(anyhow!(
-                    "Invalid character '{c}' at position {index} in FEN"
+                    "Invalid character '{c}' in rank {} of the FEN",
+                    rank + 1
+                ));
+            }
+
+            if file > 8 {
+                return Err(anyhow!(
+                    "Rank {} of the FEN has more than 8 files",
+                    rank + 1
                 ));
             }
-        } else if let Ok((p, c)) = PieceType::from_char(c) {
-            let map: &mut SideMap = match c {
-                Color::White => &mut w_pieces,
-                Color::Black => &mut b_pieces,
-            };
-
-            map.toggle(p, file, rank);
-            file += 1;
-        } else if c == '/' {
-        } else {
+        }
+
+        if file < 8 {
             return Err(anyhow!(
-                "Invalid character '{c}' at position {index} in FEN"
+                "Rank {} of the FEN has only {file} files",
+                rank + 1
             ));
         }
+    }
+
+    Ok((w_pieces, b_pieces, promoted))
+}
+
+/// Every side needs exactly one king, except in the variants
+/// where kings can be captured or blown up.
+fn check_kings(w_pieces: &SideMap, b_pieces: &SideMap, variant: Variant) -> Result<()> {
+    if matches!(variant, Variant::Antichess | Variant::Atomic) {
+        return Ok(());
+    }
 
-        if file == 8 && rank > 0 {
-            file = 0;
-            rank -= 1;
+    for (pieces, side) in [(w_pieces, "White"), (b_pieces, "Black")] {
+        let kings = pieces.get_board(PieceType::King).count();
+        if kings != 1 {
+            return Err(anyhow!("{side} has {kings} kings instead of one"));
         }
     }
 
-    Ok((w_pieces, b_pieces))
+    Ok(())
 }
 
-fn parse_castling(fen: String) -> Result<(Castling, Castling)> {
+/// Reads standard, Shredder-FEN (HAha) and X-FEN castling fields.
+/// K and Q stand for the outermost rook on that side of the king,
+/// a file letter names the rook directly.
+fn parse_castling(
+    fen: String,
+    w_pieces: &SideMap,
+    b_pieces: &SideMap,
+) -> Result<(Castling, Castling)> {
+    let mut w_castling = Castling::default();
+    let mut b_castling = Castling::default();
+
     if fen.as_str() == "-" {
-        return Ok((
-            Castling {
-                king_side: false,
-                queen_side: false,
-            },
-            Castling {
-                king_side: false,
-                queen_side: false,
-            },
-        ));
+        return Ok((w_castling, b_castling));
     }
 
-    let w_castling = Castling {
-        king_side: fen.as_str().contains('K'),
-        queen_side: fen.as_str().contains('Q'),
-    };
+    for c in fen.chars() {
+        let (castling, pieces, rank) = match c.is_ascii_uppercase() {
+            true => (&mut w_castling, w_pieces, 0),
+            false => (&mut b_castling, b_pieces, 7),
+        };
 
-    let b_castling = Castling {
-        king_side: fen.as_str().contains('k'),
-        queen_side: fen.as_str().contains('q'),
-    };
+        let king = (0..8).find(|f| pieces.get(PieceType::King, *f, rank) == Some(true));
+        let rooks: Vec<u32> = (0..8)
+            .filter(|f| pieces.get(PieceType::Rook, *f, rank) == Some(true))
+            .collect();
+
+        // Without a rook to point at, K and Q keep their standard meaning
+        match c.to_ascii_lowercase() {
+            'k' => {
+                let rook = rooks.iter().rev().find(|f| king.is_none_or(|k| **f > k));
+                castling.king_side = Some(rook.copied().unwrap_or(7));
+            }
+            'q' => {
+                let rook = rooks.iter().find(|f| king.is_none_or(|k| **f < k));
+                castling.queen_side = Some(rook.copied().unwrap_or(0));
+            }
+            f @ 'a'..='h' => {
+                let file = f as u32 - 'a' as u32;
+                match king {
+                    Some(k) if file > k => castling.king_side = Some(file),
+                    Some(k) if file < k => castling.queen_side = Some(file),
+                    _ => {
+                        return Err(anyhow!(
+                            "Castling right '{c}' without a king to castle with"
+                        ))
+                    }
+                }
+            }
+            _ => return Err(anyhow!("Invalid castling right '{c}' in FEN")),
+        }
+    }
 
     Ok((w_castling, b_castling))
 }
 
+/// Whether the castling rights only make sense in Chess960.
+fn is_chess960(castling: &Castling, pieces: &SideMap, rank: u32) -> bool {
+    if !castling.king_side() && !castling.queen_side() {
+        return false;
+    }
+
+    pieces.get(PieceType::King, 4, rank) != Some(true)
+        || castling.king_side_rook().is_some_and(|f| f != 7)
+        || castling.queen_side_rook().is_some_and(|f| f != 0)
+}
+
 pub fn from_fen(fen: String) -> Result<Position> {
-    let fen: Vec<&str> = fen.as_str().split(' ').collect();
+    from_fen_variant(fen, Variant::Standard)
+}
+
+/// Parses a FEN for a game played by the rules of variant. Three-check
+/// positions may carry a check counter, either as 3+3 before the half move
+/// clock or as +0+0 at the end.
+pub fn from_fen_variant(fen: String, variant: Variant) -> Result<Position> {
+    let text = fen.trim();
+    let mut fen: Vec<&str> = text.split_whitespace().collect();
+
+    let mut checks = (0, 0);
+    if let Some(i) = fen.iter().skip(4).position(|f| f.contains('+')) {
+        if variant != Variant::ThreeCheck {
+            return Err(anyhow!(
+                "Check counters in FEN are only used in Three-check"
+            ));
+        }
+        checks = variant::parse_checks(fen.remove(i + 4))?;
+    }
+
+    // The full move number isn't used, everything up to the half move clock is
+    if fen.len() < 5 {
+        return Err(anyhow!(
+            "FEN '{text}' needs the board, side to move, castling, en passant and half move clock fields"
+        ));
+    }
+
+    // Crazyhouse pockets follow the board, e.g. RNBQKBNR[Qp]
+    let (pieces, pockets) = match fen[0].split_once('[') {
+        Some((pieces, pockets)) => {
+            let pockets = pockets
+                .strip_suffix(']')
+                .ok_or_else(|| anyhow!("Pocket in FEN is missing its ']'"))?;
+            (pieces, Some(pockets))
+        }
+        None => (fen[0], None),
+    };
+    if pockets.is_some() && variant != Variant::Crazyhouse {
+        return Err(anyhow!("Pockets in FEN are only used in Crazyhouse"));
+    }
+    let (w_pocket, b_pocket) = match pockets {
+        Some(pockets) => crazyhouse::parse_pockets(pockets)?,
+        None => (Pocket::default(), Pocket::default()),
+    };
 
-    let pieces = fen[0].to_string();
-    let (w_pieces, b_pieces) = parse_pieces(pieces)?;
+    let (w_pieces, b_pieces, mut promoted) = parse_pieces(pieces.to_string())?;
+    check_kings(&w_pieces, &b_pieces, variant)?;
+    if variant != Variant::Crazyhouse {
+        promoted = BitBoard::new();
+    }
     let w_pieces_all = w_pieces.combine();
     let b_pieces_all = b_pieces.combine();
 
@@ -83,24 +211,20 @@ pub fn from_fen(fen: String) -> Result<Position> {
     };
 
     let castling = fen[2].to_string();
-    let (w_castling, b_castling) = parse_castling(castling)?;
+    let (mut w_castling, mut b_castling) = parse_castling(castling, &w_pieces, &b_pieces)?;
+    if variant == Variant::Antichess {
+        w_castling = Castling::default();
+        b_castling = Castling::default();
+    }
+    let chess960 = is_chess960(&w_castling, &w_pieces, 0) || is_chess960(&b_castling, &b_pieces, 7);
 
     let en_passant = match fen[3] {
         "-" => None,
-        _ => {
-            let sqr = Square::from_str(fen[3]);
-            if let Ok(s) = sqr {
-                Some(s)
-            } else {
-                None
-            }
-        }
+        _ => Square::from_str(fen[3]).ok(),
     };
 
     let halfturn: usize = fen[4].parse()?;
 
-    //TODO: Check gamestate.
-
     let mut pos = Position {
         side,
         halfturn,
@@ -113,14 +237,24 @@ pub fn from_fen(fen: String) -> Result<Position> {
         b_pieces,
         history: vec![],
         legal_moves: vec![],
+        chess960,
+        variant,
+        w_checks: checks.0,
+        b_checks: checks.1,
+        w_pocket,
+        b_pocket,
+        promoted,
         gamestate: GameState::Normal,
         w_attacks_all: BitBoard::new(),
         w_attacks: SideMap::new(),
         b_attacks_all: BitBoard::new(),
         b_attacks: SideMap::new(),
+        key: 0,
     };
 
+    pos.key = pos.compute_key();
     pos.update_attack_maps();
+    pos.update_gamestate();
 
     Ok(pos)
 }
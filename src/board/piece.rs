@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 use super::*;
 use anyhow::{anyhow, Result};
 
@@ -14,6 +12,26 @@ pub enum PieceType {
 }
 
 impl PieceType {
+    pub const ALL: [PieceType; 6] = [
+        PieceType::King,
+        PieceType::Queen,
+        PieceType::Rook,
+        PieceType::Bishop,
+        PieceType::Knight,
+        PieceType::Pawn,
+    ];
+
+    pub fn index(self) -> usize {
+        match self {
+            PieceType::King => 0,
+            PieceType::Queen => 1,
+            PieceType::Rook => 2,
+            PieceType::Bishop => 3,
+            PieceType::Knight => 4,
+            PieceType::Pawn => 5,
+        }
+    }
+
     pub fn from_char(c: char) -> Result<(Self, Color)> {
         use PieceType as Ptype;
 
@@ -33,7 +51,7 @@ impl PieceType {
         }
     }
 
-    pub fn to_char(&self, c: Color) -> char {
+    pub fn to_char(self, c: Color) -> char {
         let out = match self {
             PieceType::King => 'k',
             PieceType::Queen => 'q',
@@ -163,9 +181,9 @@ fn get_bishop_attack_map(b: &BitBoard, friendly: &BitBoard, opposing: &BitBoard)
                 for i in 1..8 - cmp::max(rank, file) {
                     out.set(file + i, rank + i);
 
-                    if friendly.get(file + i, rank + i) == Some(true) {
-                        break;
-                    } else if opposing.get(file + i, rank + i) == Some(true) {
+                    if friendly.get(file + i, rank + i) == Some(true)
+                        || opposing.get(file + i, rank + i) == Some(true)
+                    {
                         break;
                     }
                 }
@@ -174,9 +192,9 @@ fn get_bishop_attack_map(b: &BitBoard, friendly: &BitBoard, opposing: &BitBoard)
                 for i in 1..=cmp::min(file, 7 - rank) {
                     out.set(file - i, rank + i);
 
-                    if friendly.get(file - i, rank + i) == Some(true) {
-                        break;
-                    } else if opposing.get(file - i, rank + i) == Some(true) {
+                    if friendly.get(file - i, rank + i) == Some(true)
+                        || opposing.get(file - i, rank + i) == Some(true)
+                    {
                         break;
                     }
                 }
@@ -185,9 +203,9 @@ fn get_bishop_attack_map(b: &BitBoard, friendly: &BitBoard, opposing: &BitBoard)
                 for i in 1..=cmp::min(file, rank) {
                     out.set(file - i, rank - i);
 
-                    if friendly.get(file - i, rank - i) == Some(true) {
-                        break;
-                    } else if opposing.get(file - i, rank - i) == Some(true) {
+                    if friendly.get(file - i, rank - i) == Some(true)
+                        || opposing.get(file - i, rank - i) == Some(true)
+                    {
                         break;
                     }
                 }
@@ -196,9 +214,9 @@ fn get_bishop_attack_map(b: &BitBoard, friendly: &BitBoard, opposing: &BitBoard)
                 for i in 1..=cmp::min(7 - file, rank) {
                     out.set(file + i, rank - i);
 
-                    if friendly.get(file + i, rank - i) == Some(true) {
-                        break;
-                    } else if opposing.get(file + i, rank - i) == Some(true) {
+                    if friendly.get(file + i, rank - i) == Some(true)
+                        || opposing.get(file + i, rank - i) == Some(true)
+                    {
                         break;
                     }
                 }
@@ -219,9 +237,7 @@ fn get_rook_attack_map(b: &BitBoard, friendly: &BitBoard, opposing: &BitBoard) -
                 for x in (0..file).rev() {
                     out.set(x, rank);
 
-                    if friendly.get(x, rank) == Some(true) {
-                        break;
-                    } else if opposing.get(x, rank) == Some(true) {
+                    if friendly.get(x, rank) == Some(true) || opposing.get(x, rank) == Some(true) {
                         break;
                     }
                 }
@@ -229,9 +245,7 @@ fn get_rook_attack_map(b: &BitBoard, friendly: &BitBoard, opposing: &BitBoard) -
                 for x in (file + 1)..8 {
                     out.set(x, rank);
 
-                    if friendly.get(x, rank) == Some(true) {
-                        break;
-                    } else if opposing.get(x, rank) == Some(true) {
+                    if friendly.get(x, rank) == Some(true) || opposing.get(x, rank) == Some(true) {
                         break;
                     }
                 }
@@ -250,9 +264,7 @@ fn get_rook_attack_map(b: &BitBoard, friendly: &BitBoard, opposing: &BitBoard) -
                 for y in (rank + 1)..8 {
                     out.set(file, y);
 
-                    if friendly.get(file, y) == Some(true) {
-                        break;
-                    } else if opposing.get(file, y) == Some(true) {
+                    if friendly.get(file, y) == Some(true) || opposing.get(file, y) == Some(true) {
                         break;
                     }
                 }
@@ -297,12 +309,21 @@ fn get_king_attack_map(b: &BitBoard) -> BitBoard {
     out
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Color {
     Black,
     White,
 }
 
+impl Color {
+    pub fn opposite(self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
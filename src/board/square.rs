@@ -1,6 +1,5 @@
-#![allow(dead_code)]
-
 use anyhow::{anyhow, Result};
+use std::fmt::Display;
 
 ///```
 ///#[derive(Debug, Clone, PartialEq)]
@@ -9,7 +8,7 @@ use anyhow::{anyhow, Result};
 ///    rank: usize,
 ///}
 ///```
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Square {
     pub file: u32,
     pub rank: u32,
@@ -17,6 +16,7 @@ pub struct Square {
 
 impl Square {
     //TODO: Proper error handling with anyhow
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self> {
         let rank: char;
         let file: char;
@@ -33,19 +33,36 @@ impl Square {
             return Err(anyhow!("Square string '{s}' is too short"));
         }
 
-        if (rank as usize) < 0x39 && (rank as usize) > 0x30 {
-            if (file as usize) < 0x69 && (file as usize) > 0x60 {
-                return Ok(Square {
-                    file: (file as u32) - ('a' as u32),
-                    rank: (rank as u32) - ('1' as u32),
-                });
-            }
+        if (rank as usize) < 0x39
+            && (rank as usize) > 0x30
+            && (file as usize) < 0x69
+            && (file as usize) > 0x60
+        {
+            return Ok(Square {
+                file: (file as u32) - ('a' as u32),
+                rank: (rank as u32) - ('1' as u32),
+            });
         }
 
         Err(anyhow!("Invalid square '{s}'"))
     }
 }
 
+impl std::str::FromStr for Square {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Square::from_str(s)
+    }
+}
+
+impl Display for Square {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let file = char::from(b'a' + self.file as u8);
+        write!(f, "{}{}", file, self.rank + 1)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,5 +72,9 @@ mod tests {
         assert!(Square::from_str("a8").is_ok());
         assert!(Square::from_str("a9").is_err());
         assert!(Square::from_str("").is_err());
+
+        assert_eq!(Square::from_str("e4").unwrap().to_string(), "e4");
+        assert_eq!(Square::from_str("h8").unwrap().to_string(), "h8");
+        assert_eq!("c3".parse::<Square>().unwrap(), Square { file: 2, rank: 2 });
     }
 }
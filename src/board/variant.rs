@@ -0,0 +1,299 @@
+use super::movegen::Move;
+use super::piece::{Color, PieceType};
+use super::Position;
+use anyhow::{anyhow, Result};
+use std::fmt::Display;
+
+/// Rules the game is played by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    Standard,
+    /// A king reaching d4, e4, d5 or e5 wins.
+    KingOfTheHill,
+    /// Giving check for the third time wins.
+    ThreeCheck,
+    /// Captures are forced, the king is an ordinary piece
+    /// and the side that loses all its pieces wins.
+    Antichess,
+    /// Captured pieces change sides and can be dropped back onto the board.
+    Crazyhouse,
+    /// Captures blow up every piece but pawns around the capture square,
+    /// and blowing up the other king wins.
+    Atomic,
+}
+
+/// d4, e4, d5 and e5.
+const HILL: [(u32, u32); 4] = [(3, 3), (4, 3), (3, 4), (4, 4)];
+
+/// Checks that win Three-check.
+pub const CHECKS_TO_WIN: u32 = 3;
+
+const STANDARD_PROMOTIONS: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+const ANTICHESS_PROMOTIONS: [PieceType; 5] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::King,
+];
+
+impl Variant {
+    pub const ALL: [Variant; 6] = [
+        Variant::Standard,
+        Variant::KingOfTheHill,
+        Variant::ThreeCheck,
+        Variant::Antichess,
+        Variant::Crazyhouse,
+        Variant::Atomic,
+    ];
+
+    /// Name used by the UCI_Variant option.
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "chess",
+            Variant::KingOfTheHill => "kingofthehill",
+            Variant::ThreeCheck => "3check",
+            Variant::Antichess => "antichess",
+            Variant::Crazyhouse => "crazyhouse",
+            Variant::Atomic => "atomic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Variant> {
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            "giveaway" => return Ok(Variant::Antichess),
+            "threecheck" => return Ok(Variant::ThreeCheck),
+            _ => (),
+        }
+
+        Variant::ALL
+            .into_iter()
+            .find(|v| v.name() == name)
+            .ok_or_else(|| anyhow!("Unknown variant '{name}'"))
+    }
+
+    /// Pieces a pawn can promote to.
+    pub fn promotions(self) -> &'static [PieceType] {
+        match self {
+            Variant::Antichess => &ANTICHESS_PROMOTIONS,
+            _ => &STANDARD_PROMOTIONS,
+        }
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Checks given by white and black, either from a field like 3+3 with the
+/// checks each side has left or one like +1+0 with the checks given.
+pub(crate) fn parse_checks(field: &str) -> Result<(u32, u32)> {
+    let (given, rest) = match field.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, field),
+    };
+
+    let invalid = || anyhow!("Invalid check counter '{field}' in FEN");
+    let (w, b) = rest.split_once('+').ok_or_else(invalid)?;
+    let w: u32 = w.parse().map_err(|_| invalid())?;
+    let b: u32 = b.parse().map_err(|_| invalid())?;
+    if w > CHECKS_TO_WIN || b > CHECKS_TO_WIN {
+        return Err(invalid());
+    }
+
+    if given {
+        Ok((w, b))
+    } else {
+        Ok((CHECKS_TO_WIN - w, CHECKS_TO_WIN - b))
+    }
+}
+
+impl Position {
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Checks c has given so far, only counted in Three-check.
+    pub fn checks(&self, c: Color) -> u32 {
+        match c {
+            Color::White => self.w_checks,
+            Color::Black => self.b_checks,
+        }
+    }
+
+    /// The side that has won by a rule of the variant rather than by mate.
+    /// Antichess is decided by running out of moves, see update_gamestate.
+    pub(crate) fn variant_winner(&self) -> Option<Color> {
+        match self.variant {
+            Variant::KingOfTheHill => [Color::White, Color::Black].into_iter().find(|c| {
+                let kings = self.pieces(*c).get_board(PieceType::King);
+                HILL.iter().any(|(f, r)| kings.get(*f, *r) == Some(true))
+            }),
+            Variant::ThreeCheck => [Color::White, Color::Black]
+                .into_iter()
+                .find(|c| self.checks(*c) >= CHECKS_TO_WIN),
+            Variant::Atomic => self.atomic_winner(),
+            Variant::Standard | Variant::Antichess | Variant::Crazyhouse => None,
+        }
+    }
+
+    /// Antichess moves: everything the pieces can do, but only captures
+    /// when there is one.
+    pub(crate) fn antichess_moves(&self) -> Vec<Move> {
+        let moves = self.pseudo_legal_moves();
+        if moves.iter().any(|m| m.change.captured.is_some()) {
+            moves
+                .into_iter()
+                .filter(|m| m.change.captured.is_some())
+                .collect()
+        } else {
+            moves
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::notation::IllegalMove;
+    use super::super::parse;
+    use super::super::GameState;
+    use super::*;
+
+    fn position(fen: &str, variant: Variant) -> Position {
+        parse::from_fen_variant(fen.to_string(), variant).unwrap()
+    }
+
+    fn perft(fen: &str, variant: Variant, depth: u32) -> u64 {
+        let mut pos = position(fen, variant);
+        let before = pos.clone();
+        let nodes = pos.perft(depth);
+
+        assert_eq!(pos, before);
+        nodes
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn names() {
+        for v in Variant::ALL {
+            assert_eq!(Variant::from_name(v.name()).unwrap(), v);
+        }
+        assert_eq!(
+            Variant::from_name("KingOfTheHill").unwrap(),
+            Variant::KingOfTheHill
+        );
+        assert!(Variant::from_name("horde").is_err());
+    }
+
+    #[test]
+    fn perft_king_of_the_hill() {
+        assert_eq!(perft(START, Variant::KingOfTheHill, 3), 8902);
+
+        // Kd4 and Ke4 end the game, bare kings are no draw
+        let fen = "8/8/8/8/8/3K4/8/k7 w - - 0 1";
+        assert_eq!(perft(fen, Variant::KingOfTheHill, 1), 8);
+        assert_eq!(perft(fen, Variant::KingOfTheHill, 2), 15);
+        assert_eq!(perft(fen, Variant::Standard, 2), 21);
+
+        let mut pos = position(fen, Variant::KingOfTheHill);
+        pos.play_san("Kd4").unwrap();
+        assert_eq!(pos.gamestate(), &GameState::Won(Color::White));
+        assert!(pos.legal_moves().is_empty());
+        assert_eq!(pos.play_san("Kb1"), Err(IllegalMove::GameOver));
+    }
+
+    #[test]
+    fn perft_three_check() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1";
+        assert_eq!(perft(start, Variant::ThreeCheck, 3), 8902);
+
+        // White has given two checks, Rb8+ and Rh2+ win on the spot
+        let fen = "7k/8/8/8/8/8/1R6/K7 w - - 0 1 +2+0";
+        assert_eq!(perft(fen, Variant::ThreeCheck, 1), 16);
+        assert_eq!(perft(fen, Variant::ThreeCheck, 2), 38);
+        let fen = "7k/8/8/8/8/8/1R6/K7 w - - 0 1 +0+0";
+        assert_eq!(perft(fen, Variant::ThreeCheck, 2), 42);
+    }
+
+    #[test]
+    fn check_counter() {
+        // Checks left before the half move clock, or checks given at the end
+        let left = position("7k/8/8/8/8/8/1R6/K7 w - - 1+3 0 1", Variant::ThreeCheck);
+        let given = position("7k/8/8/8/8/8/1R6/K7 w - - 0 1 +2+0", Variant::ThreeCheck);
+        assert_eq!(left, given);
+        assert_eq!(given.checks(Color::White), 2);
+        assert_eq!(given.checks(Color::Black), 0);
+
+        let mut pos = given.clone();
+        pos.play_san("Rh2").unwrap();
+        assert_eq!(pos.checks(Color::White), 3);
+        assert_eq!(pos.gamestate(), &GameState::Won(Color::White));
+        pos.undo();
+        assert_eq!(pos, given);
+
+        // A third check given earlier has already decided the game
+        let pos = position("7k/8/8/8/8/8/1R6/K7 w - - 0 1 +3+0", Variant::ThreeCheck);
+        assert_eq!(pos.gamestate(), &GameState::Won(Color::White));
+
+        let fen = |s: &str| parse::from_fen_variant(s.to_string(), Variant::ThreeCheck);
+        assert!(fen("7k/8/8/8/8/8/1R6/K7 w - - 0 1 +4+0").is_err());
+        assert!(fen("7k/8/8/8/8/8/1R6/K7 w - - 0 1 +a+0").is_err());
+        assert!(parse::from_fen("7k/8/8/8/8/8/1R6/K7 w - - 0 1 +2+0".to_string()).is_err());
+    }
+
+    #[test]
+    fn perft_antichess() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1";
+        assert_eq!(perft(start, Variant::Antichess, 1), 20);
+        assert_eq!(perft(start, Variant::Antichess, 2), 400);
+        assert_eq!(perft(start, Variant::Antichess, 3), 8067);
+
+        // The king has to take, and can walk next to the other one
+        let fen = "8/8/8/8/8/8/3p4/4K2k w - - 0 1";
+        assert_eq!(perft(fen, Variant::Antichess, 1), 1);
+        assert_eq!(perft(fen, Variant::Antichess, 2), 3);
+
+        // Promotion to a king
+        let fen = "8/P7/8/8/8/8/8/7k w - - 0 1";
+        assert_eq!(perft(fen, Variant::Antichess, 1), 5);
+        assert_eq!(perft(fen, Variant::Antichess, 2), 15);
+    }
+
+    #[test]
+    fn antichess_rules() {
+        // Castling rights are ignored
+        let pos = position(START, Variant::Antichess);
+        assert_eq!(pos.castling(Color::White), Default::default());
+
+        let mut pos = position("8/8/8/8/8/8/3p4/4K3 w - - 0 1", Variant::Antichess);
+        assert_eq!(pos.play_uci("e1f1"), Err(IllegalMove::MustCapture));
+        pos.play_san("Kxd2").unwrap();
+        assert_eq!(pos.gamestate(), &GameState::Won(Color::Black));
+
+        // Being stalemated wins as well
+        let pos = position("8/8/8/8/8/p7/P7/8 w - - 0 1", Variant::Antichess);
+        assert_eq!(pos.gamestate(), &GameState::Won(Color::White));
+
+        // The king can be left attacked, and even taken
+        let mut pos = position("4k3/8/8/8/8/8/4r3/4K3 w - - 0 1", Variant::Antichess);
+        assert!(!pos.is_in_check(Color::White));
+        pos.play_san("Kxe2").unwrap();
+        assert_eq!(pos.gamestate(), &GameState::Normal);
+
+        let mut pos = position("8/P7/8/8/8/8/8/7k w - - 0 1", Variant::Antichess);
+        assert!(pos.play_san("a8=K").is_ok());
+        let mut pos = position("8/P7/8/8/8/8/8/K6k w - - 0 1", Variant::Standard);
+        assert!(matches!(pos.play_san("a8=K"), Err(IllegalMove::Invalid(_))));
+    }
+}
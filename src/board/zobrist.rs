@@ -0,0 +1,223 @@
+use super::piece::{Color, PieceType};
+use super::{Castling, Position};
+
+// Pockets hold at most this many pieces of a kind as far as the key goes
+const MAX_POCKET: usize = 31;
+
+struct Keys {
+    pieces: [[[u64; 64]; 6]; 2],
+    side: u64,
+    /// Castling with the rook on a file, by color and king or queen side.
+    castling: [[[u64; 8]; 2]; 2],
+    en_passant: [u64; 8],
+    pocket: [[[u64; MAX_POCKET + 1]; 6]; 2],
+    checks: [[u64; 4]; 2],
+    promoted: [u64; 64],
+}
+
+/// SplitMix64, good enough to fill the tables at compile time.
+const fn next(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn generate() -> Keys {
+    let mut state = 0x5EED;
+    let mut keys = Keys {
+        pieces: [[[0; 64]; 6]; 2],
+        side: 0,
+        castling: [[[0; 8]; 2]; 2],
+        en_passant: [0; 8],
+        pocket: [[[0; MAX_POCKET + 1]; 6]; 2],
+        checks: [[0; 4]; 2],
+        promoted: [0; 64],
+    };
+
+    let mut c = 0;
+    while c < 2 {
+        let mut p = 0;
+        while p < 6 {
+            let mut i = 0;
+            while i < 64 {
+                keys.pieces[c][p][i] = next(&mut state);
+                i += 1;
+            }
+            // No pieces in hand adds nothing to the key
+            let mut n = 1;
+            while n <= MAX_POCKET {
+                keys.pocket[c][p][n] = next(&mut state);
+                n += 1;
+            }
+            p += 1;
+        }
+
+        let mut file = 0;
+        while file < 8 {
+            keys.castling[c][0][file] = next(&mut state);
+            keys.castling[c][1][file] = next(&mut state);
+            file += 1;
+        }
+
+        let mut n = 1;
+        while n < 4 {
+            keys.checks[c][n] = next(&mut state);
+            n += 1;
+        }
+        c += 1;
+    }
+
+    let mut i = 0;
+    while i < 64 {
+        keys.promoted[i] = next(&mut state);
+        i += 1;
+    }
+    let mut file = 0;
+    while file < 8 {
+        keys.en_passant[file] = next(&mut state);
+        file += 1;
+    }
+    keys.side = next(&mut state);
+
+    keys
+}
+
+static KEYS: Keys = generate();
+
+fn color_index(c: Color) -> usize {
+    match c {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// Key of a piece of color c on a square.
+pub(crate) fn piece(c: Color, p: PieceType, file: u32, rank: u32) -> u64 {
+    KEYS.pieces[color_index(c)][p.index()][(rank * 8 + file) as usize]
+}
+
+fn castling(c: Color, castling: Castling) -> u64 {
+    let keys = &KEYS.castling[color_index(c)];
+    let king_side = castling.king_side.map_or(0, |f| keys[0][f as usize]);
+    let queen_side = castling.queen_side.map_or(0, |f| keys[1][f as usize]);
+
+    king_side ^ queen_side
+}
+
+impl Position {
+    /// Hash key of the position, the same for positions that only
+    /// differ in the moves that led to them.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Everything in the key apart from the pieces on the board,
+    /// which put_piece and remove_piece keep up to date.
+    pub(crate) fn state_key(&self) -> u64 {
+        let mut key = castling(Color::White, self.w_castling)
+            ^ castling(Color::Black, self.b_castling)
+            ^ KEYS.checks[0][self.w_checks.min(3) as usize]
+            ^ KEYS.checks[1][self.b_checks.min(3) as usize];
+
+        if self.side == Color::Black {
+            key ^= KEYS.side;
+        }
+        if let Some(ep) = self.en_passant {
+            key ^= KEYS.en_passant[ep.file as usize];
+        }
+
+        for (c, pocket) in [(0, &self.w_pocket), (1, &self.b_pocket)] {
+            for (p, n) in pocket.iter() {
+                key ^= KEYS.pocket[c][p.index()][(n as usize).min(MAX_POCKET)];
+            }
+        }
+        for sqr in self.promoted.squares() {
+            key ^= KEYS.promoted[(sqr.rank * 8 + sqr.file) as usize];
+        }
+
+        key
+    }
+
+    /// The key worked out from scratch.
+    pub(crate) fn compute_key(&self) -> u64 {
+        let mut key = self.state_key();
+
+        for c in [Color::White, Color::Black] {
+            for (p, board) in self.pieces(c).iter() {
+                for sqr in board.squares() {
+                    key ^= piece(c, p, sqr.file, sqr.rank);
+                }
+            }
+        }
+
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::super::variant::Variant;
+
+    #[test]
+    fn incremental() {
+        let fens = [
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                Variant::Standard,
+            ),
+            (
+                "r1bqkbnr/pppppppp/2n5/8/8/2N5/PPPPPPPP/R1BQKBNR[Pp] w KQkq - 0 1",
+                Variant::Crazyhouse,
+            ),
+            (
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+                Variant::Atomic,
+            ),
+        ];
+
+        // Every move and its take back keep the key as if worked out from scratch
+        for (fen, variant) in fens {
+            let mut pos = parse::from_fen_variant(fen.to_string(), variant).unwrap();
+            let start = pos.key();
+            assert_eq!(start, pos.compute_key());
+
+            for m in pos.generate_legal_moves() {
+                pos.change_board(m);
+                assert_eq!(pos.key(), pos.compute_key(), "{m} in {fen}");
+                for r in pos.generate_legal_moves() {
+                    pos.change_board(r);
+                    assert_eq!(pos.key(), pos.compute_key(), "{m} {r} in {fen}");
+                    pos.reverse_last_change();
+                }
+                pos.reverse_last_change();
+                assert_eq!(pos.key(), start);
+            }
+        }
+    }
+
+    #[test]
+    fn transpositions() {
+        let mut a = parse::from_fen(crate::pgn::START_FEN.to_string()).unwrap();
+        let mut b = a.clone();
+        for m in ["g1f3", "g8f6", "b1c3"] {
+            a.play_uci(m).unwrap();
+        }
+        for m in ["b1c3", "g8f6", "g1f3"] {
+            b.play_uci(m).unwrap();
+        }
+        assert_eq!(a.key(), b.key());
+
+        let fen = "rnbqkb1r/pppppppp/5n2/8/8/2N2N2/PPPPPPPP/R1BQKB1R b KQkq - 3 2";
+        let c = parse::from_fen(fen.to_string()).unwrap();
+        assert_eq!(a.key(), c.key());
+
+        // The same pieces with the other side to move or other castling rights
+        let c = parse::from_fen(fen.replace(" b ", " w ")).unwrap();
+        assert_ne!(a.key(), c.key());
+        let c = parse::from_fen(fen.replace("KQkq", "Qkq")).unwrap();
+        assert_ne!(a.key(), c.key());
+    }
+}
@@ -0,0 +1,538 @@
+mod keys;
+
+use crate::board::movegen::Move;
+use crate::board::piece::{Color, PieceType};
+use crate::board::Position;
+use crate::pgn::Game;
+use crate::uci::{parse_check, UciOption};
+use anyhow::{anyhow, Result};
+use keys::RANDOM64;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ENTRY_SIZE: usize = 16;
+
+const CASTLING: usize = 768;
+const EN_PASSANT: usize = 772;
+const TURN: usize = 780;
+
+/// Polyglot hash of the position. Polyglot books are keyed by these,
+/// so they can't share the engine's own hash keys.
+pub fn polyglot_key(pos: &Position) -> u64 {
+    let mut key = 0;
+
+    for c in [Color::White, Color::Black] {
+        // Polyglot counts the pieces from pawn up to king
+        for (p, board) in pos.pieces(c).iter() {
+            let kind = 2 * (5 - p.index()) + (c == Color::White) as usize;
+            for sqr in board.squares() {
+                key ^= RANDOM64[64 * kind + 8 * sqr.rank as usize + sqr.file as usize];
+            }
+        }
+    }
+
+    let rights = [
+        pos.w_castling.king_side(),
+        pos.w_castling.queen_side(),
+        pos.b_castling.king_side(),
+        pos.b_castling.queen_side(),
+    ];
+    for (i, right) in rights.into_iter().enumerate() {
+        if right {
+            key ^= RANDOM64[CASTLING + i];
+        }
+    }
+
+    // Only counts when a pawn could actually take en passant
+    if let Some(ep) = pos.en_passant {
+        let rank = match pos.side {
+            Color::White => ep.rank - 1,
+            Color::Black => ep.rank + 1,
+        };
+        let pawns = pos.pieces(pos.side).get_board(PieceType::Pawn);
+        let beside = |file: u32| pawns.get(file, rank) == Some(true);
+
+        if (ep.file > 0 && beside(ep.file - 1)) || beside(ep.file + 1) {
+            key ^= RANDOM64[EN_PASSANT + ep.file as usize];
+        }
+    }
+
+    if pos.side == Color::White {
+        key ^= RANDOM64[TURN];
+    }
+
+    key
+}
+
+fn promotion_bits(p: Option<PieceType>) -> u16 {
+    match p {
+        Some(PieceType::Knight) => 1,
+        Some(PieceType::Bishop) => 2,
+        Some(PieceType::Rook) => 3,
+        Some(PieceType::Queen) => 4,
+        _ => 0,
+    }
+}
+
+/// Packs a move the way Polyglot stores it, castling as the king taking its rook.
+pub fn encode_move(m: &Move) -> u16 {
+    let to_file = m.change.castling_rook.unwrap_or(m.to.file);
+
+    to_file as u16
+        | (m.to.rank as u16) << 3
+        | (m.from.file as u16) << 6
+        | (m.from.rank as u16) << 9
+        | promotion_bits(m.promotion) << 12
+}
+
+/// Finds the legal move matching a Polyglot move, None if there isn't one.
+pub fn decode_move(pos: &mut Position, mv: u16) -> Option<Move> {
+    pos.generate_legal_moves()
+        .into_iter()
+        .find(|m| encode_move(m) == mv & 0x7fff)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub key: u64,
+    pub mv: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl Entry {
+    fn from_bytes(b: &[u8]) -> Entry {
+        Entry {
+            key: u64::from_be_bytes(b[0..8].try_into().unwrap()),
+            mv: u16::from_be_bytes(b[8..10].try_into().unwrap()),
+            weight: u16::from_be_bytes(b[10..12].try_into().unwrap()),
+            learn: u32::from_be_bytes(b[12..16].try_into().unwrap()),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; ENTRY_SIZE] {
+        let mut b = [0; ENTRY_SIZE];
+        b[0..8].copy_from_slice(&self.key.to_be_bytes());
+        b[8..10].copy_from_slice(&self.mv.to_be_bytes());
+        b[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        b[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        b
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Selection {
+    /// Always the move with the highest weight.
+    Best,
+    /// Random, with a chance proportional to the weight.
+    #[default]
+    Weighted,
+}
+
+/// A Polyglot opening book.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Book {
+    /// Sorted by key, then by weight from high to low.
+    entries: Vec<Entry>,
+}
+
+impl Book {
+    pub fn new(mut entries: Vec<Entry>) -> Book {
+        entries.sort_by(|a, b| a.key.cmp(&b.key).then(b.weight.cmp(&a.weight)));
+        Book { entries }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Book> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(anyhow!(
+                "Polyglot book size {} is not a multiple of {ENTRY_SIZE} bytes",
+                bytes.len()
+            ));
+        }
+
+        Ok(Book::new(
+            bytes.chunks(ENTRY_SIZE).map(Entry::from_bytes).collect(),
+        ))
+    }
+
+    pub fn open(path: &str) -> Result<Book> {
+        let bytes = fs::read(path).map_err(|e| anyhow!("Can't read book '{path}': {e}"))?;
+        Book::from_bytes(&bytes).map_err(|e| anyhow!("Invalid book '{path}': {e}"))
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for e in &self.entries {
+            out.write_all(&e.to_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self, key: u64) -> &[Entry] {
+        let start = self.entries.partition_point(|e| e.key < key);
+        let end = self.entries.partition_point(|e| e.key <= key);
+        &self.entries[start..end]
+    }
+
+    /// Legal book moves in the position with their weights.
+    pub fn moves(&self, pos: &mut Position) -> Vec<(Move, u16)> {
+        self.entries(polyglot_key(pos))
+            .iter()
+            .filter_map(|e| decode_move(pos, e.mv).map(|m| (m, e.weight)))
+            .collect()
+    }
+
+    /// Picks a book move, random is only used for weighted selection.
+    pub fn pick(&self, pos: &mut Position, selection: Selection, random: u64) -> Option<Move> {
+        let moves = self.moves(pos);
+
+        match selection {
+            Selection::Best => moves.iter().max_by_key(|(_, w)| *w).map(|(m, _)| *m),
+            Selection::Weighted => {
+                let total: u64 = moves.iter().map(|(_, w)| *w as u64).sum();
+                if total == 0 {
+                    return None;
+                }
+
+                let mut r = random % total;
+                for (m, w) in moves {
+                    if r < w as u64 {
+                        return Some(m);
+                    }
+                    r -= w as u64;
+                }
+
+                None
+            }
+        }
+    }
+}
+
+fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+
+    // One round of splitmix64 so close seeds don't give close numbers
+    let mut z = nanos.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// The OwnBook, BookFile and BookBestMove UCI options.
+#[derive(Clone, Debug, Default)]
+pub struct BookOptions {
+    pub own_book: bool,
+    pub best_move: bool,
+    pub book: Option<Book>,
+}
+
+impl UciOption for BookOptions {
+    fn uci_options(&self) -> Vec<String> {
+        vec![
+            "option name OwnBook type check default false".to_string(),
+            "option name BookFile type string default <empty>".to_string(),
+            "option name BookBestMove type check default false".to_string(),
+        ]
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<bool> {
+        if name.eq_ignore_ascii_case("OwnBook") {
+            self.own_book = parse_check("OwnBook", value)?;
+        } else if name.eq_ignore_ascii_case("BookBestMove") {
+            self.best_move = parse_check("BookBestMove", value)?;
+        } else if name.eq_ignore_ascii_case("BookFile") {
+            self.book = match value.trim() {
+                "" | "<empty>" => None,
+                path => Some(Book::open(path)?),
+            };
+        } else {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
+impl BookOptions {
+    /// A book move to play instead of searching, if the book is turned on and has one.
+    pub fn probe(&self, pos: &mut Position) -> Option<Move> {
+        let book = self.book.as_ref().filter(|_| self.own_book)?;
+        let selection = match self.best_move {
+            true => Selection::Best,
+            false => Selection::Weighted,
+        };
+
+        book.pick(pos, selection, random_seed())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+}
+
+/// Creates a Polyglot book from PGN games.
+#[derive(Clone, Debug)]
+pub struct BookBuilder {
+    /// Moves played in fewer games are left out.
+    pub min_games: u32,
+    /// Share of points the side playing the move has to score, 0.0 to 1.0.
+    pub min_win_rate: f64,
+    /// Only the first max_ply half moves of every game are used.
+    pub max_ply: usize,
+    stats: HashMap<(u64, u16), MoveStats>,
+}
+
+impl Default for BookBuilder {
+    fn default() -> BookBuilder {
+        BookBuilder {
+            min_games: 1,
+            min_win_rate: 0.0,
+            max_ply: 30,
+            stats: HashMap::new(),
+        }
+    }
+}
+
+impl BookBuilder {
+    /// Adds the main line of a game. Games without a result are skipped.
+    pub fn add_game(&mut self, game: &Game) {
+        let winner = match game.result.as_str() {
+            "1-0" => Some(Color::White),
+            "0-1" => Some(Color::Black),
+            "1/2-1/2" => None,
+            _ => return,
+        };
+
+        let mut pos = game.start.clone();
+        for node in game.moves.iter().take(self.max_ply) {
+            let stats = self
+                .stats
+                .entry((polyglot_key(&pos), encode_move(&node.mv)))
+                .or_default();
+
+            stats.games += 1;
+            match winner {
+                Some(c) if c == pos.side => stats.wins += 1,
+                Some(_) => (),
+                None => stats.draws += 1,
+            }
+
+            pos.change_board(node.mv);
+        }
+    }
+
+    /// Weights follow the usual Polyglot convention of 2 per win and 1 per draw.
+    pub fn build(&self) -> Book {
+        let entries = self
+            .stats
+            .iter()
+            .filter(|(_, s)| s.games >= self.min_games)
+            .filter(|(_, s)| {
+                let points = s.wins as f64 + s.draws as f64 / 2.0;
+                points / s.games as f64 >= self.min_win_rate
+            })
+            .map(|(&(key, mv), s)| Entry {
+                key,
+                mv,
+                weight: (2 * s.wins + s.draws).min(u16::MAX as u32) as u16,
+                learn: 0,
+            })
+            .collect();
+
+        Book::new(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::parse;
+    use crate::pgn;
+
+    fn key(fen: &str) -> u64 {
+        polyglot_key(&parse::from_fen(fen.to_string()).unwrap())
+    }
+
+    #[test]
+    fn keys() {
+        // Test positions from the Polyglot book format description
+        let positions = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                0x463b96181691fc9c,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+                0x823c9b50fd114196,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+                0x0756b94461c50fb0,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+                0x662fafb965db29d4,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                0x22a48b5a8e47ff78,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3",
+                0x652a607ca3f242c1,
+            ),
+            (
+                "rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4",
+                0x00fdd303c946bdd9,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3",
+                0x3c8123ea7b067637,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4",
+                0x5c3f9b829b279560,
+            ),
+        ];
+
+        for (fen, expected) in positions {
+            assert_eq!(key(fen), expected, "{fen}");
+        }
+    }
+
+    #[test]
+    fn moves() {
+        let mut pos =
+            parse::from_fen("r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1".to_string()).unwrap();
+
+        // Castling is stored as the king taking its rook
+        for (uci, to_file) in [("e1g1", 7), ("e1c1", 0), ("b7a8n", 0)] {
+            let m = pos.parse_uci(uci).unwrap();
+            let mv = encode_move(&m);
+
+            assert_eq!(mv & 7, to_file);
+            assert_eq!(decode_move(&mut pos, mv), Some(m));
+        }
+
+        assert_eq!(encode_move(&pos.parse_uci("b7b8q").unwrap()) >> 12, 4);
+        // e1 to h2 isn't legal
+        assert_eq!(decode_move(&mut pos, 0x10f), None);
+    }
+
+    const GAMES: &str = r#"
+[Result "1-0"]
+1. e4 e5 2. Nf3 Nc6 1-0
+
+[Result "1-0"]
+1. e4 e5 2. Nf3 d6 1-0
+
+[Result "1/2-1/2"]
+1. e4 c5 1/2-1/2
+
+[Result "0-1"]
+1. d4 d5 0-1
+
+[Result "*"]
+1. c4 *
+"#;
+
+    fn builder() -> BookBuilder {
+        let mut builder = BookBuilder::default();
+        for game in pgn::read_games(GAMES).unwrap() {
+            builder.add_game(&game);
+        }
+        builder
+    }
+
+    #[test]
+    fn build_and_pick() {
+        let book = builder().build();
+        let mut bytes = vec![];
+        book.write(&mut bytes).unwrap();
+
+        let book = Book::from_bytes(&bytes).unwrap();
+        let mut start = parse::from_fen(pgn::START_FEN.to_string()).unwrap();
+
+        let moves = book.moves(&mut start);
+        let weight = |uci: &str| {
+            moves
+                .iter()
+                .find(|(m, _)| m.to_string() == uci)
+                .map(|(_, w)| *w)
+        };
+        assert_eq!(moves.len(), 2);
+        assert_eq!(weight("e2e4"), Some(5));
+        assert_eq!(weight("d2d4"), Some(0));
+        assert_eq!(weight("c2c4"), None);
+
+        let e4 = book.pick(&mut start, Selection::Best, 0).unwrap();
+        assert_eq!(e4.to_string(), "e2e4");
+        for r in 0..10 {
+            assert_eq!(book.pick(&mut start, Selection::Weighted, r), Some(e4));
+        }
+
+        start.change_board(e4);
+        let replies = book.moves(&mut start);
+        assert_eq!(replies.len(), 2);
+
+        assert!(Book::from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn filters() {
+        let mut builder = builder();
+        builder.min_games = 2;
+        let book = builder.build();
+        // e4, e5 and Nf3 were played twice
+        assert_eq!(book.len(), 3);
+
+        builder.min_games = 1;
+        builder.min_win_rate = 0.6;
+        let book = builder.build();
+        let mut start = parse::from_fen(pgn::START_FEN.to_string()).unwrap();
+
+        // e4 scored 2.5/3, d4 lost
+        let moves = book.moves(&mut start);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].0.to_string(), "e2e4");
+    }
+
+    #[test]
+    fn options() {
+        let mut options = BookOptions::default();
+        let mut pos = parse::from_fen(pgn::START_FEN.to_string()).unwrap();
+
+        assert!(!options.set_option("Hash", "16").unwrap());
+        assert!(options.set_option("OwnBook", "maybe").is_err());
+        assert!(options
+            .set_option("BookFile", "/does/not/exist.bin")
+            .is_err());
+
+        options.book = Some(builder().build());
+        assert_eq!(options.probe(&mut pos), None);
+
+        assert!(options.set_option("OwnBook", "true").unwrap());
+        assert!(options.set_option("BookBestMove", "true").unwrap());
+        assert_eq!(options.probe(&mut pos).unwrap().to_string(), "e2e4");
+
+        assert!(options.set_option("BookFile", "<empty>").unwrap());
+        assert_eq!(options.probe(&mut pos), None);
+        assert_eq!(options.uci_options().len(), 3);
+    }
+}
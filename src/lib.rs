@@ -0,0 +1,77 @@
+//! StockCrab, a UCI chess engine, as a library.
+//!
+//! # Stable API
+//!
+//! These items are re-exported at the crate root and only change with
+//! a new minor version:
+//!
+//! - [`Position`], [`GameState`] and [`Castling`], with FEN parsing
+//!   through [`from_fen`], or [`from_fen_variant`] for the rules of a
+//!   [`Variant`], and the move generator: `generate_legal_moves`,
+//!   `pseudo_legal_moves`, `perft`, `is_in_check`
+//! - moves: [`Move`], played with `Position::play`, `play_uci`,
+//!   `play_san` or copied into a new position with `after`, which all
+//!   check them and explain an [`IllegalMove`]. Taken back with
+//!   `Position::undo`, read and written with `parse_uci`, `parse_san`,
+//!   `move_to_uci` and `move_to_san`
+//! - [`Square`], [`BitBoard`], [`PieceType`] and [`Color`]
+//! - the search: [`Search::go`] looks for the best move within the
+//!   [`Limits`] of a UCI go command and returns a [`SearchResult`],
+//!   reporting every finished iteration as an [`Info`] to a [`Listener`].
+//!   `Search::new`, `set_threads`, `set_hash`, `clear`, `stop_flag` and
+//!   the [`TimeOptions`] in `Search::time` configure it
+//!
+//! The modules below are public so the binary can use them, but anything
+//! not listed above may change at any time.
+//!
+//! ```
+//! use chess_engine::{from_fen, GameState, Info, Limits, Search};
+//!
+//! let mut pos = from_fen("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+//! let limits = Limits {
+//!     depth: Some(1),
+//!     ..Limits::default()
+//! };
+//! let result = Search::new().go(&pos, &limits, &mut |_: &Info| ());
+//! assert_eq!(pos.move_to_san(&result.best.unwrap()), "Ra8#");
+//!
+//! pos.play_san("Ra8").unwrap();
+//! assert_eq!(pos.gamestate(), &GameState::Won(chess_engine::Color::White));
+//! ```
+
+pub mod arena;
+pub mod bench;
+pub mod board;
+pub mod book;
+pub mod mate;
+pub mod pgn;
+pub mod search;
+pub mod syzygy;
+pub mod tablebase;
+pub mod testsuite;
+pub mod timeman;
+pub(crate) mod tt;
+pub mod tui;
+pub mod uci;
+pub mod xboard;
+
+pub use board::bitboard::BitBoard;
+pub use board::movegen::Move;
+pub use board::notation::IllegalMove;
+pub use board::piece::{Color, PieceType};
+pub use board::square::Square;
+pub use board::variant::Variant;
+pub use board::{Castling, GameState, Position};
+pub use search::{Listener, Search, SearchResult};
+pub use timeman::{Limits, TimeOptions};
+pub use uci::Info;
+
+/// Parses a position in Forsyth-Edwards Notation, X-FEN or Shredder-FEN.
+pub fn from_fen(fen: &str) -> anyhow::Result<Position> {
+    board::parse::from_fen(fen.to_string())
+}
+
+/// Parses a FEN for a game of the given variant, see [`Variant`].
+pub fn from_fen_variant(fen: &str, variant: Variant) -> anyhow::Result<Position> {
+    board::parse::from_fen_variant(fen.to_string(), variant)
+}
@@ -1,15 +1,233 @@
-mod board;
-mod tui;
+use anyhow::{anyhow, Context, Result};
+use chess_engine::xboard::{self, Protocol};
+use chess_engine::{
+    arena, bench, book, from_fen, mate, pgn, tablebase, testsuite, tui, uci, Color, Info, Limits,
+    Search,
+};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal, Read, Write};
+use std::process;
+use std::time::Duration;
 
-use std::io::{self, Write};
+// Milliseconds the engine thinks per move in play
+const PLAY_MOVETIME: u64 = 1000;
 
-fn main() {
+fn read_fen() -> Result<String> {
     let mut input = String::new();
-    print!("FEN: ");
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut input).unwrap();
-    println!();
+    // A GUI talking to the engine gets no prompt
+    let prompt = io::stdin().is_terminal();
+    if prompt {
+        print!("FEN: ");
+        io::stdout().flush()?;
+    }
+    io::stdin().read_line(&mut input)?;
+    if prompt {
+        println!();
+    }
+
+    Ok(input.trim().to_string())
+}
+
+fn parse_depth(depth: &str) -> Result<u32> {
+    depth
+        .parse()
+        .map_err(|_| anyhow!("Invalid depth '{depth}'"))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Err(e) = run(&args) {
+        eprintln!("Error: {e:#}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    match args.get(1).map(|s| s.as_str()) {
+        Some("eval") => {
+            let pos = from_fen(&read_fen()?)?;
+            tui::print_eval(&pos);
+        }
+        Some("attacks") => {
+            let pos = from_fen(&read_fen()?)?;
+            let style = tui::Style::detect();
+
+            println!("White attacks:");
+            let view = tui::BoardView::new(&pos).style(style);
+            print!("{}", view.overlay(*pos.attacks_all(Color::White)).render());
+            println!("Black attacks:");
+            let view = tui::BoardView::new(&pos).style(style);
+            print!("{}", view.overlay(*pos.attacks_all(Color::Black)).render());
+        }
+        Some("makebook") => {
+            let (Some(pgn_path), Some(book_path)) = (args.get(2), args.get(3)) else {
+                eprintln!("Usage: makebook <games.pgn> <book.bin> [min games] [min win rate]");
+                return Ok(());
+            };
+
+            let mut builder = book::BookBuilder::default();
+            if let Some(n) = args.get(4) {
+                builder.min_games = n
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid minimum number of games '{n}'"))?;
+            }
+            if let Some(rate) = args.get(5) {
+                builder.min_win_rate = rate
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid minimum win rate '{rate}'"))?;
+            }
+
+            let file = File::open(pgn_path).with_context(|| format!("Can't open {pgn_path}"))?;
+            // A broken game is left out of the book, the rest still count
+            for game in pgn::PgnReader::new(BufReader::new(file)) {
+                match game {
+                    Ok(game) => builder.add_game(&game),
+                    Err(e) => eprintln!("Skipping a game in {pgn_path}: {e}"),
+                }
+            }
+
+            let book = builder.build();
+            let mut file =
+                File::create(book_path).with_context(|| format!("Can't create {book_path}"))?;
+            book.write(&mut file)?;
+            println!("Wrote {} entries to {book_path}", book.len());
+        }
+        Some("tbgen") => {
+            let Some(dir) = args.get(2) else {
+                eprintln!("Usage: tbgen <directory>");
+                return Ok(());
+            };
+
+            let tb = tablebase::Tablebase::generate(&tablebase::Material::ALL)?;
+            for m in tablebase::Material::ALL {
+                let longest = tb.get(m).unwrap().max_dtm();
+                println!("{}: longest mate in {longest}", m.name());
+            }
+            tb.save(std::path::Path::new(dir))?;
+        }
+        Some("bench") => {
+            let depth = match args.get(2) {
+                Some(depth) => parse_depth(depth)?,
+                None => bench::DEFAULT_DEPTH,
+            };
+            let result = bench::search(depth)?;
+            println!("{result}");
+        }
+        Some("perft-bench") => {
+            let depth = match args.get(2) {
+                Some(depth) => parse_depth(depth)?,
+                None => bench::DEFAULT_PERFT_DEPTH,
+            };
+            let result = bench::perft(depth)?;
+            println!("{result}");
+        }
+        Some("testsuite") => {
+            let usage = "Usage: testsuite <suite.epd> [depth <plies> | time <ms>]";
+            let Some(path) = args.get(2) else {
+                eprintln!("{usage}");
+                return Ok(());
+            };
+            let limit = match (
+                args.get(3).map(|s| s.as_str()),
+                args.get(4).map(|n| n.parse()),
+            ) {
+                (None, _) => testsuite::Limit::Depth(3),
+                (Some("depth"), Some(Ok(d))) => testsuite::Limit::Depth(d as u32),
+                (Some("time"), Some(Ok(ms))) => testsuite::Limit::Time(Duration::from_millis(ms)),
+                _ => {
+                    eprintln!("{usage}");
+                    return Ok(());
+                }
+            };
+
+            let file = File::open(path).with_context(|| format!("Can't open {path}"))?;
+            let suite = testsuite::read_suite(BufReader::new(file)).context(path.clone())?;
+            let report = testsuite::run(&suite, limit, testsuite::solve);
+            println!("{report}");
+        }
+        Some("mate") => {
+            let Some(n) = args.get(2).and_then(|n| n.parse().ok()) else {
+                eprintln!("Usage: mate <moves> [checks]");
+                return Ok(());
+            };
+            let checks_only = args.get(3).is_some_and(|a| a == "checks");
+
+            let mut pos = from_fen(&read_fen()?)?;
+            let mut search = mate::MateSearch::new().checks_only(checks_only);
+            match search.find(&mut pos, n) {
+                Some(m) => println!("Mate in {}: {}", m.moves, mate::line_to_san(&pos, &m.line)),
+                None if checks_only => println!("No mate in {n} with checks only"),
+                None => println!("No mate in {n}"),
+            }
+            println!("Nodes: {}", search.nodes());
+        }
+        Some("match") => {
+            let config = match arena::MatchConfig::from_args(&args[2..]) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{e}\n{}", arena::MATCH_USAGE);
+                    return Ok(());
+                }
+            };
+            let openings = match &config.openings {
+                Some(path) => arena::read_openings(path)?,
+                None => vec![],
+            };
+
+            let spawn = |i: usize| arena::uci_player(&config.engines[i]);
+            let score = arena::run_match(&config, &openings, spawn, &mut io::stdout())?;
+            let names = [
+                config.engines[0].name.as_str(),
+                config.engines[1].name.as_str(),
+            ];
+            println!();
+            print!("{}", arena::Report::new(score, names, config.sprt));
+        }
+        Some("play") => {
+            let movetime = match args.get(2) {
+                Some(ms) => ms
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid time per move '{ms}'"))?,
+                None => PLAY_MOVETIME,
+            };
+            let limits = Limits {
+                movetime: Some(movetime),
+                ..Limits::default()
+            };
+            let mut search = Search::new();
+            let engine: tui::play::Engine =
+                Box::new(move |pos| search.go(pos, &limits, &mut |_: &Info| ()).best);
+
+            let pos = from_fen(pgn::START_FEN)?;
+            let mut session = tui::play::Session::new(pos).with_engine(Color::Black, engine);
+            session.style = tui::Style::detect();
+            session.run(io::stdin().lock(), io::stdout())?;
+        }
+        _ => {
+            let line = read_fen()?;
+            match Protocol::detect(&line) {
+                Some(Protocol::Xboard) => {
+                    let engine = xboard::search_engine(Search::new());
+                    let mut session = xboard::Session::new().with_engine(engine);
+                    let mut out = io::stdout();
+                    if session.handle(&line, &mut out)? {
+                        session.run(io::stdin().lock(), out)?;
+                    }
+                }
+                Some(Protocol::Uci) => {
+                    // The uci command that was read to tell the protocols apart comes first
+                    let first = format!("{line}\n");
+                    let input = first.as_bytes().chain(io::stdin().lock());
+                    uci::Session::new().run(input, io::stdout())?;
+                }
+                None => {
+                    let pos = from_fen(&line)?;
+                    tui::print_position(&pos);
+                }
+            }
+        }
+    }
 
-    let pos = board::parse::from_fen(input).unwrap();
-    tui::print_position(&pos);
+    Ok(())
 }
@@ -0,0 +1,286 @@
+use crate::board::movegen::Move;
+use crate::board::piece::Color;
+use crate::board::variant::Variant;
+use crate::board::Position;
+
+/// A forced mate: how many moves it takes, and the line in which the
+/// defender holds out as long as it can.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mate {
+    pub moves: u32,
+    pub line: Vec<Move>,
+}
+
+/// Search that proves or disproves forced mates. A mating move has to give
+/// check, so on the attacker's last move only checks are tried. With
+/// checks_only every move of the attacker has to be a check, which is
+/// much faster but misses mates with a quiet move.
+#[derive(Clone, Debug, Default)]
+pub struct MateSearch {
+    checks_only: bool,
+    nodes: u64,
+}
+
+impl MateSearch {
+    pub fn new() -> MateSearch {
+        MateSearch::default()
+    }
+
+    pub fn checks_only(mut self, checks_only: bool) -> MateSearch {
+        self.checks_only = checks_only;
+        self
+    }
+
+    /// Positions visited so far.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// The shortest forced mate in at most n moves for the side to move.
+    /// None proves there is none, unless checks_only is set.
+    pub fn find(&mut self, pos: &mut Position, n: u32) -> Option<Mate> {
+        let moves = (1..=n).find(|k| self.attack(pos, *k).is_some())?;
+
+        Some(Mate {
+            moves,
+            line: self.line(pos, moves),
+        })
+    }
+
+    /// Whether playing m mates in at most n moves.
+    pub fn mates_with(&mut self, pos: &mut Position, m: Move, n: u32) -> bool {
+        pos.change_board(m);
+        let lost = self.defend(pos, n);
+        pos.reverse_last_change();
+        lost
+    }
+
+    /// A move that mates in at most k moves.
+    fn attack(&mut self, pos: &mut Position, k: u32) -> Option<Move> {
+        self.nodes += 1;
+
+        // Checks first, they are the likeliest to mate
+        let mut checks = vec![];
+        let mut others = vec![];
+        for m in pos.generate_legal_moves() {
+            pos.change_board(m);
+            let check = pos.is_in_check(pos.side());
+            pos.reverse_last_change();
+
+            if check {
+                checks.push(m);
+            } else {
+                others.push(m);
+            }
+        }
+
+        // Wins in the other variants don't need a check
+        let mate_needs_check = matches!(pos.variant(), Variant::Standard | Variant::Crazyhouse);
+        if self.checks_only || (k == 1 && mate_needs_check) {
+            others.clear();
+        }
+
+        checks.into_iter().chain(others).find(|m| {
+            pos.change_board(*m);
+            let lost = self.defend(pos, k);
+            pos.reverse_last_change();
+            lost
+        })
+    }
+
+    /// Whether the side to move, the defender, is mated or gets mated
+    /// within k - 1 more moves of the attacker.
+    fn defend(&mut self, pos: &mut Position, k: u32) -> bool {
+        self.nodes += 1;
+
+        let attacker = pos.side().opposite();
+        if let Some(winner) = pos.variant_winner() {
+            return winner == attacker;
+        }
+
+        let replies = pos.generate_legal_moves();
+        if replies.is_empty() {
+            // Mate rather than stalemate. Having no moves wins Antichess.
+            return pos.variant() != Variant::Antichess && pos.is_in_check(pos.side());
+        }
+        if k <= 1 || pos.halfturn() >= 100 || pos.is_dead_draw() {
+            return false;
+        }
+
+        replies.into_iter().all(|r| {
+            pos.change_board(r);
+            let lost = self.attack(pos, k - 1).is_some();
+            pos.reverse_last_change();
+            lost
+        })
+    }
+
+    /// The line of a mate in exactly k moves, with the defence that lasts longest.
+    fn line(&mut self, pos: &mut Position, k: u32) -> Vec<Move> {
+        let m = self.attack(pos, k).expect("a mate in k moves");
+        let mut line = vec![m];
+        pos.change_board(m);
+
+        if k > 1 && pos.variant_winner().is_none() {
+            let mut longest: Option<(u32, Move)> = None;
+            for r in pos.generate_legal_moves() {
+                pos.change_board(r);
+                let j = (1..k)
+                    .find(|j| self.attack(pos, *j).is_some())
+                    .unwrap_or(k - 1);
+                pos.reverse_last_change();
+
+                if longest.is_none_or(|(most, _)| j > most) {
+                    longest = Some((j, r));
+                }
+            }
+
+            if let Some((j, r)) = longest {
+                line.push(r);
+                pos.change_board(r);
+                line.extend(self.line(pos, j));
+                pos.reverse_last_change();
+            }
+        }
+
+        pos.reverse_last_change();
+        line
+    }
+}
+
+/// The shortest forced mate in at most n moves for the side to move,
+/// None if there is none.
+pub fn find_mate(pos: &mut Position, n: u32) -> Option<Mate> {
+    MateSearch::new().find(pos, n)
+}
+
+/// A line of moves in SAN with move numbers, e.g. "1. Ra6 bxa6 2. b7#".
+pub fn line_to_san(pos: &Position, line: &[Move]) -> String {
+    let mut pos = pos.clone();
+    let mut out = vec![];
+
+    // Count moves from the side to move, a black first move is 1...
+    let offset = usize::from(pos.side() == Color::Black);
+    for (i, m) in line.iter().enumerate() {
+        let number = (i + offset) / 2 + 1;
+        let san = pos.move_to_san(m);
+        match pos.side() {
+            Color::White => out.push(format!("{number}. {san}")),
+            Color::Black if i == 0 => out.push(format!("1... {san}")),
+            Color::Black => out.push(san),
+        }
+        pos.change_board(*m);
+    }
+
+    out.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{parse, GameState};
+
+    fn position(fen: &str) -> Position {
+        parse::from_fen(fen.to_string()).unwrap()
+    }
+
+    /// Plays the line and checks that it ends in mate.
+    fn assert_mates(fen: &str, mate: &Mate) {
+        let mut pos = position(fen);
+        let attacker = pos.side();
+        for m in &mate.line {
+            pos.play(*m).unwrap();
+        }
+        assert_eq!(pos.gamestate(), &GameState::Won(attacker));
+        assert_eq!(mate.line.len() as u32, 2 * mate.moves - 1);
+    }
+
+    #[test]
+    fn mate_in_one() {
+        let fen = "7k/8/6K1/8/8/8/8/R7 w - - 0 1";
+        let mut pos = position(fen);
+        let before = pos.clone();
+
+        let mate = find_mate(&mut pos, 3).unwrap();
+        assert_eq!(pos, before);
+        assert_eq!(mate.moves, 1);
+        assert_eq!(line_to_san(&pos, &mate.line), "1. Ra8#");
+    }
+
+    #[test]
+    fn mate_in_two() {
+        // The key is a quiet move, which checks_only can't find
+        let fen = "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1";
+        let mut pos = position(fen);
+        assert_eq!(find_mate(&mut pos, 1), None);
+
+        let mate = find_mate(&mut pos, 2).unwrap();
+        assert_eq!(mate.moves, 2);
+        assert_mates(fen, &mate);
+        assert_eq!(line_to_san(&pos, &mate.line[..1]), "1. Ra6");
+        assert_eq!(MateSearch::new().checks_only(true).find(&mut pos, 2), None);
+
+        // Sacrifice with check first
+        let fen = "r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 10";
+        let mut pos = position(fen);
+        let mut search = MateSearch::new().checks_only(true);
+        let mate = search.find(&mut pos, 2).unwrap();
+        assert_eq!(line_to_san(&pos, &mate.line), "1. Nf6+ gxf6 2. Bxf7#");
+        assert!(search.nodes() > 0);
+    }
+
+    #[test]
+    fn longest_defence() {
+        // Ra7 mates next move whatever black does
+        let fen = "6k1/8/8/8/8/8/R7/1R4K1 w - - 0 1";
+        let mut pos = position(fen);
+        let mate = find_mate(&mut pos, 3).unwrap();
+        assert_eq!(mate.moves, 2);
+        assert_mates(fen, &mate);
+
+        // Kc8 holds out a move longer than Ka7 or Ka8
+        let fen = "1k6/8/8/2K5/8/8/8/7R w - - 0 1";
+        let mut pos = position(fen);
+        let mate = find_mate(&mut pos, 4).unwrap();
+        assert_eq!(mate.moves, 3);
+        assert_mates(fen, &mate);
+        assert_eq!(line_to_san(&pos, &mate.line[..2]), "1. Kb6 Kc8");
+        assert_eq!(find_mate(&mut pos, 2), None);
+
+        // Black to move
+        let fen = "r5k1/8/8/8/8/8/5PPP/6K1 b - - 0 1";
+        let mut pos = position(fen);
+        let mate = find_mate(&mut pos, 1).unwrap();
+        assert_eq!(line_to_san(&pos, &mate.line), "1... Ra1#");
+
+        let fen = "1r4k1/r7/8/8/8/8/8/6K1 b - - 0 1";
+        let mut pos = position(fen);
+        let mate = find_mate(&mut pos, 2).unwrap();
+        assert_eq!(mate.moves, 2);
+        assert_mates(fen, &mate);
+        assert_eq!(line_to_san(&pos, &mate.line), "1... Ra2 2. Kf1 Rb1#");
+    }
+
+    #[test]
+    fn no_mate() {
+        let mut pos = position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(find_mate(&mut pos, 2), None);
+
+        // Stalemate is no mate
+        let mut pos = position("k7/8/1QK5/8/8/8/8/8 b - - 0 1");
+        assert_eq!(find_mate(&mut pos, 2), None);
+        let mut pos = position("k7/8/2K5/1Q6/8/8/8/8 w - - 0 1");
+        let mate = find_mate(&mut pos, 1).unwrap();
+        assert_eq!(line_to_san(&pos, &mate.line), "1. Qb7#");
+    }
+
+    #[test]
+    fn variants() {
+        // Reaching the hill wins without a check
+        let fen = "8/8/8/8/8/3K4/8/k7 w - - 0 1";
+        let mut pos = parse::from_fen_variant(fen.to_string(), Variant::KingOfTheHill).unwrap();
+        let mate = find_mate(&mut pos, 1).unwrap();
+        assert_eq!(line_to_san(&pos, &mate.line), "1. Kd4");
+        assert_eq!(find_mate(&mut position(fen), 1), None);
+    }
+}
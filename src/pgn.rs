@@ -0,0 +1,774 @@
+use crate::board::movegen::Move;
+use crate::board::parse;
+use crate::board::piece::Color;
+use crate::board::Position;
+use anyhow::{anyhow, bail, Result};
+use std::io::{self, BufRead, Write};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Export format keeps lines at most this long
+const LINE_WIDTH: usize = 80;
+
+/// A move in the game tree together with its annotations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub mv: Move,
+    pub san: String,
+    /// Full move number and the side that played the move.
+    pub number: usize,
+    pub side: Color,
+    pub nags: Vec<u8>,
+    pub comment_before: Option<String>,
+    pub comment: Option<String>,
+    /// Alternatives to this move, played from the position before it.
+    pub variations: Vec<Vec<Node>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub start: Position,
+    pub moves: Vec<Node>,
+    pub result: String,
+}
+
+impl Game {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Move(String),
+    Nag(u8),
+    Comment(String),
+    VariationStart,
+    VariationEnd,
+    Result(String),
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut out = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => comment.push(c),
+                        None => return Err(anyhow!("Unterminated comment")),
+                    }
+                }
+                out.push(Token::Comment(
+                    comment.split_whitespace().collect::<Vec<_>>().join(" "),
+                ));
+            }
+            ';' => {
+                let mut comment = String::new();
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                    comment.push(c);
+                }
+                out.push(Token::Comment(comment.trim().to_string()));
+            }
+            '(' => out.push(Token::VariationStart),
+            ')' => out.push(Token::VariationEnd),
+            '$' => {
+                let mut n = String::new();
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    n.push(*d);
+                    chars.next();
+                }
+                let nag = n.parse().map_err(|_| anyhow!("Invalid NAG '${n}'"))?;
+                out.push(Token::Nag(nag));
+            }
+            c if c.is_whitespace() || c == '.' => (),
+            _ => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || "{}();$".contains(*c) {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+
+                if RESULTS.contains(&word.as_str()) {
+                    out.push(Token::Result(word));
+                    continue;
+                }
+
+                // Move numbers like 12. or 12... possibly glued to the move,
+                // but not the zeros of 0-0
+                let after_number = word.trim_start_matches(|c: char| c.is_ascii_digit());
+                let word = match after_number.strip_prefix('.') {
+                    Some(rest) => rest.trim_start_matches('.'),
+                    None => word.as_str(),
+                };
+                if word.is_empty() {
+                    continue;
+                }
+
+                // Traditional suffix annotations become NAGs
+                let san = word.trim_end_matches(['!', '?']);
+                let nag = match &word[san.len()..] {
+                    "" => None,
+                    "!" => Some(1),
+                    "?" => Some(2),
+                    "!!" => Some(3),
+                    "??" => Some(4),
+                    "!?" => Some(5),
+                    "?!" => Some(6),
+                    s => return Err(anyhow!("Invalid annotation '{s}'")),
+                };
+
+                out.push(Token::Move(san.to_string()));
+                if let Some(nag) = nag {
+                    out.push(Token::Nag(nag));
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn parse_tag(line: &str) -> Result<(String, String)> {
+    let inner = line
+        .trim()
+        .strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .ok_or_else(|| anyhow!("Invalid tag pair '{line}'"))?;
+
+    let (name, value) = inner
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| anyhow!("Invalid tag pair '{line}'"))?;
+
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| anyhow!("Tag value is not quoted in '{line}'"))?;
+
+    Ok((
+        name.to_string(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+/// Replays the movetext of one game on a position.
+struct Replay<'a> {
+    pos: Position,
+    tokens: &'a [Token],
+    index: usize,
+    number: usize,
+    result: Option<String>,
+}
+
+impl Replay<'_> {
+    fn play(&mut self, node_san: &str) -> Result<Node> {
+        let mv = self.pos.parse_san(node_san)?;
+        let san = self.pos.move_to_san(&mv);
+        let side = self.pos.side;
+
+        self.pos.change_board(mv);
+        let number = self.number;
+        if side == Color::Black {
+            self.number += 1;
+        }
+
+        Ok(Node {
+            mv,
+            san,
+            number,
+            side,
+            nags: vec![],
+            comment_before: None,
+            comment: None,
+            variations: vec![],
+        })
+    }
+
+    fn redo(&mut self, node: &Node) {
+        self.pos.change_board(node.mv);
+        if node.side == Color::Black {
+            self.number += 1;
+        }
+    }
+
+    fn take_back(&mut self, node: &Node) {
+        self.pos.reverse_last_change();
+        if node.side == Color::Black {
+            self.number -= 1;
+        }
+    }
+
+    /// Parses moves until the end of the variation or game.
+    /// Every move that was played is taken back before returning.
+    fn line(&mut self, nested: bool) -> Result<Vec<Node>> {
+        let mut nodes: Vec<Node> = vec![];
+        let mut pending_comment: Option<String> = None;
+
+        while self.index < self.tokens.len() {
+            let token = &self.tokens[self.index];
+            self.index += 1;
+
+            match token {
+                Token::Move(san) => {
+                    let mut node = self
+                        .play(san)
+                        .map_err(|e| anyhow!("Move {}: {e}", self.number))?;
+                    node.comment_before = pending_comment.take();
+                    nodes.push(node);
+                }
+                Token::Nag(n) => match nodes.last_mut() {
+                    Some(node) => node.nags.push(*n),
+                    None => return Err(anyhow!("NAG ${n} before the first move")),
+                },
+                Token::Comment(c) => match (nodes.last_mut(), &pending_comment) {
+                    (Some(node), None) => match &mut node.comment {
+                        Some(existing) => {
+                            existing.push(' ');
+                            existing.push_str(c);
+                        }
+                        None => node.comment = Some(c.clone()),
+                    },
+                    _ => match &mut pending_comment {
+                        Some(existing) => {
+                            existing.push(' ');
+                            existing.push_str(c);
+                        }
+                        None => pending_comment = Some(c.clone()),
+                    },
+                },
+                Token::VariationStart => {
+                    let last = nodes
+                        .pop()
+                        .ok_or_else(|| anyhow!("Variation before the first move"))?;
+
+                    self.take_back(&last);
+                    let variation = self.line(true)?;
+                    self.redo(&last);
+
+                    let mut last = last;
+                    last.variations.push(variation);
+                    nodes.push(last);
+                }
+                Token::VariationEnd => {
+                    if !nested {
+                        return Err(anyhow!("Unexpected ')'"));
+                    }
+                    break;
+                }
+                Token::Result(r) => {
+                    if nested {
+                        return Err(anyhow!("Result inside a variation"));
+                    }
+                    self.result = Some(r.clone());
+                    break;
+                }
+            }
+        }
+
+        if nested && self.tokens.get(self.index - 1) != Some(&Token::VariationEnd) {
+            return Err(anyhow!("Unterminated variation"));
+        }
+
+        // A comment in a game without moves has nothing to belong to
+        if let (Some(c), Some(node)) = (pending_comment, nodes.last_mut()) {
+            node.comment = Some(c);
+        }
+
+        for node in nodes.iter().rev() {
+            self.take_back(node);
+        }
+
+        Ok(nodes)
+    }
+}
+
+fn start_position(tags: &[(String, String)]) -> Result<(Position, usize)> {
+    let fen = tags
+        .iter()
+        .find(|(n, _)| n == "FEN")
+        .map(|(_, v)| v.as_str())
+        .unwrap_or(START_FEN);
+
+    let pos = parse::from_fen(fen.to_string()).map_err(|e| anyhow!("Invalid FEN tag: {e}"))?;
+    let number = fen
+        .split_whitespace()
+        .nth(5)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1);
+
+    Ok((pos, number))
+}
+
+fn build_game(tags: Vec<(String, String)>, movetext: &str) -> Result<Game> {
+    let (start, number) = start_position(&tags)?;
+    let tokens = tokenize(movetext)?;
+
+    let mut replay = Replay {
+        pos: start.clone(),
+        tokens: &tokens,
+        index: 0,
+        number,
+        result: None,
+    };
+    let moves = replay.line(false)?;
+
+    let result = replay
+        .result
+        .or_else(|| {
+            tags.iter()
+                .find(|(n, _)| n == "Result")
+                .map(|(_, v)| v.clone())
+        })
+        .unwrap_or_else(|| "*".to_string());
+
+    Ok(Game {
+        tags,
+        start,
+        moves,
+        result,
+    })
+}
+
+/// Tag pairs and movetext of a game that hasn't been replayed yet.
+type RawGame = (Vec<(String, String)>, String);
+
+/// Reads games one at a time from any buffered reader,
+/// so databases of any size can be streamed.
+pub struct PgnReader<R: BufRead> {
+    reader: R,
+    pending: Option<String>,
+    games: usize,
+    done: bool,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> PgnReader<R> {
+        PgnReader {
+            reader,
+            pending: None,
+            games: 0,
+            done: false,
+        }
+    }
+
+    fn next_line(&mut self) -> io::Result<Option<String>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(line))
+    }
+
+    /// Collects the tag pairs and movetext of the next game.
+    fn read_game(&mut self) -> Result<Option<RawGame>> {
+        let mut tags = vec![];
+        let mut movetext = String::new();
+        let mut open_braces: usize = 0;
+
+        while let Some(line) = self.next_line()? {
+            let trimmed = line.trim();
+
+            // A comment left open can't swallow the games after it
+            if trimmed.starts_with("[Event ") && open_braces > 0 {
+                self.pending = Some(line);
+                bail!("Unterminated comment");
+            }
+
+            // Escaped lines are for other software
+            if trimmed.starts_with('%') && open_braces == 0 {
+                continue;
+            }
+
+            if trimmed.starts_with('[') && open_braces == 0 {
+                if !movetext.trim().is_empty() {
+                    self.pending = Some(line);
+                    break;
+                }
+                tags.push(parse_tag(trimmed)?);
+                continue;
+            }
+
+            for c in line.chars() {
+                match c {
+                    '{' => open_braces += 1,
+                    '}' => open_braces = open_braces.saturating_sub(1),
+                    // The rest of the line is a comment, braces in it don't count
+                    ';' if open_braces == 0 => break,
+                    _ => (),
+                }
+            }
+
+            movetext.push_str(&line);
+
+            let ends_with_result = trimmed
+                .split_whitespace()
+                .last()
+                .is_some_and(|w| RESULTS.contains(&w));
+            if ends_with_result && open_braces == 0 {
+                break;
+            }
+        }
+
+        if tags.is_empty() && movetext.trim().is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some((tags, movetext)))
+    }
+
+    /// Skips what is left of a broken game, up to the Event tag of the next one.
+    fn skip_game(&mut self) -> io::Result<()> {
+        while let Some(line) = self.next_line()? {
+            if line.trim_start().starts_with("[Event ") {
+                self.pending = Some(line);
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<Game>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        self.games += 1;
+        let game = self.games;
+
+        match self.read_game() {
+            Ok(Some((tags, movetext))) => {
+                Some(build_game(tags, &movetext).map_err(|e| anyhow!("Game {game}: {e}")))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // Only the broken game is lost, reading goes on with the next
+                // one unless the reader itself failed
+                if e.is::<io::Error>() || self.skip_game().is_err() {
+                    self.done = true;
+                }
+                Some(Err(anyhow!("Game {game}: {e}")))
+            }
+        }
+    }
+}
+
+/// Parses every game in a string.
+pub fn read_games(text: &str) -> Result<Vec<Game>> {
+    PgnReader::new(text.as_bytes()).collect()
+}
+
+/// Breaks movetext into lines of at most LINE_WIDTH characters.
+struct Wrapper<'a, W: Write> {
+    out: &'a mut W,
+    line: String,
+}
+
+impl<W: Write> Wrapper<'_, W> {
+    fn push(&mut self, word: &str) -> io::Result<()> {
+        if !self.line.is_empty() && self.line.len() + 1 + word.len() > LINE_WIDTH {
+            writeln!(self.out, "{}", self.line)?;
+            self.line.clear();
+        }
+
+        // Parentheses hug the moves they enclose
+        if !self.line.is_empty() && !self.line.ends_with('(') && word != ")" {
+            self.line.push(' ');
+        }
+        self.line.push_str(word);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            writeln!(self.out, "{}", self.line)?;
+            self.line.clear();
+        }
+
+        Ok(())
+    }
+}
+
+fn push_comment<W: Write>(w: &mut Wrapper<W>, comment: &str) -> io::Result<()> {
+    let words: Vec<&str> = comment.split_whitespace().collect();
+
+    if words.is_empty() {
+        return w.push("{}");
+    }
+
+    for (i, word) in words.iter().enumerate() {
+        let mut word = word.to_string();
+        if i == 0 {
+            word.insert(0, '{');
+        }
+        if i == words.len() - 1 {
+            word.push('}');
+        }
+        w.push(&word)?;
+    }
+
+    Ok(())
+}
+
+fn write_line<W: Write>(w: &mut Wrapper<W>, nodes: &[Node]) -> io::Result<()> {
+    // Black moves need their number after anything that interrupts the moves
+    let mut need_number = true;
+
+    for node in nodes {
+        if let Some(c) = &node.comment_before {
+            push_comment(w, c)?;
+            need_number = true;
+        }
+
+        let mut word = String::new();
+        match node.side {
+            Color::White => word.push_str(&format!("{}. ", node.number)),
+            Color::Black if need_number => word.push_str(&format!("{}... ", node.number)),
+            Color::Black => (),
+        }
+        word.push_str(&node.san);
+
+        // Keep the number and the move on the same line
+        w.push(&word)?;
+
+        for nag in &node.nags {
+            w.push(&format!("${nag}"))?;
+        }
+
+        need_number = false;
+
+        if let Some(c) = &node.comment {
+            push_comment(w, c)?;
+            need_number = true;
+        }
+
+        for variation in &node.variations {
+            w.push("(")?;
+            write_line(w, variation)?;
+            w.push(")")?;
+            need_number = true;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a game in PGN export format.
+pub fn write_game<W: Write>(out: &mut W, game: &Game) -> io::Result<()> {
+    for (name, value) in &game.tags {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(out, "[{name} \"{value}\"]")?;
+    }
+    writeln!(out)?;
+
+    let mut w = Wrapper {
+        out,
+        line: String::new(),
+    };
+    write_line(&mut w, &game.moves)?;
+    w.push(&game.result)?;
+    w.finish()?;
+
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Read};
+
+    const GAME: &str = r#"[Event "Casual \"blitz\" game"]
+[Site "?"]
+[Date "2022.07.10"]
+[Round "-"]
+[White "Crab"]
+[Black "Human"]
+[Result "1-0"]
+
+{Opening comment} 1. e4 e5 2. Nf3 Nc6 3.Bc4 Nf6?! (3... Bc5 4. c3 $1 {The main line}
+(4. O-O Nf6) 4... Nf6) 4. Ng5 d5 5. exd5 Nxd5?? ; Fried liver
+6. Nxf7 Kxf7 7. Qf3+ Ke6 8. Nc3 $14 Ncb4 9. O-O c6 10. d4 Kd7 11. Rd1!
+1-0
+"#;
+
+    #[test]
+    fn read() {
+        let games = read_games(GAME).unwrap();
+        assert_eq!(games.len(), 1);
+
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Casual \"blitz\" game"));
+        assert_eq!(game.tag("White"), Some("Crab"));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.moves.len(), 21);
+
+        let first = &game.moves[0];
+        assert_eq!(first.san, "e4");
+        assert_eq!(first.comment_before.as_deref(), Some("Opening comment"));
+
+        let nf6 = &game.moves[5];
+        assert_eq!(nf6.san, "Nf6");
+        assert_eq!(nf6.number, 3);
+        assert_eq!(nf6.side, Color::Black);
+        assert_eq!(nf6.nags, vec![6]);
+        assert_eq!(nf6.variations.len(), 1);
+
+        let variation = &nf6.variations[0];
+        assert_eq!(variation[0].san, "Bc5");
+        assert_eq!(variation[1].san, "c3");
+        assert_eq!(variation[1].nags, vec![1]);
+        assert_eq!(variation[1].comment.as_deref(), Some("The main line"));
+        assert_eq!(variation[1].variations[0][0].san, "O-O");
+
+        let nxd5 = &game.moves[9];
+        assert_eq!(nxd5.nags, vec![4]);
+        assert_eq!(nxd5.comment.as_deref(), Some("Fried liver"));
+
+        assert_eq!(game.moves[12].san, "Qf3+");
+        assert_eq!(game.moves[20].san, "Rd1");
+    }
+
+    #[test]
+    fn castling_with_zeros() {
+        let pgn =
+            "1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 d6 5. d3 Bg4 6. Nc3 Qd7 7.h3 Bh5 8. a3 0-0-0 *";
+        let game = &read_games(pgn).unwrap()[0];
+        assert_eq!(game.moves.len(), 16);
+        assert_eq!(game.moves[6].san, "O-O");
+        assert_eq!(game.moves[12].san, "h3");
+        assert_eq!(game.moves[15].san, "O-O-O");
+    }
+
+    #[test]
+    fn errors() {
+        let err = read_games("1. e4 e5 2. Ke3 *").unwrap_err().to_string();
+        assert!(err.contains("Game 1") && err.contains("Ke3"), "{err}");
+
+        assert!(read_games("1. e4 (1. d4 *").is_err());
+        assert!(read_games("1. e4 {unterminated *").is_err());
+        assert!(read_games("[Event \"x\"\n\n1. e4 *").is_err());
+    }
+
+    #[test]
+    fn recover() {
+        let pgn = "[Event \"a\"]\n\n1. e4 *\n\n\
+                   [Event \"b\"\n[Site \"?\"]\n\n1. d4 *\n\n\
+                   [Event \"c\"]\n\n1. e4 e5 2. Ke3 *\n\n\
+                   [Event \"d\"]\n\n1. c4 {never closed *\n\n\
+                   [Event \"e\"]\n\n1. Nf3 ; a {brace\n1... d5 *\n";
+
+        // Every broken game is an error of its own and the others still get read
+        let games: Vec<_> = PgnReader::new(pgn.as_bytes()).collect();
+        assert_eq!(games.len(), 5);
+        assert_eq!(games[0].as_ref().unwrap().tag("Event"), Some("a"));
+        for (i, game) in games[1..4].iter().enumerate() {
+            let err = game.as_ref().unwrap_err().to_string();
+            assert!(err.starts_with(&format!("Game {}", i + 2)), "{err}");
+        }
+        assert!(games[3]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("comment"));
+
+        let e = games[4].as_ref().unwrap();
+        assert_eq!(e.tag("Event"), Some("e"));
+        assert_eq!(e.moves.len(), 2);
+    }
+
+    #[test]
+    fn from_fen_tag() {
+        let pgn =
+            "[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]\n[SetUp \"1\"]\n\n40... Kd7 41. e4 *\n";
+        let game = &read_games(pgn).unwrap()[0];
+        assert_eq!(game.moves[0].number, 40);
+        assert_eq!(game.moves[1].number, 41);
+        assert_eq!(game.result, "*");
+    }
+
+    #[test]
+    fn round_trip() {
+        let game = &read_games(GAME).unwrap()[0];
+
+        let mut out = vec![];
+        write_game(&mut out, game).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.lines().all(|l| l.len() <= LINE_WIDTH));
+        assert!(text.contains("[Event \"Casual \\\"blitz\\\" game\"]"));
+        // Compare without the line breaks
+        let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(
+            flat.contains(
+                "3. Bc4 Nf6 $6 (3... Bc5 4. c3 $1 {The main line} (4. O-O Nf6) 4... Nf6)"
+            ),
+            "{text}"
+        );
+        assert!(text.contains("4. Ng5"));
+
+        let again = &read_games(&text).unwrap()[0];
+        assert_eq!(again, game);
+    }
+
+    /// Endless source of games that is never held in memory as a whole.
+    struct Endless {
+        data: &'static [u8],
+        offset: usize,
+    }
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.data.len() - self.offset);
+            buf[..n].copy_from_slice(&self.data[self.offset..self.offset + n]);
+            self.offset = (self.offset + n) % self.data.len();
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let source = Endless {
+            data: b"[White \"a\"]\n[Black \"b\"]\n\n1. d4 d5 2. c4 e6 1/2-1/2\n\n",
+            offset: 0,
+        };
+
+        let reader = PgnReader::new(BufReader::new(source));
+        let games: Vec<Game> = reader.take(50).map(|g| g.unwrap()).collect();
+
+        assert_eq!(games.len(), 50);
+        assert!(games
+            .iter()
+            .all(|g| g.moves.len() == 4 && g.result == "1/2-1/2"));
+    }
+}
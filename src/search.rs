@@ -0,0 +1,798 @@
+use crate::board::eval;
+use crate::board::movegen::Move;
+use crate::board::piece::PieceType;
+use crate::board::square::Square;
+use crate::board::{GameState, Position};
+use crate::syzygy::{self, SyzygyOptions, Tablebases, Wdl};
+use crate::timeman::{Limits, TimeManager, TimeOptions};
+use crate::tt::{Bound, Entry, TranspositionTable};
+use crate::uci::{parse_spin, Info, UciOption, MATE, MATE_BOUND, MAX_PLY};
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Deepest iteration the search starts.
+pub const MAX_DEPTH: u32 = 64;
+
+pub const THREADS_DEFAULT: usize = 1;
+pub const THREADS_MAX: usize = 256;
+
+/// Size of the hash table in megabytes.
+pub const HASH_DEFAULT: usize = 16;
+pub const HASH_MIN: usize = 1;
+pub const HASH_MAX: usize = 65536;
+
+// Above any score the search can return
+const INFINITY: i32 = MATE + 1;
+
+// Tablebase wins score below any mate, less the further from the root they are
+const TB_WIN: i32 = MATE_BOUND - 1;
+
+// The clock and the node limit are only looked at this often
+const CHECK_EVERY: u64 = 64;
+
+// Values used to order captures, indexed by PieceType::index
+const ORDER_VALUE: [i32; 6] = [2000, 900, 500, 330, 320, 100];
+
+/// Receives the progress of a search.
+pub trait Listener {
+    /// Called after every finished iteration.
+    fn info(&mut self, info: &Info);
+
+    /// Called before each root move is searched, number counts from 1.
+    fn currmove(&mut self, _mv: &Move, _number: usize, _elapsed: Duration) {}
+}
+
+impl<F: FnMut(&Info)> Listener for F {
+    fn info(&mut self, info: &Info) {
+        self(info)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchResult {
+    /// None when the side to move has no legal moves.
+    pub best: Option<Move>,
+    /// Score from the point of view of the side to move.
+    pub score: i32,
+    /// Last iteration that finished.
+    pub depth: u32,
+    /// Nodes searched by all threads.
+    pub nodes: u64,
+    pub pv: Vec<Move>,
+}
+
+/// Iterative deepening alpha-beta search with a quiescence search over
+/// captures. The first root move of the first iteration is always
+/// searched, so a search that is stopped early still returns a move.
+///
+/// With more than one thread the others search the same position as
+/// helpers, sharing only the hash table (Lazy SMP). The result is always
+/// the one of the main thread.
+pub struct Search {
+    pub time: TimeOptions,
+    pub syzygy: SyzygyOptions,
+    threads: usize,
+    tt: TranspositionTable,
+    stop: Arc<AtomicBool>,
+}
+
+impl Default for Search {
+    fn default() -> Search {
+        Search {
+            time: TimeOptions::default(),
+            syzygy: SyzygyOptions::default(),
+            threads: THREADS_DEFAULT,
+            tt: TranspositionTable::new(HASH_DEFAULT),
+            stop: Arc::default(),
+        }
+    }
+}
+
+impl Search {
+    pub fn new() -> Search {
+        Search::default()
+    }
+
+    /// Flag that stops a running search when set from another thread.
+    /// It stays set until it is cleared again.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Sets the number of threads, the main one included.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, THREADS_MAX);
+    }
+
+    /// Replaces the hash table with an empty one of about mb megabytes.
+    pub fn set_hash(&mut self, mb: usize) {
+        self.tt = TranspositionTable::new(mb.clamp(HASH_MIN, HASH_MAX));
+    }
+
+    /// Forgets everything learned in earlier searches, for a new game.
+    pub fn clear(&mut self) {
+        self.tt.clear();
+    }
+
+    /// Searches pos within limits and returns the best move found.
+    /// An infinite search only returns once the stop flag is set.
+    pub fn go(
+        &mut self,
+        pos: &Position,
+        limits: &Limits,
+        listener: &mut dyn Listener,
+    ) -> SearchResult {
+        let mut pos = pos.clone();
+        let moves = pos.generate_legal_moves();
+        let tm = TimeManager::new(limits, pos.side, &self.time, moves.len());
+        let moves = self.root_moves(&mut pos, moves);
+
+        if moves.is_empty() {
+            let score = match pos.gamestate_with(&moves) {
+                GameState::Won(c) if c == pos.side => MATE,
+                GameState::Won(_) => -MATE,
+                _ => 0,
+            };
+            self.wait_for_stop(limits);
+            return SearchResult {
+                score,
+                ..SearchResult::default()
+            };
+        }
+
+        self.tt.new_search();
+        let keys = game_keys(&pos);
+        let done = AtomicBool::new(false);
+        let helper_nodes = AtomicU64::new(0);
+
+        let result = thread::scope(|s| {
+            for id in 1..self.threads {
+                // Helpers have no limits of their own, they stop with the main thread
+                let tm = TimeManager::new(&Limits::default(), pos.side, &self.time, moves.len());
+                let mut helper = Worker::new(pos.clone(), keys.clone(), self, &done, tm, None);
+                helper.shared_nodes = Some(&helper_nodes);
+                let mut moves = moves.clone();
+
+                // Half of them start one iteration ahead so the threads spread out
+                s.spawn(move || {
+                    for depth in 1 + id as u32 % 2..=MAX_DEPTH {
+                        if helper.root(depth, &mut moves, &mut |_: &Info| ()).is_none() {
+                            break;
+                        }
+                    }
+                });
+            }
+
+            let mut main = Worker::new(pos, keys, self, &self.stop, tm, limits.nodes);
+            let result = main.iterate(moves, limits, listener, &helper_nodes);
+            done.store(true, Ordering::Relaxed);
+            result
+        });
+
+        let mut result = result;
+        result.nodes += helper_nodes.load(Ordering::Relaxed);
+        self.wait_for_stop(limits);
+        result
+    }
+
+    /// Leaves out the root moves that throw away the result the tablebases
+    /// have for the position, if they cover it.
+    fn root_moves(&self, pos: &mut Position, moves: Vec<Move>) -> Vec<Move> {
+        let tb = &self.syzygy.tablebases;
+        if tb.is_empty() || !tb.covers(pos) {
+            return moves;
+        }
+
+        match syzygy::filter_root_moves(pos, |p| tb.probe_wdl(p)) {
+            Ok(Some(kept)) => kept,
+            _ => moves,
+        }
+    }
+
+    fn wait_for_stop(&self, limits: &Limits) {
+        while limits.infinite && !self.stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// Threads and Hash, and the options of the time manager and the tablebases.
+impl UciOption for Search {
+    fn uci_options(&self) -> Vec<String> {
+        let mut out = vec![
+            format!(
+                "option name Threads type spin default {THREADS_DEFAULT} min 1 max {THREADS_MAX}"
+            ),
+            format!(
+                "option name Hash type spin default {HASH_DEFAULT} min {HASH_MIN} max {HASH_MAX}"
+            ),
+        ];
+        out.extend(self.time.uci_options());
+        out.extend(self.syzygy.uci_options());
+        out
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<bool> {
+        if name.eq_ignore_ascii_case("Threads") {
+            let n = parse_spin("Threads", value, 1, THREADS_MAX as u64)?;
+            self.set_threads(n as usize);
+        } else if name.eq_ignore_ascii_case("Hash") {
+            let mb = parse_spin("Hash", value, HASH_MIN as u64, HASH_MAX as u64)?;
+            self.set_hash(mb as usize);
+        } else {
+            return Ok(self.time.set_option(name, value)? || self.syzygy.set_option(name, value)?);
+        }
+
+        Ok(true)
+    }
+}
+
+/// Keys of the positions since the last capture or pawn move,
+/// the current one last.
+fn game_keys(pos: &Position) -> Vec<u64> {
+    let mut pos = pos.clone();
+    let mut keys = vec![pos.key];
+
+    for _ in 0..pos.halfturn.min(pos.history.len()) {
+        pos.reverse_last_change();
+        keys.push(pos.key);
+    }
+
+    keys.reverse();
+    keys
+}
+
+/// State of one thread searching through the tree.
+struct Worker<'a> {
+    pos: Position,
+    /// Keys of the positions from the last capture or pawn move
+    /// up to the current one, to find repetitions.
+    keys: Vec<u64>,
+    tt: &'a TranspositionTable,
+    tb: &'a Tablebases,
+    stop: &'a AtomicBool,
+    tm: TimeManager,
+    node_limit: Option<u64>,
+    nodes: u64,
+    /// Where a helper adds its nodes.
+    shared_nodes: Option<&'a AtomicU64>,
+    seldepth: u32,
+    /// Only false while the first move of the first iteration is searched.
+    can_abort: bool,
+    aborted: bool,
+    killers: Vec<[Option<Move>; 2]>,
+    /// Principal variation found from each ply on.
+    pv: Vec<Vec<Move>>,
+}
+
+impl<'a> Worker<'a> {
+    fn new(
+        pos: Position,
+        keys: Vec<u64>,
+        search: &'a Search,
+        stop: &'a AtomicBool,
+        tm: TimeManager,
+        node_limit: Option<u64>,
+    ) -> Self {
+        Worker {
+            pos,
+            keys,
+            tt: &search.tt,
+            tb: &search.syzygy.tablebases,
+            stop,
+            tm,
+            node_limit,
+            nodes: 0,
+            shared_nodes: None,
+            seldepth: 0,
+            can_abort: false,
+            aborted: false,
+            killers: vec![[None; 2]; MAX_PLY as usize + 1],
+            pv: vec![vec![]; MAX_PLY as usize + 1],
+        }
+    }
+
+    /// Iterative deepening on the main thread, reporting every iteration.
+    fn iterate(
+        &mut self,
+        mut moves: Vec<Move>,
+        limits: &Limits,
+        listener: &mut dyn Listener,
+        helper_nodes: &AtomicU64,
+    ) -> SearchResult {
+        let mut result = SearchResult::default();
+        let max_depth = limits
+            .depth
+            .map_or(MAX_DEPTH, |d| d.clamp(1, MAX_DEPTH as u64) as u32);
+
+        for depth in 1..=max_depth {
+            let Some(score) = self.root(depth, &mut moves, listener) else {
+                break;
+            };
+
+            let best_changed = result.best.is_some_and(|b| !b.same_as(&moves[0]));
+            let nodes = self.nodes + helper_nodes.load(Ordering::Relaxed);
+            result = SearchResult {
+                best: Some(moves[0]),
+                score,
+                depth,
+                nodes,
+                pv: self.pv[0].clone(),
+            };
+            listener.info(&Info {
+                depth,
+                seldepth: self.seldepth,
+                score,
+                nodes,
+                time: self.tm.elapsed(),
+                hashfull: self.tt.hashfull(),
+                pv: result.pv.clone(),
+                chess960: self.pos.chess960,
+            });
+
+            self.tm.update(best_changed, score);
+            // No need to look deeper than a mate that was found
+            let mate_found = score.abs() >= MATE_BOUND && MATE - score.abs() <= depth as i32;
+            if !limits.infinite && (mate_found || self.tm.should_stop()) {
+                break;
+            }
+        }
+
+        result.nodes = self.nodes;
+        result
+    }
+
+    /// Counts a node and tells whether the search has to be given up.
+    fn visit(&mut self) -> bool {
+        self.nodes += 1;
+        let check = self.nodes.is_multiple_of(CHECK_EVERY);
+        if check {
+            if let Some(shared) = self.shared_nodes {
+                shared.fetch_add(CHECK_EVERY, Ordering::Relaxed);
+            }
+        }
+
+        if !self.can_abort {
+            return false;
+        }
+        if !self.aborted {
+            self.aborted = self.stop.load(Ordering::Relaxed)
+                || self.node_limit.is_some_and(|n| self.nodes >= n)
+                || (check && self.tm.out_of_time());
+        }
+        self.aborted
+    }
+
+    /// Whether the position was already reached since the last capture
+    /// or pawn move. Repeating once is scored as a draw.
+    fn is_repetition(&self) -> bool {
+        let now = self.keys.len() - 1;
+        let back = self.pos.halfturn.min(now);
+
+        (4..=back)
+            .step_by(2)
+            .any(|i| self.keys[now - i] == self.pos.key)
+    }
+
+    /// Searches every root move and moves the best one to the front.
+    /// None when the iteration was aborted.
+    fn root(&mut self, depth: u32, moves: &mut [Move], listener: &mut dyn Listener) -> Option<i32> {
+        self.pv[0].clear();
+
+        let mut alpha = -INFINITY;
+        let mut best = 0;
+
+        for (i, &m) in moves.iter().enumerate() {
+            self.can_abort = depth > 1 || i > 0;
+            listener.currmove(&m, i + 1, self.tm.elapsed());
+
+            // The first iteration keeps the moves it got through
+            let Some(score) = self.child(m, depth as i32 - 1, 1, alpha, INFINITY, i == 0) else {
+                if depth > 1 {
+                    return None;
+                }
+                break;
+            };
+            if score > alpha {
+                alpha = score;
+                best = i;
+                self.update_pv(0, m);
+            }
+        }
+
+        // The best move is searched first in the next iteration
+        moves[..=best].rotate_right(1);
+        if !self.aborted {
+            self.store(Some(moves[0]), depth as i32, 0, alpha, Bound::Exact);
+        }
+        Some(alpha)
+    }
+
+    /// Negamax alpha-beta with principal variation search. None when aborted.
+    fn search(&mut self, mut depth: i32, ply: usize, mut alpha: i32, beta: i32) -> Option<i32> {
+        if self.visit() {
+            return None;
+        }
+        self.seldepth = self.seldepth.max(ply as u32);
+        self.pv[ply].clear();
+
+        if self.is_repetition() {
+            return Some(0);
+        }
+
+        let mut moves = self.pos.generate_legal_moves();
+        match self.pos.gamestate_with(&moves) {
+            GameState::Won(c) if c == self.pos.side => return Some(MATE - ply as i32),
+            GameState::Won(_) => return Some(-MATE + ply as i32),
+            GameState::Draw => return Some(0),
+            // Checks are looked at one ply deeper
+            GameState::InCheck(_) => depth += 1,
+            GameState::Normal => (),
+        }
+
+        if let Some(score) = self.probe_wdl(ply) {
+            return Some(score);
+        }
+        if ply >= MAX_PLY as usize {
+            return Some(eval::evaluate(&self.pos));
+        }
+        if depth <= 0 {
+            return self.quiesce(ply, alpha, beta);
+        }
+
+        // Scores from the table only cut off outside the principal variation
+        let entry = self.tt.probe(self.pos.key);
+        if let Some(e) = entry.filter(|e| e.depth as i32 >= depth && beta - alpha == 1) {
+            let score = score_from_tt(e.score, ply);
+            match e.bound {
+                Bound::Exact => return Some(score),
+                Bound::Lower if score >= beta => return Some(score),
+                Bound::Upper if score <= alpha => return Some(score),
+                _ => (),
+            }
+        }
+
+        self.order(&mut moves, ply, entry.and_then(|e| e.mv));
+        let mut best = None;
+        for (i, m) in moves.into_iter().enumerate() {
+            let score = self.child(m, depth - 1, ply + 1, alpha, beta, i == 0)?;
+            if score >= beta {
+                if m.change.captured.is_none() && !is_killer(&m, &self.killers[ply]) {
+                    self.killers[ply] = [Some(m), self.killers[ply][0]];
+                }
+                self.store(Some(m), depth, ply, beta, Bound::Lower);
+                return Some(beta);
+            }
+            if score > alpha {
+                alpha = score;
+                best = Some(m);
+                self.update_pv(ply, m);
+            }
+        }
+
+        let bound = if best.is_some() {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.store(best, depth, ply, alpha, bound);
+        Some(alpha)
+    }
+
+    /// Score of the tablebase result. The tables are only probed right after
+    /// a capture or pawn move, when the fifty move counter starts again.
+    fn probe_wdl(&self, ply: usize) -> Option<i32> {
+        if self.pos.halfturn != 0 || self.tb.is_empty() || !self.tb.covers(&self.pos) {
+            return None;
+        }
+
+        let score = match self.tb.probe_wdl(&self.pos).ok()?? {
+            Wdl::Win => TB_WIN - ply as i32,
+            Wdl::CursedWin => 1,
+            Wdl::Draw => 0,
+            Wdl::BlessedLoss => -1,
+            Wdl::Loss => -TB_WIN + ply as i32,
+        };
+        Some(score)
+    }
+
+    fn store(&self, m: Option<Move>, depth: i32, ply: usize, score: i32, bound: Bound) {
+        let entry = Entry {
+            mv: m.map(|m| (m.from, m.to, m.promotion)),
+            score: score_to_tt(score, ply),
+            depth: depth.clamp(0, u8::MAX as i32) as u8,
+            bound,
+        };
+        self.tt.store(self.pos.key, entry);
+    }
+
+    /// Score of m searched to depth with a null window around alpha, unless
+    /// it's the first move. Moves that beat alpha are searched again with the
+    /// full window.
+    fn child(
+        &mut self,
+        m: Move,
+        depth: i32,
+        ply: usize,
+        alpha: i32,
+        beta: i32,
+        first: bool,
+    ) -> Option<i32> {
+        self.pos.change_board(m);
+        self.keys.push(self.pos.key);
+
+        let mut score = match first {
+            true => self.search(depth, ply, -beta, -alpha).map(|s| -s),
+            false => self.search(depth, ply, -alpha - 1, -alpha).map(|s| -s),
+        };
+        if !first && score.is_some_and(|s| s > alpha && s < beta) {
+            score = self.search(depth, ply, -beta, -alpha).map(|s| -s);
+        }
+
+        self.keys.pop();
+        self.pos.reverse_last_change();
+        score
+    }
+
+    /// Only looks at captures and promotions, or every move when in check,
+    /// so the evaluation isn't taken in the middle of an exchange.
+    fn quiesce(&mut self, ply: usize, mut alpha: i32, beta: i32) -> Option<i32> {
+        if self.visit() {
+            return None;
+        }
+        self.seldepth = self.seldepth.max(ply as u32);
+        self.pv[ply].clear();
+
+        let mut moves = self.pos.generate_legal_moves();
+        let in_check = match self.pos.gamestate_with(&moves) {
+            GameState::Won(c) if c == self.pos.side => return Some(MATE - ply as i32),
+            GameState::Won(_) => return Some(-MATE + ply as i32),
+            GameState::Draw => return Some(0),
+            GameState::InCheck(_) => true,
+            GameState::Normal => false,
+        };
+
+        if ply >= MAX_PLY as usize {
+            return Some(eval::evaluate(&self.pos));
+        }
+        if !in_check {
+            let stand_pat = eval::evaluate(&self.pos);
+            if stand_pat >= beta {
+                return Some(beta);
+            }
+            alpha = alpha.max(stand_pat);
+            moves.retain(|m| m.change.captured.is_some() || m.promotion == Some(PieceType::Queen));
+        }
+
+        self.order(&mut moves, ply, None);
+        for m in moves {
+            self.pos.change_board(m);
+            let score = self.quiesce(ply + 1, -beta, -alpha).map(|s| -s);
+            self.pos.reverse_last_change();
+
+            let score = score?;
+            if score >= beta {
+                return Some(beta);
+            }
+            if score > alpha {
+                alpha = score;
+                self.update_pv(ply, m);
+            }
+        }
+
+        Some(alpha)
+    }
+
+    /// The move from the hash table first, then captures by the value of the
+    /// victim and then the attacker, followed by the killer moves and the
+    /// other quiet moves.
+    fn order(&self, moves: &mut [Move], ply: usize, tt_move: Option<TtMove>) {
+        let killers = &self.killers[ply];
+
+        moves.sort_by_cached_key(|m| {
+            let score = match m.change.captured {
+                _ if tt_move == Some((m.from, m.to, m.promotion)) => 20_000,
+                Some(victim) => {
+                    10_000 + ORDER_VALUE[victim.index()] * 10 - ORDER_VALUE[m.ptype.index()] / 10
+                }
+                None if m.promotion.is_some() => 9_000,
+                None if is_killer(m, killers) => 8_000,
+                None => 0,
+            };
+            -score
+        });
+    }
+
+    fn update_pv(&mut self, ply: usize, m: Move) {
+        let (head, tail) = self.pv.split_at_mut(ply + 1);
+        head[ply].clear();
+        head[ply].push(m);
+        head[ply].extend_from_slice(&tail[0]);
+    }
+}
+
+type TtMove = (Square, Square, Option<PieceType>);
+
+/// Mate scores are stored as the distance to mate from the position
+/// rather than from the root, so they stay right wherever it comes up again.
+fn score_to_tt(score: i32, ply: usize) -> i16 {
+    let score = match score {
+        s if s >= MATE_BOUND => s + ply as i32,
+        s if s <= -MATE_BOUND => s - ply as i32,
+        s => s,
+    };
+    score.clamp(-INFINITY, INFINITY) as i16
+}
+
+fn score_from_tt(score: i16, ply: usize) -> i32 {
+    match score as i32 {
+        s if s >= MATE_BOUND => s - ply as i32,
+        s if s <= -MATE_BOUND => s + ply as i32,
+        s => s,
+    }
+}
+
+fn is_killer(m: &Move, killers: &[Option<Move>; 2]) -> bool {
+    killers.iter().flatten().any(|k| k.same_as(m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::parse;
+
+    fn search(fen: &str, limits: Limits) -> (SearchResult, Vec<Info>) {
+        let pos = parse::from_fen(fen.to_string()).unwrap();
+        let mut infos = vec![];
+        let result = Search::new().go(&pos, &limits, &mut |info: &Info| infos.push(info.clone()));
+        (result, infos)
+    }
+
+    fn depth(d: u64) -> Limits {
+        Limits {
+            depth: Some(d),
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn mate_in_one() {
+        let (result, infos) = search("7k/8/6K1/8/8/8/8/R7 w - - 0 1", depth(3));
+
+        assert_eq!(result.best.unwrap().to_string(), "a1a8");
+        assert_eq!(result.score, MATE - 1);
+        // Stops at the first iteration that sees the mate
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].pv, result.pv);
+    }
+
+    #[test]
+    fn wins_material() {
+        let (result, _) = search("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", depth(2));
+        assert_eq!(result.best.unwrap().to_string(), "d2d5");
+        assert!(result.score > 300);
+        assert_eq!(result.depth, 2);
+    }
+
+    #[test]
+    fn game_over() {
+        let (result, infos) = search("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", depth(3));
+        assert_eq!(result.best, None);
+        assert_eq!(result.score, 0);
+        assert!(infos.is_empty());
+
+        let (result, _) = search("R6k/8/6K1/8/8/8/8/8 b - - 0 1", depth(3));
+        assert_eq!(result.score, -MATE);
+    }
+
+    #[test]
+    fn hash_table() {
+        let pos = parse::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1".to_string()).unwrap();
+        let mut search = Search::new();
+        search.set_hash(1);
+
+        let first = search.go(&pos, &depth(3), &mut |_: &Info| ());
+        let second = search.go(&pos, &depth(3), &mut |_: &Info| ());
+        assert!(
+            second.nodes < first.nodes,
+            "{} {}",
+            second.nodes,
+            first.nodes
+        );
+
+        search.clear();
+        let third = search.go(&pos, &depth(3), &mut |_: &Info| ());
+        assert_eq!(third, first);
+    }
+
+    #[test]
+    fn repetition() {
+        let mut pos = parse::from_fen(crate::pgn::START_FEN.to_string()).unwrap();
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+            pos.play_uci(m).unwrap();
+        }
+        let keys = game_keys(&pos);
+        assert_eq!(keys.len(), 8);
+        assert_eq!(keys[0], keys[4]);
+
+        let search = Search::new();
+        let stop = AtomicBool::new(false);
+        let tm = TimeManager::new(&Limits::default(), pos.side, &TimeOptions::default(), 20);
+        let mut worker = Worker::new(pos.clone(), keys, &search, &stop, tm, None);
+        assert!(worker.is_repetition());
+
+        let m = worker.pos.find_uci("f6g8").unwrap();
+        worker.pos.change_board(m);
+        worker.keys.push(worker.pos.key);
+        assert!(worker.is_repetition());
+    }
+
+    #[test]
+    fn threads() {
+        let pos = parse::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1".to_string()).unwrap();
+        let mut search = Search::new();
+        search.set_option("Threads", "3").unwrap();
+        assert_eq!(search.threads(), 3);
+
+        let result = search.go(&pos, &depth(3), &mut |_: &Info| ());
+        assert_eq!(result.best.unwrap().to_string(), "d2d5");
+        assert_eq!(result.depth, 3);
+
+        assert!(search.set_option("Threads", "0").is_err());
+        assert!(search.set_option("Hash", "x").is_err());
+        assert!(search.set_option("Move Overhead", "50").unwrap());
+        assert!(!search.set_option("Ponder", "true").unwrap());
+    }
+
+    #[test]
+    fn limits() {
+        // The first move is searched even with no nodes to spare
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let limits = Limits {
+            nodes: Some(1),
+            ..Limits::default()
+        };
+        let (result, infos) = search(fen, limits);
+        assert!(result.best.is_some());
+        assert_eq!(result.depth, 1);
+        assert_eq!(infos.len(), 1);
+
+        let mut search = Search::new();
+        let stop = search.stop_flag();
+        stop.store(true, Ordering::Relaxed);
+        let pos = parse::from_fen(fen.to_string()).unwrap();
+        let limits = Limits {
+            infinite: true,
+            ..Limits::default()
+        };
+        let result = search.go(&pos, &limits, &mut |_: &Info| ());
+        assert_eq!(result.depth, 1);
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy files, see tests/fixtures/syzygy/README.md"]
+    fn syzygy() {
+        let mut search = Search::new();
+        search.syzygy.tablebases = crate::syzygy::tests::official();
+
+        // Rb3+ lets the king take the rook
+        let mut pos = parse::from_fen("8/8/8/8/8/2k5/8/KR6 w - - 0 1".to_string()).unwrap();
+        let moves = pos.generate_legal_moves();
+        let kept = search.root_moves(&mut pos, moves.clone());
+        assert!(kept.len() < moves.len());
+        assert!(kept.iter().all(|m| m.to_string() != "b1b3"));
+
+        // KQvKP isn't covered, but after taking the pawn the tables know it's a win
+        let pos = parse::from_fen("4k3/8/8/3p4/8/8/8/3QK3 w - - 0 1".to_string()).unwrap();
+        let result = search.go(&pos, &depth(2), &mut |_: &Info| ());
+        assert_eq!(result.best.unwrap().to_string(), "d1d5");
+        assert_eq!(result.score, TB_WIN - 1);
+    }
+}
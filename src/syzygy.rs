@@ -0,0 +1,786 @@
+use crate::board::movegen::Move;
+use crate::board::piece::{Color, PieceType};
+use crate::board::variant::Variant;
+use crate::board::Position;
+use crate::uci::UciOption;
+use anyhow::{anyhow, Context, Result};
+use file::{Kind, TableFile};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+mod file;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+const WDL_SUFFIX: &str = "rtbw";
+const DTZ_SUFFIX: &str = "rtbz";
+
+// Piece letters in the order Syzygy file names use them
+const PIECE_ORDER: [PieceType; 6] = [
+    PieceType::King,
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Pawn,
+];
+
+/// Win/draw/loss for the side to move. Cursed wins and blessed losses
+/// are wins and losses that the fifty move rule turns into draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    /// From the -2..=2 values stored in the tables.
+    pub fn from_value(v: i8) -> Option<Wdl> {
+        match v {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    /// The same result seen from the other side.
+    pub fn flip(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+}
+
+/// Name of the material of one side, e.g. KRP.
+fn side_name(pos: &Position, c: Color) -> String {
+    let mut out = String::new();
+
+    for p in PIECE_ORDER {
+        let n = pos.pieces(c).get_board(p).count();
+        for _ in 0..n {
+            out.push(p.to_char(Color::White));
+        }
+    }
+
+    out
+}
+
+/// Table name for the position with white's pieces first, e.g. KRPvKR.
+pub fn material_name(pos: &Position) -> String {
+    format!(
+        "{}v{}",
+        side_name(pos, Color::White),
+        side_name(pos, Color::Black)
+    )
+}
+
+fn piece_count(pos: &Position) -> u32 {
+    pos.w_pieces_all.count() + pos.b_pieces_all.count()
+}
+
+/// Codes and squares of the pieces from a1 to h8, as the tables index them.
+fn board_pieces(pos: &Position) -> Vec<(u8, u8)> {
+    let mut pieces = vec![];
+
+    for c in [Color::White, Color::Black] {
+        for p in PieceType::ALL {
+            for sq in pos.pieces(c).get_board(p).squares() {
+                pieces.push((file::piece_code(p, c), (sq.rank * 8 + sq.file) as u8));
+            }
+        }
+    }
+
+    pieces.sort_by_key(|(_, sq)| *sq);
+    pieces
+}
+
+fn wdl_sign(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Loss | Wdl::BlessedLoss => -1,
+        Wdl::Draw => 0,
+        Wdl::CursedWin | Wdl::Win => 1,
+    }
+}
+
+/// DTZ of a position whose best move zeroes the fifty move counter.
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Loss => -1,
+        Wdl::BlessedLoss => -101,
+        Wdl::Draw => 0,
+        Wdl::CursedWin => 101,
+        Wdl::Win => 1,
+    }
+}
+
+/// Whether path can be read and starts with the expected magic number.
+fn has_magic(path: &Path, magic: [u8; 4]) -> bool {
+    let mut header = [0; 4];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .is_ok_and(|_| header == magic)
+}
+
+/// The files of one material, read the first time they are probed.
+#[derive(Clone, Debug, Default)]
+struct Table {
+    wdl: Option<PathBuf>,
+    dtz: Option<PathBuf>,
+    wdl_file: OnceLock<Arc<TableFile>>,
+    dtz_file: OnceLock<Arc<TableFile>>,
+}
+
+impl Table {
+    fn load(&self, name: &str, kind: Kind) -> Result<Option<&TableFile>> {
+        let (path, cell) = match kind {
+            Kind::Wdl => (&self.wdl, &self.wdl_file),
+            Kind::Dtz => (&self.dtz, &self.dtz_file),
+        };
+        let Some(path) = path else {
+            return Ok(None);
+        };
+
+        if let Some(file) = cell.get() {
+            return Ok(Some(file));
+        }
+        let bytes =
+            fs::read(path).with_context(|| format!("Can't read tablebase '{}'", path.display()))?;
+        let file = TableFile::new(name, kind, bytes)
+            .with_context(|| format!("Can't read tablebase '{}'", path.display()))?;
+        Ok(Some(cell.get_or_init(|| Arc::new(file))))
+    }
+}
+
+/// What a DTZ table has for a position.
+enum Stored {
+    Plies(i32),
+    /// The table only has the position with the other side to move.
+    OtherSide,
+}
+
+/// The Syzygy files found in the directories of SyzygyPath.
+#[derive(Clone, Debug, Default)]
+pub struct Tablebases {
+    tables: HashMap<String, Table>,
+    max_pieces: u32,
+}
+
+impl Tablebases {
+    /// Finds the tables in a list of directories separated by ':' or,
+    /// on Windows, ';'. Files that aren't tables are skipped.
+    pub fn open(paths: &str) -> Result<Tablebases> {
+        let mut tb = Tablebases::default();
+        let separator = if cfg!(windows) { ';' } else { ':' };
+
+        for dir in paths.split(separator).filter(|d| !d.is_empty()) {
+            let entries =
+                fs::read_dir(dir).map_err(|e| anyhow!("Can't read SyzygyPath '{dir}': {e}"))?;
+
+            for entry in entries {
+                let path = entry?.path();
+                let (Some(name), Some(ext)) = (
+                    path.file_stem().and_then(|s| s.to_str()),
+                    path.extension().and_then(|s| s.to_str()),
+                ) else {
+                    continue;
+                };
+
+                let magic = match ext {
+                    WDL_SUFFIX => WDL_MAGIC,
+                    DTZ_SUFFIX => DTZ_MAGIC,
+                    _ => continue,
+                };
+                let Some(sides) = file::parse_name(name) else {
+                    continue;
+                };
+                if !has_magic(&path, magic) {
+                    continue;
+                }
+
+                let table = tb.tables.entry(name.to_string()).or_default();
+                match ext {
+                    WDL_SUFFIX => table.wdl = Some(path.clone()),
+                    _ => table.dtz = Some(path.clone()),
+                }
+
+                let pieces = (sides[0].len() + sides[1].len()) as u32;
+                tb.max_pieces = tb.max_pieces.max(pieces);
+            }
+        }
+
+        Ok(tb)
+    }
+
+    /// Most pieces on the board that any table covers.
+    pub fn max_pieces(&self) -> u32 {
+        self.max_pieces
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// The table covering the position, its name and whether colours have to
+    /// be swapped, since only one of KQvK and KvKQ exists on disk.
+    fn find(&self, pos: &Position) -> Option<(&str, &Table, bool)> {
+        let white = side_name(pos, Color::White);
+        let black = side_name(pos, Color::Black);
+
+        if let Some((name, t)) = self.tables.get_key_value(&format!("{white}v{black}")) {
+            return Some((name, t, false));
+        }
+        self.tables
+            .get_key_value(&format!("{black}v{white}"))
+            .map(|(name, t)| (name.as_str(), t, true))
+    }
+
+    /// Whether probing can say anything about the position.
+    /// Tables don't cover positions where castling is still possible.
+    pub fn covers(&self, pos: &Position) -> bool {
+        let castling = pos.w_castling.king_side()
+            || pos.w_castling.queen_side()
+            || pos.b_castling.king_side()
+            || pos.b_castling.queen_side();
+
+        pos.variant() == Variant::Standard
+            && !castling
+            && piece_count(pos) <= self.max_pieces.max(2)
+    }
+
+    /// WDL of the position for the side to move, None if no table covers it.
+    pub fn probe_wdl(&self, pos: &Position) -> Result<Option<Wdl>> {
+        if !self.covers(pos) {
+            return Ok(None);
+        }
+
+        let mut pos = pos.clone();
+        Ok(self.search(&mut pos, false)?.map(|(wdl, _)| wdl))
+    }
+
+    /// Distance to zeroing the fifty move counter in plies, negative when
+    /// the side to move loses. None if no table covers the position.
+    pub fn probe_dtz(&self, pos: &Position) -> Result<Option<i32>> {
+        if !self.covers(pos) {
+            return Ok(None);
+        }
+
+        let mut pos = pos.clone();
+        self.dtz(&mut pos)
+    }
+
+    /// WDL as stored in the table.
+    fn table_wdl(&self, pos: &Position) -> Result<Option<Wdl>> {
+        // Bare kings have no table
+        if piece_count(pos) == 2 {
+            return Ok(Some(Wdl::Draw));
+        }
+
+        let Some((name, table, flipped)) = self.find(pos) else {
+            return Ok(None);
+        };
+        let Some(file) = table.load(name, Kind::Wdl)? else {
+            return Ok(None);
+        };
+
+        let value = file.wdl(&board_pieces(pos), pos.side() == Color::Black, flipped)?;
+        Wdl::from_value(value)
+            .map(Some)
+            .ok_or_else(|| anyhow!("Invalid WDL value {value} in {name}"))
+    }
+
+    /// DTZ as stored in the table, wdl is the result of the position.
+    fn table_dtz(&self, pos: &Position, wdl: Wdl) -> Result<Option<Stored>> {
+        let Some((name, table, flipped)) = self.find(pos) else {
+            return Ok(None);
+        };
+        let Some(file) = table.load(name, Kind::Dtz)? else {
+            return Ok(None);
+        };
+
+        let value = wdl as i8 - Wdl::Draw as i8;
+        let dtz = file.dtz(
+            &board_pieces(pos),
+            pos.side() == Color::Black,
+            flipped,
+            value,
+        )?;
+        Ok(Some(dtz.map_or(Stored::OtherSide, Stored::Plies)))
+    }
+
+    /// The WDL of the position, and whether the best move zeroes the fifty
+    /// move counter. Captures, and pawn moves if zeroing is set, are searched
+    /// first: the tables don't know about en passant and only store
+    /// "don't care" values where a capture is the best move.
+    fn search(&self, pos: &mut Position, zeroing: bool) -> Result<Option<(Wdl, bool)>> {
+        let moves = pos.generate_legal_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+
+        for m in &moves {
+            if m.change.captured.is_none() && !(zeroing && m.ptype == PieceType::Pawn) {
+                continue;
+            }
+            searched += 1;
+
+            pos.change_board(*m);
+            let result = self.search(pos, false);
+            pos.reverse_last_change();
+
+            let Some((value, _)) = result? else {
+                return Ok(None);
+            };
+            let value = value.flip();
+            if value == Wdl::Win {
+                return Ok(Some((value, true)));
+            }
+            best = best.max(value);
+        }
+
+        // With every move searched the table isn't needed, and might
+        // not even be right because of en passant
+        let all = searched > 0 && searched == moves.len();
+        let value = match all {
+            true => best,
+            false => match self.table_wdl(pos)? {
+                Some(value) => value,
+                None => return Ok(None),
+            },
+        };
+
+        if best >= value {
+            return Ok(Some((best, best > Wdl::Draw || all)));
+        }
+        Ok(Some((value, false)))
+    }
+
+    fn dtz(&self, pos: &mut Position) -> Result<Option<i32>> {
+        let Some((wdl, zeroing)) = self.search(pos, true)? else {
+            return Ok(None);
+        };
+
+        // The tables store "don't care" values for draws and for
+        // positions where the best move zeroes
+        if wdl == Wdl::Draw {
+            return Ok(Some(0));
+        }
+        if zeroing {
+            return Ok(Some(dtz_before_zeroing(wdl)));
+        }
+
+        let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+        match self.table_dtz(pos, wdl)? {
+            None => Ok(None),
+            Some(Stored::Plies(dtz)) => Ok(Some((dtz + 100 * cursed as i32) * wdl_sign(wdl))),
+            Some(Stored::OtherSide) => self.dtz_after_moves(pos, wdl),
+        }
+    }
+
+    /// DTZ from the positions after each move, for tables that only
+    /// have the other side to move.
+    fn dtz_after_moves(&self, pos: &mut Position, wdl: Wdl) -> Result<Option<i32>> {
+        let mut min = None;
+
+        for m in pos.generate_legal_moves() {
+            let zeroing = m.change.captured.is_some() || m.ptype == PieceType::Pawn;
+
+            pos.change_board(m);
+            // After a zeroing move the counter starts again, so only
+            // the result matters
+            let dtz = match zeroing {
+                true => self
+                    .search(pos, false)
+                    .map(|r| r.map(|(wdl, _)| -dtz_before_zeroing(wdl))),
+                false => self.dtz(pos).map(|r| r.map(|dtz| -dtz)),
+            };
+            let mate = pos.is_in_check(pos.side()) && pos.generate_legal_moves().is_empty();
+            pos.reverse_last_change();
+
+            let Some(mut dtz) = dtz? else {
+                return Ok(None);
+            };
+            if dtz == 1 && mate {
+                min = Some(1);
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz.signum() == wdl_sign(wdl) && min.is_none_or(|min| dtz < min) {
+                min = Some(dtz);
+            }
+        }
+
+        // Without moves the side to move is mated
+        Ok(Some(min.unwrap_or(-1)))
+    }
+}
+
+/// Keeps the root moves that preserve the best tablebase result.
+/// probe gives the WDL for the side to move after each move.
+/// Returns None when a position after one of the moves isn't covered.
+pub fn filter_root_moves<F>(pos: &mut Position, mut probe: F) -> Result<Option<Vec<Move>>>
+where
+    F: FnMut(&Position) -> Result<Option<Wdl>>,
+{
+    let mut scored = vec![];
+
+    for m in pos.generate_legal_moves() {
+        pos.change_board(m);
+        let wdl = probe(pos);
+        pos.reverse_last_change();
+
+        match wdl? {
+            Some(wdl) => scored.push((m, wdl.flip())),
+            None => return Ok(None),
+        }
+    }
+
+    let Some(best) = scored.iter().map(|(_, wdl)| *wdl).max() else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        scored
+            .into_iter()
+            .filter(|(_, wdl)| *wdl == best)
+            .map(|(m, _)| m)
+            .collect(),
+    ))
+}
+
+/// The SyzygyPath UCI option.
+#[derive(Clone, Debug, Default)]
+pub struct SyzygyOptions {
+    pub path: String,
+    pub tablebases: Tablebases,
+}
+
+impl UciOption for SyzygyOptions {
+    fn uci_options(&self) -> Vec<String> {
+        vec!["option name SyzygyPath type string default <empty>".to_string()]
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<bool> {
+        if !name.eq_ignore_ascii_case("SyzygyPath") {
+            return Ok(false);
+        }
+
+        let path = match value.trim() {
+            "<empty>" => "",
+            path => path,
+        };
+        self.tablebases = Tablebases::open(path)?;
+        self.path = path.to_string();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::board::parse;
+    use crate::tablebase::Dtm;
+    use std::env;
+
+    fn pos(fen: &str) -> Position {
+        parse::from_fen(fen.to_string()).unwrap()
+    }
+
+    /// A directory with files that only have the header of a table.
+    fn fixture(name: &str, files: &[(&str, [u8; 4])]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("syzygy-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (file, magic) in files {
+            fs::write(dir.join(file), magic).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn material_names() {
+        assert_eq!(material_name(&pos("8/8/8/8/8/2k5/8/KQ6 w - - 0 1")), "KQvK");
+        assert_eq!(
+            material_name(&pos("8/3p4/8/8/2r5/2k5/8/K1RN4 b - - 0 1")),
+            "KRNvKRP"
+        );
+    }
+
+    #[test]
+    fn open() {
+        let dir = fixture(
+            "open",
+            &[
+                ("KQvK.rtbw", WDL_MAGIC),
+                ("KQvK.rtbz", DTZ_MAGIC),
+                ("KRvKN.rtbw", WDL_MAGIC),
+                ("README.txt", [0; 4]),
+            ],
+        );
+        let tb = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(tb.len(), 2);
+        assert_eq!(tb.max_pieces(), 4);
+
+        // Black has the queen, so colours are swapped
+        let (name, table, flipped) = tb.find(&pos("8/8/8/8/8/2K5/8/kq6 w - - 0 1")).unwrap();
+        assert_eq!(name, "KQvK");
+        assert!(flipped);
+        assert!(table.dtz.is_some());
+
+        let (_, table, flipped) = tb.find(&pos("8/8/8/8/3n4/2K5/8/kR6 w - - 0 1")).unwrap();
+        assert!(!flipped);
+        assert!(table.dtz.is_none());
+
+        // One broken file doesn't keep the others from loading
+        let bad = fixture(
+            "bad",
+            &[
+                ("KQvK.rtbw", DTZ_MAGIC),
+                ("KRvK.rtbw", WDL_MAGIC),
+                ("KXvK.rtbw", WDL_MAGIC),
+            ],
+        );
+        let tb = Tablebases::open(bad.to_str().unwrap()).unwrap();
+        assert_eq!(tb.len(), 1);
+        assert!(tb.find(&pos("8/8/8/8/8/2k5/8/KR6 w - - 0 1")).is_some());
+        assert!(Tablebases::open("/does/not/exist").is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(bad).unwrap();
+    }
+
+    #[test]
+    fn probe() {
+        let tb = Tablebases::default();
+
+        let kings = pos("8/8/8/8/8/2k5/8/K7 w - - 0 1");
+        assert_eq!(tb.probe_wdl(&kings).unwrap(), Some(Wdl::Draw));
+        assert_eq!(tb.probe_dtz(&kings).unwrap(), Some(0));
+
+        let kqk = pos("8/8/8/8/8/2k5/8/KQ6 w - - 0 1");
+        assert_eq!(tb.probe_wdl(&kqk).unwrap(), None);
+
+        let start = pos(crate::pgn::START_FEN);
+        assert!(!tb.covers(&start));
+    }
+
+    /// The official KQvK, KRvK, KBvK, KPvK and KNNvK tables, which have to
+    /// be downloaded into tests/fixtures/syzygy.
+    pub(crate) fn official() -> Tablebases {
+        Tablebases::open(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/syzygy"
+        ))
+        .unwrap()
+    }
+
+    /// FEN with the pieces on squares from a1 to h8, None if two share a square.
+    fn fen(pieces: &[(char, u8)], black_to_move: bool) -> Option<String> {
+        let mut board = [None; 64];
+        for (c, sq) in pieces {
+            if board[*sq as usize].replace(*c).is_some() {
+                return None;
+            }
+        }
+
+        let ranks: Vec<String> = (0..8)
+            .rev()
+            .map(|rank| {
+                let mut out = String::new();
+                let mut empty = 0;
+                for c in &board[rank * 8..rank * 8 + 8] {
+                    match c {
+                        Some(c) => {
+                            if empty > 0 {
+                                out.push_str(&empty.to_string());
+                            }
+                            empty = 0;
+                            out.push(*c);
+                        }
+                        None => empty += 1,
+                    }
+                }
+                if empty > 0 {
+                    out.push_str(&empty.to_string());
+                }
+                out
+            })
+            .collect();
+
+        let side = if black_to_move { 'b' } else { 'w' };
+        Some(format!("{} {side} - - 0 1", ranks.join("/")))
+    }
+
+    /// Legal positions spread over a material, and the same with colours swapped.
+    fn samples(piece: char) -> Vec<(String, Position)> {
+        let mut out = vec![];
+
+        for i in (0..2 * 64 * 64 * 64).step_by(1021) {
+            let (wk, bk, sq) = (
+                (i / 64 / 64 % 64) as u8,
+                (i / 64 % 64) as u8,
+                (i % 64) as u8,
+            );
+            let black_to_move = i >= 64 * 64 * 64;
+            if piece == 'P' && !(8..56).contains(&sq) {
+                continue;
+            }
+
+            let swapped = [
+                ('k', wk ^ 56),
+                ('K', bk ^ 56),
+                (piece.to_ascii_lowercase(), sq ^ 56),
+            ];
+            for (pieces, black_to_move) in [
+                ([('K', wk), ('k', bk), (piece, sq)], black_to_move),
+                (swapped, !black_to_move),
+            ] {
+                let Some(fen) = fen(&pieces, black_to_move) else {
+                    continue;
+                };
+                let pos = pos(&fen);
+                if !pos.is_in_check(pos.side().opposite()) {
+                    out.push((fen, pos));
+                }
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy files, see tests/fixtures/syzygy/README.md"]
+    fn open_official() {
+        let tb = official();
+        assert_eq!(tb.len(), 5);
+        assert_eq!(tb.max_pieces(), 4);
+        assert!(tb.covers(&pos("8/8/8/8/8/2k5/8/KNN5 w - - 0 1")));
+        assert!(!tb.covers(&pos("8/8/8/8/8/2k5/8/KNNN4 w - - 0 1")));
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy files, see tests/fixtures/syzygy/README.md"]
+    fn probe_official() {
+        let tb = official();
+        let probe = |fen: &str| {
+            let pos = pos(fen);
+            (
+                tb.probe_wdl(&pos).unwrap().unwrap(),
+                tb.probe_dtz(&pos).unwrap().unwrap(),
+            )
+        };
+
+        assert_eq!(probe("8/8/8/8/8/2k5/8/K7 w - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe("8/8/8/8/8/2k5/8/KN6 b - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe("8/8/8/3k4/8/8/2NN4/K7 w - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe("7k/5N2/5NK1/8/8/8/8/8 b - - 0 1"), (Wdl::Loss, -1));
+        assert_eq!(probe("7k/8/5NK1/6N1/8/8/8/8 w - - 0 1"), (Wdl::Win, 1));
+        assert_eq!(probe("7K/8/5nk1/6n1/8/8/8/8 b - - 0 1"), (Wdl::Win, 1));
+
+        // Without pawns only mates end a win
+        assert_eq!(probe("8/8/8/8/8/2k5/8/KQ6 w - - 0 1"), (Wdl::Win, 11));
+        assert_eq!(probe("8/8/8/8/8/2k5/8/KQ6 b - - 0 1"), (Wdl::Loss, -14));
+        assert_eq!(probe("8/8/8/8/3k4/8/8/KR6 w - - 0 1"), (Wdl::Win, 29));
+        assert_eq!(probe("7k/8/8/8/8/8/8/KR6 b - - 0 1"), (Wdl::Loss, -26));
+        assert_eq!(probe("8/8/8/8/8/2k5/8/KB6 w - - 0 1"), (Wdl::Draw, 0));
+
+        // A king in front of its pawn wins, one behind it doesn't
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").0, Wdl::Win);
+        assert_eq!(probe("8/8/8/4k3/8/8/4P3/4K3 w - - 0 1"), (Wdl::Draw, 0));
+
+        // The king takes the queen
+        assert_eq!(probe("8/8/8/8/8/8/1q6/K3k3 w - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe("8/8/8/8/8/8/1q6/K1k5 w - - 0 1"), (Wdl::Loss, -1));
+        // Promoting zeroes the counter
+        assert_eq!(probe("8/4P3/8/8/8/k7/8/4K3 w - - 0 1"), (Wdl::Win, 1));
+
+        // Five pieces are past the largest table
+        let five = pos("8/8/8/8/8/2k5/8/KQRB4 w - - 0 1");
+        assert_eq!(tb.probe_wdl(&five).unwrap(), None);
+        // and there is no KBvKB
+        let missing = pos("8/8/8/8/8/2k5/8/KB5b w - - 0 1");
+        assert_eq!(tb.probe_dtz(&missing).unwrap(), None);
+    }
+
+    #[test]
+    #[ignore = "needs the official Syzygy files, see tests/fixtures/syzygy/README.md"]
+    fn official_match_tablebase() {
+        let tb = official();
+        let truth = crate::tablebase::tests::tables();
+
+        for piece in ['Q', 'R', 'P'] {
+            for (fen, pos) in samples(piece) {
+                let dtm = truth.probe(&pos).unwrap();
+                let wdl = tb.probe_wdl(&pos).unwrap().unwrap();
+                let dtz = tb.probe_dtz(&pos).unwrap().unwrap();
+
+                let expected = match dtm {
+                    Dtm::Win(_) => Wdl::Win,
+                    Dtm::Draw => Wdl::Draw,
+                    Dtm::Loss(_) => Wdl::Loss,
+                };
+                assert_eq!(wdl, expected, "{fen}");
+                assert_eq!(dtz.signum(), wdl_sign(wdl), "{fen}");
+
+                // Without pawns only mates end a win
+                let expected = match dtm {
+                    Dtm::Win(n) => 2 * n as i32 - 1,
+                    Dtm::Draw => 0,
+                    Dtm::Loss(0) => -1,
+                    Dtm::Loss(n) => -2 * n as i32,
+                };
+                if piece != 'P' {
+                    assert_eq!(dtz, expected, "{fen}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn root_moves() {
+        // The only move that keeps the win is taking the rook
+        let mut pos = pos("r7/8/8/8/8/2k5/8/6KQ w - - 0 1");
+        let probe = |p: &Position| {
+            let queens = p.w_pieces.get_board(PieceType::Queen).count();
+            let rooks = p.b_pieces.get_board(PieceType::Rook).count();
+            Ok(Some(match (queens, rooks) {
+                (1, 0) => Wdl::Loss,
+                _ => Wdl::Draw,
+            }))
+        };
+
+        let moves = filter_root_moves(&mut pos, probe).unwrap().unwrap();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].to_string(), "h1a8");
+
+        let none = filter_root_moves(&mut pos, |_| Ok(None)).unwrap();
+        assert_eq!(none, None);
+    }
+
+    #[test]
+    fn options() {
+        let mut options = SyzygyOptions::default();
+        assert!(!options.set_option("Hash", "16").unwrap());
+        assert!(options.set_option("SyzygyPath", "/does/not/exist").is_err());
+        assert!(options.set_option("SyzygyPath", "<empty>").unwrap());
+        assert!(options.tablebases.is_empty());
+    }
+}
@@ -0,0 +1,906 @@
+use super::{DTZ_MAGIC, WDL_MAGIC};
+use crate::board::piece::{Color, PieceType};
+use anyhow::{anyhow, Result};
+use std::sync::OnceLock;
+
+const MAX_PIECES: usize = 7;
+
+// Flags of the table for one side and file
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// Flags in the first byte after the magic number
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+// Right child of a symbol that stands for a single value
+const LEAF: u16 = 0xfff;
+
+const PAWN: u8 = 1;
+
+// Size of the leading group without pawns: three unique pieces, or the two kings
+const UNIQUE_SIZE: u64 = 31332;
+const KINGS_SIZE: u64 = 462;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Kind {
+    Wdl,
+    Dtz,
+}
+
+/// Code of a piece in the files: 1 to 6 from pawn to king, plus 8 for black.
+pub(super) fn piece_code(p: PieceType, c: Color) -> u8 {
+    let code = match p {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+    };
+
+    match c {
+        Color::White => code,
+        Color::Black => code | 8,
+    }
+}
+
+fn file_of(sq: u8) -> u8 {
+    sq & 7
+}
+
+fn rank_of(sq: u8) -> u8 {
+    sq >> 3
+}
+
+/// Negative below the a1-h8 diagonal, positive above it.
+fn off_diagonal(sq: u8) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+/// Lookup tables of the index encoding, the same for every file.
+struct Maps {
+    /// Squares a2 to h7 numbered so the leading pawn has the highest number.
+    pawns: [u64; 64],
+    /// Squares below the a1-h8 diagonal numbered 0 to 27.
+    b1h1h7: [u64; 64],
+    /// The a1-d1-d4 triangle numbered 0 to 9, the diagonal last.
+    a1d1d4: [u64; 64],
+    /// The 462 ways to place the kings with the first one in the triangle.
+    kings: [[u64; 64]; 10],
+    /// binomial[k][n] ways to pick k of n squares.
+    binomial: [[u64; 64]; 6],
+    lead_pawn_index: [[u64; 64]; 6],
+    /// Number of ways to place the leading pawns, by number of them and file.
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut m = Maps {
+            pawns: [0; 64],
+            b1h1h7: [0; 64],
+            a1d1d4: [0; 64],
+            kings: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            lead_pawn_index: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let below = (0..64).filter(|sq| off_diagonal(*sq) < 0);
+        for (code, sq) in below.enumerate() {
+            m.b1h1h7[sq as usize] = code as u64;
+        }
+
+        let triangle = (0..28).filter(|sq| file_of(*sq) <= 3);
+        let below = triangle.clone().filter(|sq| off_diagonal(*sq) < 0);
+        let diagonal = triangle.filter(|sq| off_diagonal(*sq) == 0);
+        for (code, sq) in below.chain(diagonal).enumerate() {
+            m.a1d1d4[sq as usize] = code as u64;
+        }
+
+        // With the first king on the diagonal the other one stays below it,
+        // and both kings on the diagonal come last
+        let mut both_on_diagonal = vec![];
+        let mut code = 0;
+        for i in 0..10 {
+            let Some(k1) = (0..28).find(|sq| m.a1d1d4[*sq as usize] == i && (i > 0 || *sq == 1))
+            else {
+                continue;
+            };
+
+            for k2 in 0..64u8 {
+                let touching = file_of(k1).abs_diff(file_of(k2)) <= 1
+                    && rank_of(k1).abs_diff(rank_of(k2)) <= 1;
+                if touching || (off_diagonal(k1) == 0 && off_diagonal(k2) > 0) {
+                    continue;
+                }
+
+                if off_diagonal(k1) == 0 && off_diagonal(k2) == 0 {
+                    both_on_diagonal.push((i, k2));
+                } else {
+                    m.kings[i as usize][k2 as usize] = code;
+                    code += 1;
+                }
+            }
+        }
+        for (i, k2) in both_on_diagonal {
+            m.kings[i as usize][k2 as usize] = code;
+            code += 1;
+        }
+
+        m.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                let with = if k > 0 { m.binomial[k - 1][n - 1] } else { 0 };
+                let without = if k < n { m.binomial[k][n - 1] } else { 0 };
+                m.binomial[k][n] = with + without;
+            }
+        }
+
+        // A leading pawn further from the edge, or higher up on the same
+        // file, leaves two squares fewer for the other pawns
+        let mut taken = 0;
+        for pawns in 1..6 {
+            for file in 0..4 {
+                let mut index = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if pawns == 1 {
+                        m.pawns[sq] = 47 - taken;
+                        m.pawns[sq ^ 7] = 46 - taken;
+                        taken += 2;
+                    }
+                    m.lead_pawn_index[pawns][sq] = index;
+                    index += m.binomial[pawns - 1][m.pawns[sq] as usize];
+                }
+                m.lead_pawns_size[pawns][file] = index;
+            }
+        }
+
+        m
+    })
+}
+
+/// How the values of one side to move, and one file of the leading pawn,
+/// are split into groups of pieces and compressed.
+#[derive(Clone, Debug, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    /// There is an entry in the sparse index for about every span values.
+    span: u64,
+    num_blocks: usize,
+    max_sym_len: u8,
+    /// Also the value of a table with a single value.
+    min_sym_len: u8,
+    lowest_sym: usize,
+    /// base64[l - min_sym_len] is the lowest code of length l, left aligned.
+    base64: Vec<u64>,
+    /// Number of values minus one a symbol stands for.
+    symlen: Vec<u8>,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    /// Piece codes in the order the index encodes them.
+    pieces: [u8; MAX_PIECES],
+    group_len: [usize; MAX_PIECES + 1],
+    group_index: [u64; MAX_PIECES + 1],
+    /// Where the DTZ values of wins, losses, cursed wins and blessed losses start in the map.
+    map_index: [usize; 4],
+}
+
+impl PairsData {
+    fn size(&self) -> u64 {
+        let groups = self.group_len.iter().position(|l| *l == 0).unwrap();
+        self.group_index[groups]
+    }
+}
+
+fn read(bytes: &[u8], at: usize, len: usize) -> Result<&[u8]> {
+    bytes
+        .get(at..at + len)
+        .ok_or_else(|| anyhow!("Table ends at {} bytes, expected {}", bytes.len(), at + len))
+}
+
+fn read_u8(bytes: &[u8], at: usize) -> Result<u8> {
+    Ok(read(bytes, at, 1)?[0])
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(read(bytes, at, 2)?.try_into().unwrap()))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(read(bytes, at, 4)?.try_into().unwrap()))
+}
+
+/// Big endian, with zeros past the end: the last block can be read a little beyond.
+fn read_be(bytes: &[u8], at: usize, len: usize) -> u64 {
+    (at..at + len).fold(0, |n, i| n << 8 | *bytes.get(i).unwrap_or(&0) as u64)
+}
+
+/// The pieces of each side in a file name like KRPvKR.
+pub(super) fn parse_name(name: &str) -> Option<[Vec<PieceType>; 2]> {
+    let (white, black) = name.split_once('v')?;
+    let side = |s: &str| {
+        let pieces: Option<Vec<PieceType>> = s
+            .chars()
+            .map(|c| match c {
+                'K' => Some(PieceType::King),
+                'Q' => Some(PieceType::Queen),
+                'R' => Some(PieceType::Rook),
+                'B' => Some(PieceType::Bishop),
+                'N' => Some(PieceType::Knight),
+                'P' => Some(PieceType::Pawn),
+                _ => None,
+            })
+            .collect();
+        pieces.filter(|p| p.iter().filter(|p| **p == PieceType::King).count() == 1)
+    };
+
+    let sides = [side(white)?, side(black)?];
+    let count = sides[0].len() + sides[1].len();
+    (count <= MAX_PIECES).then_some(sides)
+}
+
+/// A WDL or DTZ file read into memory, with what is needed to find
+/// the value of a position in it.
+#[derive(Debug)]
+pub(super) struct TableFile {
+    kind: Kind,
+    bytes: Vec<u8>,
+    /// Both sides have the same pieces, so only white to move is stored.
+    symmetric: bool,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    piece_count: usize,
+    /// Pawns of the side the leading pawns belong to, then of the other side.
+    pawn_count: [usize; 2],
+    /// By side to move, then by file of the leading pawn.
+    items: Vec<Vec<PairsData>>,
+    /// Start of the DTZ value maps.
+    map: usize,
+}
+
+impl TableFile {
+    pub(super) fn new(name: &str, kind: Kind, bytes: Vec<u8>) -> Result<TableFile> {
+        let (mut table, at) = TableFile::layout(name, kind, bytes)?;
+        table.read_blocks(at)?;
+        Ok(table)
+    }
+
+    /// Reads the header up to the piece order, where the compression
+    /// settings start.
+    fn layout(name: &str, kind: Kind, bytes: Vec<u8>) -> Result<(TableFile, usize)> {
+        let sides = parse_name(name).ok_or_else(|| anyhow!("'{name}' is not a table name"))?;
+        let magic = match kind {
+            Kind::Wdl => WDL_MAGIC,
+            Kind::Dtz => DTZ_MAGIC,
+        };
+        if read(&bytes, 0, 4)? != magic {
+            return Err(anyhow!("{name} has the wrong magic number"));
+        }
+
+        let count = |c: usize, p: PieceType| sides[c].iter().filter(|x| **x == p).count();
+        let pawns = [count(0, PieceType::Pawn), count(1, PieceType::Pawn)];
+        // The side with fewer pawns leads, as it compresses better
+        let white_leads = pawns[1] == 0 || (pawns[0] > 0 && pawns[1] >= pawns[0]);
+
+        let mut table = TableFile {
+            kind,
+            bytes,
+            symmetric: sides[0] == sides[1],
+            has_pawns: pawns[0] + pawns[1] > 0,
+            has_unique_pieces: (0..2).any(|c| {
+                PieceType::ALL
+                    .into_iter()
+                    .any(|p| p != PieceType::King && count(c, p) == 1)
+            }),
+            piece_count: sides[0].len() + sides[1].len(),
+            pawn_count: match white_leads {
+                true => pawns,
+                false => [pawns[1], pawns[0]],
+            },
+            items: vec![],
+            map: 0,
+        };
+
+        let flags = read_u8(&table.bytes, 4)?;
+        let split = !table.symmetric;
+        if (flags & HAS_PAWNS != 0) != table.has_pawns || (flags & SPLIT != 0) != split {
+            return Err(anyhow!("The header of {name} doesn't match its pieces"));
+        }
+
+        let mut expected: Vec<u8> = [Color::White, Color::Black]
+            .into_iter()
+            .zip(&sides)
+            .flat_map(|(c, pieces)| pieces.iter().map(move |p| piece_code(*p, c)))
+            .collect();
+        expected.sort();
+
+        let sides = table.sides();
+        let both_pawns = table.pawn_count[1] > 0;
+        let mut items = vec![vec![PairsData::default(); table.files()]; sides];
+        let mut at = 5;
+
+        for f in 0..table.files() {
+            let first = read_u8(&table.bytes, at)?;
+            let second = match both_pawns {
+                true => read_u8(&table.bytes, at + 1)?,
+                false => 0xff,
+            };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            at += 1 + both_pawns as usize;
+
+            for (i, side) in items.iter_mut().enumerate() {
+                let d = &mut side[f];
+                for k in 0..table.piece_count {
+                    let b = read_u8(&table.bytes, at + k)?;
+                    d.pieces[k] = if i == 1 { b >> 4 } else { b & 0xf };
+                }
+
+                let mut pieces = d.pieces[..table.piece_count].to_vec();
+                pieces.sort();
+                if pieces != expected || (table.has_pawns && d.pieces[0] & 7 != PAWN) {
+                    return Err(anyhow!(
+                        "The piece order of {name} doesn't match its pieces"
+                    ));
+                }
+
+                table.set_groups(d, order[i], f);
+            }
+            at += table.piece_count;
+        }
+
+        table.items = items;
+        Ok((table, at + (at & 1)))
+    }
+
+    fn sides(&self) -> usize {
+        match self.kind {
+            Kind::Wdl if !self.symmetric => 2,
+            _ => 1,
+        }
+    }
+
+    fn files(&self) -> usize {
+        match self.has_pawns {
+            true => 4,
+            false => 1,
+        }
+    }
+
+    /// Splits the pieces into groups and works out the factor of each
+    /// group in the index, in the order the file asks for.
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], file: usize) {
+        let m = maps();
+        let mut first_len: i32 = match (self.has_pawns, self.has_unique_pieces) {
+            (true, _) => 0,
+            (false, true) => 3,
+            (false, false) => 2,
+        };
+
+        let mut n = 0;
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+        let mut index = 1;
+
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_index[0] = index;
+                index *= match (self.has_pawns, self.has_unique_pieces) {
+                    (true, _) => m.lead_pawns_size[d.group_len[0]][file],
+                    (false, true) => UNIQUE_SIZE,
+                    (false, false) => KINGS_SIZE,
+                };
+            } else if k == order[1] {
+                d.group_index[1] = index;
+                index *= m.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_index[next] = index;
+                index *= m.binomial[d.group_len[next]][free];
+                free -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+
+        d.group_index[n] = index;
+    }
+
+    /// Reads the compression settings, the DTZ maps and where the
+    /// indexes and blocks of every side and file start.
+    fn read_blocks(&mut self, mut at: usize) -> Result<()> {
+        let mut items = std::mem::take(&mut self.items);
+
+        for f in 0..self.files() {
+            for side in items.iter_mut() {
+                at = self.set_sizes(&mut side[f], at)?;
+            }
+        }
+
+        if self.kind == Kind::Dtz {
+            self.map = at;
+            for d in items[0].iter_mut() {
+                if d.flags & MAPPED == 0 {
+                    continue;
+                }
+
+                if d.flags & WIDE != 0 {
+                    at += at & 1;
+                    for i in 0..4 {
+                        d.map_index[i] = (at - self.map) / 2 + 1;
+                        at += 2 * read_u16(&self.bytes, at)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        d.map_index[i] = at - self.map + 1;
+                        at += read_u8(&self.bytes, at)? as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for f in 0..self.files() {
+            for side in items.iter_mut() {
+                side[f].sparse_index = at;
+                at += side[f].sparse_index_size * 6;
+            }
+        }
+
+        for f in 0..self.files() {
+            for side in items.iter_mut() {
+                side[f].block_length = at;
+                at += side[f].block_length_size * 2;
+            }
+        }
+
+        for f in 0..self.files() {
+            for side in items.iter_mut() {
+                at = (at + 0x3f) & !0x3f;
+                side[f].data = at;
+                at += side[f].num_blocks * side[f].block_size;
+            }
+        }
+
+        if at > self.bytes.len() {
+            return Err(anyhow!(
+                "Table ends at {} bytes, expected {at}",
+                self.bytes.len()
+            ));
+        }
+
+        self.items = items;
+        Ok(())
+    }
+
+    fn set_sizes(&self, d: &mut PairsData, mut at: usize) -> Result<usize> {
+        let bytes = &self.bytes;
+        d.flags = read_u8(bytes, at)?;
+        at += 1;
+
+        if d.flags & SINGLE_VALUE != 0 {
+            d.min_sym_len = read_u8(bytes, at)?;
+            return Ok(at + 1);
+        }
+
+        let block_size = read_u8(bytes, at)?;
+        let span = read_u8(bytes, at + 1)?;
+        if block_size > 30 || span > 30 {
+            return Err(anyhow!("Invalid block size or span in table"));
+        }
+        d.block_size = 1 << block_size;
+        d.span = 1 << span;
+        d.sparse_index_size = d.size().div_ceil(d.span) as usize;
+        let padding = read_u8(bytes, at + 2)? as usize;
+        d.num_blocks = read_u32(bytes, at + 3)? as usize;
+        d.block_length_size = d.num_blocks + padding;
+        d.max_sym_len = read_u8(bytes, at + 7)?;
+        d.min_sym_len = read_u8(bytes, at + 8)?;
+        at += 9;
+
+        // Longer codes hold lower numbers, there have to be enough
+        // bits left in the buffer for the longest one
+        if d.min_sym_len == 0 || d.max_sym_len < d.min_sym_len || d.max_sym_len > 32 {
+            return Err(anyhow!("Invalid symbol lengths in table"));
+        }
+
+        d.lowest_sym = at;
+        let lengths = (d.max_sym_len - d.min_sym_len + 1) as usize;
+        let lowest = |i: usize| read_u16(bytes, at + 2 * i).map(|s| s as u64);
+        d.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            d.base64[i] = (d.base64[i + 1] + lowest(i)?).wrapping_sub(lowest(i + 1)?) / 2;
+        }
+        for (i, base) in d.base64.iter_mut().enumerate() {
+            *base <<= 64 - i - d.min_sym_len as usize;
+        }
+        at += 2 * lengths;
+
+        let symbols = read_u16(bytes, at)? as usize;
+        at += 2;
+        d.btree = at;
+        read(bytes, at, 3 * symbols)?;
+
+        d.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                d.symlen[sym] = self.set_symlen(d, sym, &mut visited)?;
+            }
+        }
+
+        Ok(at + 3 * symbols + (symbols & 1))
+    }
+
+    /// Number of values minus one that sym expands to.
+    fn set_symlen(&self, d: &mut PairsData, sym: usize, visited: &mut [bool]) -> Result<u8> {
+        visited[sym] = true;
+        let (left, right) = self.children(d, sym)?;
+        if right == LEAF as usize {
+            return Ok(0);
+        }
+
+        for child in [left, right] {
+            if child >= visited.len() {
+                return Err(anyhow!("Invalid symbol {child} in table"));
+            }
+            if !visited[child] {
+                d.symlen[child] = self.set_symlen(d, child, visited)?;
+            }
+        }
+
+        Ok(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1))
+    }
+
+    /// The pair a symbol stands for, or its value and LEAF.
+    fn children(&self, d: &PairsData, sym: usize) -> Result<(usize, usize)> {
+        let lr = read(&self.bytes, d.btree + 3 * sym, 3)?;
+        let left = (lr[1] as usize & 0xf) << 8 | lr[0] as usize;
+        let right = (lr[2] as usize) << 4 | (lr[1] as usize) >> 4;
+        Ok((left, right))
+    }
+
+    fn block_length(&self, d: &PairsData, block: usize) -> Result<i64> {
+        if block >= d.block_length_size {
+            return Err(anyhow!("Block {block} is out of range"));
+        }
+        Ok(read_u16(&self.bytes, d.block_length + 2 * block)? as i64)
+    }
+
+    /// The value stored at index.
+    fn decompress(&self, d: &PairsData, index: u64) -> Result<u16> {
+        if d.flags & SINGLE_VALUE != 0 {
+            return Ok(d.min_sym_len as u16);
+        }
+
+        // Find the block from the nearest entry of the sparse index, which
+        // points at the value in the middle of every span
+        let k = (index / d.span) as usize;
+        if k >= d.sparse_index_size {
+            return Err(anyhow!("Index {index} is out of range"));
+        }
+        let entry = d.sparse_index + 6 * k;
+        let mut block = read_u32(&self.bytes, entry)? as usize;
+        let mut offset = read_u16(&self.bytes, entry + 4)? as i64;
+        offset += (index % d.span) as i64 - (d.span / 2) as i64;
+
+        while offset < 0 {
+            block = block
+                .checked_sub(1)
+                .ok_or_else(|| anyhow!("Index {index} is before the first block"))?;
+            offset += self.block_length(d, block)? + 1;
+        }
+        while offset > self.block_length(d, block)? {
+            offset -= self.block_length(d, block)? + 1;
+            block += 1;
+        }
+        if block >= d.num_blocks {
+            return Err(anyhow!("Index {index} is past the last block"));
+        }
+
+        // Skip the Huffman coded symbols before the one holding offset
+        let min_len = d.min_sym_len as usize;
+        let mut at = d.data + block * d.block_size;
+        let mut buf = read_be(&self.bytes, at, 8);
+        let mut buf_size = 64;
+        at += 8;
+
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < d.base64[len] {
+                len += 1;
+            }
+
+            sym = ((buf - d.base64[len]) >> (64 - len - min_len)) as usize;
+            sym += read_u16(&self.bytes, d.lowest_sym + 2 * len)? as usize;
+            let sym_len = *d
+                .symlen
+                .get(sym)
+                .ok_or_else(|| anyhow!("Invalid symbol {sym} in table"))?
+                as i64;
+
+            if offset <= sym_len {
+                break;
+            }
+
+            offset -= sym_len + 1;
+            len += min_len;
+            buf <<= len;
+            buf_size -= len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= read_be(&self.bytes, at, 4) << (64 - buf_size);
+                at += 4;
+            }
+        }
+
+        // Then go down the pairs to the value
+        for _ in 0..d.symlen.len() {
+            if d.symlen[sym] == 0 {
+                return Ok(self.children(d, sym)?.0 as u16);
+            }
+
+            let (left, right) = self.children(d, sym)?;
+            let left_len = *d
+                .symlen
+                .get(left)
+                .ok_or_else(|| anyhow!("Invalid symbol {left} in table"))?
+                as i64;
+            if offset <= left_len {
+                sym = left;
+            } else {
+                offset -= left_len + 1;
+                sym = right;
+            }
+
+            if sym >= d.symlen.len() {
+                return Err(anyhow!("Invalid symbol {sym} in table"));
+            }
+        }
+
+        Err(anyhow!("The pairs of the table loop"))
+    }
+
+    /// Where a position is stored: the side to move and file of the table
+    /// and the index in it. pieces are the codes and squares of all pieces
+    /// from a1 to h8. flipped is set when black has the pieces of the
+    /// first side of the table name.
+    fn index(
+        &self,
+        pieces: &[(u8, u8)],
+        black_to_move: bool,
+        flipped: bool,
+    ) -> (usize, usize, u64) {
+        let m = maps();
+
+        // Tables are stored with the first side of the name as white, and
+        // tables where both sides have the same pieces with white to move
+        let flip = flipped || (self.symmetric && black_to_move);
+        let (flip_color, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+        let stm = (flip != black_to_move) as usize;
+
+        let mut squares = [0; MAX_PIECES];
+        let mut codes = [0; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut file = 0;
+
+        // Tables with pawns have one part for each file of the leading
+        // pawn, the one closest to the edge and then to the first rank
+        let lead = self.items[0][0].pieces[0] ^ flip_color;
+        if self.has_pawns {
+            for (code, sq) in pieces.iter().filter(|(code, _)| *code == lead) {
+                squares[size] = sq ^ flip_squares;
+                codes[size] = code ^ flip_color;
+                size += 1;
+            }
+            lead_pawns = size;
+
+            let first = (0..lead_pawns)
+                .rev()
+                .max_by_key(|i| m.pawns[squares[*i] as usize])
+                .unwrap();
+            squares.swap(0, first);
+            file = file_of(squares[0]).min(7 - file_of(squares[0])) as usize;
+        }
+
+        for (code, sq) in pieces {
+            if !self.has_pawns || *code != lead {
+                squares[size] = sq ^ flip_squares;
+                codes[size] = code ^ flip_color;
+                size += 1;
+            }
+        }
+
+        let d = &self.items[stm % self.sides()][file];
+
+        // Put the pieces in the order of the table
+        for i in lead_pawns..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|j| codes[*j] == d.pieces[i]) {
+                codes.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        if file_of(squares[0]) > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 7;
+            }
+        }
+
+        let mut index;
+        if self.has_pawns {
+            index = m.lead_pawn_index[lead_pawns][squares[0] as usize];
+            squares[1..lead_pawns].sort_by_key(|sq| m.pawns[*sq as usize]);
+            for (i, sq) in squares[..lead_pawns].iter().enumerate().skip(1) {
+                index += m.binomial[i][m.pawns[*sq as usize] as usize];
+            }
+        } else {
+            if rank_of(squares[0]) > 3 {
+                for sq in &mut squares[..size] {
+                    *sq ^= 56;
+                }
+            }
+
+            // The first piece of the leading group off the diagonal goes below it
+            for i in 0..d.group_len[0] {
+                match off_diagonal(squares[i]) {
+                    0 => continue,
+                    o if o > 0 => {
+                        for sq in &mut squares[i..size] {
+                            *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                        }
+                    }
+                    _ => (),
+                }
+                break;
+            }
+
+            index = match self.has_unique_pieces {
+                true => unique_index(&squares),
+                false => m.kings[m.a1d1d4[squares[0] as usize] as usize][squares[1] as usize],
+            };
+        }
+
+        // The other groups as combinations of the squares that are left
+        index *= d.group_index[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort();
+
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let below = squares[..start].iter().filter(|s| sq > **s).count();
+                let free = (sq as usize).saturating_sub(below + 8 * remaining_pawns as usize);
+                n += m.binomial[i + 1][free];
+            }
+
+            remaining_pawns = false;
+            index += n * d.group_index[next];
+            start += len;
+            next += 1;
+        }
+
+        (stm % self.sides(), file, index)
+    }
+
+    /// The WDL value from -2 for a loss to 2 for a win.
+    pub(super) fn wdl(
+        &self,
+        pieces: &[(u8, u8)],
+        black_to_move: bool,
+        flipped: bool,
+    ) -> Result<i8> {
+        let (side, file, index) = self.index(pieces, black_to_move, flipped);
+        let value = self.decompress(&self.items[side][file], index)?;
+        Ok(value as i8 - 2)
+    }
+
+    /// DTZ in plies, None when the table only has the other side to move.
+    pub(super) fn dtz(
+        &self,
+        pieces: &[(u8, u8)],
+        black_to_move: bool,
+        flipped: bool,
+        wdl: i8,
+    ) -> Result<Option<i32>> {
+        let (_, file, index) = self.index(pieces, black_to_move, flipped);
+        let d = &self.items[0][file];
+
+        let flip = flipped || (self.symmetric && black_to_move);
+        let stm = (flip != black_to_move) as u8;
+        // Without pawns, tables where both sides have the same pieces have both
+        if d.flags & STM != stm && (self.has_pawns || !self.symmetric) {
+            return Ok(None);
+        }
+
+        let mut value = self.decompress(d, index)? as usize;
+
+        if d.flags & MAPPED != 0 {
+            // Wins, losses, cursed wins and blessed losses have a map each
+            let map = match wdl {
+                2 => 0,
+                -2 => 1,
+                1 => 2,
+                _ => 3,
+            };
+            let at = d.map_index[map] + value;
+            value = match d.flags & WIDE != 0 {
+                true => read_u16(&self.bytes, self.map + 2 * at)? as usize,
+                false => read_u8(&self.bytes, self.map + at)? as usize,
+            };
+        }
+
+        // Stored in moves unless the table says plies
+        let plies = match wdl {
+            2 => d.flags & WIN_PLIES != 0,
+            -2 => d.flags & LOSS_PLIES != 0,
+            _ => false,
+        };
+        if !plies {
+            value *= 2;
+        }
+
+        Ok(Some(value as i32 + 1))
+    }
+}
+
+/// Index of the three leading pieces when they all differ, the first in
+/// the a1-d1-d4 triangle and below the diagonal if it is on it.
+fn unique_index(sq: &[u8]) -> u64 {
+    let m = maps();
+    let adjust1 = (sq[1] > sq[0]) as u64;
+    let adjust2 = (sq[2] > sq[0]) as u64 + (sq[2] > sq[1]) as u64;
+    let rank = |s: u8| rank_of(s) as u64;
+
+    if off_diagonal(sq[0]) != 0 {
+        (m.a1d1d4[sq[0] as usize] * 63 + sq[1] as u64 - adjust1) * 62 + sq[2] as u64 - adjust2
+    } else if off_diagonal(sq[1]) != 0 {
+        (6 * 63 + rank(sq[0]) * 28 + m.b1h1h7[sq[1] as usize]) * 62 + sq[2] as u64 - adjust2
+    } else if off_diagonal(sq[2]) != 0 {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + rank(sq[0]) * 7 * 28
+            + (rank(sq[1]) - adjust1) * 28
+            + m.b1h1h7[sq[2] as usize]
+    } else {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + 4 * 7 * 28
+            + rank(sq[0]) * 7 * 6
+            + (rank(sq[1]) - adjust1) * 6
+            + rank(sq[2])
+            - adjust2
+    }
+}
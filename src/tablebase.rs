@@ -0,0 +1,981 @@
+use crate::board::bitboard::BitBoard;
+use crate::board::movegen::Move;
+use crate::board::piece::{self, Color, PieceType};
+use crate::board::Position;
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+const MAGIC: &[u8; 4] = b"SCTB";
+const VERSION: u8 = 1;
+const SUFFIX: &str = "sctb";
+
+// Black positions that can't be lost because black can take a piece
+const ESCAPE: u8 = u8::MAX;
+
+/// The material sets tables can be generated for. The first side has
+/// a king and the listed pieces, the other side only a king.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Material {
+    Kqk,
+    Krk,
+    Kpk,
+    Kbnk,
+}
+
+impl Material {
+    /// In the order they have to be generated, KPK needs KQK and KRK for promotions.
+    pub const ALL: [Material; 4] = [Material::Kqk, Material::Krk, Material::Kpk, Material::Kbnk];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Material::Kqk => "KQK",
+            Material::Krk => "KRK",
+            Material::Kpk => "KPK",
+            Material::Kbnk => "KBNK",
+        }
+    }
+
+    /// Pieces of the stronger side apart from the king.
+    fn pieces(self) -> &'static [PieceType] {
+        match self {
+            Material::Kqk => &[PieceType::Queen],
+            Material::Krk => &[PieceType::Rook],
+            Material::Kpk => &[PieceType::Pawn],
+            Material::Kbnk => &[PieceType::Bishop, PieceType::Knight],
+        }
+    }
+
+    /// The table a pawn promoting to p leads into. None for a bishop or a
+    /// knight, a lone minor piece can't mate so those positions are draws.
+    fn promoted_to(p: PieceType) -> Option<Material> {
+        match p {
+            PieceType::Queen => Some(Material::Kqk),
+            PieceType::Rook => Some(Material::Krk),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u8 {
+        Material::ALL.iter().position(|m| *m == self).unwrap() as u8
+    }
+
+    /// Number of indices: a square for every piece and the side to move.
+    fn size(self) -> usize {
+        2 * self.half()
+    }
+
+    /// Indices below this have white to move.
+    fn half(self) -> usize {
+        1 << (6 * (2 + self.pieces().len()))
+    }
+
+    /// Board symmetries that keep results the same: all eight without
+    /// pawns, only the mirror between the wings with them.
+    fn symmetries(self) -> u8 {
+        match self.pieces().contains(&PieceType::Pawn) {
+            true => 2,
+            false => 8,
+        }
+    }
+
+    /// White king squares stored in files, every other position is a
+    /// reflection of one of these.
+    fn king_squares(self) -> Vec<u8> {
+        (0..64)
+            .filter(|sq| {
+                let (file, rank) = (sq % 8, sq / 8);
+                match self.symmetries() {
+                    2 => file < 4,
+                    _ => file < 4 && rank <= file,
+                }
+            })
+            .collect()
+    }
+
+    /// The material of pos, and whether the colours have to be swapped
+    /// because black is the stronger side.
+    pub(crate) fn of(pos: &Position) -> Option<(Material, bool)> {
+        let strong = |c: Color| {
+            let pieces = pos.pieces(c);
+            Material::ALL.into_iter().find(|m| {
+                PieceType::ALL.into_iter().all(|p| {
+                    let expected = match p {
+                        PieceType::King => 1,
+                        _ => m.pieces().iter().filter(|x| **x == p).count() as u32,
+                    };
+                    pieces.get_board(p).count() == expected
+                })
+            })
+        };
+        let bare = |c: Color| pos.pieces_all(c).count() == 1;
+
+        match (strong(Color::White), strong(Color::Black)) {
+            (Some(m), _) if bare(Color::Black) => Some((m, false)),
+            (_, Some(m)) if bare(Color::White) => Some((m, true)),
+            _ => None,
+        }
+    }
+}
+
+/// Result for the side to move in moves, Loss(0) means it is mated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dtm {
+    Win(u32),
+    Draw,
+    Loss(u32),
+}
+
+impl Dtm {
+    /// Higher is better for the side to move.
+    fn rank(self) -> i64 {
+        match self {
+            Dtm::Win(n) => 1000 - n as i64,
+            Dtm::Draw => 0,
+            Dtm::Loss(n) => -1000 + n as i64,
+        }
+    }
+
+    /// The result one move earlier, seen from the side that made the move.
+    fn before(self) -> Dtm {
+        match self {
+            Dtm::Win(n) => Dtm::Loss(n),
+            Dtm::Draw => Dtm::Draw,
+            Dtm::Loss(n) => Dtm::Win(n + 1),
+        }
+    }
+}
+
+fn square_bit(sq: u8) -> u64 {
+    1 << sq
+}
+
+/// Iterates over the squares set in b.
+fn bits(mut b: u64) -> impl Iterator<Item = u8> {
+    std::iter::from_fn(move || {
+        let sq = b.trailing_zeros() as u8;
+        b &= b.wrapping_sub(1);
+        (sq < 64).then_some(sq)
+    })
+}
+
+fn king_steps(sq: u8) -> u64 {
+    attacks(PieceType::King, sq, 0)
+}
+
+/// Squares a white piece on sq attacks, from the same attack maps the
+/// move generator uses.
+fn board_attacks(p: PieceType, sq: u8, occupied: u64) -> u64 {
+    let piece = BitBoard::from(square_bit(sq));
+    let blockers = BitBoard::from(occupied & !square_bit(sq));
+    let map = piece::get_piece_attack_map(p, Color::White, &piece, &blockers, &BitBoard::new());
+
+    // The king's map includes its own square
+    map.as_u64() & !square_bit(sq)
+}
+
+/// board_attacks with the maps on an empty board looked up, generation
+/// asks for the same few thousand of them millions of times.
+pub(crate) fn attacks(p: PieceType, sq: u8, occupied: u64) -> u64 {
+    static EMPTY: OnceLock<Vec<[u64; 64]>> = OnceLock::new();
+    let empty = EMPTY.get_or_init(|| {
+        PieceType::ALL
+            .iter()
+            .map(|p| std::array::from_fn(|sq| board_attacks(*p, sq as u8, 0)))
+            .collect()
+    });
+
+    let map = empty[p.index()][sq as usize];
+    match p {
+        PieceType::Queen | PieceType::Rook | PieceType::Bishop if map & occupied != 0 => {
+            board_attacks(p, sq, occupied)
+        }
+        _ => map,
+    }
+}
+
+/// A decoded index. The stronger side is always white here.
+///
+/// Generating a table walks every index forwards and backwards, millions
+/// of times for KBNK. Position keeps a full board, castling rights, move
+/// history and a key up to date on every move, which makes it orders of
+/// magnitude slower for this than a few squares in an array and the
+/// attack tables below. Position is only used at the edges, to read the
+/// index of a real position and to check the moves here in the tests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Setup {
+    /// The white king first, then the pieces in the order of Material::pieces.
+    white: [(PieceType, u8); 3],
+    count: usize,
+    black_king: u8,
+    white_to_move: bool,
+}
+
+impl Setup {
+    fn decode(material: Material, mut index: usize) -> Setup {
+        let white_to_move = index < material.half();
+        index %= material.half();
+
+        let pieces = material.pieces();
+        let mut white = [(PieceType::King, 0); 3];
+        for i in (0..pieces.len()).rev() {
+            white[i + 1] = (pieces[i], (index & 63) as u8);
+            index >>= 6;
+        }
+
+        let black_king = (index & 63) as u8;
+        white[0].1 = (index >> 6) as u8;
+
+        Setup {
+            white,
+            count: pieces.len() + 1,
+            black_king,
+            white_to_move,
+        }
+    }
+
+    fn encode(&self) -> usize {
+        let mut index = self.white[0].1 as usize * 64 + self.black_king as usize;
+        for (_, sq) in self.pieces().iter().skip(1) {
+            index = index * 64 + *sq as usize;
+        }
+
+        // Keeping the sides apart gives longer runs in the files
+        match self.white_to_move {
+            true => index,
+            false => index + (1 << (6 * (self.count + 1))),
+        }
+    }
+
+    fn pieces(&self) -> &[(PieceType, u8)] {
+        &self.white[..self.count]
+    }
+
+    fn white_occupied(&self) -> u64 {
+        self.pieces()
+            .iter()
+            .fold(0, |b, (_, sq)| b | square_bit(*sq))
+    }
+
+    /// Squares white attacks, leaving out the piece with index skip.
+    /// The black king doesn't block, so it can't step back along a line.
+    fn attacked_squares(&self, skip: Option<usize>) -> u64 {
+        let occupied = self.white_occupied();
+
+        self.pieces()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != skip)
+            .fold(0, |b, (_, (p, from))| b | attacks(*p, *from, occupied))
+    }
+
+    fn attacked(&self, sq: u8, skip: Option<usize>) -> bool {
+        self.attacked_squares(skip) & square_bit(sq) != 0
+    }
+
+    fn is_valid(&self) -> bool {
+        let occupied = self.white_occupied() | square_bit(self.black_king);
+        if occupied.count_ones() as usize != self.count + 1 {
+            return false;
+        }
+
+        let pawn_on_edge = self
+            .pieces()
+            .iter()
+            .any(|(p, sq)| *p == PieceType::Pawn && (*sq < 8 || *sq >= 56));
+        let kings_touch = king_steps(self.white[0].1) & square_bit(self.black_king) != 0;
+
+        // Black can't be in check with white to move
+        let black_in_check = self.white_to_move && self.attacked(self.black_king, None);
+        !(pawn_on_edge || kings_touch || black_in_check)
+    }
+
+    /// Legal black moves that stay in the table, whether black can take
+    /// a piece instead and whether black is in check.
+    fn black_moves(&self) -> (u8, bool, bool) {
+        let mut count = 0;
+        let mut capture = false;
+        let attacked = self.attacked_squares(None);
+
+        for to in bits(king_steps(self.black_king)) {
+            match self.pieces().iter().position(|(_, sq)| *sq == to) {
+                Some(i) => capture |= !self.attacked(to, Some(i)),
+                None if attacked & square_bit(to) == 0 => count += 1,
+                None => (),
+            }
+        }
+
+        (count, capture, attacked & square_bit(self.black_king) != 0)
+    }
+
+    /// White to move positions black could have come from.
+    fn black_unmoves(&self) -> Vec<Setup> {
+        let occupied = self.white_occupied();
+        let near_white_king = king_steps(self.white[0].1);
+
+        bits(king_steps(self.black_king) & !(occupied | near_white_king))
+            .map(|from| Setup {
+                black_king: from,
+                white_to_move: false,
+                ..*self
+            })
+            .collect()
+    }
+
+    /// White to move positions white could have come from.
+    fn white_unmoves(&self) -> Vec<Setup> {
+        let occupied = self.white_occupied() | square_bit(self.black_king);
+        let mut out = vec![];
+
+        for (i, (p, sq)) in self.pieces().iter().enumerate() {
+            let from = match p {
+                PieceType::Pawn => {
+                    let mut b = 0;
+                    let one = sq - 8;
+                    if occupied & square_bit(one) == 0 && one >= 8 {
+                        b |= square_bit(one);
+                        if sq / 8 == 3 && occupied & square_bit(one - 8) == 0 {
+                            b |= square_bit(one - 8);
+                        }
+                    }
+                    b
+                }
+                PieceType::King => king_steps(*sq) & !king_steps(self.black_king),
+                _ => attacks(*p, *sq, occupied),
+            } & !occupied;
+
+            for from in bits(from) {
+                let mut prev = *self;
+                prev.white[i].1 = from;
+                prev.white_to_move = true;
+
+                if !prev.attacked(prev.black_king, None) {
+                    out.push(prev);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Black to move positions after a white pawn promotes, with the new piece.
+    fn promotions(&self) -> Vec<(PieceType, Setup)> {
+        let mut out = vec![];
+
+        for (i, (p, sq)) in self.pieces().iter().enumerate() {
+            if *p != PieceType::Pawn || *sq < 48 {
+                continue;
+            }
+
+            let to = sq + 8;
+            if (self.white_occupied() | square_bit(self.black_king)) & square_bit(to) != 0 {
+                continue;
+            }
+
+            for promotion in [
+                PieceType::Queen,
+                PieceType::Rook,
+                PieceType::Bishop,
+                PieceType::Knight,
+            ] {
+                let mut next = *self;
+                next.white[i] = (promotion, to);
+                next.white_to_move = false;
+                out.push((promotion, next));
+            }
+        }
+
+        out
+    }
+
+    /// Bit 0 mirrors files, bit 1 ranks and bit 2 the long diagonal.
+    fn reflect(&self, symmetry: u8) -> Setup {
+        let square = |sq: u8| {
+            let (mut file, mut rank) = (sq % 8, sq / 8);
+            if symmetry & 4 != 0 {
+                (file, rank) = (rank, file);
+            }
+            if symmetry & 1 != 0 {
+                file = 7 - file;
+            }
+            if symmetry & 2 != 0 {
+                rank = 7 - rank;
+            }
+            rank * 8 + file
+        };
+
+        let mut out = *self;
+        for (_, sq) in out.white[..self.count].iter_mut() {
+            *sq = square(*sq);
+        }
+        out.black_king = square(self.black_king);
+        out
+    }
+
+    fn from_position(material: Material, pos: &Position, flipped: bool) -> Setup {
+        let (strong, weak) = match flipped {
+            false => (Color::White, Color::Black),
+            true => (Color::Black, Color::White),
+        };
+        // Mirror the board when black is the stronger side
+        let square = |board: crate::board::bitboard::BitBoard| {
+            let sq = board.squares()[0];
+            let rank = if flipped { 7 - sq.rank } else { sq.rank };
+            (rank * 8 + sq.file) as u8
+        };
+
+        let mut white = [(PieceType::King, 0); 3];
+        white[0].1 = square(pos.pieces(strong).get_board(PieceType::King));
+
+        let mut pieces = *pos.pieces(strong);
+        for (i, p) in material.pieces().iter().enumerate() {
+            white[i + 1] = (*p, square(pieces.get_board(*p)));
+            // Takes care of two pieces of the same type
+            let sq = pieces.get_board(*p).squares()[0];
+            pieces.unset(*p, sq.file, sq.rank);
+        }
+
+        Setup {
+            white,
+            count: material.pieces().len() + 1,
+            black_king: square(pos.pieces(weak).get_board(PieceType::King)),
+            white_to_move: pos.side == strong,
+        }
+    }
+}
+
+/// Distance to mate for every position of one material set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    pub material: Material,
+    /// 0 for draws and impossible positions, otherwise plies to mate + 1.
+    dtm: Vec<u8>,
+}
+
+impl Table {
+    /// Builds the table by retrograde analysis, starting from the mates and
+    /// working back one ply at a time. tables has to hold the tables
+    /// promotions lead into.
+    pub(crate) fn generate(material: Material, tables: &Tablebase) -> Result<Table> {
+        let size = material.size();
+        let half = material.half();
+        let mut dtm = vec![0u8; size];
+        // Moves left for black before the position is lost, by index - half
+        let mut counters = vec![0u8; half];
+
+        // Positions to process for every ply
+        let mut plies: Vec<Vec<u32>> = vec![vec![]];
+        // White positions that mate through a promotion in a given ply
+        let mut promotions: Vec<Vec<u32>> = vec![];
+
+        for index in 0..size {
+            let setup = Setup::decode(material, index);
+            if !setup.is_valid() {
+                continue;
+            }
+
+            if !setup.white_to_move {
+                let (count, capture, in_check) = setup.black_moves();
+                counters[index - half] = if capture { ESCAPE } else { count };
+                if count == 0 && !capture && in_check {
+                    dtm[index] = 1;
+                    plies[0].push(index as u32);
+                }
+                continue;
+            }
+
+            for (p, next) in setup.promotions() {
+                let Some(into) = Material::promoted_to(p) else {
+                    continue;
+                };
+                let table = tables.get(into).ok_or_else(|| {
+                    anyhow!("{} needs the {} table", material.name(), into.name())
+                })?;
+
+                let value = table.dtm[next.encode()];
+                if value > 0 {
+                    let ply = value as usize;
+                    promotions.resize(promotions.len().max(ply + 1), vec![]);
+                    promotions[ply].push(index as u32);
+                }
+            }
+        }
+
+        let mut ply = 0;
+        while ply < promotions.len() || plies.get(ply).is_some_and(|p| !p.is_empty()) {
+            plies.resize(plies.len().max(ply + 2), vec![]);
+
+            for &index in promotions.get(ply).unwrap_or(&vec![]) {
+                if dtm[index as usize] == 0 {
+                    dtm[index as usize] = ply as u8 + 1;
+                    plies[ply].push(index);
+                }
+            }
+
+            for index in std::mem::take(&mut plies[ply]) {
+                let setup = Setup::decode(material, index as usize);
+
+                if setup.white_to_move {
+                    for prev in setup.black_unmoves() {
+                        let i = prev.encode();
+                        if dtm[i] != 0 || counters[i - half] == ESCAPE {
+                            continue;
+                        }
+
+                        // Lost once every move leads to a white win
+                        counters[i - half] -= 1;
+                        if counters[i - half] == 0 {
+                            dtm[i] = ply as u8 + 2;
+                            plies[ply + 1].push(i as u32);
+                        }
+                    }
+                } else {
+                    for prev in setup.white_unmoves() {
+                        let i = prev.encode();
+                        if dtm[i] == 0 {
+                            dtm[i] = ply as u8 + 2;
+                            plies[ply + 1].push(i as u32);
+                        }
+                    }
+                }
+            }
+
+            ply += 1;
+        }
+
+        Ok(Table { material, dtm })
+    }
+
+    fn lookup(&self, setup: &Setup) -> Dtm {
+        match self.dtm[setup.encode()] {
+            0 => Dtm::Draw,
+            v if setup.white_to_move => Dtm::Win(v as u32 / 2),
+            v => Dtm::Loss((v as u32 - 1) / 2),
+        }
+    }
+
+    /// Longest mate in moves with the stronger side to move.
+    pub fn max_dtm(&self) -> u32 {
+        self.dtm[..self.material.half()]
+            .iter()
+            .map(|v| *v as u32 / 2)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Only positions with the white king on one of Material::king_squares
+    /// are stored, as run length encoded distances in moves: a byte and
+    /// the length of its run as a LEB128 number. Impossible positions join
+    /// whatever run they are in.
+    pub(crate) fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION, self.material.id()])?;
+
+        let kings = self.material.king_squares();
+        let moves: Vec<Option<u8>> = (0..self.dtm.len())
+            .filter_map(|i| {
+                let setup = Setup::decode(self.material, i);
+                kings
+                    .contains(&setup.white[0].1)
+                    .then(|| setup.is_valid().then(|| self.dtm[i].div_ceil(2)))
+            })
+            .collect();
+
+        let mut i = 0;
+        while i < moves.len() {
+            let value = moves[i..].iter().find_map(|v| *v).unwrap_or(0);
+            let run = moves[i..]
+                .iter()
+                .take_while(|v| v.is_none_or(|v| v == value))
+                .count();
+            i += run;
+
+            out.write_all(&[value])?;
+            let mut n = run;
+            loop {
+                let byte = (n & 0x7f) as u8;
+                n >>= 7;
+                if n == 0 {
+                    out.write_all(&[byte])?;
+                    break;
+                }
+                out.write_all(&[byte | 0x80])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read<R: Read>(input: &mut R) -> Result<Table> {
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+
+        if bytes.len() < 6 || &bytes[..4] != MAGIC {
+            return Err(anyhow!("Not an endgame table"));
+        }
+        if bytes[4] != VERSION {
+            return Err(anyhow!("Unsupported endgame table version {}", bytes[4]));
+        }
+        let material = *Material::ALL
+            .get(bytes[5] as usize)
+            .ok_or_else(|| anyhow!("Unknown material {} in endgame table", bytes[5]))?;
+
+        let kings = material.king_squares();
+        // Positions for every white king square
+        let block = material.half() / 64;
+        let stored = 2 * kings.len() * block;
+
+        let mut moves = Vec::with_capacity(stored);
+        let mut rest = bytes[6..].iter();
+        while let Some(&value) = rest.next() {
+            let mut run = 0;
+            for shift in (0..).step_by(7) {
+                let byte = *rest
+                    .next()
+                    .ok_or_else(|| anyhow!("Endgame table ends in the middle of a run"))?;
+                run |= ((byte & 0x7f) as usize) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            moves.resize(moves.len() + run, value);
+        }
+
+        if moves.len() != stored {
+            return Err(anyhow!(
+                "{} table has {} entries instead of {stored}",
+                material.name(),
+                moves.len(),
+            ));
+        }
+
+        let dtm = (0..material.size())
+            .map(|i| {
+                let setup = Setup::decode(material, i);
+                if !setup.is_valid() {
+                    return 0;
+                }
+
+                let stored = (0..material.symmetries())
+                    .map(|s| setup.reflect(s))
+                    .find(|s| kings.contains(&s.white[0].1))
+                    .unwrap();
+                let king = kings.iter().position(|k| *k == stored.white[0].1).unwrap();
+                let side = if stored.white_to_move { 0 } else { kings.len() };
+                let v = moves[(side + king) * block + stored.encode() % block];
+
+                match v {
+                    0 => 0,
+                    v if setup.white_to_move => 2 * v,
+                    v => 2 * v - 1,
+                }
+            })
+            .collect();
+
+        Ok(Table { material, dtm })
+    }
+}
+
+/// A set of generated tables.
+#[derive(Clone, Debug, Default)]
+pub struct Tablebase {
+    tables: Vec<Table>,
+}
+
+impl Tablebase {
+    pub fn generate(materials: &[Material]) -> Result<Tablebase> {
+        let mut tb = Tablebase::default();
+        for m in materials {
+            let table = Table::generate(*m, &tb)?;
+            tb.tables.push(table);
+        }
+
+        Ok(tb)
+    }
+
+    pub fn get(&self, material: Material) -> Option<&Table> {
+        self.tables.iter().find(|t| t.material == material)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        for table in &self.tables {
+            let path = dir.join(format!("{}.{SUFFIX}", table.material.name()));
+            let mut out = BufWriter::new(File::create(path)?);
+            table.write(&mut out)?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the tables that exist in dir.
+    pub fn load(dir: &Path) -> Result<Tablebase> {
+        let mut tb = Tablebase::default();
+
+        for m in Material::ALL {
+            let path = dir.join(format!("{}.{SUFFIX}", m.name()));
+            if !path.exists() {
+                continue;
+            }
+
+            let table = Table::read(&mut BufReader::new(File::open(&path)?))
+                .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+            tb.tables.push(table);
+        }
+
+        Ok(tb)
+    }
+
+    /// Distance to mate for the side to move, None without a table for the material.
+    pub fn probe(&self, pos: &Position) -> Option<Dtm> {
+        let (material, flipped) = Material::of(pos)?;
+        let table = self.get(material)?;
+
+        Some(table.lookup(&Setup::from_position(material, pos, flipped)))
+    }
+
+    /// The move that wins fastest, or loses slowest. Captures and promotions
+    /// to a piece without a table count as draws.
+    pub fn best_move(&self, pos: &mut Position) -> Option<(Move, Dtm)> {
+        self.probe(pos)?;
+
+        let mut best: Option<(Move, Dtm)> = None;
+        for m in pos.generate_legal_moves() {
+            pos.change_board(m);
+            let dtm = self.probe(pos).unwrap_or(Dtm::Draw).before();
+            pos.reverse_last_change();
+
+            if best.is_none_or(|(_, b)| dtm.rank() > b.rank()) {
+                best = Some((m, dtm));
+            }
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::board::parse;
+
+    fn pos(fen: &str) -> Position {
+        parse::from_fen(fen.to_string()).unwrap()
+    }
+
+    /// KQK, KRK and KPK, generated once for all tests.
+    pub(crate) fn tables() -> &'static Tablebase {
+        static TABLES: OnceLock<Tablebase> = OnceLock::new();
+        TABLES.get_or_init(|| {
+            Tablebase::generate(&[Material::Kqk, Material::Krk, Material::Kpk]).unwrap()
+        })
+    }
+
+    /// FEN for a setup, to compare with the move generator of Position.
+    fn fen(setup: &Setup) -> String {
+        let mut board = [' '; 64];
+        for (p, sq) in setup.pieces() {
+            board[*sq as usize] = p.to_char(Color::White);
+        }
+        board[setup.black_king as usize] = 'k';
+
+        let mut out = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match board[rank * 8 + file] {
+                    ' ' => empty += 1,
+                    c => {
+                        if empty > 0 {
+                            out.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        out.push(c);
+                    }
+                }
+            }
+            if empty > 0 {
+                out.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                out.push('/');
+            }
+        }
+
+        let side = if setup.white_to_move { 'w' } else { 'b' };
+        format!("{out} {side} - - 0 1")
+    }
+
+    /// Plays the best moves for both sides and checks that the game
+    /// ends in mate after exactly as many moves as predicted.
+    fn play_out(tb: &Tablebase, fen: &str) {
+        let mut p = pos(fen);
+        let plies = match tb.probe(&p) {
+            Some(Dtm::Win(n)) => 2 * n - 1,
+            Some(Dtm::Loss(n)) => 2 * n,
+            _ => panic!("{fen} should be decided"),
+        };
+
+        for _ in 0..plies {
+            let (m, _) = tb.best_move(&mut p).unwrap();
+            p.change_board(m);
+        }
+
+        assert!(p.generate_legal_moves().is_empty());
+        assert!(p.is_in_check(p.side));
+    }
+
+    #[test]
+    fn index() {
+        for material in Material::ALL {
+            for index in (0..material.size()).step_by(9973) {
+                assert_eq!(Setup::decode(material, index).encode(), index);
+            }
+        }
+
+        let p = pos("8/8/8/3k4/8/8/1B6/K1N5 b - - 0 1");
+        let setup = Setup::from_position(Material::Kbnk, &p, false);
+        assert_eq!(fen(&setup), "8/8/8/3k4/8/8/1B6/K1N5 b - - 0 1");
+
+        // Black is the stronger side, so the board is mirrored
+        let p = pos("8/3p4/8/8/8/8/2k5/K7 w - - 0 1");
+        assert_eq!(Material::of(&p), Some((Material::Kpk, true)));
+        let setup = Setup::from_position(Material::Kpk, &p, true);
+        assert_eq!(fen(&setup), "k7/2K5/8/8/8/8/3P4/8 b - - 0 1");
+
+        assert_eq!(Material::of(&pos("8/8/8/3k4/8/8/1Q6/K1N5 b - - 0 1")), None);
+    }
+
+    #[test]
+    fn black_moves_match_movegen() {
+        // A few hundred positions of each table, checked against Position
+        for material in Material::ALL {
+            let step = material.half() / 300 + 1;
+            for index in (material.half()..material.size()).step_by(step) {
+                let setup = Setup::decode(material, index);
+                if !setup.is_valid() {
+                    continue;
+                }
+
+                let mut p = pos(&fen(&setup));
+                let (count, capture, in_check) = setup.black_moves();
+                let legal = p.generate_legal_moves();
+                let captures = legal.iter().filter(|m| m.change.captured.is_some()).count();
+
+                assert_eq!(legal.len() - captures, count as usize, "{}", fen(&setup));
+                assert_eq!(captures > 0, capture, "{}", fen(&setup));
+                assert_eq!(p.is_in_check(Color::Black), in_check);
+            }
+        }
+    }
+
+    #[test]
+    fn kqk_and_krk() {
+        let tb = tables();
+
+        // Longest mates known from the literature
+        assert_eq!(tb.get(Material::Kqk).unwrap().max_dtm(), 10);
+        assert_eq!(tb.get(Material::Krk).unwrap().max_dtm(), 16);
+
+        assert_eq!(
+            tb.probe(&pos("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1")),
+            Some(Dtm::Win(1))
+        );
+        assert_eq!(
+            tb.probe(&pos("k6Q/8/1K6/8/8/8/8/8 b - - 0 1")),
+            Some(Dtm::Loss(0))
+        );
+        assert_eq!(
+            tb.probe(&pos("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1")),
+            Some(Dtm::Draw)
+        );
+        // Black takes the undefended rook
+        assert_eq!(
+            tb.probe(&pos("8/8/8/8/4k3/8/1r6/K7 w - - 0 1")),
+            Some(Dtm::Draw)
+        );
+        assert_eq!(
+            tb.probe(&pos("8/8/8/8/8/1k6/7r/K7 b - - 0 1")),
+            Some(Dtm::Win(1))
+        );
+
+        play_out(tb, "8/8/3k4/8/8/8/8/R3K3 w - - 0 1");
+    }
+
+    #[test]
+    fn kpk() {
+        let tb = tables();
+        assert_eq!(tb.get(Material::Kpk).unwrap().max_dtm(), 28);
+
+        // Opposition decides
+        assert_eq!(
+            tb.probe(&pos("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1")),
+            Some(Dtm::Draw)
+        );
+        play_out(tb, "8/4k3/8/4K3/4P3/8/8/8 b - - 0 1");
+        // Rook pawn
+        assert_eq!(
+            tb.probe(&pos("k7/8/1K6/P7/8/8/8/8 w - - 0 1")),
+            Some(Dtm::Draw)
+        );
+
+        // Promoting to a queen stalemates, to a rook wins
+        let mut p = pos("8/5P1k/8/6K1/8/8/8/8 w - - 0 1");
+        assert!(matches!(tb.probe(&p), Some(Dtm::Win(_))));
+        for (uci, won) in [("f7f8q", false), ("f7f8r", true)] {
+            let m = p.parse_uci(uci).unwrap();
+            p.change_board(m);
+            assert_eq!(matches!(tb.probe(&p), Some(Dtm::Loss(_))), won, "{uci}");
+            p.reverse_last_change();
+        }
+
+        // Every promotion is there, a bishop or a knight can't mate
+        let setup = Setup::from_position(Material::Kpk, &p, false);
+        let promotions: Vec<_> = setup.promotions().iter().map(|(p, _)| *p).collect();
+        assert_eq!(
+            promotions,
+            [
+                PieceType::Queen,
+                PieceType::Rook,
+                PieceType::Bishop,
+                PieceType::Knight
+            ]
+        );
+        let m = p.parse_uci("f7f8n").unwrap();
+        p.change_board(m);
+        assert_eq!(tb.probe(&p), None);
+        p.reverse_last_change();
+    }
+
+    #[test]
+    #[ignore = "takes a while without optimisations, run with --release --ignored"]
+    fn kbnk() {
+        let tb = Tablebase::generate(&[Material::Kbnk]).unwrap();
+        assert_eq!(tb.get(Material::Kbnk).unwrap().max_dtm(), 33);
+    }
+
+    #[test]
+    fn files() {
+        let tb = tables();
+        let table = tb.get(Material::Kqk).unwrap();
+
+        let mut bytes = vec![];
+        table.write(&mut bytes).unwrap();
+        assert!(bytes.len() < table.dtm.len() / 10);
+        assert_eq!(&Table::read(&mut bytes.as_slice()).unwrap(), table);
+
+        assert!(Table::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        assert!(Table::read(&mut &b"SCTB"[..]).is_err());
+
+        let dir = std::env::temp_dir().join(format!("sctb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        tb.save(&dir).unwrap();
+        let loaded = Tablebase::load(&dir).unwrap();
+        assert_eq!(loaded.get(Material::Kqk), Some(table));
+        assert_eq!(loaded.get(Material::Kpk), tb.get(Material::Kpk));
+        assert_eq!(loaded.get(Material::Kbnk), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+use crate::board::epd::{self, Epd};
+use crate::board::movegen::Move;
+use crate::board::Position;
+use crate::mate::MateSearch;
+use crate::search::Search;
+use crate::timeman::Limits;
+use crate::uci::Info;
+use anyhow::{anyhow, Result};
+use std::fmt::{self, Display};
+use std::io::BufRead;
+use std::time::Duration;
+
+/// How long every position gets searched for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Depth(u32),
+    Time(Duration),
+}
+
+impl Limit {
+    /// The limit as a go command would give it.
+    pub fn limits(self) -> Limits {
+        match self {
+            Limit::Depth(d) => Limits {
+                depth: Some(d.into()),
+                ..Limits::default()
+            },
+            Limit::Time(t) => Limits {
+                movetime: Some(t.as_millis() as u64),
+                ..Limits::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PositionResult {
+    pub id: String,
+    /// SAN of the move the solver picked.
+    pub found: Option<String>,
+    pub expected: String,
+    pub solved: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub results: Vec<PositionResult>,
+}
+
+impl Report {
+    pub fn solved(&self) -> usize {
+        self.results.iter().filter(|r| r.solved).count()
+    }
+
+    pub fn total(&self) -> usize {
+        self.results.len()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.results {
+            let status = if r.solved { "solved" } else { "failed" };
+            let found = r.found.as_deref().unwrap_or("none");
+            writeln!(
+                f,
+                "{:<16} {status}  found {found:<8} expected {}",
+                r.id, r.expected
+            )?;
+        }
+
+        write!(f, "Score: {}/{}", self.solved(), self.total())
+    }
+}
+
+/// Reads one EPD per line, skipping blank lines and lines starting with '#'.
+pub fn read_suite<R: BufRead>(reader: R) -> Result<Vec<Epd>> {
+    let mut out = vec![];
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        out.push(epd::from_epd(line).map_err(|e| anyhow!("Line {}: {e}", i + 1))?);
+    }
+
+    Ok(out)
+}
+
+/// Lets solve pick a move in every position and checks it against the
+/// bm and am operations, and that it mates in time when there is a dm.
+pub fn run<F>(suite: &[Epd], limit: Limit, mut solve: F) -> Report
+where
+    F: FnMut(&mut Position, Limit) -> Option<Move>,
+{
+    let mut report = Report::default();
+
+    for (i, entry) in suite.iter().enumerate() {
+        let id = entry
+            .id()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("#{}", i + 1));
+
+        // A bad bm, am or dm only fails its own position
+        let operations = (entry.best_moves(), entry.avoid_moves(), entry.direct_mate());
+        let (best, avoid, mate) = match operations {
+            (Ok(best), Ok(avoid), Ok(mate))
+                if !best.is_empty() || !avoid.is_empty() || mate.is_some() =>
+            {
+                (best, avoid, mate)
+            }
+            (Ok(_), Ok(_), Ok(_)) => {
+                report.results.push(invalid(id, "neither bm, am nor dm"));
+                continue;
+            }
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                report.results.push(invalid(id, &e.to_string()));
+                continue;
+            }
+        };
+
+        let mut expected = vec![];
+        if !best.is_empty() {
+            expected.push(format!(
+                "bm {}",
+                entry.get("bm").unwrap_or_default().join(" ")
+            ));
+        }
+        if !avoid.is_empty() {
+            expected.push(format!(
+                "am {}",
+                entry.get("am").unwrap_or_default().join(" ")
+            ));
+        }
+        if let Some(n) = mate {
+            expected.push(format!("dm {n}"));
+        }
+
+        let mut pos = entry.position.clone();
+        let found = solve(&mut pos, limit);
+
+        let mut pos = entry.position.clone();
+        let solved = match found {
+            Some(m) => {
+                (best.is_empty() || best.iter().any(|b| b.same_as(&m)))
+                    && !avoid.iter().any(|a| a.same_as(&m))
+                    && mate.is_none_or(|n| MateSearch::new().mates_with(&mut pos, m, n))
+            }
+            None => false,
+        };
+
+        report.results.push(PositionResult {
+            id,
+            found: found.map(|m| pos.move_to_san(&m)),
+            expected: expected.join(", "),
+            solved,
+        });
+    }
+
+    report
+}
+
+fn invalid(id: String, reason: &str) -> PositionResult {
+    PositionResult {
+        id,
+        found: None,
+        expected: format!("invalid ({reason})"),
+        solved: false,
+    }
+}
+
+/// Picks a move with the engine's search.
+pub fn solve(pos: &mut Position, limit: Limit) -> Option<Move> {
+    Search::new()
+        .go(pos, &limit.limits(), &mut |_: &Info| ())
+        .best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: &str = r#"
+# WAC.001 and two made up positions
+2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";
+r1b1k2r/ppppnppp/2n2q2/2b5/3NP3/2P1B3/PP3PPP/RN1QKB1R w KQkq - bm Nb5; id "scotch";
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - am f3 g4; id "start";
+"#;
+
+    #[test]
+    fn run() {
+        let suite = read_suite(SUITE.as_bytes()).unwrap();
+        assert_eq!(suite.len(), 3);
+
+        // Always plays the first legal move in UCI order
+        let report = super::run(&suite, Limit::Depth(1), |pos, _| {
+            let mut moves = pos.generate_legal_moves();
+            moves.sort_by_key(|m| m.to_string());
+            moves.into_iter().next()
+        });
+
+        assert_eq!(report.total(), 3);
+        assert!(report.results[2].solved);
+        assert_eq!(report.results[2].expected, "am f3 g4");
+
+        // Plays the first best move given
+        let report = super::run(&suite, Limit::Time(Duration::from_millis(10)), |pos, _| {
+            let entry = suite
+                .iter()
+                .find(|e| e.position.w_pieces == pos.w_pieces)
+                .unwrap();
+            entry.best_moves().unwrap().into_iter().next()
+        });
+        assert_eq!(report.solved(), 2);
+        assert_eq!(report.results[0].found.as_deref(), Some("Qg6"));
+        assert!(report.to_string().ends_with("Score: 2/3"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(read_suite("8/8/8/8/8/8/8/K6k w".as_bytes()).is_err());
+
+        // A position without anything to check only fails itself
+        let suite = "8/8/8/8/8/8/8/K6k w - - id \"x\";\n\
+                     8/8/8/8/8/8/8/K6k w - - bm Kb1; id \"y\";";
+        let suite = read_suite(suite.as_bytes()).unwrap();
+        let report = super::run(&suite, Limit::Depth(1), |pos, _| {
+            pos.legal_moves()
+                .iter()
+                .find(|m| m.to.file == 1 && m.to.rank == 0)
+                .copied()
+        });
+        assert_eq!(
+            report.results[0].expected,
+            "invalid (neither bm, am nor dm)"
+        );
+        assert!(report.results[1].solved);
+
+        // A move that isn't legal fails that position, the others still run
+        let suite = "8/8/8/8/8/8/8/K6k w - - bm Kc3; id \"bad\";\n\
+                     8/8/8/8/8/8/8/K6k w - - bm Kb1; id \"good\";";
+        let suite = read_suite(suite.as_bytes()).unwrap();
+        let report = super::run(&suite, Limit::Depth(1), |pos, _| {
+            pos.legal_moves()
+                .iter()
+                .find(|m| m.to.file == 1 && m.to.rank == 0)
+                .copied()
+        });
+        assert!(!report.results[0].solved);
+        assert!(report.results[0].expected.starts_with("invalid ("));
+        assert!(report.results[1].solved);
+    }
+
+    #[test]
+    fn direct_mate() {
+        let suite =
+            read_suite("kbK5/pp6/1P6/8/8/8/8/R7 w - - dm 2; id \"quiet\";".as_bytes()).unwrap();
+
+        let report = super::run(&suite, Limit::Depth(1), |pos, _| {
+            crate::mate::find_mate(pos, 2).map(|m| m.line[0])
+        });
+        assert!(report.results[0].solved);
+        assert_eq!(report.results[0].expected, "dm 2");
+
+        // Any other move doesn't mate in time
+        let report = super::run(&suite, Limit::Depth(1), |pos, _| pos.parse_uci("a1a2").ok());
+        assert!(!report.results[0].solved);
+    }
+
+    #[test]
+    fn solve() {
+        // Mate in one, and a queen left hanging
+        let suite = read_suite(
+            "7k/8/6K1/8/8/8/8/R7 w - - bm Ra8#;\n\
+             4k3/8/8/3q4/8/8/3R4/4K3 w - - bm Rxd5;"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let report = super::run(&suite, Limit::Depth(2), super::solve);
+        assert_eq!(report.solved(), 2, "{report}");
+        let report = super::run(&suite, Limit::Time(Duration::from_millis(50)), super::solve);
+        assert_eq!(report.solved(), 2);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::board::piece::Color;
+use crate::uci::{parse_spin, UciOption};
+use anyhow::{anyhow, Result};
+use std::time::{Duration, Instant};
+
+pub const MOVE_OVERHEAD_DEFAULT: u64 = 10;
+pub const MOVE_OVERHEAD_MIN: u64 = 0;
+pub const MOVE_OVERHEAD_MAX: u64 = 5000;
+
+// Moves we expect to still play when the GUI doesn't send movestogo
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+// Never plan to use more than this share of the remaining time on one move
+const MAX_SOFT_SHARE: f64 = 0.4;
+const MAX_HARD_SHARE: f64 = 0.75;
+
+const HARD_FACTOR: u64 = 4;
+
+/// Limits sent with the UCI go command: the clock, and how deep or
+/// how many nodes to search. All times are in milliseconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u64>,
+    pub movetime: Option<u64>,
+    pub depth: Option<u64>,
+    pub nodes: Option<u64>,
+    pub infinite: bool,
+}
+
+impl Limits {
+    /// Parses the arguments of a go command, e.g. "go wtime 1000 btime 1000 winc 10".
+    /// Arguments that aren't supported, like searchmoves, are skipped.
+    pub fn from_go(cmd: &str) -> Result<Limits> {
+        let mut out = Limits::default();
+        let mut tokens = cmd.split_whitespace().skip_while(|t| *t == "go");
+
+        while let Some(token) = tokens.next() {
+            let field = match token {
+                "wtime" => &mut out.wtime,
+                "btime" => &mut out.btime,
+                "winc" => &mut out.winc,
+                "binc" => &mut out.binc,
+                "movestogo" => &mut out.movestogo,
+                "movetime" => &mut out.movetime,
+                "depth" => &mut out.depth,
+                "nodes" => &mut out.nodes,
+                "infinite" => {
+                    out.infinite = true;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let value = tokens
+                .next()
+                .ok_or_else(|| anyhow!("Missing value for '{token}'"))?;
+
+            // Some GUIs send negative times when the clock has run out
+            let value: i64 = value
+                .parse()
+                .map_err(|_| anyhow!("Invalid value '{value}' for '{token}'"))?;
+            *field = Some(value.max(0) as u64);
+        }
+
+        Ok(out)
+    }
+
+    fn time_and_inc(&self, side: Color) -> (Option<u64>, u64) {
+        match side {
+            Color::White => (self.wtime, self.winc.unwrap_or(0)),
+            Color::Black => (self.btime, self.binc.unwrap_or(0)),
+        }
+    }
+}
+
+/// Settings of the time manager that are exposed as UCI options.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeOptions {
+    /// Time lost per move to communication with the GUI.
+    pub move_overhead: Duration,
+}
+
+impl Default for TimeOptions {
+    fn default() -> TimeOptions {
+        TimeOptions {
+            move_overhead: Duration::from_millis(MOVE_OVERHEAD_DEFAULT),
+        }
+    }
+}
+
+impl UciOption for TimeOptions {
+    fn uci_options(&self) -> Vec<String> {
+        vec![format!(
+            "option name Move Overhead type spin default {MOVE_OVERHEAD_DEFAULT} min {MOVE_OVERHEAD_MIN} max {MOVE_OVERHEAD_MAX}"
+        )]
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<bool> {
+        if !name.eq_ignore_ascii_case("Move Overhead") {
+            return Ok(false);
+        }
+
+        let ms = parse_spin("Move Overhead", value, MOVE_OVERHEAD_MIN, MOVE_OVERHEAD_MAX)?;
+        self.move_overhead = Duration::from_millis(ms);
+        Ok(true)
+    }
+}
+
+/// Decides how long to think on a move.
+///
+/// The soft limit is checked between iterations of the search and gets
+/// stretched when the best move keeps changing or the score drops.
+/// The hard limit is never exceeded and should abort the search midway.
+#[derive(Clone, Debug)]
+pub(crate) struct TimeManager {
+    start: Instant,
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+    forced: bool,
+
+    instability: f64,
+    score_drop: i32,
+    first_score: Option<i32>,
+    iterations: u32,
+}
+
+impl TimeManager {
+    /// legal_moves is the number of legal moves at the root,
+    /// with only one the search stops after the first iteration.
+    pub fn new(limits: &Limits, side: Color, options: &TimeOptions, legal_moves: usize) -> Self {
+        let overhead = options.move_overhead.as_millis() as u64;
+        let (soft, hard) = Self::allocate(limits, side, overhead);
+
+        TimeManager {
+            start: Instant::now(),
+            soft: soft.map(Duration::from_millis),
+            hard: hard.map(Duration::from_millis),
+            forced: legal_moves == 1,
+            instability: 0.0,
+            score_drop: 0,
+            first_score: None,
+            iterations: 0,
+        }
+    }
+
+    /// Soft and hard limits in milliseconds, None when there is no limit.
+    fn allocate(limits: &Limits, side: Color, overhead: u64) -> (Option<u64>, Option<u64>) {
+        if limits.infinite {
+            return (None, None);
+        }
+
+        if let Some(movetime) = limits.movetime {
+            let t = movetime.saturating_sub(overhead).max(1);
+            return (Some(t), Some(t));
+        }
+
+        let (time, inc) = limits.time_and_inc(side);
+        let time = match time {
+            Some(t) => t,
+            None => return (None, None),
+        };
+
+        let left = time.saturating_sub(overhead).max(1);
+        let mtg = limits
+            .movestogo
+            .unwrap_or(DEFAULT_MOVES_TO_GO)
+            .clamp(1, DEFAULT_MOVES_TO_GO * 2);
+
+        let base = left / mtg + inc * 3 / 4;
+
+        // On the last move before the time control we can use almost everything
+        let (soft_cap, hard_cap) = if mtg == 1 {
+            (left * 9 / 10, left * 9 / 10)
+        } else {
+            (
+                (left as f64 * MAX_SOFT_SHARE) as u64,
+                (left as f64 * MAX_HARD_SHARE) as u64,
+            )
+        };
+
+        let soft = base.min(soft_cap).max(1);
+        let hard = (base * HARD_FACTOR).min(hard_cap).max(soft);
+
+        (Some(soft), Some(hard))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Reports the result of a finished iteration.
+    pub fn update(&mut self, best_move_changed: bool, score: i32) {
+        self.iterations += 1;
+
+        self.instability *= 0.5;
+        if best_move_changed {
+            self.instability += 1.0;
+        }
+
+        let first = *self.first_score.get_or_insert(score);
+        self.score_drop = (first - score).max(0);
+    }
+
+    /// How much the soft limit is stretched right now.
+    fn scale(&self) -> f64 {
+        let instability = 1.0 + self.instability * 0.6;
+        let drop = 1.0 + (self.score_drop.min(100) as f64 / 100.0) * 0.5;
+
+        instability * drop
+    }
+
+    /// The soft limit after taking search stability into account.
+    pub fn soft_limit(&self) -> Option<Duration> {
+        let soft = self.soft?;
+        let scaled = soft.mul_f64(self.scale());
+
+        match self.hard {
+            Some(hard) => Some(scaled.min(hard)),
+            None => Some(scaled),
+        }
+    }
+
+    #[cfg(test)]
+    fn hard_limit(&self) -> Option<Duration> {
+        self.hard
+    }
+
+    /// Whether to start another iteration of the search.
+    pub fn should_stop(&self) -> bool {
+        if self.forced && self.iterations > 0 {
+            return true;
+        }
+
+        match self.soft_limit() {
+            Some(soft) => self.elapsed() >= soft,
+            None => false,
+        }
+    }
+
+    /// Whether the running iteration has to be aborted.
+    pub fn out_of_time(&self) -> bool {
+        match self.hard {
+            Some(hard) => self.elapsed() >= hard,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_go() {
+        let l =
+            Limits::from_go("go wtime 60000 btime 50000 winc 1000 binc 500 movestogo 20").unwrap();
+        assert_eq!(l.wtime, Some(60000));
+        assert_eq!(l.btime, Some(50000));
+        assert_eq!(l.winc, Some(1000));
+        assert_eq!(l.binc, Some(500));
+        assert_eq!(l.movestogo, Some(20));
+        assert!(!l.infinite);
+
+        let l = Limits::from_go("go depth 5 infinite").unwrap();
+        assert!(l.infinite);
+        assert_eq!(l.depth, Some(5));
+
+        let l = Limits::from_go("go nodes 20000 searchmoves e2e4").unwrap();
+        assert_eq!(l.nodes, Some(20000));
+        assert_eq!(l.wtime, None);
+
+        let l = Limits::from_go("go wtime -20 btime 100").unwrap();
+        assert_eq!(l.wtime, Some(0));
+
+        assert!(Limits::from_go("go wtime").is_err());
+        assert!(Limits::from_go("go wtime abc").is_err());
+    }
+
+    #[test]
+    fn allocate() {
+        let opts = TimeOptions::default();
+
+        let l = Limits::from_go("go wtime 60000 btime 1000").unwrap();
+        let white = TimeManager::new(&l, Color::White, &opts, 20);
+        let black = TimeManager::new(&l, Color::Black, &opts, 20);
+        assert!(white.soft_limit() > black.soft_limit());
+        assert!(white.hard_limit() >= white.soft_limit());
+        assert!(white.hard_limit().unwrap() < Duration::from_millis(60000));
+
+        // Never more than what is left on the clock, even on the last move
+        let l = Limits::from_go("go wtime 1000 btime 1000 movestogo 1").unwrap();
+        let tm = TimeManager::new(&l, Color::White, &opts, 20);
+        assert!(tm.hard_limit().unwrap() < Duration::from_millis(1000));
+
+        // The increment is used
+        let no_inc = Limits::from_go("go wtime 10000 btime 10000").unwrap();
+        let inc = Limits::from_go("go wtime 10000 btime 10000 winc 1000 binc 1000").unwrap();
+        assert!(
+            TimeManager::new(&inc, Color::White, &opts, 20).soft_limit()
+                > TimeManager::new(&no_inc, Color::White, &opts, 20).soft_limit()
+        );
+
+        let l = Limits::from_go("go movetime 500").unwrap();
+        let tm = TimeManager::new(&l, Color::White, &opts, 20);
+        assert_eq!(tm.hard_limit(), Some(Duration::from_millis(490)));
+
+        let l = Limits::from_go("go infinite").unwrap();
+        let tm = TimeManager::new(&l, Color::White, &opts, 20);
+        assert_eq!(tm.hard_limit(), None);
+        assert!(!tm.should_stop());
+    }
+
+    #[test]
+    fn extend() {
+        let opts = TimeOptions::default();
+        let l = Limits::from_go("go wtime 60000 btime 60000").unwrap();
+
+        let mut stable = TimeManager::new(&l, Color::White, &opts, 20);
+        let mut unstable = stable.clone();
+        let mut dropping = stable.clone();
+
+        for i in 0..5 {
+            stable.update(false, 20);
+            unstable.update(i % 2 == 0, 20);
+            dropping.update(false, 20 - i * 20);
+        }
+
+        assert!(unstable.soft_limit() > stable.soft_limit());
+        assert!(dropping.soft_limit() > stable.soft_limit());
+        assert!(unstable.soft_limit() <= unstable.hard_limit());
+    }
+
+    #[test]
+    fn single_legal_move() {
+        let opts = TimeOptions::default();
+        let l = Limits::from_go("go wtime 60000 btime 60000").unwrap();
+
+        let mut tm = TimeManager::new(&l, Color::White, &opts, 1);
+        assert!(!tm.should_stop());
+        tm.update(false, 0);
+        assert!(tm.should_stop());
+    }
+
+    #[test]
+    fn move_overhead() {
+        let mut opts = TimeOptions::default();
+        assert!(opts.set_option("Move Overhead", "100").unwrap());
+        assert_eq!(opts.move_overhead, Duration::from_millis(100));
+        assert!(!opts.set_option("Hash", "16").unwrap());
+        assert!(opts.set_option("Move Overhead", "99999").is_err());
+
+        let l = Limits::from_go("go movetime 500").unwrap();
+        let tm = TimeManager::new(&l, Color::White, &opts, 20);
+        assert_eq!(tm.hard_limit(), Some(Duration::from_millis(400)));
+    }
+}
@@ -0,0 +1,360 @@
+use crate::board::piece::PieceType;
+use crate::board::square::Square;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+const ENTRY_SIZE: usize = 16;
+
+// Set in every stored entry, so even an all zero entry isn't mistaken for an empty slot
+const VALID: u64 = 1 << 42;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+impl Bound {
+    fn to_bits(self) -> u64 {
+        match self {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        }
+    }
+
+    fn from_bits(b: u64) -> Bound {
+        match b {
+            1 => Bound::Lower,
+            2 => Bound::Upper,
+            _ => Bound::Exact,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    /// From and to square of the best move found, and what a pawn promotes to.
+    pub mv: Option<(Square, Square, Option<PieceType>)>,
+    pub score: i16,
+    pub depth: u8,
+    pub bound: Bound,
+}
+
+// Layout of the data word:
+// bits 0..12   move, from + to * 64, 0 when there is no move
+// bits 12..16  promotion, 0 when there is none
+// bits 16..32  score
+// bits 32..40  depth
+// bits 40..42  bound
+// bit  42      valid
+// bits 48..56  generation
+impl Entry {
+    fn pack(&self, generation: u8) -> u64 {
+        let mv = match self.mv {
+            Some((from, to, promotion)) => {
+                let promotion = match promotion {
+                    Some(PieceType::Queen) => 1,
+                    Some(PieceType::Rook) => 2,
+                    Some(PieceType::Bishop) => 3,
+                    Some(PieceType::Knight) => 4,
+                    _ => 0,
+                };
+                (from.rank * 8 + from.file) as u64
+                    | ((to.rank * 8 + to.file) as u64) << 6
+                    | promotion << 12
+            }
+            None => 0,
+        };
+
+        mv | (self.score as u16 as u64) << 16
+            | (self.depth as u64) << 32
+            | self.bound.to_bits() << 40
+            | VALID
+            | (generation as u64) << 48
+    }
+
+    fn unpack(data: u64) -> Entry {
+        let square = |i: u64| Square {
+            file: (i % 8) as u32,
+            rank: (i / 8) as u32,
+        };
+
+        let promotion = match (data >> 12) & 0xf {
+            1 => Some(PieceType::Queen),
+            2 => Some(PieceType::Rook),
+            3 => Some(PieceType::Bishop),
+            4 => Some(PieceType::Knight),
+            _ => None,
+        };
+        let mv = match data & 0xfff {
+            0 => None,
+            m => Some((square(m & 63), square((m >> 6) & 63), promotion)),
+        };
+
+        Entry {
+            mv,
+            score: (data >> 16) as u16 as i16,
+            depth: (data >> 32) as u8,
+            bound: Bound::from_bits((data >> 40) & 3),
+        }
+    }
+}
+
+fn generation_of(data: u64) -> u8 {
+    (data >> 48) as u8
+}
+
+/// The key is stored XORed with the data, so an entry that two threads
+/// wrote to at the same time no longer matches its key and is ignored.
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn empty() -> Slot {
+        Slot {
+            key: AtomicU64::new(0),
+            data: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Hash table shared by all search threads without any locking.
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    /// Creates a table that uses about mb megabytes.
+    pub fn new(mb: usize) -> TranspositionTable {
+        let len = (mb * 1024 * 1024 / ENTRY_SIZE).max(1);
+
+        TranspositionTable {
+            slots: (0..len).map(|_| Slot::empty()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        let i = ((key as u128 * self.slots.len() as u128) >> 64) as usize;
+        &self.slots[i]
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Called once before every search, so entries from earlier
+    /// searches are replaced first.
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Permille of entries written during the current search,
+    /// sampled from the start of the table.
+    pub fn hashfull(&self) -> u32 {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let sample = self.slots.len().min(1000);
+
+        let used = self.slots[..sample]
+            .iter()
+            .filter(|slot| {
+                let data = slot.data.load(Ordering::Relaxed);
+                data & VALID != 0 && generation_of(data) == generation
+            })
+            .count();
+
+        (used * 1000 / sample) as u32
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        let stored = slot.key.load(Ordering::Relaxed);
+
+        if data & VALID != 0 && stored ^ data == key {
+            Some(Entry::unpack(data))
+        } else {
+            None
+        }
+    }
+
+    pub fn store(&self, key: u64, entry: Entry) {
+        let slot = self.slot(key);
+        let generation = self.generation.load(Ordering::Relaxed);
+
+        let old_data = slot.data.load(Ordering::Relaxed);
+        let old_key = slot.key.load(Ordering::Relaxed) ^ old_data;
+
+        // Keep deeper results for this search unless the new one is exact
+        let occupied = old_data & VALID != 0;
+        if occupied && generation_of(old_data) == generation && entry.bound != Bound::Exact {
+            let old = Entry::unpack(old_data);
+            if old_key == key && old.depth > entry.depth.saturating_add(2) {
+                return;
+            }
+            if old_key != key && old.depth > entry.depth && old.bound == Bound::Exact {
+                return;
+            }
+        }
+
+        // Keep the old move when the new entry doesn't have one
+        let mut entry = entry;
+        if entry.mv.is_none() && occupied && old_key == key {
+            entry.mv = Entry::unpack(old_data).mv;
+        }
+
+        let data = entry.pack(generation);
+        slot.data.store(data, Ordering::Relaxed);
+        slot.key.store(key ^ data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn entry(depth: u8, score: i16, bound: Bound) -> Entry {
+        Entry {
+            mv: Some((
+                Square::from_str("e2").unwrap(),
+                Square::from_str("e4").unwrap(),
+                None,
+            )),
+            score,
+            depth,
+            bound,
+        }
+    }
+
+    #[test]
+    fn store_and_probe() {
+        let tt = TranspositionTable::new(1);
+        let e = entry(5, -300, Bound::Lower);
+
+        tt.store(12345, e);
+        assert_eq!(tt.probe(12345), Some(e));
+        assert_eq!(tt.probe(54321), None);
+
+        tt.clear();
+        assert_eq!(tt.probe(12345), None);
+
+        // Underpromotions come back as they went in
+        let e = Entry {
+            mv: Some((
+                Square::from_str("e7").unwrap(),
+                Square::from_str("e8").unwrap(),
+                Some(PieceType::Knight),
+            )),
+            ..entry(3, 900, Bound::Exact)
+        };
+        tt.store(12345, e);
+        assert_eq!(tt.probe(12345), Some(e));
+
+        // An entry of all zeros is still an entry
+        let e = Entry {
+            mv: None,
+            ..entry(0, 0, Bound::Exact)
+        };
+        tt.store(0, e);
+        assert_eq!(tt.probe(0), Some(e));
+    }
+
+    #[test]
+    fn replacement() {
+        let tt = TranspositionTable::new(1);
+
+        tt.store(1, entry(10, 50, Bound::Lower));
+        tt.store(1, entry(2, 70, Bound::Upper));
+        assert_eq!(tt.probe(1).unwrap().depth, 10);
+
+        // Exact scores always go in
+        tt.store(1, entry(2, 70, Bound::Exact));
+        assert_eq!(tt.probe(1).unwrap().depth, 2);
+
+        // Old searches get replaced
+        tt.store(1, entry(10, 50, Bound::Lower));
+        tt.new_search();
+        tt.store(
+            1,
+            Entry {
+                mv: None,
+                ..entry(1, 0, Bound::Upper)
+            },
+        );
+        let e = tt.probe(1).unwrap();
+        assert_eq!(e.depth, 1);
+        assert!(e.mv.is_some());
+
+        // Depths near the top of the range don't overflow
+        tt.store(2, entry(u8::MAX, 10, Bound::Lower));
+        tt.store(2, entry(254, 20, Bound::Lower));
+        assert_eq!(tt.probe(2).unwrap().score, 20);
+    }
+
+    #[test]
+    fn hashfull() {
+        let tt = TranspositionTable::new(1);
+        assert_eq!(tt.hashfull(), 0);
+
+        // Fill every slot in the sampled range
+        let len = tt.len() as u128;
+        for i in 0..1000u128 {
+            let key = ((i << 64) / len + 1) as u64;
+            tt.store(key, entry(1, 0, Bound::Exact));
+        }
+        assert_eq!(tt.hashfull(), 1000);
+
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+    }
+
+    #[test]
+    fn torn_entry() {
+        let tt = TranspositionTable::new(1);
+        tt.store(777, entry(5, 10, Bound::Exact));
+
+        // Simulate another thread overwriting only the data half
+        tt.slot(777)
+            .data
+            .store(entry(6, 20, Bound::Exact).pack(0), Ordering::Relaxed);
+        assert_eq!(tt.probe(777), None);
+    }
+
+    #[test]
+    fn threads() {
+        let tt = TranspositionTable::new(1);
+
+        // Every thread writes entries whose score is derived from the key,
+        // so any entry that made it through the key check must agree with it
+        thread::scope(|s| {
+            for t in 0..4u64 {
+                let tt = &tt;
+                s.spawn(move || {
+                    for i in 0..20000u64 {
+                        let key = i.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (t << 60);
+                        let score = (key % 1000) as i16;
+                        tt.store(key, entry((t + 1) as u8, score, Bound::Exact));
+
+                        if let Some(e) = tt.probe(key) {
+                            assert_eq!(e.score, score);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
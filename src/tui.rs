@@ -1,17 +1,361 @@
+pub mod play;
+
+use crate::board::bitboard::BitBoard;
+use crate::board::eval::{self, Score};
+use crate::board::piece::{Color, PieceType};
+use crate::board::square::Square;
+use crate::board::variant::Variant;
 use crate::board::Position;
+use std::io::{self, IsTerminal};
 
-pub fn print_position(pos: &Position) {
-    let pos = pos.into_char_vec();
+/// How the board gets drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    /// Chess glyphs instead of letters.
+    pub unicode: bool,
+    /// ANSI colours for the squares, the last move and checks.
+    pub colour: bool,
+    pub coordinates: bool,
+}
+
+impl Style {
+    pub fn fancy() -> Style {
+        Style {
+            unicode: true,
+            colour: true,
+            coordinates: true,
+        }
+    }
 
-    for (i, c) in pos.into_iter().rev().enumerate() {
-        if c == ' ' {
-            print!("|·");
+    /// Fancy when stdout is a terminal, plain ASCII otherwise.
+    pub fn detect() -> Style {
+        if io::stdout().is_terminal() {
+            Style::fancy()
         } else {
-            print!("|{c}");
+            Style {
+                coordinates: true,
+                ..Style::default()
+            }
         }
+    }
+}
+
+// 256 colour palette
+const LIGHT: u8 = 180;
+const DARK: u8 = 137;
+const LAST_MOVE: u8 = 143;
+const CHECK: u8 = 160;
+const OVERLAY: u8 = 67;
+const WHITE_PIECE: u8 = 231;
+const BLACK_PIECE: u8 = 16;
 
-        if (i + 1) % 8 == 0 {
-            println!("|");
+fn glyph(p: PieceType, c: Color) -> char {
+    match (c, p) {
+        (Color::White, PieceType::King) => '♔',
+        (Color::White, PieceType::Queen) => '♕',
+        (Color::White, PieceType::Rook) => '♖',
+        (Color::White, PieceType::Bishop) => '♗',
+        (Color::White, PieceType::Knight) => '♘',
+        (Color::White, PieceType::Pawn) => '♙',
+        (Color::Black, PieceType::King) => '♚',
+        (Color::Black, PieceType::Queen) => '♛',
+        (Color::Black, PieceType::Rook) => '♜',
+        (Color::Black, PieceType::Bishop) => '♝',
+        (Color::Black, PieceType::Knight) => '♞',
+        (Color::Black, PieceType::Pawn) => '♟',
+    }
+}
+
+/// A position ready to be drawn, with an optional BitBoard on top of it.
+pub struct BoardView<'a> {
+    pos: &'a Position,
+    style: Style,
+    flipped: bool,
+    overlay: Option<BitBoard>,
+}
+
+impl<'a> BoardView<'a> {
+    pub fn new(pos: &'a Position) -> BoardView<'a> {
+        BoardView {
+            pos,
+            style: Style::default(),
+            flipped: false,
+            overlay: None,
         }
     }
+
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Draw the board from black's side.
+    pub fn flipped(mut self, flipped: bool) -> Self {
+        self.flipped = flipped;
+        self
+    }
+
+    /// Marks the squares set in board, e.g. pos.w_attacks_all.
+    pub fn overlay(mut self, board: BitBoard) -> Self {
+        self.overlay = Some(board);
+        self
+    }
+
+    fn piece_char(&self, piece: Option<(PieceType, Color)>, marked: bool) -> char {
+        match piece {
+            // Coloured glyphs look the same for both sides, so the filled ones are used
+            Some((p, _)) if self.style.unicode && self.style.colour => glyph(p, Color::Black),
+            Some((p, c)) if self.style.unicode => glyph(p, c),
+            Some((p, c)) => p.to_char(c),
+            None if marked && !self.style.colour => 'x',
+            None if self.style.colour => ' ',
+            None => '·',
+        }
+    }
+
+    /// Pieces in hand of c in Crazyhouse, e.g. [NPP].
+    fn pocket_line(&self, c: Color) -> String {
+        let pieces: String = self
+            .pos
+            .pocket(c)
+            .iter()
+            .flat_map(|(p, n)| {
+                let ch = if self.style.unicode {
+                    glyph(p, c)
+                } else {
+                    p.to_char(c)
+                };
+                std::iter::repeat_n(ch, n as usize)
+            })
+            .collect();
+
+        format!("[{pieces}]\n")
+    }
+
+    fn square_colour(&self, sqr: Square, checked_king: Option<Square>) -> u8 {
+        let last_move = self.pos.history.last();
+
+        if checked_king == Some(sqr) {
+            CHECK
+        } else if self.overlay.and_then(|b| b.get(sqr.file, sqr.rank)) == Some(true) {
+            OVERLAY
+        } else if last_move.is_some_and(|m| m.from == sqr || m.to == sqr) {
+            LAST_MOVE
+        } else if (sqr.file + sqr.rank).is_multiple_of(2) {
+            DARK
+        } else {
+            LIGHT
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let side = self.pos.side;
+        let checked_king = self
+            .pos
+            .king_square(side)
+            .filter(|_| self.pos.is_in_check(side));
+
+        let (ranks, files): (Vec<u32>, Vec<u32>) = if self.flipped {
+            ((0..8).collect(), (0..8).rev().collect())
+        } else {
+            ((0..8).rev().collect(), (0..8).collect())
+        };
+
+        // The pocket of each side goes on its own side of the board
+        let crazyhouse = self.pos.variant == Variant::Crazyhouse;
+        let (top, bottom) = match self.flipped {
+            false => (Color::Black, Color::White),
+            true => (Color::White, Color::Black),
+        };
+
+        let mut out = String::new();
+        if crazyhouse {
+            out.push_str(&self.pocket_line(top));
+        }
+
+        for &rank in &ranks {
+            if self.style.coordinates {
+                out.push_str(&format!("{} ", rank + 1));
+            }
+
+            for &file in &files {
+                let sqr = Square { file, rank };
+                let piece = self.pos.piece_at(file, rank);
+                let marked = self.overlay.and_then(|b| b.get(file, rank)) == Some(true);
+                let c = self.piece_char(piece, marked);
+
+                if self.style.colour {
+                    let fg = match piece {
+                        Some((_, Color::White)) => WHITE_PIECE,
+                        _ => BLACK_PIECE,
+                    };
+                    let bg = self.square_colour(sqr, checked_king);
+                    out.push_str(&format!("\x1b[48;5;{bg};38;5;{fg}m {c} "));
+                } else {
+                    out.push('|');
+                    out.push(c);
+                }
+            }
+
+            if self.style.colour {
+                out.push_str("\x1b[0m\n");
+            } else {
+                out.push_str("|\n");
+            }
+        }
+
+        if self.style.coordinates {
+            let mut footer = String::from(if self.style.colour { "  " } else { "   " });
+            for file in files {
+                let c = char::from(b'a' + file as u8);
+                if self.style.colour {
+                    footer.push_str(&format!(" {c} "));
+                } else {
+                    footer.push_str(&format!("{c} "));
+                }
+            }
+            out.push_str(footer.trim_end());
+            out.push('\n');
+        }
+
+        if crazyhouse {
+            out.push_str(&self.pocket_line(bottom));
+        }
+
+        out
+    }
+}
+
+pub fn print_position(pos: &Position) {
+    print!("{}", BoardView::new(pos).style(Style::detect()).render());
+}
+
+fn format_row(name: &str, w: Score, b: Score) -> String {
+    let t = w - b;
+    format!(
+        "{name:>12} | {:>5} {:>5} | {:>5} {:>5} | {:>5} {:>5}",
+        w.mg, w.eg, b.mg, b.eg, t.mg, t.eg
+    )
+}
+
+/// Prints the position followed by a table with every evaluation term
+/// for white, black and the difference between them, in centipawns.
+pub fn print_eval(pos: &Position) {
+    let trace = eval::trace(pos);
+    let line = "-------------+-------------+-------------+------------";
+
+    print_position(pos);
+    println!();
+    println!("        Term |    White    |    Black    |    Total");
+    println!("             |   MG    EG  |   MG    EG  |   MG    EG");
+    println!("{line}");
+
+    for (name, w, b) in trace.terms() {
+        println!("{}", format_row(name, w, b));
+    }
+
+    println!("{line}");
+    println!(
+        "{}",
+        format_row("Total", trace.white_total(), trace.black_total())
+    );
+    println!();
+    println!("Phase: {}/{}", trace.phase, eval::MAX_PHASE);
+    println!("Evaluation: {} (white side)", trace.total());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::parse;
+
+    fn pos(fen: &str) -> Position {
+        parse::from_fen(fen.to_string()).unwrap()
+    }
+
+    #[test]
+    fn plain() {
+        let pos = pos("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+
+        let out = BoardView::new(&pos).render();
+        assert_eq!(out.lines().next(), Some("|·|·|·|·|k|·|·|·|"));
+        assert_eq!(out.lines().last(), Some("|R|·|·|·|K|·|·|·|"));
+
+        let style = Style {
+            coordinates: true,
+            ..Style::default()
+        };
+        let out = BoardView::new(&pos).style(style).flipped(true).render();
+        assert_eq!(out.lines().next(), Some("1 |·|·|·|K|·|·|·|R|"));
+        assert_eq!(out.lines().last(), Some("   h g f e d c b a"));
+
+        let out = BoardView::new(&pos)
+            .style(style)
+            .overlay(pos.w_attacks_all)
+            .render();
+        assert_eq!(out.lines().nth(6), Some("2 |x|·|·|x|x|x|·|·|"));
+        assert_eq!(out.lines().nth(7), Some("1 |R|x|x|x|K|x|·|·|"));
+    }
+
+    #[test]
+    fn unicode() {
+        let pos = pos("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        let style = Style {
+            unicode: true,
+            ..Style::default()
+        };
+
+        let out = BoardView::new(&pos).style(style).render();
+        assert_eq!(out.lines().next(), Some("|·|·|·|·|♚|·|·|·|"));
+        assert_eq!(out.lines().last(), Some("|♖|·|·|·|♔|·|·|·|"));
+    }
+
+    #[test]
+    fn pockets() {
+        let pos = parse::from_fen_variant(
+            "4k3/8/8/8/8/8/8/R3K3[NPPq] w - - 0 1".to_string(),
+            Variant::Crazyhouse,
+        )
+        .unwrap();
+
+        let out = BoardView::new(&pos).render();
+        assert_eq!(out.lines().next(), Some("[q]"));
+        assert_eq!(out.lines().nth(1), Some("|·|·|·|·|k|·|·|·|"));
+        assert_eq!(out.lines().last(), Some("[NPP]"));
+
+        let out = BoardView::new(&pos).flipped(true).render();
+        assert_eq!(out.lines().next(), Some("[NPP]"));
+
+        let style = Style {
+            unicode: true,
+            ..Style::default()
+        };
+        let out = BoardView::new(&pos).style(style).render();
+        assert_eq!(out.lines().last(), Some("[♘♙♙]"));
+    }
+
+    #[test]
+    fn highlights() {
+        let mut pos = pos("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        let m = pos.parse_san("Ra8+").unwrap();
+        pos.change_board(m);
+
+        let out = BoardView::new(&pos).style(Style::fancy()).render();
+        let rank_8 = out.lines().next().unwrap();
+
+        // Rook on a8 is on the last move, king on e8 is in check
+        assert!(rank_8.starts_with(&format!("8 \x1b[48;5;{LAST_MOVE};38;5;{WHITE_PIECE}m ♜ ")));
+        assert!(rank_8.contains(&format!("\x1b[48;5;{CHECK};38;5;{BLACK_PIECE}m ♚ ")));
+        assert!(rank_8.ends_with("\x1b[0m"));
+        assert!(out
+            .lines()
+            .nth(7)
+            .unwrap()
+            .contains(&format!("\x1b[48;5;{LAST_MOVE};")));
+        assert_eq!(out.lines().last(), Some("   a  b  c  d  e  f  g  h"));
+
+        // b1 is a light square
+        let rank_1 = out.lines().nth(7).unwrap();
+        assert!(rank_1.contains(&format!("\x1b[48;5;{LIGHT};38;5;{BLACK_PIECE}m   ")));
+    }
 }
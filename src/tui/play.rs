@@ -0,0 +1,437 @@
+use crate::board::movegen::Move;
+use crate::board::piece::Color;
+use crate::board::square::Square;
+use crate::board::variant::Variant;
+use crate::board::{parse, GameState, Position};
+use crate::pgn::START_FEN;
+use crate::tui::{BoardView, Style};
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, Write};
+
+/// Picks a move for the side to move, None if it has nothing to play.
+pub type Engine = Box<dyn FnMut(&mut Position) -> Option<Move>>;
+
+const HELP: &str = "\
+Enter moves in SAN (Nf3, exd5, O-O, e8=Q) or UCI notation (g1f3, e7e8q).
+  undo          take back the last move
+  redo          replay a move that was taken back
+  flip          turn the board around
+  moves [sqr]   list the legal moves, only those from sqr if given
+  fen <FEN>     load a position
+  new           start a new game
+  go            let the engine play the side to move
+  help          show this message
+  quit          leave";
+
+pub struct Session {
+    pub pos: Position,
+    pub flipped: bool,
+    pub style: Style,
+    /// Moves that were taken back, the most recent last.
+    redo: Vec<Move>,
+    engine: Option<Engine>,
+    engine_side: Option<Color>,
+}
+
+fn color_name(c: Color) -> &'static str {
+    match c {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+/// Whether s looks like e2e4 or e7e8q rather than SAN.
+fn is_uci(s: &str) -> bool {
+    let b = s.as_bytes();
+    let square = |f: u8, r: u8| (b'a'..=b'h').contains(&f) && (b'1'..=b'8').contains(&r);
+
+    (b.len() == 4 || b.len() == 5) && square(b[0], b[1]) && square(b[2], b[3])
+}
+
+impl Session {
+    pub fn new(mut pos: Position) -> Session {
+        pos.update_gamestate();
+
+        Session {
+            pos,
+            flipped: false,
+            style: Style::default(),
+            redo: vec![],
+            engine: None,
+            engine_side: None,
+        }
+    }
+
+    /// Lets engine answer every move made for the other side.
+    pub fn with_engine(mut self, side: Color, engine: Engine) -> Session {
+        self.engine = Some(engine);
+        self.engine_side = Some(side);
+        self
+    }
+
+    /// Message to show when the game has ended.
+    pub fn game_over(&self) -> Option<String> {
+        match self.pos.gamestate {
+            GameState::Won(c) => Some(self.win_reason(c)),
+            GameState::Draw if self.pos.legal_moves.is_empty() => {
+                Some("Stalemate, the game is drawn".to_string())
+            }
+            GameState::Draw if self.pos.is_dead_draw() => {
+                Some("Draw, neither side can mate".to_string())
+            }
+            GameState::Draw => Some("Draw by the fifty move rule".to_string()),
+            _ => None,
+        }
+    }
+
+    /// How c won, in the same order update_gamestate checks the rules.
+    fn win_reason(&self, c: Color) -> String {
+        let winner = color_name(c);
+        let loser = color_name(c.opposite());
+
+        if self.pos.variant_winner().is_some() {
+            match self.pos.variant() {
+                Variant::KingOfTheHill => format!("King on the hill, {winner} wins"),
+                Variant::ThreeCheck => format!("Third check, {winner} wins"),
+                Variant::Atomic => format!("{loser}'s king exploded, {winner} wins"),
+                _ => format!("{winner} wins"),
+            }
+        } else if self.pos.variant() == Variant::Antichess {
+            if self.pos.pieces_all(c).as_u64() == 0 {
+                format!("{winner} has no pieces left and wins")
+            } else {
+                format!("{winner} has no moves left and wins")
+            }
+        } else {
+            format!("Checkmate, {winner} wins")
+        }
+    }
+
+    fn print_board<W: Write>(&self, out: &mut W) -> Result<()> {
+        let view = BoardView::new(&self.pos)
+            .style(self.style)
+            .flipped(self.flipped);
+        write!(out, "{}", view.render())?;
+
+        match self.game_over() {
+            Some(msg) => writeln!(out, "{msg}")?,
+            None => {
+                let check = match self.pos.gamestate {
+                    GameState::InCheck(_) => ", in check",
+                    _ => "",
+                };
+                writeln!(out, "{} to move{check}", color_name(self.pos.side))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_move(&mut self, s: &str) -> Result<Move> {
+        if is_uci(s) {
+            self.pos.parse_uci(s)
+        } else {
+            self.pos.parse_san(s)
+        }
+    }
+
+    fn play<W: Write>(&mut self, m: Move, out: &mut W) -> Result<()> {
+        let san = self.pos.move_to_san(&m);
+        writeln!(out, "{} plays {san}", color_name(self.pos.side))?;
+
+        self.pos.change_board(m);
+        self.pos.update_gamestate();
+        Ok(())
+    }
+
+    fn engine_move<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        let engine = self
+            .engine
+            .as_mut()
+            .ok_or_else(|| anyhow!("There is no engine to play against"))?;
+
+        let mut pos = self.pos.clone();
+        match engine(&mut pos) {
+            Some(m) => self.play(m, out),
+            None => Err(anyhow!("The engine did not find a move")),
+        }
+    }
+
+    fn undo(&mut self) -> Result<()> {
+        let m = *self
+            .pos
+            .history
+            .last()
+            .ok_or_else(|| anyhow!("There is no move to take back"))?;
+
+        self.pos.reverse_last_change();
+        self.pos.update_gamestate();
+        self.redo.push(m);
+        Ok(())
+    }
+
+    fn legal_moves(&mut self, from: Option<&str>) -> Result<String> {
+        let from = from.map(Square::from_str).transpose()?;
+        let moves: Vec<Move> = self
+            .pos
+            .legal_moves
+            .clone()
+            .into_iter()
+            .filter(|m| from.is_none_or(|sqr| m.from == sqr))
+            .collect();
+
+        if moves.is_empty() {
+            return Ok("No legal moves".to_string());
+        }
+
+        let san: Vec<String> = moves.iter().map(|m| self.pos.move_to_san(m)).collect();
+        Ok(san.join(" "))
+    }
+
+    /// Handles one line of input, returns false when the user wants to quit.
+    pub fn handle<W: Write>(&mut self, line: &str, out: &mut W) -> Result<bool> {
+        let line = line.trim();
+        let (cmd, arg) = match line.split_once(' ') {
+            Some((cmd, arg)) => (cmd, Some(arg.trim())),
+            None => (line, None),
+        };
+
+        match cmd {
+            "" => return Ok(true),
+            "quit" | "exit" => return Ok(false),
+            "help" => {
+                writeln!(out, "{HELP}")?;
+                return Ok(true);
+            }
+            "moves" => {
+                writeln!(out, "{}", self.legal_moves(arg)?)?;
+                return Ok(true);
+            }
+            "flip" => self.flipped = !self.flipped,
+            "undo" => {
+                self.undo()?;
+                // Against the engine, go back to the last position the user had to move in
+                if self.engine_side == Some(self.pos.side) && !self.pos.history.is_empty() {
+                    self.undo()?;
+                }
+            }
+            "redo" => {
+                let m = self
+                    .redo
+                    .pop()
+                    .ok_or_else(|| anyhow!("There is no move to redo"))?;
+                self.play(m, out)?;
+            }
+            "fen" | "new" => {
+                let fen = match (cmd, arg) {
+                    ("new", _) => START_FEN,
+                    (_, Some(fen)) => fen,
+                    (_, None) => return Err(anyhow!("Usage: fen <FEN>")),
+                };
+
+                self.pos = parse::from_fen(fen.to_string())?;
+                self.redo.clear();
+            }
+            "go" => {
+                if self.game_over().is_some() {
+                    return Err(anyhow!("The game is over"));
+                }
+                if self.engine.is_none() {
+                    return Err(anyhow!("There is no engine to play against"));
+                }
+                self.engine_side = Some(self.pos.side);
+                self.engine_move(out)?;
+                self.redo.clear();
+            }
+            _ => {
+                if self.game_over().is_some() {
+                    return Err(anyhow!("The game is over, use undo, fen or new to go on"));
+                }
+
+                let m = self.parse_move(line)?;
+                self.play(m, out)?;
+                self.redo.clear();
+
+                if self.engine.is_some()
+                    && self.engine_side == Some(self.pos.side)
+                    && self.game_over().is_none()
+                {
+                    self.engine_move(out)?;
+                }
+            }
+        }
+
+        self.print_board(out)?;
+        Ok(true)
+    }
+
+    /// Reads commands until the input ends or the user quits.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> Result<()> {
+        self.print_board(&mut out)?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(out, "> ")?;
+            out.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            match self.handle(&line, &mut out) {
+                Ok(true) => (),
+                Ok(false) => return Ok(()),
+                Err(e) => writeln!(out, "{e}")?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new(parse::from_fen(START_FEN.to_string()).unwrap())
+    }
+
+    fn run(session: &mut Session, input: &str) -> String {
+        let mut out = vec![];
+        session.run(input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn variant_wins() {
+        let game_over = |fen: &str, variant: Variant, san: &str| {
+            let mut pos = parse::from_fen_variant(fen.to_string(), variant).unwrap();
+            pos.play_san(san).unwrap();
+            Session::new(pos).game_over().unwrap()
+        };
+
+        let fen = "8/8/8/8/8/3K4/8/k7 w - - 0 1";
+        assert_eq!(
+            game_over(fen, Variant::KingOfTheHill, "Kd4"),
+            "King on the hill, White wins"
+        );
+        let fen = "7k/8/8/8/8/8/1R6/K7 w - - 0 1 +2+0";
+        assert_eq!(
+            game_over(fen, Variant::ThreeCheck, "Rh2"),
+            "Third check, White wins"
+        );
+        let fen = "4k3/R3r3/8/8/8/8/3q4/4K3 w - - 0 1";
+        assert_eq!(
+            game_over(fen, Variant::Atomic, "Rxe7"),
+            "Black's king exploded, White wins"
+        );
+        let fen = "8/8/8/8/8/8/3p4/4K3 w - - 0 1";
+        assert_eq!(
+            game_over(fen, Variant::Antichess, "Kxd2"),
+            "Black has no pieces left and wins"
+        );
+
+        let pos = parse::from_fen_variant(
+            "8/8/8/8/8/p7/P7/8 w - - 0 1".to_string(),
+            Variant::Antichess,
+        );
+        assert_eq!(
+            Session::new(pos.unwrap()).game_over().unwrap(),
+            "White has no moves left and wins"
+        );
+    }
+
+    #[test]
+    fn moves_and_game_over() {
+        let mut s = session();
+        let out = run(&mut s, "f3\ne7e5\ng4\nQh4#\ne4\n");
+
+        assert!(out.contains("White plays f3"));
+        assert!(out.contains("Black plays e5"));
+        assert!(out.contains("Black plays Qh4#"));
+        assert!(out.contains("Checkmate, Black wins"));
+        assert!(out.ends_with("The game is over, use undo, fen or new to go on\n> "));
+        assert_eq!(s.pos.gamestate, GameState::Won(Color::Black));
+
+        let mut s = session();
+        let out = run(&mut s, "e4\nf5\nQh5+\nNf6\n");
+        assert!(out.contains("Black to move, in check"));
+        assert!(out.contains("Illegal move 'Nf6'"));
+
+        let out = run(&mut s, "fen 7k/8/6K1/8/8/8/8/5Q2 w - - 0 1\nQf7\n");
+        assert!(out.contains("Stalemate, the game is drawn"));
+        assert_eq!(s.pos.gamestate, GameState::Draw);
+
+        let out = run(
+            &mut s,
+            "fen 7k/8/8/8/8/8/1n6/K7 w - - 0 1
+Kxb2
+",
+        );
+        assert!(out.contains("Draw, neither side can mate"));
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut s = session();
+        let start = s.pos.clone();
+
+        run(&mut s, "e4\ne5\nundo\nundo\n");
+        assert_eq!(s.pos, start);
+
+        run(&mut s, "redo\nredo\n");
+        assert_eq!(s.pos.history.len(), 2);
+
+        let out = run(&mut s, "redo\n");
+        assert!(out.contains("There is no move to redo"));
+
+        run(&mut s, "undo\nd5\n");
+        let out = run(&mut s, "redo\n");
+        assert!(out.contains("There is no move to redo"));
+
+        let out = run(&mut s, "new\nundo\n");
+        assert!(out.contains("There is no move to take back"));
+    }
+
+    #[test]
+    fn commands() {
+        let mut s = session();
+
+        let out = run(&mut s, "moves g1\n");
+        assert!(out.contains("Nf3") && out.contains("Nh3"));
+        assert!(!out.contains("e4"));
+        assert!(run(&mut s, "moves e4\n").contains("No legal moves"));
+        assert!(run(&mut s, "moves x9\n").contains("Invalid square 'x9'"));
+
+        let out = run(&mut s, "flip\n");
+        assert!(s.flipped);
+        assert!(out.contains("|R|N|B|K|Q|B|N|R|\n|P|P|P|P|P|P|P|P|"));
+        assert!(out.contains("|r|n|b|k|q|b|n|r|\n"));
+
+        run(&mut s, "fen 8/8/8/8/8/8/R7/K6k b - - 0 1\n");
+        assert_eq!(s.pos.side, Color::Black);
+        assert!(run(&mut s, "fen\n").contains("Usage: fen <FEN>"));
+        assert!(run(&mut s, "go\n").contains("There is no engine"));
+        assert!(run(&mut s, "help\nquit\nKb1\n").contains("undo"));
+        assert_eq!(s.pos.history.len(), 0);
+    }
+
+    #[test]
+    fn engine() {
+        let first: Engine = Box::new(|pos| pos.generate_legal_moves().into_iter().next());
+        let mut s = session().with_engine(Color::Black, first);
+
+        run(&mut s, "e4\n");
+        assert_eq!(s.pos.history.len(), 2);
+        assert_eq!(s.pos.side, Color::White);
+
+        run(&mut s, "undo\n");
+        assert_eq!(s.pos.history.len(), 0);
+
+        // The engine takes over white
+        run(&mut s, "go\n");
+        assert_eq!(s.pos.history.len(), 1);
+        run(&mut s, "e5\n");
+        assert_eq!(s.pos.history.len(), 3);
+    }
+}
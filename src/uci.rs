@@ -0,0 +1,690 @@
+use crate::board::movegen::Move;
+use crate::board::variant::Variant;
+use crate::board::{parse, Position};
+use crate::book::BookOptions;
+use crate::pgn::START_FEN;
+use crate::search::{Listener, Search, SearchResult};
+use crate::timeman::Limits;
+use anyhow::{anyhow, Result};
+use std::fmt::{self, Display};
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub mod client;
+
+/// Score of being mated right now, a mate in n plies is MATE - n.
+pub const MATE: i32 = 32000;
+pub const MAX_PLY: i32 = 256;
+
+/// Scores beyond this are mate scores.
+pub const MATE_BOUND: i32 = MATE - MAX_PLY;
+
+// currmove lines are only sent once the search has run this long
+const CURRMOVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Score as UCI expects it, in centipawns or in moves until mate.
+/// Negative mate scores mean the engine is getting mated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UciScore {
+    Cp(i32),
+    Mate(i32),
+}
+
+impl UciScore {
+    pub fn from_score(score: i32) -> UciScore {
+        if score >= MATE_BOUND {
+            UciScore::Mate((MATE - score + 1) / 2)
+        } else if score <= -MATE_BOUND {
+            UciScore::Mate(-(MATE + score) / 2)
+        } else {
+            UciScore::Cp(score)
+        }
+    }
+}
+
+impl Display for UciScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciScore::Cp(cp) => write!(f, "cp {cp}"),
+            UciScore::Mate(n) => write!(f, "mate {n}"),
+        }
+    }
+}
+
+/// Result of one iteration of the search, displayed as an info line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Info {
+    pub depth: u32,
+    pub seldepth: u32,
+    /// Score from the point of view of the side to move.
+    pub score: i32,
+    pub nodes: u64,
+    pub time: Duration,
+    /// Permille of the hash table in use.
+    pub hashfull: u32,
+    pub pv: Vec<Move>,
+    /// Write castling in the pv the Chess960 way.
+    pub chess960: bool,
+}
+
+impl Info {
+    pub fn nps(&self) -> u64 {
+        let ms = self.time.as_millis() as u64;
+        (self.nodes * 1000).checked_div(ms).unwrap_or(0)
+    }
+}
+
+impl Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "info depth {} seldepth {} score {} nodes {} nps {} time {} hashfull {}",
+            self.depth,
+            self.seldepth.max(self.depth),
+            UciScore::from_score(self.score),
+            self.nodes,
+            self.nps(),
+            self.time.as_millis(),
+            self.hashfull,
+        )?;
+
+        if !self.pv.is_empty() {
+            let pv: Vec<String> = self.pv.iter().map(|m| m.to_uci(self.chess960)).collect();
+            write!(f, " pv {}", pv.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A group of settings a GUI can change with setoption. The UCI loop
+/// announces the options of every group and offers each setoption to
+/// them in turn.
+pub trait UciOption {
+    /// Option lines to send in response to the uci command.
+    fn uci_options(&self) -> Vec<String>;
+
+    /// Handles a setoption command. Returns Ok(false) when the option
+    /// belongs to another group.
+    fn set_option(&mut self, name: &str, value: &str) -> Result<bool>;
+}
+
+/// Option lines of all groups, in order.
+pub fn option_lines(groups: &[&dyn UciOption]) -> Vec<String> {
+    groups.iter().flat_map(|g| g.uci_options()).collect()
+}
+
+/// Hands a setoption to the first group that knows the option.
+pub fn set_option(groups: &mut [&mut dyn UciOption], name: &str, value: &str) -> Result<()> {
+    for group in groups {
+        if group.set_option(name, value)? {
+            return Ok(());
+        }
+    }
+
+    Err(anyhow!("No such option: {name}"))
+}
+
+/// Value of a check option.
+pub fn parse_check(name: &str, value: &str) -> Result<bool> {
+    match value.trim() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(anyhow!(
+            "Invalid value '{value}' for {name}, expected true or false"
+        )),
+    }
+}
+
+/// Value of a spin option, which has to be between min and max.
+pub fn parse_spin(name: &str, value: &str, min: u64, max: u64) -> Result<u64> {
+    let n: u64 = value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid value '{value}' for {name}"))?;
+
+    if !(min..=max).contains(&n) {
+        return Err(anyhow!("{name} must be between {min} and {max}"));
+    }
+    Ok(n)
+}
+
+/// Options that change the rules of the game or how moves are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GameOptions {
+    pub chess960: bool,
+    pub variant: Variant,
+}
+
+impl UciOption for GameOptions {
+    fn uci_options(&self) -> Vec<String> {
+        let variants: Vec<String> = Variant::ALL.iter().map(|v| format!("var {v}")).collect();
+
+        vec![
+            "option name UCI_Chess960 type check default false".to_string(),
+            format!(
+                "option name UCI_Variant type combo default {} {}",
+                Variant::Standard,
+                variants.join(" ")
+            ),
+        ]
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<bool> {
+        if name.eq_ignore_ascii_case("UCI_Variant") {
+            self.variant = Variant::from_name(value)?;
+        } else if name.eq_ignore_ascii_case("UCI_Chess960") {
+            self.chess960 = parse_check("UCI_Chess960", value)?;
+        } else {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
+/// Line announcing the root move being searched,
+/// None during the first second so short searches aren't flooded.
+pub fn currmove(mv: &Move, number: usize, elapsed: Duration, chess960: bool) -> Option<String> {
+    if elapsed < CURRMOVE_DELAY {
+        return None;
+    }
+
+    let mv = mv.to_uci(chess960);
+    Some(format!("info currmove {mv} currmovenumber {number}"))
+}
+
+/// bestmove line for the result of a search, with the reply it expects
+/// to ponder on, or 0000 when there was nothing to play.
+pub fn bestmove_line(result: &SearchResult, chess960: bool) -> String {
+    let Some(best) = result.best else {
+        return "bestmove 0000".to_string();
+    };
+
+    let mut line = format!("bestmove {}", best.to_uci(chess960));
+    if let Some(reply) = result.pv.get(1) {
+        line += &format!(" ponder {}", reply.to_uci(chess960));
+    }
+    line
+}
+
+const IDLE: &str = "the search is back once it has finished";
+
+fn send<W: Write>(out: &Mutex<W>, line: &str) -> io::Result<()> {
+    let mut out = out.lock().unwrap_or_else(|e| e.into_inner());
+    writeln!(out, "{line}")?;
+    out.flush()
+}
+
+/// Sends the progress of a running search as info lines.
+struct Thinking<W> {
+    out: Arc<Mutex<W>>,
+    chess960: bool,
+}
+
+impl<W: Write> Listener for Thinking<W> {
+    fn info(&mut self, info: &Info) {
+        // The GUI is gone if this fails, the search ends on its own
+        let _ = send(&self.out, &info.to_string());
+    }
+
+    fn currmove(&mut self, mv: &Move, number: usize, elapsed: Duration) {
+        if let Some(line) = currmove(mv, number, elapsed, self.chess960) {
+            let _ = send(&self.out, &line);
+        }
+    }
+}
+
+/// The engine side of a UCI conversation. Searches run on a thread of
+/// their own, so isready and stop are answered while the engine thinks.
+pub struct Session {
+    pub pos: Position,
+    pub game: GameOptions,
+    pub book: BookOptions,
+    /// None while the search is out on its thread.
+    search: Option<Search>,
+    stop: Arc<AtomicBool>,
+    thinking: Option<JoinHandle<Search>>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::with_search(Search::new())
+    }
+
+    pub fn with_search(search: Search) -> Session {
+        Session {
+            pos: parse::from_fen(START_FEN.to_string()).unwrap(),
+            game: GameOptions::default(),
+            book: BookOptions::default(),
+            stop: search.stop_flag(),
+            search: Some(search),
+            thinking: None,
+        }
+    }
+
+    /// Waits for the running search, which sends its bestmove on the way out.
+    /// The search is back in place afterwards.
+    fn finish(&mut self) -> Result<()> {
+        let Some(thinking) = self.thinking.take() else {
+            return Ok(());
+        };
+
+        match thinking.join() {
+            Ok(search) => {
+                self.search = Some(search);
+                Ok(())
+            }
+            Err(_) => {
+                *self = Session::new();
+                Err(anyhow!("The search crashed, all options are reset"))
+            }
+        }
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.finish()
+    }
+
+    /// Sets up "startpos" or "fen <fen>", then plays the moves after "moves".
+    fn position(&mut self, args: &str) -> Result<()> {
+        let (setup, moves) = args.split_once("moves").unwrap_or((args, ""));
+        let fen = match setup.trim() {
+            "startpos" => START_FEN,
+            setup => setup
+                .strip_prefix("fen ")
+                .ok_or_else(|| anyhow!("Expected startpos or fen, got '{setup}'"))?,
+        };
+
+        let mut pos = parse::from_fen_variant(fen.to_string(), self.game.variant)?;
+        pos.set_chess960(self.game.chess960 || pos.is_chess960());
+        for m in moves.split_whitespace() {
+            pos.play_uci(m)
+                .map_err(|e| anyhow!("Illegal move {m}: {e}"))?;
+        }
+
+        self.pos = pos;
+        Ok(())
+    }
+
+    /// Plays from the book when OwnBook is on, otherwise starts the search.
+    fn go<W: Write + Send + 'static>(&mut self, args: &str, out: &Arc<Mutex<W>>) -> Result<()> {
+        let limits = Limits::from_go(args)?;
+        self.finish()?;
+        let mut pos = self.pos.clone();
+        let chess960 = pos.is_chess960();
+
+        // An infinite search has to wait for stop before its bestmove
+        if !limits.infinite {
+            if let Some(m) = self.book.probe(&mut pos) {
+                send(out, &format!("bestmove {}", m.to_uci(chess960)))?;
+                return Ok(());
+            }
+        }
+
+        let mut search = self.search.take().expect(IDLE);
+        self.stop.store(false, Ordering::Relaxed);
+        let out = out.clone();
+        self.thinking = Some(thread::spawn(move || {
+            let mut thinking = Thinking {
+                out: out.clone(),
+                chess960,
+            };
+            let result = search.go(&pos, &limits, &mut thinking);
+            let _ = send(&out, &bestmove_line(&result, chess960));
+            search
+        }));
+
+        Ok(())
+    }
+
+    /// Handles one command, returns false on quit.
+    pub fn handle<W: Write + Send + 'static>(
+        &mut self,
+        line: &str,
+        out: &Arc<Mutex<W>>,
+    ) -> Result<bool> {
+        let line = line.trim();
+        let (cmd, arg) = match line.split_once(' ') {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, ""),
+        };
+
+        match cmd {
+            "" | "debug" | "ponderhit" | "register" => (),
+            "quit" => {
+                self.stop()?;
+                return Ok(false);
+            }
+            "uci" => {
+                send(out, "id name StockCrab")?;
+                send(out, "id author the StockCrab authors")?;
+                self.finish()?;
+                let search = self.search.as_ref().expect(IDLE);
+                for option in option_lines(&[&self.game, search, &self.book]) {
+                    send(out, &option)?;
+                }
+                send(out, "uciok")?;
+            }
+            "isready" => send(out, "readyok")?,
+            "ucinewgame" => {
+                self.finish()?;
+                self.search.as_mut().expect(IDLE).clear();
+            }
+            "position" => self.position(arg)?,
+            "go" => self.go(arg, out)?,
+            "stop" => self.stop()?,
+            "setoption" => {
+                let arg = arg
+                    .strip_prefix("name ")
+                    .ok_or_else(|| anyhow!("Usage: setoption name <id> [value <x>]"))?;
+                let (name, value) = arg.split_once(" value ").unwrap_or((arg, ""));
+                self.finish()?;
+                let search = self.search.as_mut().expect(IDLE);
+                set_option(
+                    &mut [&mut self.game, search, &mut self.book],
+                    name.trim(),
+                    value,
+                )?;
+            }
+            _ => send(out, &format!("info string Unknown command: {line}"))?,
+        }
+
+        Ok(true)
+    }
+
+    /// Reads commands until the input ends or the GUI sends quit. A search
+    /// still running at the end of the input is stopped.
+    pub fn run<R: BufRead, W: Write + Send + 'static>(&mut self, input: R, out: W) -> Result<()> {
+        let out = Arc::new(Mutex::new(out));
+
+        for line in input.lines() {
+            let line = line?;
+            let keep_going = match self.handle(&line, &out) {
+                Ok(keep_going) => keep_going,
+                Err(e) => {
+                    send(&out, &format!("info string Error ({e}): {}", line.trim()))?;
+                    true
+                }
+            };
+
+            if !keep_going {
+                return Ok(());
+            }
+        }
+
+        self.stop()
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::movegen::StateChange;
+    use crate::board::piece::PieceType;
+    use crate::board::square::Square;
+    use crate::book::{self, Book, Entry};
+    use crate::timeman::TimeOptions;
+
+    fn mv(from: &str, to: &str) -> Move {
+        Move {
+            from: Square::from_str(from).unwrap(),
+            to: Square::from_str(to).unwrap(),
+            ptype: PieceType::Pawn,
+            promotion: None,
+            drop: false,
+            change: StateChange::default(),
+        }
+    }
+
+    #[test]
+    fn score() {
+        assert_eq!(UciScore::from_score(35), UciScore::Cp(35));
+        assert_eq!(UciScore::from_score(-120), UciScore::Cp(-120));
+
+        // Mate in 1 is found one ply from the root
+        assert_eq!(UciScore::from_score(MATE - 1), UciScore::Mate(1));
+        assert_eq!(UciScore::from_score(MATE - 3), UciScore::Mate(2));
+        assert_eq!(UciScore::from_score(MATE - 5), UciScore::Mate(3));
+
+        // Getting mated
+        assert_eq!(UciScore::from_score(-MATE), UciScore::Mate(0));
+        assert_eq!(UciScore::from_score(-MATE + 2), UciScore::Mate(-1));
+        assert_eq!(UciScore::from_score(-MATE + 4), UciScore::Mate(-2));
+
+        assert_eq!(UciScore::from_score(MATE - 3).to_string(), "mate 2");
+        assert_eq!(UciScore::from_score(-7).to_string(), "cp -7");
+    }
+
+    #[test]
+    fn info() {
+        let info = Info {
+            depth: 6,
+            seldepth: 11,
+            score: 27,
+            nodes: 150000,
+            time: Duration::from_millis(500),
+            hashfull: 12,
+            pv: vec![mv("e2", "e4"), mv("e7", "e5")],
+            chess960: false,
+        };
+
+        assert_eq!(
+            info.to_string(),
+            "info depth 6 seldepth 11 score cp 27 nodes 150000 nps 300000 time 500 hashfull 12 pv e2e4 e7e5"
+        );
+
+        let info = Info {
+            score: MATE - 1,
+            time: Duration::ZERO,
+            pv: vec![],
+            ..info
+        };
+        assert_eq!(
+            info.to_string(),
+            "info depth 6 seldepth 11 score mate 1 nodes 150000 nps 0 time 0 hashfull 12"
+        );
+    }
+
+    #[test]
+    fn options() {
+        let mut game = GameOptions::default();
+        let mut time = TimeOptions::default();
+        assert_eq!(option_lines(&[&game, &time]).len(), 3);
+
+        set_option(&mut [&mut game, &mut time], "Move Overhead", "30").unwrap();
+        set_option(&mut [&mut game, &mut time], "UCI_Chess960", "true").unwrap();
+        assert_eq!(time.move_overhead, Duration::from_millis(30));
+        assert!(game.chess960);
+
+        let err = set_option(&mut [&mut game, &mut time], "Ponder", "true").unwrap_err();
+        assert_eq!(err.to_string(), "No such option: Ponder");
+        assert!(parse_spin("Hash", "0", 1, 16).is_err());
+        assert!(parse_spin("Hash", " 16", 1, 16).is_ok());
+        assert!(parse_check("OwnBook", "1").is_err());
+    }
+
+    #[test]
+    fn chess960() {
+        let mut options = GameOptions::default();
+        assert!(options.set_option("uci_chess960", "true").unwrap());
+        assert!(options.chess960);
+        assert!(!options.set_option("OwnBook", "true").unwrap());
+        assert!(options.set_option("UCI_Chess960", "yes").is_err());
+
+        let mut castling = mv("e1", "g1");
+        castling.ptype = PieceType::King;
+        castling.change.castling_rook = Some(7);
+        let info = Info {
+            depth: 1,
+            seldepth: 1,
+            score: 0,
+            nodes: 1,
+            time: Duration::ZERO,
+            hashfull: 0,
+            pv: vec![castling],
+            chess960: true,
+        };
+        assert!(info.to_string().ends_with("pv e1h1"));
+    }
+
+    #[test]
+    fn variant() {
+        let mut options = GameOptions::default();
+        assert_eq!(options.variant, Variant::Standard);
+        assert!(options.uci_options()[1].ends_with(
+            "default chess var chess var kingofthehill var 3check var antichess var crazyhouse var atomic"
+        ));
+
+        assert!(options.set_option("UCI_Variant", "3check").unwrap());
+        assert_eq!(options.variant, Variant::ThreeCheck);
+        assert!(options.set_option("uci_variant", "giveaway").unwrap());
+        assert_eq!(options.variant, Variant::Antichess);
+        assert!(options.set_option("UCI_Variant", "horde").is_err());
+    }
+
+    #[test]
+    fn currmove() {
+        let m = mv("g1", "f3");
+        assert_eq!(
+            super::currmove(&m, 3, Duration::from_millis(200), false),
+            None
+        );
+        assert_eq!(
+            super::currmove(&m, 3, Duration::from_millis(1500), false).unwrap(),
+            "info currmove g1f3 currmovenumber 3"
+        );
+
+        let mut castling = mv("e1", "g1");
+        castling.ptype = PieceType::King;
+        castling.change.castling_rook = Some(7);
+        let line = super::currmove(&castling, 1, Duration::from_secs(2), true).unwrap();
+        assert_eq!(line, "info currmove e1h1 currmovenumber 1");
+    }
+
+    fn run(session: &mut Session, input: &str) -> String {
+        let out = Arc::new(Mutex::new(vec![]));
+        for line in input.lines() {
+            session.handle(line, &out).unwrap();
+        }
+        session.finish().unwrap();
+
+        let out = out.lock().unwrap().clone();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn handshake() {
+        let out = run(&mut Session::new(), "uci\nisready\n");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "id name StockCrab");
+        assert!(lines.contains(&"option name UCI_Chess960 type check default false"));
+        assert!(lines.iter().any(|l| l.starts_with("option name Threads")));
+        assert!(lines.iter().any(|l| l.starts_with("option name OwnBook")));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("option name SyzygyPath")));
+        assert_eq!(lines[lines.len() - 2..], ["uciok", "readyok"]);
+    }
+
+    #[test]
+    fn search() {
+        let mut session = Session::new();
+        let out = run(&mut session, "position startpos moves e2e4\ngo depth 2\n");
+        assert!(out.starts_with("info depth 1 "), "{out}");
+        assert!(out.contains("\ninfo depth 2 "));
+
+        let best = out
+            .lines()
+            .last()
+            .unwrap()
+            .strip_prefix("bestmove ")
+            .unwrap();
+        let best = best.split(' ').next().unwrap();
+        assert!(session.pos.clone().play_uci(best).is_ok(), "{best}");
+        assert_eq!(session.pos.history().len(), 1);
+
+        let out = run(
+            &mut session,
+            "ucinewgame\nposition fen 7k/8/6K1/8/8/8/8/R7 w - - 0 1\ngo depth 2\n",
+        );
+        assert!(out.ends_with("bestmove a1a8\n"), "{out}");
+    }
+
+    #[test]
+    fn stop() {
+        let mut session = Session::new();
+        let out = Arc::new(Mutex::new(vec![]));
+        session.handle("go infinite", &out).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        session.handle("isready", &out).unwrap();
+        assert!(session
+            .handle("quit", &out)
+            .is_ok_and(|keep_going| !keep_going));
+
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        assert!(out.contains("readyok\n"));
+        assert!(
+            out.lines().last().unwrap().starts_with("bestmove "),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn setoption() {
+        let mut session = Session::new();
+        let out = run(
+            &mut session,
+            "setoption name Threads value 2\nsetoption name UCI_Chess960 value true\n\
+             setoption name UCI_Variant value kingofthehill\nposition startpos moves e2e4\n",
+        );
+        assert_eq!(out, "");
+        assert_eq!(session.search.as_ref().unwrap().threads(), 2);
+        assert!(session.pos.is_chess960());
+        assert_eq!(session.pos.variant(), Variant::KingOfTheHill);
+
+        let out = Arc::new(Mutex::new(vec![]));
+        let err = session.handle("setoption name Ponder value true", &out);
+        assert_eq!(err.unwrap_err().to_string(), "No such option: Ponder");
+        assert!(session.handle("setoption Hash 16", &out).is_err());
+        assert!(session
+            .handle("position startpos moves e2e5", &out)
+            .is_err());
+        assert_eq!(session.pos.history().len(), 1);
+    }
+
+    #[test]
+    fn own_book() {
+        let mut session = Session::new();
+        let mut pos = session.pos.clone();
+        let m = pos.parse_uci("g1f3").unwrap();
+        session.book.book = Some(Book::new(vec![Entry {
+            key: book::polyglot_key(&pos),
+            mv: book::encode_move(&m),
+            weight: 1,
+            learn: 0,
+        }]));
+
+        // The book is only used with OwnBook on
+        let out = run(
+            &mut session,
+            "setoption name OwnBook value true\ngo depth 1\n",
+        );
+        assert_eq!(out, "bestmove g1f3\n");
+        let out = run(
+            &mut session,
+            "setoption name OwnBook value false\ngo depth 1\n",
+        );
+        assert!(out.starts_with("info depth 1 "));
+    }
+}
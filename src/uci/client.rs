@@ -0,0 +1,665 @@
+use super::UciScore;
+use crate::pgn::START_FEN;
+use crate::timeman::Limits;
+use anyhow::{anyhow, Result};
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long an engine gets to answer uci and isready
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long a search that ran out of time gets to answer stop
+const STOP_GRACE: Duration = Duration::from_millis(500);
+
+// How long an engine gets to exit after quit before it is killed
+const QUIT_GRACE: Duration = Duration::from_millis(500);
+
+/// Why talking to an engine failed, found with downcast_ref on the anyhow error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EngineError {
+    /// The engine didn't answer a command in time.
+    Timeout(String),
+    /// The engine exited, with its exit status if there was one.
+    Crashed(Option<String>),
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Timeout(cmd) => write!(f, "No answer to {cmd} in time"),
+            EngineError::Crashed(Some(status)) => write!(f, "The engine terminated ({status})"),
+            EngineError::Crashed(None) => write!(f, "The engine terminated"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// Whether an info score is only a bound on the real one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Lower,
+    Upper,
+}
+
+/// An info line, with the fields the engine sent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineInfo {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<UciScore>,
+    pub bound: Option<Bound>,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub time: Option<Duration>,
+    pub hashfull: Option<u32>,
+    pub tbhits: Option<u64>,
+    pub currmove: Option<String>,
+    pub currmovenumber: Option<u32>,
+    /// Moves in UCI notation.
+    pub pv: Vec<String>,
+    pub string: Option<String>,
+}
+
+impl EngineInfo {
+    /// Parses an info line. Unknown fields are skipped.
+    pub fn parse(line: &str) -> Result<EngineInfo> {
+        let mut tokens = line.split_whitespace().peekable();
+        if tokens.next() != Some("info") {
+            return Err(anyhow!("Not an info line: '{line}'"));
+        }
+
+        let mut info = EngineInfo::default();
+        while let Some(token) = tokens.next() {
+            let mut value = || {
+                tokens
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for '{token}' in '{line}'"))
+            };
+
+            match token {
+                "depth" => info.depth = Some(number(token, value()?)?),
+                "seldepth" => info.seldepth = Some(number(token, value()?)?),
+                "multipv" => info.multipv = Some(number(token, value()?)?),
+                "nodes" => info.nodes = Some(number(token, value()?)?),
+                "nps" => info.nps = Some(number(token, value()?)?),
+                "hashfull" => info.hashfull = Some(number(token, value()?)?),
+                "tbhits" => info.tbhits = Some(number(token, value()?)?),
+                "time" => info.time = Some(Duration::from_millis(number(token, value()?)?)),
+                "currmove" => info.currmove = Some(value()?.to_string()),
+                "currmovenumber" => info.currmovenumber = Some(number(token, value()?)?),
+                "score" => {
+                    let kind = value()?;
+                    let n = number(kind, value()?)?;
+                    info.score = Some(match kind {
+                        "cp" => UciScore::Cp(n),
+                        "mate" => UciScore::Mate(n),
+                        _ => return Err(anyhow!("Invalid score '{kind}' in '{line}'")),
+                    });
+                    match tokens.peek() {
+                        Some(&"lowerbound") => info.bound = Some(Bound::Lower),
+                        Some(&"upperbound") => info.bound = Some(Bound::Upper),
+                        _ => continue,
+                    }
+                    tokens.next();
+                }
+                "pv" => {
+                    info.pv = tokens.by_ref().map(|m| m.to_string()).collect();
+                }
+                "string" => {
+                    info.string = Some(tokens.by_ref().collect::<Vec<_>>().join(" "));
+                }
+                _ => (),
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+fn number<T: std::str::FromStr>(token: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value '{value}' for '{token}'"))
+}
+
+/// Parses a bestmove line into the move and the move to ponder on.
+pub fn parse_bestmove(line: &str) -> Result<(String, Option<String>)> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+
+    match tokens[..] {
+        ["bestmove", mv] => Ok((mv.to_string(), None)),
+        ["bestmove", mv, "ponder", ponder] => Ok((mv.to_string(), Some(ponder.to_string()))),
+        _ => Err(anyhow!("Invalid bestmove line '{line}'")),
+    }
+}
+
+/// An option the engine announced in response to uci.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineOption {
+    pub name: String,
+    /// check, spin, combo, button or string.
+    pub kind: String,
+    pub default: Option<String>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// Values of a combo.
+    pub vars: Vec<String>,
+}
+
+impl EngineOption {
+    /// Parses an option line, e.g. "option name Hash type spin default 16 min 1 max 1024".
+    pub fn parse(line: &str) -> Result<EngineOption> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.first() != Some(&"option") {
+            return Err(anyhow!("Not an option line: '{line}'"));
+        }
+
+        const KEYWORDS: [&str; 6] = ["name", "type", "default", "min", "max", "var"];
+        let mut option = EngineOption::default();
+        let mut i = 1;
+
+        while i < tokens.len() {
+            let keyword = tokens[i];
+            // Names and values can have spaces, they run to the next keyword
+            let end = tokens[i + 1..]
+                .iter()
+                .position(|t| KEYWORDS.contains(t))
+                .map_or(tokens.len(), |n| i + 1 + n);
+            let value = tokens[i + 1..end].join(" ");
+            let number = || {
+                value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid {keyword} '{value}' in '{line}'"))
+            };
+
+            match keyword {
+                "name" => option.name = value.clone(),
+                "type" => option.kind = value.clone(),
+                "default" => option.default = Some(value.clone()),
+                "min" => option.min = Some(number()?),
+                "max" => option.max = Some(number()?),
+                "var" => option.vars.push(value.clone()),
+                _ => return Err(anyhow!("Unexpected '{keyword}' in '{line}'")),
+            }
+            i = end;
+        }
+
+        if option.name.is_empty() || option.kind.is_empty() {
+            return Err(anyhow!("Option without a name or type: '{line}'"));
+        }
+        Ok(option)
+    }
+}
+
+/// What a search ended with: the best move, the move to ponder on and
+/// every info line sent while searching.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchResult {
+    pub best: String,
+    pub ponder: Option<String>,
+    pub info: Vec<EngineInfo>,
+}
+
+impl SearchResult {
+    /// The last score of the main line.
+    pub fn score(&self) -> Option<UciScore> {
+        self.main_line().find_map(|i| i.score)
+    }
+
+    /// The deepest depth reached by the main line.
+    pub fn depth(&self) -> Option<u32> {
+        self.main_line().find_map(|i| i.depth)
+    }
+
+    /// Info lines of the first pv, newest first.
+    fn main_line(&self) -> impl Iterator<Item = &EngineInfo> {
+        self.info
+            .iter()
+            .rev()
+            .filter(|i| i.multipv.unwrap_or(1) == 1 && i.currmove.is_none())
+    }
+}
+
+/// A UCI engine running in its own process. Lines are read on a separate
+/// thread so every wait can time out, and the process is killed when the
+/// client is dropped.
+pub struct UciEngine {
+    name: String,
+    author: Option<String>,
+    options: Vec<EngineOption>,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl UciEngine {
+    /// Starts cmd with args and waits for uciok.
+    pub fn spawn(cmd: &str, args: &[String]) -> Result<UciEngine> {
+        let mut child = Command::new(cmd)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("Could not start {cmd}: {e}"))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = UciEngine {
+            name: cmd.to_string(),
+            author: None,
+            options: vec![],
+            child,
+            stdin,
+            lines,
+        };
+        engine.send("uci")?;
+
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = engine.read_line(deadline, "uci")?;
+            if line == "uciok" {
+                break;
+            } else if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if let Some(author) = line.strip_prefix("id author ") {
+                engine.author = Some(author.trim().to_string());
+            } else if line.starts_with("option ") {
+                // An option that can't be parsed just can't be set, the engine still works
+                if let Ok(option) = EngineOption::parse(&line) {
+                    engine.options.push(option);
+                }
+            }
+        }
+
+        Ok(engine)
+    }
+
+    /// Name the engine gave with id name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    pub fn options(&self) -> &[EngineOption] {
+        &self.options
+    }
+
+    /// Sets an option the engine announced, names are case insensitive.
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<()> {
+        let option = self
+            .options
+            .iter()
+            .find(|o| o.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("{} has no option {name}", self.name))?;
+
+        let line = format!("setoption name {} value {value}", option.name);
+        self.send(&line)
+    }
+
+    /// Waits until the engine has handled everything sent so far.
+    pub fn is_ready(&mut self) -> Result<()> {
+        self.send("isready")?;
+
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while self.read_line(deadline, "isready")? != "readyok" {}
+        Ok(())
+    }
+
+    pub fn new_game(&mut self) -> Result<()> {
+        self.send("ucinewgame")?;
+        self.is_ready()
+    }
+
+    /// Sends the position the moves, in UCI notation, lead to from fen.
+    pub fn set_position(&mut self, fen: &str, moves: &[String]) -> Result<()> {
+        let mut line = if fen == START_FEN {
+            "position startpos".to_string()
+        } else {
+            format!("position fen {fen}")
+        };
+        if !moves.is_empty() {
+            line.push_str(" moves ");
+            line.push_str(&moves.join(" "));
+        }
+
+        self.send(&line)
+    }
+
+    /// Searches with the clock in limits. Without an answer before timeout
+    /// the search is stopped and EngineError::Timeout returned.
+    pub fn go(&mut self, limits: &Limits, timeout: Option<Duration>) -> Result<SearchResult> {
+        self.search(&go_command(limits), timeout)
+    }
+
+    pub fn go_depth(&mut self, depth: u32, timeout: Option<Duration>) -> Result<SearchResult> {
+        self.search(&format!("go depth {depth}"), timeout)
+    }
+
+    fn search(&mut self, go: &str, timeout: Option<Duration>) -> Result<SearchResult> {
+        self.send(go)?;
+
+        // Far enough away to never be reached
+        let deadline = Instant::now() + timeout.unwrap_or(Duration::from_secs(365 * 24 * 3600));
+        let mut result = SearchResult::default();
+
+        loop {
+            let line = match self.read_line(deadline, "go") {
+                Err(e) if matches!(e.downcast_ref(), Some(EngineError::Timeout(_))) => {
+                    // Give the engine the chance to answer stop, so it can be used again
+                    self.send("stop")?;
+                    let _ = self.read_until_bestmove(Instant::now() + STOP_GRACE);
+                    return Err(e);
+                }
+                line => line?,
+            };
+
+            if line.starts_with("info") {
+                // Engines send the odd malformed info line, which doesn't end the search
+                if let Ok(info) = EngineInfo::parse(&line) {
+                    result.info.push(info);
+                }
+            } else if line.starts_with("bestmove") {
+                let (best, ponder) = parse_bestmove(&line)?;
+                result.best = best;
+                result.ponder = ponder;
+                return Ok(result);
+            }
+        }
+    }
+
+    fn read_until_bestmove(&mut self, deadline: Instant) -> Result<()> {
+        while !self.read_line(deadline, "stop")?.starts_with("bestmove") {}
+        Ok(())
+    }
+
+    /// Tells the engine to quit, and kills it if it doesn't.
+    pub fn quit(mut self) -> Result<()> {
+        self.shut_down()
+    }
+
+    fn shut_down(&mut self) -> Result<()> {
+        if self.child.try_wait()?.is_some() {
+            return Ok(());
+        }
+
+        let _ = self.send("quit");
+        let deadline = Instant::now() + QUIT_GRACE;
+        while Instant::now() < deadline {
+            if self.child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        self.child.kill()?;
+        self.child.wait()?;
+        Ok(())
+    }
+
+    pub fn send(&mut self, line: &str) -> Result<()> {
+        let sent = writeln!(self.stdin, "{line}").and_then(|_| self.stdin.flush());
+        if sent.is_err() {
+            return Err(self.crashed().into());
+        }
+        Ok(())
+    }
+
+    /// The next line from the engine, waiting for what until deadline.
+    fn read_line(&mut self, deadline: Instant, what: &str) -> Result<String> {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match self.lines.recv_timeout(wait) {
+            Ok(line) => Ok(line.trim().to_string()),
+            Err(RecvTimeoutError::Timeout) => Err(EngineError::Timeout(what.to_string()).into()),
+            Err(RecvTimeoutError::Disconnected) => Err(self.crashed().into()),
+        }
+    }
+
+    fn crashed(&mut self) -> EngineError {
+        // Output ends a moment before the process does
+        for _ in 0..20 {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                return EngineError::Crashed(Some(status.to_string()));
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        EngineError::Crashed(None)
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.shut_down();
+    }
+}
+
+fn go_command(limits: &Limits) -> String {
+    let mut go = "go".to_string();
+    let fields = [
+        ("wtime", limits.wtime),
+        ("btime", limits.btime),
+        ("winc", limits.winc),
+        ("binc", limits.binc),
+        ("movestogo", limits.movestogo),
+        ("movetime", limits.movetime),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            go.push_str(&format!(" {name} {value}"));
+        }
+    }
+    if limits.infinite {
+        go.push_str(" infinite");
+    }
+    go
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::BookOptions;
+    use crate::search::Search;
+    use crate::uci::{option_lines, GameOptions, UciOption};
+
+    #[test]
+    fn info() {
+        let info = EngineInfo::parse(
+            "info depth 12 seldepth 18 multipv 1 score cp -31 upperbound nodes 123456 \
+             nps 800000 hashfull 42 tbhits 0 time 154 pv e7e5 g1f3 b8c6",
+        )
+        .unwrap();
+        assert_eq!(info.depth, Some(12));
+        assert_eq!(info.seldepth, Some(18));
+        assert_eq!(info.score, Some(UciScore::Cp(-31)));
+        assert_eq!(info.bound, Some(Bound::Upper));
+        assert_eq!(info.nodes, Some(123456));
+        assert_eq!(info.time, Some(Duration::from_millis(154)));
+        assert_eq!(info.pv, vec!["e7e5", "g1f3", "b8c6"]);
+
+        let info =
+            EngineInfo::parse("info depth 3 score mate -2 currmove e2e4 currmovenumber 1").unwrap();
+        assert_eq!(info.score, Some(UciScore::Mate(-2)));
+        assert_eq!(info.currmove.as_deref(), Some("e2e4"));
+        assert_eq!(info.bound, None);
+
+        let info = EngineInfo::parse("info string NNUE evaluation enabled").unwrap();
+        assert_eq!(info.string.as_deref(), Some("NNUE evaluation enabled"));
+
+        // Lines the search itself writes
+        let ours = crate::uci::Info {
+            depth: 6,
+            seldepth: 11,
+            score: crate::uci::MATE - 3,
+            nodes: 150000,
+            time: Duration::from_millis(500),
+            hashfull: 12,
+            pv: vec![],
+            chess960: false,
+        };
+        let info = EngineInfo::parse(&ours.to_string()).unwrap();
+        assert_eq!(info.score, Some(UciScore::Mate(2)));
+        assert_eq!(info.nps, Some(300000));
+
+        assert!(EngineInfo::parse("info depth x").is_err());
+        assert!(EngineInfo::parse("info score wdl 1 2 3").is_err());
+        assert!(EngineInfo::parse("bestmove e2e4").is_err());
+    }
+
+    #[test]
+    fn bestmove() {
+        assert_eq!(
+            parse_bestmove("bestmove e2e4").unwrap(),
+            ("e2e4".to_string(), None)
+        );
+        assert_eq!(
+            parse_bestmove("bestmove e7e8q ponder d1d8").unwrap(),
+            ("e7e8q".to_string(), Some("d1d8".to_string()))
+        );
+        assert!(parse_bestmove("bestmove").is_err());
+    }
+
+    #[test]
+    fn options() {
+        let hash =
+            EngineOption::parse("option name Hash type spin default 16 min 1 max 1024").unwrap();
+        assert_eq!(hash.name, "Hash");
+        assert_eq!(hash.kind, "spin");
+        assert_eq!(hash.default.as_deref(), Some("16"));
+        assert_eq!((hash.min, hash.max), (Some(1), Some(1024)));
+
+        let clear = EngineOption::parse("option name Clear Hash type button").unwrap();
+        assert_eq!(clear.name, "Clear Hash");
+        assert_eq!(clear.default, None);
+
+        // Every option we announce ourselves
+        let game = GameOptions::default();
+        let ours = option_lines(&[&game, &Search::new(), &BookOptions::default()]);
+        for line in &ours {
+            EngineOption::parse(line).unwrap();
+        }
+        let variant = EngineOption::parse(&game.uci_options()[1]).unwrap();
+        assert_eq!(variant.kind, "combo");
+        assert_eq!(variant.default.as_deref(), Some("chess"));
+        assert_eq!(variant.vars.len(), 6);
+
+        assert!(EngineOption::parse("option type spin").is_err());
+        assert!(EngineOption::parse("option name Hash type spin min x").is_err());
+    }
+
+    #[test]
+    fn go() {
+        let limits = Limits {
+            wtime: Some(60000),
+            btime: Some(59000),
+            winc: Some(1000),
+            binc: Some(1000),
+            movestogo: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(
+            go_command(&limits),
+            "go wtime 60000 btime 59000 winc 1000 binc 1000 movestogo 20"
+        );
+        assert_eq!(
+            go_command(&Limits::from_go("go movetime 300").unwrap()),
+            "go movetime 300"
+        );
+    }
+
+    /// A shell script standing in for an engine. What it does on go is up to the test.
+    #[cfg(unix)]
+    fn fake_engine(on_go: &str) -> Result<UciEngine> {
+        let script = format!(
+            "while read -r cmd rest; do case $cmd in \
+             uci) echo 'id name Fake Engine'; echo 'id author Nobody'; \
+             echo 'option name Hash type spin default 16 min 1 max 128'; \
+             echo 'option name Style type spin min x'; echo uciok;; \
+             isready) echo readyok;; \
+             go) {on_go};; \
+             stop) echo 'bestmove a7a6';; \
+             quit) exit 0;; \
+             esac; done"
+        );
+        UciEngine::spawn("sh", &["-c".to_string(), script])
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn engine() {
+        let mut engine = fake_engine(
+            "echo 'info depth 1 score cp 10 pv e7e5'; \
+             echo 'info depth 2 multipv 2 score cp -50 pv a7a6'; \
+             echo 'info depth 2 score cp 20 pv e7e5 g1f3'; \
+             echo 'info currmove d7d5 currmovenumber 2'; \
+             echo 'bestmove e7e5 ponder g1f3'",
+        )
+        .unwrap();
+        assert_eq!(engine.name(), "Fake Engine");
+        assert_eq!(engine.author(), Some("Nobody"));
+        assert_eq!(engine.options()[0].max, Some(128));
+        assert_eq!(engine.options().len(), 1);
+
+        engine.set_option("hash", "64").unwrap();
+        assert!(engine.set_option("Threads", "2").is_err());
+        engine.new_game().unwrap();
+        engine
+            .set_position(START_FEN, &["e2e4".to_string()])
+            .unwrap();
+
+        let result = engine.go_depth(2, Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(result.best, "e7e5");
+        assert_eq!(result.ponder.as_deref(), Some("g1f3"));
+        assert_eq!(result.info.len(), 4);
+        assert_eq!(result.score(), Some(UciScore::Cp(20)));
+        assert_eq!(result.depth(), Some(2));
+
+        engine.quit().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failures() {
+        // Answers stop, but only gets it once the time is up
+        let mut engine = fake_engine(":").unwrap();
+        let started = Instant::now();
+        let err = engine
+            .go(&Limits::default(), Some(Duration::from_millis(100)))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EngineError::Timeout("go".to_string()))
+        );
+        assert!(started.elapsed() < STOP_GRACE);
+        // and is ready for the next search
+        engine.is_ready().unwrap();
+
+        let mut engine = fake_engine("exit 3").unwrap();
+        let err = engine.go(&Limits::default(), None).unwrap_err();
+        match err.downcast_ref() {
+            Some(EngineError::Crashed(Some(status))) => assert!(status.contains('3'), "{status}"),
+            other => panic!("{other:?}"),
+        }
+        assert!(engine.is_ready().is_err());
+
+        assert!(UciEngine::spawn("/nonexistent/engine", &[]).is_err());
+    }
+}
@@ -0,0 +1,594 @@
+use crate::board::movegen::Move;
+use crate::board::notation::IllegalMove;
+use crate::board::piece::Color;
+use crate::board::variant::Variant;
+use crate::board::{parse, GameState, Position};
+use crate::pgn::START_FEN;
+use crate::search::Search;
+use crate::timeman::Limits;
+use crate::uci::{Info, UciScore};
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, Write};
+
+/// Picks a move for the side to move within the clock and the depth limit
+/// set with sd, None if it has nothing to play. Progress goes to the info
+/// sink, which sends it as thinking output in post mode.
+pub type Engine =
+    Box<dyn FnMut(&mut Position, &Limits, Option<u32>, &mut dyn FnMut(&Info)) -> Option<Move>>;
+
+/// An engine that plays with search, stopping at the sd depth if one is set.
+pub fn search_engine(mut search: Search) -> Engine {
+    Box::new(move |pos, limits, depth, mut info| {
+        let limits = Limits {
+            depth: depth.map(u64::from).or(limits.depth),
+            ..limits.clone()
+        };
+        search.go(pos, &limits, &mut info).best
+    })
+}
+
+/// Protocol a GUI speaks, told apart by the first command it sends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Uci,
+    Xboard,
+}
+
+impl Protocol {
+    pub fn detect(first_line: &str) -> Option<Protocol> {
+        match first_line.split_whitespace().next() {
+            Some("uci") => Some(Protocol::Uci),
+            Some("xboard") | Some("protover") => Some(Protocol::Xboard),
+            _ => None,
+        }
+    }
+}
+
+/// Variant names as CECP has them.
+fn variant_name(v: Variant) -> &'static str {
+    match v {
+        Variant::Standard => "normal",
+        Variant::Antichess => "giveaway",
+        v => v.name(),
+    }
+}
+
+// Commands that need no answer from an engine without pondering or an opening book
+const IGNORED: [&str; 13] = [
+    "xboard", "accepted", "rejected", "random", "hard", "easy", "computer", "name", "rating",
+    "ics", "?", "white", "black",
+];
+
+/// Thinking output for post mode: ply, score, time in centiseconds, nodes and pv.
+/// Mate scores are written as 100000 + moves to mate.
+pub fn thinking_line(info: &Info) -> String {
+    let score = match UciScore::from_score(info.score) {
+        UciScore::Cp(cp) => cp,
+        UciScore::Mate(n) if n > 0 => 100000 + n,
+        UciScore::Mate(n) => -100000 + n,
+    };
+    let pv: Vec<String> = info.pv.iter().map(|m| m.to_uci(false)).collect();
+
+    format!(
+        "{} {score} {} {} {}",
+        info.depth,
+        info.time.as_millis() / 10,
+        info.nodes,
+        pv.join(" ")
+    )
+    .trim_end()
+    .to_string()
+}
+
+/// Time control set with level or st, and the clocks sent with time and otim.
+/// All times are in milliseconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Clock {
+    /// Moves per time control, 0 for the whole game.
+    pub moves: u64,
+    pub base: u64,
+    pub inc: u64,
+    pub per_move: Option<u64>,
+    pub depth: Option<u32>,
+    pub own: Option<u64>,
+    pub opponent: Option<u64>,
+}
+
+impl Clock {
+    /// Parses the arguments of level, e.g. "40 5 0" or "0 2:30 1".
+    fn level(&mut self, args: &str) -> Result<()> {
+        let usage = || anyhow!("Usage: level <moves> <minutes[:seconds]> <increment>");
+        let args: Vec<&str> = args.split_whitespace().collect();
+        let [moves, base, inc] = args[..] else {
+            return Err(usage());
+        };
+
+        let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+        let minutes: u64 = minutes.parse().map_err(|_| usage())?;
+        let seconds: u64 = seconds.parse().map_err(|_| usage())?;
+        let inc: f64 = inc.parse().map_err(|_| usage())?;
+
+        self.moves = moves.parse().map_err(|_| usage())?;
+        self.base = (minutes * 60 + seconds) * 1000;
+        self.inc = (inc * 1000.0) as u64;
+        self.per_move = None;
+        Ok(())
+    }
+
+    /// Limits as a UCI go command would give them, for the engine playing side
+    /// after played moves of its own.
+    pub fn limits(&self, side: Color, played: u64) -> Limits {
+        if let Some(ms) = self.per_move {
+            return Limits {
+                movetime: Some(ms),
+                ..Limits::default()
+            };
+        }
+
+        let own = self.own.unwrap_or(self.base);
+        let opponent = self.opponent.unwrap_or(self.base);
+        let (wtime, btime) = match side {
+            Color::White => (own, opponent),
+            Color::Black => (opponent, own),
+        };
+        let movestogo = (self.moves > 0).then(|| self.moves - played % self.moves);
+
+        Limits {
+            wtime: Some(wtime),
+            btime: Some(btime),
+            winc: Some(self.inc),
+            binc: Some(self.inc),
+            movestogo,
+            ..Limits::default()
+        }
+    }
+}
+
+pub struct Session {
+    pub pos: Position,
+    pub variant: Variant,
+    pub clock: Clock,
+    /// Send thinking output.
+    pub post: bool,
+    engine: Option<Engine>,
+    /// None in force mode, when the engine only keeps track of the moves.
+    engine_side: Option<Color>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            pos: parse::from_fen(START_FEN.to_string()).unwrap(),
+            variant: Variant::Standard,
+            clock: Clock::default(),
+            post: false,
+            engine: None,
+            engine_side: None,
+        }
+    }
+
+    pub fn with_engine(mut self, engine: Engine) -> Session {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Sets up fen in the current variant, with castling written the
+    /// Chess960 way if chess960 or if the FEN needs it.
+    fn reset(&mut self, fen: &str, chess960: bool) -> Result<()> {
+        self.pos = parse::from_fen_variant(fen.to_string(), self.variant)?;
+        self.pos.set_chess960(chess960 || self.pos.is_chess960());
+        Ok(())
+    }
+
+    /// Result line once the game has ended, e.g. 1-0 {White wins}.
+    fn result(&self) -> Option<String> {
+        match self.pos.gamestate() {
+            GameState::Won(Color::White) => Some("1-0 {White wins}".to_string()),
+            GameState::Won(Color::Black) => Some("0-1 {Black wins}".to_string()),
+            GameState::Draw => Some("1/2-1/2 {Draw}".to_string()),
+            _ => None,
+        }
+    }
+
+    /// A move in the notation CECP expects: coordinates, O-O in Chess960.
+    fn move_text(&self, m: &Move) -> String {
+        match m.castling_rook() {
+            Some(_) if self.pos.is_chess960() => {
+                let long = m.to.file == 2;
+                (if long { "O-O-O" } else { "O-O" }).to_string()
+            }
+            _ => m.to_string(),
+        }
+    }
+
+    fn user_move<W: Write>(&mut self, s: &str, out: &mut W) -> Result<()> {
+        // Coordinate notation is asked for with san=0, but O-O comes in Chess960
+        let played = match self.pos.play_uci(s) {
+            Err(IllegalMove::Invalid(_)) => self.pos.play_san(s),
+            played => played,
+        };
+
+        if let Err(e) = played {
+            writeln!(out, "Illegal move ({e}): {s}")?;
+            return Ok(());
+        }
+
+        self.after_move(out)
+    }
+
+    /// Announces the end of the game, or lets the engine answer.
+    fn after_move<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        if let Some(result) = self.result() {
+            writeln!(out, "{result}")?;
+        } else if self.engine_side == Some(self.pos.side()) {
+            self.engine_move(out)?;
+        }
+
+        Ok(())
+    }
+
+    fn engine_move<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        let side = self.pos.side();
+        let played = self.pos.history().len() as u64 / 2;
+        let limits = self.clock.limits(side, played);
+        let depth = self.clock.depth;
+
+        let Some(engine) = self.engine.as_mut() else {
+            self.engine_side = None;
+            writeln!(out, "Error (no engine to search with): go")?;
+            return Ok(());
+        };
+
+        let post = self.post;
+        let mut thinking = Ok(());
+        let mut sink = |info: &Info| {
+            if post && thinking.is_ok() {
+                thinking = writeln!(out, "{}", thinking_line(info));
+            }
+        };
+
+        let mut pos = self.pos.clone();
+        let found = engine(&mut pos, &limits, depth, &mut sink);
+        thinking?;
+        let Some(m) = found else {
+            self.engine_side = None;
+            writeln!(out, "Error (no move found): go")?;
+            return Ok(());
+        };
+
+        let text = self.move_text(&m);
+        self.pos
+            .play(m)
+            .map_err(|e| anyhow!("The engine played an illegal move {text}: {e}"))?;
+        writeln!(out, "move {text}")?;
+
+        if let Some(result) = self.result() {
+            writeln!(out, "{result}")?;
+        }
+        Ok(())
+    }
+
+    fn take_back(&mut self, plies: usize) {
+        for _ in 0..plies {
+            self.pos.undo();
+        }
+    }
+
+    /// Handles one command, returns false on quit.
+    pub fn handle<W: Write>(&mut self, line: &str, out: &mut W) -> Result<bool> {
+        let line = line.trim();
+        let (cmd, arg) = match line.split_once(' ') {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, ""),
+        };
+        let number = |arg: &str| -> Result<u64> {
+            arg.parse()
+                .map_err(|_| anyhow!("Expected a number after {cmd}, got '{arg}'"))
+        };
+
+        match cmd {
+            "" => (),
+            "quit" => return Ok(false),
+            "protover" => {
+                if number(arg)? >= 2 {
+                    let variants: Vec<&str> = Variant::ALL
+                        .iter()
+                        .map(|v| variant_name(*v))
+                        .chain(["fischerandom"])
+                        .collect();
+                    writeln!(
+                        out,
+                        "feature myname=\"StockCrab\" ping=1 setboard=1 usermove=1 playother=0 \
+                         san=0 time=1 draw=0 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 \
+                         variants=\"{}\"",
+                        variants.join(",")
+                    )?;
+                    writeln!(out, "feature done=1")?;
+                }
+            }
+            "ping" => writeln!(out, "pong {arg}")?,
+            "new" => {
+                self.variant = Variant::Standard;
+                self.reset(START_FEN, false)?;
+                self.engine_side = Some(Color::Black);
+                self.clock.depth = None;
+            }
+            "variant" => {
+                let chess960 = arg == "fischerandom";
+                self.variant = match arg {
+                    "normal" | "fischerandom" => Variant::Standard,
+                    "giveaway" => Variant::Antichess,
+                    _ => Variant::from_name(arg)?,
+                };
+                self.reset(START_FEN, chess960)?;
+            }
+            "setboard" => {
+                if let Err(e) = self.reset(arg, self.pos.is_chess960()) {
+                    writeln!(out, "tellusererror Illegal position: {e}")?;
+                }
+            }
+            "force" => self.engine_side = None,
+            "go" => {
+                self.engine_side = Some(self.pos.side());
+                if self.result().is_none() {
+                    self.engine_move(out)?;
+                }
+            }
+            "usermove" => self.user_move(arg, out)?,
+            "level" => self.clock.level(arg)?,
+            "st" => self.clock.per_move = Some(number(arg)? * 1000),
+            "sd" => self.clock.depth = Some(number(arg)? as u32),
+            "time" => self.clock.own = Some(number(arg)? * 10),
+            "otim" => self.clock.opponent = Some(number(arg)? * 10),
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "result" => self.engine_side = None,
+            cmd if IGNORED.contains(&cmd) => (),
+            _ => writeln!(out, "Error (unknown command): {line}")?,
+        }
+
+        Ok(true)
+    }
+
+    /// Reads commands until the input ends or the GUI sends quit.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            let keep_going = match self.handle(&line, &mut out) {
+                Ok(keep_going) => keep_going,
+                Err(e) => {
+                    writeln!(out, "Error ({e}): {}", line.trim())?;
+                    true
+                }
+            };
+            out.flush()?;
+
+            if !keep_going {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Plays the first legal move.
+    fn first_move() -> Engine {
+        Box::new(|pos, _, _, _| pos.legal_moves().first().copied())
+    }
+
+    fn run(session: &mut Session, input: &str) -> String {
+        let mut out = vec![];
+        session.run(input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Protocol::detect("uci"), Some(Protocol::Uci));
+        assert_eq!(Protocol::detect("xboard\n"), Some(Protocol::Xboard));
+        assert_eq!(Protocol::detect("protover 2"), Some(Protocol::Xboard));
+        assert_eq!(Protocol::detect("8/8/8/8/8/8/8/8 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn features() {
+        let out = run(&mut Session::new(), "xboard\nprotover 2\nping 7\n");
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("feature myname=\"StockCrab\""));
+        assert!(lines[0].contains("setboard=1 usermove=1"));
+        assert!(lines[0].contains("variants=\"normal,kingofthehill,3check,giveaway,"));
+        assert_eq!(lines[1], "feature done=1");
+        assert_eq!(lines[2], "pong 7");
+    }
+
+    #[test]
+    fn game() {
+        let mut session = Session::new().with_engine(first_move());
+
+        // The engine plays black after new
+        let out = run(&mut session, "new\nusermove e2e4\n");
+        assert!(out.starts_with("move "), "{out}");
+        assert_eq!(session.pos.history().len(), 2);
+
+        // but not in force mode
+        let out = run(&mut session, "force\nusermove d2d4\nusermove d7d5\n");
+        assert_eq!(out, "");
+        assert_eq!(session.pos.history().len(), 4);
+
+        let out = run(&mut session, "usermove e2e5\nundo\nremove\n");
+        assert!(out.starts_with("Illegal move ("), "{out}");
+        assert!(out.trim_end().ends_with(": e2e5"));
+        assert_eq!(session.pos.history().len(), 1);
+
+        // go switches the engine to the side to move
+        let out = run(&mut session, "go\n");
+        assert!(out.starts_with("move "));
+        assert_eq!(session.pos.side(), Color::White);
+
+        let out = run(
+            &mut session,
+            "foo\nresult 1-0 {White resigns}\nusermove e1e2\n",
+        );
+        assert_eq!(out, "Error (unknown command): foo\n");
+    }
+
+    #[test]
+    fn search() {
+        let mut session = Session::new().with_engine(search_engine(Search::new()));
+        let out = run(&mut session, "new\nsd 1\npost\nusermove e2e4\n");
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("1 "), "{out}");
+        assert!(lines[1].starts_with("move "));
+        assert_eq!(session.pos.history().len(), 2);
+    }
+
+    #[test]
+    fn results() {
+        let mut session = Session::new().with_engine(first_move());
+        let out = run(
+            &mut session,
+            "new\nforce\nsetboard rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2\n\
+             usermove d8h4\n",
+        );
+        assert_eq!(out, "0-1 {Black wins}\n");
+
+        let out = run(&mut session, "setboard 4k3/8/8/8/8/8/8/4K3 x - - 0 1\n");
+        assert!(out.starts_with("tellusererror Illegal position"), "{out}");
+
+        let mut session = Session::new();
+        let out = run(&mut session, "new\nwhite\ngo\n");
+        assert!(out.ends_with("Error (no engine to search with): go\n"));
+    }
+
+    #[test]
+    fn variants() {
+        let mut session = Session::new();
+        run(
+            &mut session,
+            "new\nvariant giveaway\nforce\nusermove e2e3\nusermove b7b5\n",
+        );
+        assert_eq!(session.pos.variant(), Variant::Antichess);
+        let out = run(&mut session, "usermove e3e4\n");
+        assert!(
+            out.starts_with("Illegal move (a capture is possible"),
+            "{out}"
+        );
+
+        run(
+            &mut session,
+            "new\nvariant fischerandom\nsetboard bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9\n",
+        );
+        assert!(session.pos.is_chess960());
+        assert_eq!(session.pos.variant(), Variant::Standard);
+    }
+
+    #[test]
+    fn clock() {
+        let mut session = Session::new();
+        run(&mut session, "level 40 5 2\ntime 12000\notim 9000\n");
+
+        let limits = session.clock.limits(Color::Black, 3);
+        assert_eq!(limits.btime, Some(120_000));
+        assert_eq!(limits.wtime, Some(90_000));
+        assert_eq!(limits.binc, Some(2000));
+        assert_eq!(limits.movestogo, Some(37));
+
+        run(&mut session, "level 0 2:30 0.5\n");
+        assert_eq!(session.clock.base, 150_000);
+        assert_eq!(session.clock.inc, 500);
+        assert_eq!(session.clock.limits(Color::White, 10).movestogo, None);
+
+        run(&mut session, "st 3\nsd 6\n");
+        assert_eq!(session.clock.limits(Color::White, 0).movetime, Some(3000));
+        assert_eq!(session.clock.depth, Some(6));
+
+        let out = run(&mut session, "level 40\n");
+        assert!(out.starts_with("Error (Usage: level"));
+    }
+
+    #[test]
+    fn thinking() {
+        let mut pos = parse::from_fen(START_FEN.to_string()).unwrap();
+        let info = Info {
+            depth: 5,
+            seldepth: 9,
+            score: 31,
+            nodes: 12345,
+            time: Duration::from_millis(1520),
+            hashfull: 0,
+            pv: vec![pos.parse_uci("e2e4").unwrap()],
+            chess960: false,
+        };
+        assert_eq!(thinking_line(&info), "5 31 152 12345 e2e4");
+
+        let mate = Info {
+            score: crate::uci::MATE - 3,
+            pv: vec![],
+            ..info
+        };
+        assert_eq!(thinking_line(&mate), "5 100002 152 12345");
+    }
+
+    #[test]
+    fn post() {
+        // Reports one finished iteration before answering
+        let engine: Engine = Box::new(|pos, _, _, info| {
+            let m = pos.legal_moves().first().copied()?;
+            info(&Info {
+                depth: 1,
+                seldepth: 1,
+                score: 12,
+                nodes: 20,
+                time: Duration::from_millis(30),
+                hashfull: 0,
+                pv: vec![m],
+                chess960: false,
+            });
+            Some(m)
+        });
+        let mut session = Session::new().with_engine(engine);
+
+        let out = run(
+            &mut session,
+            "new
+white
+force
+go
+",
+        );
+        assert!(out.starts_with("move "), "{out}");
+
+        let out = run(
+            &mut session,
+            "post
+go
+",
+        );
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("1 12 3 20 "), "{out}");
+        assert!(lines[1].starts_with("move "));
+
+        let out = run(
+            &mut session,
+            "nopost
+go
+",
+        );
+        assert!(out.starts_with("move "), "{out}");
+    }
+}
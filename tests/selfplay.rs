@@ -0,0 +1,38 @@
+use chess_engine::arena::{self, EngineConfig, MatchConfig, TimeControl};
+
+#[test]
+fn self_play() {
+    let engine = EngineConfig {
+        name: "StockCrab".to_string(),
+        cmd: env!("CARGO_BIN_EXE_chess-engine").to_string(),
+        // Leaves room for the slow nodes of a debug build
+        options: vec![("Move Overhead".to_string(), "150".to_string())],
+        ..EngineConfig::default()
+    };
+    let config = MatchConfig {
+        engines: [engine.clone(), engine],
+        games: 2,
+        time: TimeControl::MoveTime(300),
+        max_moves: Some(6),
+        ..MatchConfig::default()
+    };
+
+    let mut out = vec![];
+    let spawn = |i: usize| arena::uci_player(&config.engines[i]);
+    let score = arena::run_match(&config, &[], spawn, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    // Both games last until the move limit, nobody crashes or runs out of time
+    assert_eq!(score.games(), 2, "{out}");
+    let finished: Vec<&str> = out
+        .lines()
+        .filter(|l| l.starts_with("Finished game"))
+        .collect();
+    assert_eq!(finished.len(), 2);
+    for line in finished {
+        assert!(
+            line.ends_with("{Draw by adjudication: move limit}"),
+            "{line}"
+        );
+    }
+}